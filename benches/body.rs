@@ -0,0 +1,73 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Measures the cost of observing a streaming response body with the
+//! callback and logging middleware, compared to polling it directly.
+
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use http_body_util::BodyExt;
+use sui_http::body::BoxBody;
+use sui_http::middleware::logging::LoggingLayer;
+use sui_http::middleware::metrics::Metrics;
+use tower::Layer;
+use tower::ServiceExt;
+
+const CHUNKS: usize = 1024;
+
+async fn stream<B>(_request: http::Request<B>) -> Result<http::Response<BoxBody>, Infallible> {
+    let frames = (0..CHUNKS)
+        .map(|_| Ok::<_, Infallible>(http_body::Frame::data(Bytes::from_static(&[0; 1024]))));
+    let body = http_body_util::StreamBody::new(futures::stream::iter(frames));
+    Ok(http::Response::new(sui_http::body::boxed(body)))
+}
+
+async fn drain(body: BoxBody) {
+    let mut body = body;
+    while let Some(frame) = body.frame().await {
+        std::hint::black_box(frame.unwrap());
+    }
+}
+
+fn streaming_response(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let metrics = Metrics::new();
+
+    let mut group = c.benchmark_group("streaming_response");
+    group.throughput(Throughput::Elements(CHUNKS as u64));
+
+    group.bench_function(BenchmarkId::new("bare", CHUNKS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let response = tower::service_fn(stream)
+                .oneshot(http::Request::new(sui_http::body::empty()))
+                .await
+                .unwrap();
+            drain(response.into_body()).await;
+        });
+    });
+
+    group.bench_function(BenchmarkId::new("metrics_and_logging", CHUNKS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let service =
+                LoggingLayer::new().layer(metrics.layer().layer(tower::service_fn(stream)));
+            let response = service
+                .oneshot(http::Request::new(sui_http::body::empty()))
+                .await
+                .unwrap();
+            drain(sui_http::body::boxed(response.into_body())).await;
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, streaming_response);
+criterion_main!(benches);
@@ -0,0 +1,818 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Certificates obtained and renewed automatically from an ACME CA, such
+//! as Let's Encrypt.
+//!
+//! [`Builder::acme`](crate::Builder::acme) serves TLS with a certificate
+//! for the configured domains, ordered from the CA with the TLS-ALPN-01
+//! challenge ([RFC 8737]): the CA proves control of each domain by
+//! connecting to the server on port 443 itself, so the server must be
+//! reachable there under every domain, and no other port or DNS record is
+//! needed.
+//!
+//! Certificates and the ACME account key are kept in a [`Store`], so that
+//! restarts reuse them instead of ordering again, which CAs rate limit.
+//! Certificates are renewed in the background a while before they expire,
+//! and swapped in without interrupting any connection. Until the first
+//! certificate has been issued, TLS handshakes other than the CA's fail.
+//!
+//! ```no_run
+//! use sui_http::acme::AcmeConfig;
+//! use sui_http::acme::DirStore;
+//!
+//! # async fn serve(app: axum::Router) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let acme = AcmeConfig::new(["rpc.example.com"])
+//!     .contact("ops@example.com")
+//!     .store(DirStore::new("/var/lib/rpc/acme"));
+//! let handle = sui_http::Builder::new()
+//!     .acme(acme)
+//!     .serve(("0.0.0.0", 443), app)?;
+//! # let _ = handle;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [RFC 8737]: https://www.rfc-editor.org/rfc/rfc8737
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::digest::SHA256;
+use ring::digest::digest;
+use ring::rand::SystemRandom;
+use ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+use ring::signature::EcdsaKeyPair;
+use ring::signature::KeyPair as _;
+use serde_json::Value;
+use serde_json::json;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::server::ClientHello;
+use tokio_rustls::rustls::server::ResolvesServerCert;
+use tokio_rustls::rustls::sign::CertifiedKey;
+
+use crate::BoxError;
+
+/// The directory of Let's Encrypt's production CA.
+pub const LETS_ENCRYPT_PRODUCTION: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// The directory of Let's Encrypt's staging CA, whose certificates are not
+/// trusted but whose rate limits are far higher, for trying things out.
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// The ALPN protocol the CA offers when validating a TLS-ALPN-01
+/// challenge.
+pub(crate) const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+const DEFAULT_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How often, and how many times, pending authorizations and orders are
+/// polled before giving up.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// Persists the ACME account key and the issued certificates.
+///
+/// Values are small and written rarely, so the methods are called directly
+/// from the renewal task and may block briefly. Keys are short ASCII
+/// strings that are safe to use as file names.
+pub trait Store: Send + Sync + 'static {
+    /// Returns the value stored under `key`, if any.
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// Stores `value` under `key`, replacing any previous value.
+    fn store(&self, key: &str, value: &[u8]) -> std::io::Result<()>;
+}
+
+/// A [`Store`] keeping each value in a file of a directory, which is
+/// created when the first value is stored.
+///
+/// The files include private keys, so the directory should only be
+/// readable by the server.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    dir: std::path::PathBuf,
+}
+
+impl DirStore {
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Store for DirStore {
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.dir.join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first, so a crash never leaves a
+        // truncated value behind.
+        let path = self.dir.join(key);
+        let tmp = self.dir.join(format!(".{key}.tmp"));
+        std::fs::write(&tmp, value)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// A [`Store`] that keeps values in memory only, so every restart orders a
+/// new certificate. The default, but only suitable for testing.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: std::sync::Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Store for MemoryStore {
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn store(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+}
+
+/// Which certificate to obtain, from which CA; see the [module
+/// docs](self).
+#[derive(Clone)]
+pub struct AcmeConfig {
+    domains: Vec<String>,
+    contact: Vec<String>,
+    directory_url: String,
+    store: Arc<dyn Store>,
+    renew_before: Duration,
+    retry_interval: Duration,
+}
+
+impl AcmeConfig {
+    /// Obtains a single certificate valid for every domain in `domains`
+    /// from Let's Encrypt's production CA, agreeing to its terms of
+    /// service.
+    pub fn new<I>(domains: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            domains: domains.into_iter().map(Into::into).collect(),
+            contact: Vec::new(),
+            directory_url: LETS_ENCRYPT_PRODUCTION.to_owned(),
+            store: Arc::new(MemoryStore::default()),
+            renew_before: DEFAULT_RENEW_BEFORE,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        }
+    }
+
+    /// Uses the CA with the directory at `url`, such as
+    /// [`LETS_ENCRYPT_STAGING`].
+    pub fn directory(self, url: impl Into<String>) -> Self {
+        Self {
+            directory_url: url.into(),
+            ..self
+        }
+    }
+
+    /// Adds an email address the CA may use to reach the operator, e.g.
+    /// about expiring certificates.
+    pub fn contact(mut self, email: impl AsRef<str>) -> Self {
+        self.contact.push(format!("mailto:{}", email.as_ref()));
+        self
+    }
+
+    /// Persists the account key and certificates in `store`. Defaults to a
+    /// [`MemoryStore`].
+    pub fn store(self, store: impl Store) -> Self {
+        Self {
+            store: Arc::new(store),
+            ..self
+        }
+    }
+
+    /// Renews the certificate once it expires within `renew_before`.
+    ///
+    /// Default is 30 days.
+    pub fn renew_before(self, renew_before: Duration) -> Self {
+        Self {
+            renew_before,
+            ..self
+        }
+    }
+
+    /// Waits `retry_interval` before trying again after obtaining a
+    /// certificate failed.
+    ///
+    /// Default is 10 minutes.
+    pub fn retry_interval(self, retry_interval: Duration) -> Self {
+        Self {
+            retry_interval,
+            ..self
+        }
+    }
+
+    /// The store key of the account key for this config's CA.
+    fn account_key(&self) -> String {
+        format!("account-{}", short_digest(&[&self.directory_url]))
+    }
+
+    /// The store key of the certificate for this config's CA and domains.
+    fn certificate_key(&self) -> String {
+        let mut parts = vec![self.directory_url.as_str()];
+        parts.extend(self.domains.iter().map(String::as_str));
+        format!("certificate-{}", short_digest(&parts))
+    }
+}
+
+impl std::fmt::Debug for AcmeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcmeConfig")
+            .field("domains", &self.domains)
+            .field("contact", &self.contact)
+            .field("directory_url", &self.directory_url)
+            .field("renew_before", &self.renew_before)
+            .field("retry_interval", &self.retry_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Serves the current certificate, and the challenge certificates while the
+/// CA validates them.
+#[derive(Debug, Default)]
+struct AcmeResolver {
+    certificate: RwLock<Option<Arc<CertifiedKey>>>,
+    /// Challenge certificates by domain.
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for AcmeResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN));
+        if is_challenge {
+            let domain = client_hello.server_name()?;
+            return self.challenges.read().unwrap().get(domain).cloned();
+        }
+        self.certificate.read().unwrap().clone()
+    }
+}
+
+/// Obtains and renews the certificate of a server.
+pub(crate) struct Acme {
+    config: AcmeConfig,
+    provider: Arc<CryptoProvider>,
+    resolver: Arc<AcmeResolver>,
+}
+
+impl Acme {
+    pub(crate) fn new(config: AcmeConfig) -> Self {
+        let provider = CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()));
+        Self {
+            config,
+            provider,
+            resolver: Arc::default(),
+        }
+    }
+
+    /// A TLS config serving the certificates obtained by [`Acme::run`].
+    ///
+    /// [`ACME_TLS_ALPN`] must be added to its ALPN protocols for the CA's
+    /// validation to succeed.
+    pub(crate) fn server_config(&self) -> Result<rustls::ServerConfig, BoxError> {
+        Ok(
+            rustls::ServerConfig::builder_with_provider(self.provider.clone())
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_cert_resolver(self.resolver.clone()),
+        )
+    }
+
+    /// Keeps the certificate up to date until `shutdown` is cancelled.
+    pub(crate) async fn run(self, shutdown: tokio_util::sync::CancellationToken) {
+        loop {
+            let wait = match self.renew().await {
+                Ok(expires) => {
+                    let renew_at = expires
+                        .checked_sub(self.config.renew_before)
+                        .unwrap_or(SystemTime::UNIX_EPOCH);
+                    renew_at
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default()
+                        .max(self.config.retry_interval)
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        domains = ?self.config.domains,
+                        error = %e,
+                        "failed to obtain an ACME certificate"
+                    );
+                    self.config.retry_interval
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.cancelled() => return,
+            }
+        }
+    }
+
+    /// Installs a certificate that is not due for renewal, from the store
+    /// or the CA, returning when it expires.
+    async fn renew(&self) -> Result<SystemTime, BoxError> {
+        let key = self.config.certificate_key();
+        // Once a certificate is installed this only runs when it is due, and
+        // the stored one is the same.
+        let installed = self.resolver.certificate.read().unwrap().is_some();
+        if !installed && let Some(pem) = self.config.store.load(&key)? {
+            match self.install(&pem) {
+                Ok(expires) if !self.is_due(expires) => return Ok(expires),
+                Ok(_) => tracing::info!("stored ACME certificate is due for renewal"),
+                Err(e) => tracing::warn!(error = %e, "ignoring invalid stored ACME certificate"),
+            }
+        }
+
+        tracing::info!(domains = ?self.config.domains, "ordering ACME certificate");
+        let mut client = Client::new(&self.config).await?;
+        let pem = client.order(&self.config.domains, self).await?;
+        let expires = self.install(&pem)?;
+        self.config.store.store(&key, &pem)?;
+        tracing::info!(domains = ?self.config.domains, ?expires, "installed ACME certificate");
+        Ok(expires)
+    }
+
+    fn is_due(&self, expires: SystemTime) -> bool {
+        !expires
+            .duration_since(SystemTime::now())
+            .is_ok_and(|left| left > self.config.renew_before)
+    }
+
+    /// Serves the certificate chain and private key in `pem`, returning
+    /// when the certificate expires.
+    fn install(&self, pem: &[u8]) -> Result<SystemTime, BoxError> {
+        let chain = CertificateDer::pem_slice_iter(pem).collect::<Result<Vec<_>, _>>()?;
+        let leaf = chain.first().ok_or("no certificate in PEM")?;
+        let (_, parsed) = x509_parser::parse_x509_certificate(leaf)?;
+        let not_after = u64::try_from(parsed.validity().not_after.timestamp())?;
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(not_after);
+
+        let key = PrivateKeyDer::from_pem_slice(pem)?;
+        let certified = CertifiedKey::from_der(chain, key, &self.provider)?;
+        *self.resolver.certificate.write().unwrap() = Some(Arc::new(certified));
+        Ok(expires)
+    }
+
+    /// Serves a TLS-ALPN-01 challenge certificate for `domain`.
+    fn add_challenge(&self, domain: &str, key_authorization: &str) -> Result<(), BoxError> {
+        let key = rcgen::KeyPair::generate()?;
+        let mut params = rcgen::CertificateParams::new(vec![domain.to_owned()])?;
+        params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(
+            digest(&SHA256, key_authorization.as_bytes()).as_ref(),
+        )];
+        let cert = params.self_signed(&key)?;
+        // Not `CertifiedKey::from_der`, whose consistency check rejects the
+        // critical acmeIdentifier extension.
+        let signing_key = self
+            .provider
+            .key_provider
+            .load_private_key(PrivateKeyDer::Pkcs8(key.serialize_der().into()))?;
+        let certified = CertifiedKey::new(vec![cert.der().clone()], signing_key);
+        self.resolver
+            .challenges
+            .write()
+            .unwrap()
+            .insert(domain.to_owned(), Arc::new(certified));
+        Ok(())
+    }
+
+    fn remove_challenge(&self, domain: &str) {
+        self.resolver.challenges.write().unwrap().remove(domain);
+    }
+}
+
+/// The URLs of a CA's directory.
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+/// A response to a signed request.
+struct Response {
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(&self) -> Result<Value, BoxError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+/// An ACME client, logged into its account.
+struct Client {
+    http: reqwest::Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    /// The account URL, once registered.
+    kid: Option<String>,
+    nonce: Option<String>,
+    rng: SystemRandom,
+}
+
+impl Client {
+    async fn new(config: &AcmeConfig) -> Result<Self, BoxError> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("sui-http/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let directory: Value = http
+            .get(&config.directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json_value()
+            .await?;
+        let url = |name: &str| {
+            directory[name]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| format!("ACME directory has no {name}"))
+        };
+        let directory = Directory {
+            new_nonce: url("newNonce")?,
+            new_account: url("newAccount")?,
+            new_order: url("newOrder")?,
+        };
+
+        let rng = SystemRandom::new();
+        let account_key = config.account_key();
+        let pkcs8 = match config.store.load(&account_key)? {
+            Some(pkcs8) => pkcs8,
+            None => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| "failed to generate ACME account key")?;
+                config.store.store(&account_key, pkcs8.as_ref())?;
+                pkcs8.as_ref().to_vec()
+            }
+        };
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|e| format!("invalid ACME account key: {e}"))?;
+
+        let mut client = Self {
+            http,
+            directory,
+            key,
+            kid: None,
+            nonce: None,
+            rng,
+        };
+        let new_account = client.directory.new_account.clone();
+        let account = client
+            .post(
+                &new_account,
+                Some(json!({
+                    "termsOfServiceAgreed": true,
+                    "contact": config.contact,
+                })),
+            )
+            .await?;
+        client.kid = Some(account.location.ok_or("ACME account has no URL")?);
+        Ok(client)
+    }
+
+    /// Orders a certificate for `domains`, returning its PEM chain followed
+    /// by its private key.
+    async fn order(&mut self, domains: &[String], acme: &Acme) -> Result<Vec<u8>, BoxError> {
+        let identifiers = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect::<Vec<_>>();
+        let new_order = self.directory.new_order.clone();
+        let response = self
+            .post(&new_order, Some(json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = response.location.clone().ok_or("ACME order has no URL")?;
+        let order = response.json()?;
+
+        for authorization in order["authorizations"].as_array().into_iter().flatten() {
+            let authorization = authorization
+                .as_str()
+                .ok_or("invalid ACME authorization URL")?;
+            self.authorize(authorization, acme).await?;
+        }
+
+        let order = self.poll(&order_url, &["pending"]).await?;
+        if order["status"] != "ready" && order["status"] != "valid" {
+            return Err(format!("ACME order is {}", order["status"]).into());
+        }
+
+        let key = rcgen::KeyPair::generate()?;
+        let csr = rcgen::CertificateParams::new(domains.to_vec())?.serialize_request(&key)?;
+        if order["status"] == "ready" {
+            let finalize = order["finalize"]
+                .as_str()
+                .ok_or("ACME order has no finalize URL")?;
+            self.post(
+                finalize,
+                Some(json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+            )
+            .await?;
+        }
+        let order = self.poll(&order_url, &["ready", "processing"]).await?;
+        let certificate = order["certificate"]
+            .as_str()
+            .ok_or_else(|| format!("ACME order is {}", order["status"]))?;
+
+        let mut pem = self.post(certificate, None).await?.body;
+        pem.extend_from_slice(key.serialize_pem().as_bytes());
+        Ok(pem)
+    }
+
+    /// Completes the TLS-ALPN-01 challenge of the authorization at `url`,
+    /// unless it is already valid.
+    async fn authorize(&mut self, url: &str, acme: &Acme) -> Result<(), BoxError> {
+        let authorization = self.post(url, None).await?.json()?;
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+        let domain = authorization["identifier"]["value"]
+            .as_str()
+            .ok_or("ACME authorization has no identifier")?;
+        let challenge = authorization["challenges"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|challenge| challenge["type"] == "tls-alpn-01")
+            .ok_or_else(|| format!("CA offers no tls-alpn-01 challenge for {domain}"))?;
+        let (Some(challenge_url), Some(token)) =
+            (challenge["url"].as_str(), challenge["token"].as_str())
+        else {
+            return Err("invalid ACME challenge".into());
+        };
+
+        let key_authorization = format!("{token}.{}", self.thumbprint());
+        acme.add_challenge(domain, &key_authorization)?;
+        let result = async {
+            self.post(challenge_url, Some(json!({}))).await?;
+            self.poll(url, &["pending"]).await
+        }
+        .await;
+        acme.remove_challenge(domain);
+
+        let authorization = result?;
+        if authorization["status"] != "valid" {
+            return Err(format!(
+                "ACME authorization for {domain} is {}: {}",
+                authorization["status"], authorization["challenges"]
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Fetches the resource at `url` until its status is no longer one of
+    /// `statuses`.
+    async fn poll(&mut self, url: &str, statuses: &[&str]) -> Result<Value, BoxError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource = self.post(url, None).await?.json()?;
+            if !statuses.iter().any(|status| resource["status"] == *status) {
+                return Ok(resource);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Err(format!("timed out waiting for ACME resource {url}").into())
+    }
+
+    /// Sends a signed request to `url`, or a POST-as-GET without a
+    /// `payload`, retrying once if the CA rejects the nonce.
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> Result<Response, BoxError> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce().await?,
+            };
+            let body = self.sign(url, payload.as_ref(), &nonce)?;
+            let response = self
+                .http
+                .post(url)
+                .header(http::header::CONTENT_TYPE, "application/jose+json")
+                .body(body)
+                .send()
+                .await?;
+            self.nonce = header(&response, "replay-nonce");
+            let location = header(&response, http::header::LOCATION.as_str());
+            let status = response.status();
+            let body = response.bytes().await?.to_vec();
+
+            if status.is_success() {
+                return Ok(Response { location, body });
+            }
+            let problem: Value = serde_json::from_slice(&body).unwrap_or_default();
+            if problem["type"] == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(format!(
+                "ACME request to {url} failed with {status}: {}",
+                problem["detail"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+    }
+
+    async fn new_nonce(&self) -> Result<String, BoxError> {
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        header(&response, "replay-nonce").ok_or_else(|| "ACME CA sent no nonce".into())
+    }
+
+    /// Signs a request as a flattened JWS, identifying the account by its
+    /// URL once registered, and by its public key before.
+    fn sign(&self, url: &str, payload: Option<&Value>, nonce: &str) -> Result<Vec<u8>, BoxError> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map_or_else(String::new, |payload| {
+            URL_SAFE_NO_PAD.encode(payload.to_string())
+        });
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| "failed to sign ACME request")?;
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        });
+        Ok(jws.to_string().into_bytes())
+    }
+
+    /// The account's public key as a JWK, with its members in the
+    /// lexicographic order the thumbprint requires.
+    fn jwk(&self) -> Value {
+        // An uncompressed P-256 point: 0x04, then the x and y coordinates.
+        let point = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        })
+    }
+
+    /// The JWK thumbprint of the account key ([RFC 7638]).
+    ///
+    /// [RFC 7638]: https://www.rfc-editor.org/rfc/rfc7638
+    fn thumbprint(&self) -> String {
+        // serde_json sorts object members, as the thumbprint requires.
+        let jwk = self.jwk().to_string();
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.as_bytes()))
+    }
+}
+
+/// Parses a JSON response body.
+trait JsonValue {
+    async fn json_value(self) -> Result<Value, BoxError>;
+}
+
+impl JsonValue for reqwest::Response {
+    async fn json_value(self) -> Result<Value, BoxError> {
+        Ok(serde_json::from_slice(&self.bytes().await?)?)
+    }
+}
+
+fn header(response: &reqwest::Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// A short digest of `parts`, for naming stored values.
+fn short_digest(parts: &[&str]) -> String {
+    let joined = parts.join("\n");
+    URL_SAFE_NO_PAD.encode(&digest(&SHA256, joined.as_bytes()).as_ref()[..12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        Client {
+            http: reqwest::Client::new(),
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap(),
+            kid: None,
+            nonce: None,
+            rng,
+        }
+    }
+
+    #[test]
+    fn signs_requests_as_flattened_jws() {
+        let mut client = client();
+        let jws: Value = serde_json::from_slice(
+            &client
+                .sign("https://ca/new-acct", Some(&json!({"a": 1})), "n1")
+                .unwrap(),
+        )
+        .unwrap();
+        let decode = |field: &str| {
+            URL_SAFE_NO_PAD
+                .decode(jws[field].as_str().unwrap())
+                .unwrap()
+        };
+        let protected: Value = serde_json::from_slice(&decode("protected")).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "n1");
+        assert_eq!(protected["url"], "https://ca/new-acct");
+        assert_eq!(protected["jwk"], client.jwk());
+        assert_eq!(decode("payload"), br#"{"a":1}"#);
+
+        let signed = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P256_SHA256_FIXED,
+            client.key.public_key().as_ref(),
+        )
+        .verify(signed.as_bytes(), &decode("signature"))
+        .unwrap();
+
+        // Once registered, the account is identified by its URL, and
+        // POST-as-GET requests have an empty payload.
+        client.kid = Some("https://ca/acct/1".to_owned());
+        let jws: Value =
+            serde_json::from_slice(&client.sign("https://ca/order", None, "n2").unwrap()).unwrap();
+        let protected: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(jws["protected"].as_str().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(protected["kid"], "https://ca/acct/1");
+        assert!(protected.get("jwk").is_none());
+        assert_eq!(jws["payload"], "");
+    }
+
+    #[test]
+    fn thumbprints_the_canonical_jwk() {
+        let client = client();
+        let jwk = client.jwk().to_string();
+        assert!(
+            jwk.starts_with(r#"{"crv":"P-256","kty":"EC","x":""#),
+            "{jwk}"
+        );
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(client.thumbprint()).unwrap(),
+            digest(&SHA256, jwk.as_bytes()).as_ref()
+        );
+    }
+
+    #[test]
+    fn stores_keys_per_ca_and_domains() {
+        let config = AcmeConfig::new(["a.example.com"]);
+        let staging = config.clone().directory(LETS_ENCRYPT_STAGING);
+        let other = AcmeConfig::new(["b.example.com"]);
+        assert_ne!(config.account_key(), staging.account_key());
+        assert_eq!(config.account_key(), other.account_key());
+        assert_ne!(config.certificate_key(), other.certificate_key());
+        assert!(
+            config
+                .certificate_key()
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        );
+    }
+}
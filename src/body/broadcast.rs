@@ -0,0 +1,313 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fanning a single produced stream out to many response bodies.
+//!
+//! Subscription endpoints (new checkpoints, events) produce one stream of
+//! chunks that every connected client wants a copy of. A [`Broadcast`]
+//! hands each chunk to every [`BroadcastBody`] subscribed to it without
+//! waiting for any of them: each subscriber has its own bounded buffer, and
+//! a subscriber that falls `capacity` chunks behind is handled according to
+//! the [`LagPolicy`], so one slow client never holds back the producer or
+//! the other clients.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use bytes::Bytes;
+use http_body::Frame;
+
+/// What to do with a subscriber whose buffer is full when a new chunk is
+/// sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Discard the subscriber's oldest buffered chunk to make room.
+    #[default]
+    DropOldest,
+    /// End the subscriber's body with a [`Lagged`] error.
+    Disconnect,
+}
+
+/// The error a [`BroadcastBody`] ends with when it is disconnected under
+/// [`LagPolicy::Disconnect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lagged {
+    capacity: usize,
+}
+
+impl std::fmt::Display for Lagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "subscriber fell more than {} chunks behind the broadcast",
+            self.capacity
+        )
+    }
+}
+
+impl std::error::Error for Lagged {}
+
+/// The sending side of a broadcast; see the [module docs](self).
+///
+/// Share it between the producer and the handlers that subscribe clients by
+/// wrapping it in an `Arc`. Dropping it ends every subscribed body once
+/// the chunks already buffered for it have been read.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use sui_http::body::Broadcast;
+/// use sui_http::body::LagPolicy;
+///
+/// let broadcast = Arc::new(Broadcast::new(64, LagPolicy::DropOldest));
+///
+/// // In a handler:
+/// let body = broadcast.subscribe();
+/// let response = http::Response::new(sui_http::body::boxed(body));
+/// # let _ = response;
+///
+/// // In the producer:
+/// broadcast.send(bytes::Bytes::from_static(b"checkpoint 1\n"));
+/// ```
+#[derive(Debug)]
+pub struct Broadcast {
+    capacity: usize,
+    policy: LagPolicy,
+    subscribers: Mutex<Vec<Weak<Mutex<Subscriber>>>>,
+}
+
+impl Broadcast {
+    /// Creates a broadcast buffering up to `capacity` chunks per subscriber.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize, policy: LagPolicy) -> Self {
+        assert!(capacity > 0, "broadcast capacity must be positive");
+        Self {
+            capacity,
+            policy,
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Subscribes a new body to every chunk sent from now on.
+    pub fn subscribe(&self) -> BroadcastBody {
+        let subscriber = Arc::new(Mutex::new(Subscriber {
+            queue: VecDeque::new(),
+            waker: None,
+            state: State::Open,
+        }));
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&subscriber));
+        BroadcastBody { subscriber }
+    }
+
+    /// Sends `chunk` to every subscriber, returning how many received it.
+    ///
+    /// This never waits: subscribers whose buffer is full are handled
+    /// according to the [`LagPolicy`].
+    pub fn send(&self, chunk: Bytes) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut delivered = 0;
+        subscribers.retain(|subscriber| {
+            let Some(subscriber) = subscriber.upgrade() else {
+                return false;
+            };
+            let mut subscriber = subscriber.lock().unwrap();
+
+            if subscriber.queue.len() >= self.capacity {
+                match self.policy {
+                    LagPolicy::DropOldest => {
+                        subscriber.queue.pop_front();
+                        tracing::trace!("broadcast subscriber lagged, dropping oldest chunk");
+                    }
+                    LagPolicy::Disconnect => {
+                        tracing::debug!("broadcast subscriber lagged, disconnecting");
+                        subscriber.state = State::Lagged(self.capacity);
+                        subscriber.queue.clear();
+                        subscriber.wake();
+                        return false;
+                    }
+                }
+            }
+
+            subscriber.queue.push_back(chunk.clone());
+            subscriber.wake();
+            delivered += 1;
+            true
+        });
+        delivered
+    }
+
+    /// The number of subscribed bodies that have not been dropped or
+    /// disconnected.
+    pub fn subscriber_count(&self) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        subscribers.len()
+    }
+}
+
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        for subscriber in self.subscribers.get_mut().unwrap().drain(..) {
+            if let Some(subscriber) = subscriber.upgrade() {
+                let mut subscriber = subscriber.lock().unwrap();
+                subscriber.state = State::Closed;
+                subscriber.wake();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    queue: VecDeque<Bytes>,
+    waker: Option<Waker>,
+    state: State,
+}
+
+impl Subscriber {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Open,
+    Closed,
+    /// Disconnected after falling the given number of chunks behind.
+    Lagged(usize),
+}
+
+/// A response body receiving the chunks sent to a [`Broadcast`].
+#[derive(Debug)]
+pub struct BroadcastBody {
+    subscriber: Arc<Mutex<Subscriber>>,
+}
+
+impl http_body::Body for BroadcastBody {
+    type Data = Bytes;
+    type Error = Lagged;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut subscriber = self.subscriber.lock().unwrap();
+        if let Some(chunk) = subscriber.queue.pop_front() {
+            return Poll::Ready(Some(Ok(Frame::data(chunk))));
+        }
+
+        match subscriber.state {
+            State::Open => {
+                subscriber.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Closed => Poll::Ready(None),
+            State::Lagged(capacity) => {
+                // Report the lag once, then end the body.
+                subscriber.state = State::Closed;
+                Poll::Ready(Some(Err(Lagged { capacity })))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let subscriber = self.subscriber.lock().unwrap();
+        subscriber.queue.is_empty() && subscriber.state == State::Closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    async fn next(body: &mut BroadcastBody) -> Option<Result<Bytes, Lagged>> {
+        body.frame()
+            .await
+            .map(|frame| frame.map(|frame| frame.into_data().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn delivers_to_every_subscriber() {
+        let broadcast = Broadcast::new(4, LagPolicy::DropOldest);
+        let mut a = broadcast.subscribe();
+        let mut b = broadcast.subscribe();
+
+        assert_eq!(broadcast.send(Bytes::from_static(b"1")), 2);
+        drop(broadcast);
+
+        assert_eq!(next(&mut a).await, Some(Ok(Bytes::from_static(b"1"))));
+        assert_eq!(next(&mut a).await, None);
+        assert_eq!(next(&mut b).await, Some(Ok(Bytes::from_static(b"1"))));
+        assert_eq!(next(&mut b).await, None);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_latest_chunks() {
+        let broadcast = Broadcast::new(2, LagPolicy::DropOldest);
+        let mut body = broadcast.subscribe();
+        for chunk in [b"1", b"2", b"3"] {
+            broadcast.send(Bytes::from_static(chunk));
+        }
+        drop(broadcast);
+
+        assert_eq!(next(&mut body).await, Some(Ok(Bytes::from_static(b"2"))));
+        assert_eq!(next(&mut body).await, Some(Ok(Bytes::from_static(b"3"))));
+        assert_eq!(next(&mut body).await, None);
+    }
+
+    #[tokio::test]
+    async fn disconnect_ends_slow_subscribers_only() {
+        let broadcast = Broadcast::new(1, LagPolicy::Disconnect);
+        let mut slow = broadcast.subscribe();
+        let mut fast = broadcast.subscribe();
+
+        broadcast.send(Bytes::from_static(b"1"));
+        assert_eq!(next(&mut fast).await, Some(Ok(Bytes::from_static(b"1"))));
+        assert_eq!(broadcast.send(Bytes::from_static(b"2")), 1);
+        assert_eq!(broadcast.subscriber_count(), 1);
+
+        assert!(next(&mut slow).await.unwrap().is_err());
+        assert_eq!(next(&mut slow).await, None);
+        assert_eq!(next(&mut fast).await, Some(Ok(Bytes::from_static(b"2"))));
+    }
+
+    #[tokio::test]
+    async fn wakes_waiting_subscribers() {
+        let broadcast = Arc::new(Broadcast::new(4, LagPolicy::DropOldest));
+        let mut body = broadcast.subscribe();
+
+        let producer = {
+            let broadcast = broadcast.clone();
+            tokio::spawn(async move {
+                tokio::task::yield_now().await;
+                broadcast.send(Bytes::from_static(b"hello"));
+            })
+        };
+
+        assert_eq!(
+            next(&mut body).await,
+            Some(Ok(Bytes::from_static(b"hello")))
+        );
+        producer.await.unwrap();
+        assert_eq!(broadcast.subscriber_count(), 1);
+        drop(body);
+        assert_eq!(broadcast.subscriber_count(), 0);
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use bytes::Buf;
+use bytes::Bytes;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http_body::Body;
+use http_body::Frame;
+use http_body::SizeHint;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+/// Header (and trailer) carrying the byte offset of a [`Checkpoint`].
+pub const CHECKPOINT_OFFSET_HEADER: HeaderName = HeaderName::from_static("x-checkpoint-offset");
+/// Header (and trailer) carrying the sequence number of a [`Checkpoint`].
+pub const CHECKPOINT_SEQUENCE_HEADER: HeaderName = HeaderName::from_static("x-checkpoint-sequence");
+
+/// A position in a streamed body from which it can be resumed.
+///
+/// `offset` is the number of body bytes delivered so far and `sequence` the
+/// number of data frames they were delivered in. A client whose stream is
+/// interrupted sends the last checkpoint it observed back as request
+/// headers (see [`Checkpoint::from_headers`]) and the server restarts the
+/// body from that point with [`Checkpointed::starting_at`] or
+/// [`Checkpointed::skip_to`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub offset: u64,
+    pub sequence: u64,
+}
+
+impl Checkpoint {
+    /// Parses a checkpoint from the `x-checkpoint-offset` and
+    /// `x-checkpoint-sequence` headers.
+    ///
+    /// Returns `Ok(None)` when no offset header is present. A missing
+    /// sequence header is treated as `0`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, InvalidCheckpoint> {
+        let Some(offset) = headers.get(CHECKPOINT_OFFSET_HEADER) else {
+            return Ok(None);
+        };
+        let offset = parse_u64(offset)?;
+        let sequence = headers
+            .get(CHECKPOINT_SEQUENCE_HEADER)
+            .map(parse_u64)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Some(Self { offset, sequence }))
+    }
+
+    /// Writes this checkpoint into `headers`, replacing any existing
+    /// checkpoint.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(CHECKPOINT_OFFSET_HEADER, self.offset.into());
+        headers.insert(CHECKPOINT_SEQUENCE_HEADER, self.sequence.into());
+    }
+}
+
+fn parse_u64(value: &HeaderValue) -> Result<u64, InvalidCheckpoint> {
+    value
+        .to_str()
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(InvalidCheckpoint(()))
+}
+
+/// Error returned by [`Checkpoint::from_headers`] when a checkpoint header
+/// is not a valid unsigned integer.
+#[derive(Debug)]
+pub struct InvalidCheckpoint(());
+
+impl std::fmt::Display for InvalidCheckpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid checkpoint header")
+    }
+}
+
+impl std::error::Error for InvalidCheckpoint {}
+
+pin_project! {
+    /// Body wrapper that tracks resumable [`Checkpoint`]s for a long-lived
+    /// stream.
+    ///
+    /// Every `interval` bytes the current checkpoint is reported to the
+    /// `on_checkpoint` hook, so applications can persist whatever state they
+    /// need to restart the stream, and once the inner body completes the
+    /// final checkpoint is appended to the response trailers (merged into
+    /// the inner body's trailers, if it has any).
+    pub struct Checkpointed<B, F = fn(&Checkpoint)> {
+        #[pin]
+        inner: B,
+        position: Checkpoint,
+        skip: u64,
+        interval: u64,
+        next_checkpoint: u64,
+        on_checkpoint: F,
+        done: bool,
+    }
+}
+
+impl<B> Checkpointed<B> {
+    /// The default distance, in bytes, between reported checkpoints.
+    pub const DEFAULT_INTERVAL: u64 = 1024 * 1024;
+
+    /// Wrap `inner`, starting from the beginning of the stream.
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            position: Checkpoint::default(),
+            skip: 0,
+            interval: Self::DEFAULT_INTERVAL,
+            next_checkpoint: Self::DEFAULT_INTERVAL,
+            on_checkpoint: |_| {},
+            done: false,
+        }
+    }
+}
+
+impl<B, F> Checkpointed<B, F> {
+    /// Sets the distance, in bytes, between checkpoints reported to the
+    /// `on_checkpoint` hook.
+    pub fn interval(mut self, bytes: u64) -> Self {
+        self.interval = bytes.max(1);
+        self.next_checkpoint = self.position.offset + self.interval;
+        self
+    }
+
+    /// Sets the hook invoked with each periodic checkpoint.
+    pub fn on_checkpoint<G>(self, on_checkpoint: G) -> Checkpointed<B, G>
+    where
+        G: FnMut(&Checkpoint),
+    {
+        Checkpointed {
+            inner: self.inner,
+            position: self.position,
+            skip: self.skip,
+            interval: self.interval,
+            next_checkpoint: self.next_checkpoint,
+            on_checkpoint,
+            done: self.done,
+        }
+    }
+
+    /// Resume from `checkpoint` with an inner body that is already
+    /// positioned at `checkpoint.offset`.
+    ///
+    /// Reported checkpoints continue counting from `checkpoint`.
+    pub fn starting_at(mut self, checkpoint: Checkpoint) -> Self {
+        self.position = checkpoint;
+        self.skip = 0;
+        self.next_checkpoint = checkpoint.offset + self.interval;
+        self
+    }
+
+    /// Resume from `checkpoint` with an inner body that restarts from the
+    /// beginning of the stream: the first `checkpoint.offset` bytes it
+    /// produces are discarded.
+    pub fn skip_to(mut self, checkpoint: Checkpoint) -> Self {
+        self.position = Checkpoint {
+            offset: 0,
+            sequence: checkpoint.sequence,
+        };
+        self.skip = checkpoint.offset;
+        self.next_checkpoint = checkpoint.offset + self.interval;
+        self
+    }
+
+    /// The checkpoint reached so far.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            offset: self.position.offset + self.skip,
+            ..self.position
+        }
+    }
+}
+
+impl<B, F> Body for Checkpointed<B, F>
+where
+    B: Body<Data = Bytes>,
+    F: FnMut(&Checkpoint),
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+
+        loop {
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            let frame = match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    *this.done = true;
+                    let mut trailers = HeaderMap::new();
+                    this.position.write_headers(&mut trailers);
+                    return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+                }
+            };
+
+            let mut data = match frame.into_data() {
+                Ok(data) => data,
+                Err(frame) => {
+                    let frame = match frame.into_trailers() {
+                        Ok(mut trailers) => {
+                            *this.done = true;
+                            this.position.write_headers(&mut trailers);
+                            Frame::trailers(trailers)
+                        }
+                        Err(frame) => frame,
+                    };
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+            };
+
+            if *this.skip > 0 {
+                let skipped = (*this.skip).min(data.len() as u64);
+                data.advance(skipped as usize);
+                *this.skip -= skipped;
+                this.position.offset += skipped;
+                if data.is_empty() {
+                    continue;
+                }
+            }
+
+            this.position.offset += data.len() as u64;
+            this.position.sequence += 1;
+            if this.position.offset >= *this.next_checkpoint {
+                (this.on_checkpoint)(this.position);
+                *this.next_checkpoint = this.position.offset + *this.interval;
+            }
+
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = self.inner.size_hint();
+        let mut adjusted = SizeHint::new();
+        adjusted.set_lower(hint.lower().saturating_sub(self.skip));
+        if let Some(upper) = hint.upper() {
+            adjusted.set_upper(upper.saturating_sub(self.skip));
+        }
+        adjusted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use http_body_util::BodyExt;
+    use http_body_util::StreamBody;
+    use std::convert::Infallible;
+
+    fn chunks(
+        chunks: &[&'static [u8]],
+    ) -> StreamBody<impl futures::Stream<Item = Result<Frame<Bytes>, Infallible>>> {
+        StreamBody::new(stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok(Frame::data(Bytes::from_static(c))))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn appends_final_checkpoint_trailers() {
+        let mut reported = Vec::new();
+        let body = Checkpointed::new(chunks(&[b"aaaa", b"bbbb", b"cc"]))
+            .interval(4)
+            .on_checkpoint(|c: &Checkpoint| reported.push(*c));
+
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(collected.to_bytes(), "aaaabbbbcc");
+        assert_eq!(
+            Checkpoint::from_headers(&trailers).unwrap(),
+            Some(Checkpoint {
+                offset: 10,
+                sequence: 3
+            })
+        );
+        assert_eq!(
+            reported,
+            vec![
+                Checkpoint {
+                    offset: 4,
+                    sequence: 1
+                },
+                Checkpoint {
+                    offset: 8,
+                    sequence: 2
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_to_discards_already_delivered_bytes() {
+        let checkpoint = Checkpoint {
+            offset: 6,
+            sequence: 2,
+        };
+        let body = Checkpointed::new(chunks(&[b"aaaa", b"bbbb", b"cc"])).skip_to(checkpoint);
+
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(collected.to_bytes(), "bbcc");
+        assert_eq!(
+            Checkpoint::from_headers(&trailers).unwrap(),
+            Some(Checkpoint {
+                offset: 10,
+                sequence: 4
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn starting_at_continues_counting() {
+        let checkpoint = Checkpoint {
+            offset: 100,
+            sequence: 7,
+        };
+        let body = Checkpointed::new(chunks(&[b"xyz"])).starting_at(checkpoint);
+
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert_eq!(collected.to_bytes(), "xyz");
+        assert_eq!(
+            Checkpoint::from_headers(&trailers).unwrap(),
+            Some(Checkpoint {
+                offset: 103,
+                sequence: 8
+            })
+        );
+    }
+
+    #[test]
+    fn parse_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Checkpoint::from_headers(&headers).unwrap(), None);
+
+        headers.insert(CHECKPOINT_OFFSET_HEADER, HeaderValue::from_static("42"));
+        assert_eq!(
+            Checkpoint::from_headers(&headers).unwrap(),
+            Some(Checkpoint {
+                offset: 42,
+                sequence: 0
+            })
+        );
+
+        headers.insert(CHECKPOINT_SEQUENCE_HEADER, HeaderValue::from_static("x"));
+        assert!(Checkpoint::from_headers(&headers).is_err());
+    }
+}
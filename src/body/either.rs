@@ -0,0 +1,151 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use bytes::Bytes;
+use http_body::Body;
+use http_body::Frame;
+use http_body::SizeHint;
+use pin_project_lite::pin_project;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+pin_project! {
+    /// One of two body types with the same data and error types.
+    ///
+    /// Lets middleware that sometimes answers on its own, with an [`Empty`]
+    /// or another body of its own, return either without boxing:
+    ///
+    /// ```
+    /// use sui_http::body::Either;
+    /// use sui_http::body::Empty;
+    ///
+    /// fn rejected<B: http_body::Body>() -> http::Response<Either<B, Empty<B::Data, B::Error>>> {
+    ///     let mut response = http::Response::new(Either::right(Empty::new()));
+    ///     *response.status_mut() = http::StatusCode::FORBIDDEN;
+    ///     response
+    /// }
+    /// # let _ = rejected::<sui_http::body::BoxBody>;
+    /// ```
+    #[project = EitherProj]
+    #[derive(Debug, Clone)]
+    pub enum Either<A, B> {
+        Left {
+            #[pin]
+            inner: A,
+        },
+        Right {
+            #[pin]
+            inner: B,
+        },
+    }
+}
+
+impl<A, B> Either<A, B> {
+    pub fn left(inner: A) -> Self {
+        Self::Left { inner }
+    }
+
+    pub fn right(inner: B) -> Self {
+        Self::Right { inner }
+    }
+}
+
+/// Defaults to the right body, which is typically the alternate one, such
+/// as an [`Empty`] body.
+impl<A, B: Default> Default for Either<A, B> {
+    fn default() -> Self {
+        Self::right(B::default())
+    }
+}
+
+impl<A, B> Body for Either<A, B>
+where
+    A: Body,
+    B: Body<Data = A::Data, Error = A::Error>,
+{
+    type Data = A::Data;
+    type Error = A::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
+            EitherProj::Left { inner } => inner.poll_frame(cx),
+            EitherProj::Right { inner } => inner.poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Self::Left { inner } => inner.is_end_stream(),
+            Self::Right { inner } => inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Self::Left { inner } => inner.size_hint(),
+            Self::Right { inner } => inner.size_hint(),
+        }
+    }
+}
+
+/// A body without any frames.
+///
+/// Unlike `http_body_util::Empty`, its error type is a parameter too, so
+/// that it can stand in for a body of any type in [`Either`].
+pub struct Empty<D = Bytes, E = Infallible> {
+    _marker: PhantomData<fn() -> (D, E)>,
+}
+
+impl<D, E> Empty<D, E> {
+    pub fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D, E> Default for Empty<D, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D, E> Clone for Empty<D, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D, E> Copy for Empty<D, E> {}
+
+impl<D, E> std::fmt::Debug for Empty<D, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Empty")
+    }
+}
+
+impl<D: bytes::Buf, E> Body for Empty<D, E> {
+    type Data = D;
+    type Error = E;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Poll::Ready(None)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        true
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(0)
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::BoxError;
+use http_body::Body;
+use http_body::Frame;
+use http_body::SizeHint;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+/// The error a [`Limited`] body fails with once its inner body goes past
+/// the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthLimitExceeded {
+    limit: u64,
+}
+
+impl LengthLimitExceeded {
+    /// The limit, in bytes, that was exceeded.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl std::fmt::Display for LengthLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "body is longer than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for LengthLimitExceeded {}
+
+pin_project! {
+    /// Body wrapper yielding at most `limit` bytes of the inner body.
+    ///
+    /// A data frame that would take the body past the limit is not yielded;
+    /// the body fails with a [`LengthLimitExceeded`] error instead, which
+    /// can be recovered from the boxed error with `downcast_ref`. Bodies
+    /// whose size hint already exceeds the limit fail on the first poll.
+    ///
+    /// Works for both request and response bodies.
+    #[derive(Debug)]
+    pub struct Limited<B> {
+        #[pin]
+        inner: B,
+        limit: u64,
+        remaining: u64,
+        failed: bool,
+    }
+}
+
+impl<B> Limited<B> {
+    pub fn new(inner: B, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            remaining: limit,
+            failed: false,
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B> Body for Limited<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = B::Data;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.failed {
+            return Poll::Ready(None);
+        }
+
+        let exceeded = || LengthLimitExceeded { limit: *this.limit }.into();
+        if this.inner.size_hint().lower() > *this.remaining {
+            *this.failed = true;
+            return Poll::Ready(Some(Err(exceeded())));
+        }
+
+        let frame = match ready!(this.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => return Poll::Ready(Some(Err(error.into()))),
+            None => return Poll::Ready(None),
+        };
+        if let Some(data) = frame.data_ref() {
+            let len = bytes::Buf::remaining(data) as u64;
+            if len > *this.remaining {
+                *this.failed = true;
+                return Poll::Ready(Some(Err(exceeded())));
+            }
+            *this.remaining -= len;
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.failed || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = self.inner.size_hint();
+        let mut limited = SizeHint::new();
+        limited.set_lower(hint.lower().min(self.remaining));
+        limited.set_upper(hint.upper().unwrap_or(u64::MAX).min(self.remaining));
+        limited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use http_body_util::StreamBody;
+    use std::convert::Infallible;
+
+    fn chunks(
+        chunks: &[&'static [u8]],
+    ) -> StreamBody<impl futures::Stream<Item = Result<Frame<Bytes>, Infallible>>> {
+        StreamBody::new(stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok(Frame::data(Bytes::from_static(c))))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn yields_bodies_within_the_limit() {
+        let body = Limited::new(chunks(&[b"aaaa", b"bb"]), 6);
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "aaaabb");
+    }
+
+    #[tokio::test]
+    async fn fails_once_past_the_limit() {
+        let mut body = Limited::new(chunks(&[b"aaaa", b"bb"]), 5);
+        let first = body.frame().await.unwrap().unwrap();
+        assert_eq!(first.into_data().unwrap(), "aaaa");
+
+        let error = body.frame().await.unwrap().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<LengthLimitExceeded>(),
+            Some(&LengthLimitExceeded { limit: 5 })
+        );
+        assert!(body.is_end_stream());
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_early_on_the_size_hint() {
+        let mut body = Limited::new(Full::new(Bytes::from_static(b"hello")), 4);
+        let error = body.frame().await.unwrap().unwrap_err();
+        assert!(error.is::<LengthLimitExceeded>());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::BoxError;
+use bytes::Bytes;
+use http_body::Body;
+use http_body::Frame;
+use http_body_util::BodyExt;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tokio::io::AsyncRead;
+use tokio::io::ReadBuf;
+
+mod broadcast;
+mod checkpoint;
+mod either;
+mod limited;
+pub mod sse;
+
+pub use broadcast::Broadcast;
+pub use broadcast::BroadcastBody;
+pub use broadcast::LagPolicy;
+pub use broadcast::Lagged;
+
+pub use checkpoint::CHECKPOINT_OFFSET_HEADER;
+pub use checkpoint::CHECKPOINT_SEQUENCE_HEADER;
+pub use checkpoint::Checkpoint;
+pub use checkpoint::Checkpointed;
+pub use checkpoint::InvalidCheckpoint;
+
+pub use either::Either;
+pub use either::Empty;
+
+pub use limited::LengthLimitExceeded;
+pub use limited::Limited;
+
+/// A type-erased body.
+///
+/// The error type defaults to [`BoxError`](crate::BoxError), which is what
+/// the server expects; other error types (e.g. `tonic::Status`) can be kept
+/// by boxing with [`boxed_with_error`].
+pub type BoxBody<E = BoxError> = http_body_util::combinators::UnsyncBoxBody<Bytes, E>;
+
+pub fn boxed<B>(body: B) -> BoxBody
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    try_downcast(body).unwrap_or_else(|body| body.map_err(Into::into).boxed_unsync())
+}
+
+/// Box `body` while keeping its error type.
+pub fn boxed_with_error<B>(body: B) -> BoxBody<B::Error>
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+{
+    try_downcast(body).unwrap_or_else(|body: B| body.boxed_unsync())
+}
+
+/// An empty body.
+pub fn empty() -> BoxBody {
+    boxed(http_body_util::Empty::new())
+}
+
+/// A body of a single chunk.
+pub fn full(data: impl Into<Bytes>) -> BoxBody {
+    boxed(http_body_util::Full::new(data.into()))
+}
+
+/// A body yielding each chunk of `stream` as a data frame.
+pub fn from_stream<S, E>(stream: S) -> BoxBody
+where
+    S: futures_core::Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError>,
+{
+    boxed(StreamBody { stream })
+}
+
+/// A body reading `reader` to its end, in chunks of up to `chunk_size`
+/// bytes.
+///
+/// # Panics
+///
+/// Panics if `chunk_size` is zero.
+pub fn from_async_read<R>(reader: R, chunk_size: usize) -> BoxBody
+where
+    R: AsyncRead + Send + 'static,
+{
+    assert!(chunk_size > 0, "chunk size must be positive");
+    boxed(ReaderBody {
+        reader,
+        buf: vec![0; chunk_size],
+        done: false,
+    })
+}
+
+/// Collects `body` into a single buffer, failing with
+/// [`LengthLimitExceeded`] once it grows past `limit` bytes.
+pub async fn collect_to_bytes<B>(body: B, limit: u64) -> Result<Bytes, BoxError>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let collected = Limited::new(body, limit).collect().await?;
+    Ok(collected.to_bytes())
+}
+
+pin_project! {
+    struct StreamBody<S> {
+        #[pin]
+        stream: S,
+    }
+}
+
+impl<S, E> Body for StreamBody<S>
+where
+    S: futures_core::Stream<Item = Result<Bytes, E>>,
+{
+    type Data = Bytes;
+    type Error = E;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let chunk = ready!(self.project().stream.poll_next(cx));
+        Poll::Ready(chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+}
+
+pin_project! {
+    struct ReaderBody<R> {
+        #[pin]
+        reader: R,
+        buf: Vec<u8>,
+        done: bool,
+    }
+}
+
+impl<R: AsyncRead> Body for ReaderBody<R> {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        let mut buf = ReadBuf::new(this.buf);
+        if let Err(error) = ready!(this.reader.poll_read(cx, &mut buf)) {
+            *this.done = true;
+            return Poll::Ready(Some(Err(error)));
+        }
+        if buf.filled().is_empty() {
+            *this.done = true;
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(Ok(Frame::data(Bytes::copy_from_slice(buf.filled())))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
+
+pub(crate) fn try_downcast<T, K>(k: K) -> Result<T, K>
+where
+    T: 'static,
+    K: Send + 'static,
+{
+    let mut k = Some(k);
+    if let Some(k) = <dyn std::any::Any>::downcast_mut::<Option<T>>(&mut k) {
+        Ok(k.take().unwrap())
+    } else {
+        Err(k.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::StreamBody;
+
+    #[derive(Debug, PartialEq)]
+    struct Status(u16);
+
+    #[tokio::test]
+    async fn constructors() {
+        assert!(empty().is_end_stream());
+        assert_eq!(empty().collect().await.unwrap().to_bytes(), "");
+        assert_eq!(full("hello").collect().await.unwrap().to_bytes(), "hello");
+
+        let chunks = [
+            Ok::<_, std::io::Error>(Bytes::from_static(b"a")),
+            Ok("b".into()),
+        ];
+        let body = from_stream(futures::stream::iter(chunks));
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "ab");
+
+        let mut body = from_async_read(&b"hello world"[..], 4);
+        let mut chunks = Vec::new();
+        while let Some(frame) = body.frame().await {
+            chunks.push(frame.unwrap().into_data().unwrap());
+        }
+        assert_eq!(chunks, ["hell", "o wo", "rld"]);
+    }
+
+    #[tokio::test]
+    async fn either_forwards_to_its_side() {
+        let left: Either<BoxBody, Empty<Bytes, BoxError>> = Either::left(full("hello"));
+        assert_eq!(left.size_hint().exact(), Some(5));
+        assert_eq!(left.collect().await.unwrap().to_bytes(), "hello");
+
+        let right: Either<BoxBody, Empty<Bytes, BoxError>> = Either::default();
+        assert!(right.is_end_stream());
+        assert_eq!(right.size_hint().exact(), Some(0));
+        assert_eq!(right.collect().await.unwrap().to_bytes(), "");
+    }
+
+    #[tokio::test]
+    async fn collect_to_bytes_enforces_the_limit() {
+        assert_eq!(collect_to_bytes(full("hello"), 5).await.unwrap(), "hello");
+        let error = collect_to_bytes(full("hello"), 4).await.unwrap_err();
+        assert!(error.is::<LengthLimitExceeded>());
+    }
+
+    #[tokio::test]
+    async fn boxed_with_error_keeps_the_error_type() {
+        let frames = futures::stream::iter([Err::<http_body::Frame<Bytes>, _>(Status(14))]);
+        let body: BoxBody<Status> = boxed_with_error(StreamBody::new(frames));
+
+        assert_eq!(body.collect().await.unwrap_err(), Status(14));
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server-Sent Events.
+//!
+//! [`Sse`] turns a stream of [`Event`]s into a `text/event-stream` body,
+//! framing each event as the [specification] requires, and optionally
+//! sending a comment whenever the stream has been quiet for a while, so
+//! that proxies and load balancers do not time out the idle connection.
+//!
+//! # Example
+//!
+//! ```
+//! use futures::StreamExt;
+//! use std::time::Duration;
+//! use sui_http::body::sse::Event;
+//! use sui_http::body::sse::KeepAlive;
+//! use sui_http::body::sse::Sse;
+//!
+//! let checkpoints = futures::stream::iter([41, 42]).map(|sequence| {
+//!     Event::new()
+//!         .event("checkpoint")
+//!         .id(sequence.to_string())
+//!         .data(format!(r#"{{"sequence":{sequence}}}"#))
+//! });
+//! let response = Sse::new(checkpoints)
+//!     .keep_alive(KeepAlive::new().interval(Duration::from_secs(10)))
+//!     .into_response();
+//! assert_eq!(response.headers()["content-type"], "text/event-stream");
+//! ```
+//!
+//! [specification]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use http::HeaderValue;
+use http::Response;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use tokio::time::Sleep;
+
+use super::BoxBody;
+
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A single Server-Sent Event.
+///
+/// # Panics
+///
+/// The `event` and `id` fields are single lines, so setting them to a
+/// value containing a line break panics, as does an `id` containing a NUL
+/// character, which clients ignore.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    comment: Option<String>,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the event's data. Line breaks are kept, by sending each line in
+    /// its own `data` field.
+    pub fn data(self, data: impl Into<String>) -> Self {
+        Self {
+            data: Some(data.into()),
+            ..self
+        }
+    }
+
+    /// Sets the event's type, which clients dispatch on.
+    pub fn event(self, event: impl Into<String>) -> Self {
+        let event = event.into();
+        assert!(
+            !event.contains(['\r', '\n']),
+            "SSE event type must not contain line breaks"
+        );
+        Self {
+            event: Some(event),
+            ..self
+        }
+    }
+
+    /// Sets the event's ID, which clients send back in `Last-Event-ID`
+    /// when they reconnect.
+    pub fn id(self, id: impl Into<String>) -> Self {
+        let id = id.into();
+        assert!(
+            !id.contains(['\r', '\n', '\0']),
+            "SSE event ID must not contain line breaks or NUL characters"
+        );
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    /// Sets how long clients wait before reconnecting once the stream
+    /// ends.
+    pub fn retry(self, retry: Duration) -> Self {
+        Self {
+            retry: Some(retry),
+            ..self
+        }
+    }
+
+    /// Adds a comment, which clients ignore.
+    pub fn comment(self, comment: impl Into<String>) -> Self {
+        Self {
+            comment: Some(comment.into()),
+            ..self
+        }
+    }
+
+    /// Encodes the event, including the blank line that ends it.
+    fn encode(&self) -> Bytes {
+        let mut buf = String::new();
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                push_value(&mut buf, line);
+            }
+        }
+        if let Some(event) = &self.event {
+            buf.push_str("event");
+            push_value(&mut buf, event);
+        }
+        if let Some(id) = &self.id {
+            buf.push_str("id");
+            push_value(&mut buf, id);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(buf, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                buf.push_str("data");
+                push_value(&mut buf, line);
+            }
+        }
+        buf.push('\n');
+        Bytes::from(buf)
+    }
+}
+
+/// Splits `text` on `\n`, `\r\n` and `\r`, keeping empty lines.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n')
+        .flat_map(|line| line.strip_suffix('\r').unwrap_or(line).split('\r'))
+}
+
+/// Appends `: <value>\n`, completing a field or, on its own, a comment.
+/// The space keeps a value that starts with a space from being trimmed by
+/// the client.
+fn push_value(buf: &mut String, value: &str) {
+    if !value.is_empty() {
+        buf.push_str(": ");
+        buf.push_str(value);
+    }
+    buf.push('\n');
+}
+
+/// Sends a comment on an otherwise idle [`Sse`] stream.
+#[derive(Debug, Clone)]
+pub struct KeepAlive {
+    interval: Duration,
+    comment: Bytes,
+}
+
+impl KeepAlive {
+    /// Sends an empty comment after 15 seconds without events.
+    pub fn new() -> Self {
+        Self {
+            interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            comment: Bytes::from_static(b":\n\n"),
+        }
+    }
+
+    /// Sets how long the stream may be idle before a comment is sent.
+    pub fn interval(self, interval: Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Sets the text of the comment.
+    pub fn text(self, text: impl AsRef<str>) -> Self {
+        Self {
+            comment: Event::new().comment(text.as_ref()).encode(),
+            ..self
+        }
+    }
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pin_project! {
+    /// A `text/event-stream` body sending each event of a stream; see the
+    /// [module docs](self).
+    pub struct Sse<S> {
+        #[pin]
+        stream: S,
+        keep_alive: Option<KeepAlive>,
+        #[pin]
+        idle: Option<Sleep>,
+    }
+}
+
+impl<S> Sse<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            keep_alive: None,
+            idle: None,
+        }
+    }
+
+    /// Sends keep-alive comments while the stream is idle.
+    pub fn keep_alive(self, keep_alive: KeepAlive) -> Self {
+        Self {
+            keep_alive: Some(keep_alive),
+            ..self
+        }
+    }
+
+    /// A response streaming the events, with the `text/event-stream`
+    /// content type and caching disabled.
+    pub fn into_response(self) -> Response<BoxBody>
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        let mut response = Response::new(super::boxed(self));
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for Sse<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sse")
+            .field("stream", &self.stream)
+            .field("keep_alive", &self.keep_alive)
+            .finish()
+    }
+}
+
+impl<S> Body for Sse<S>
+where
+    S: Stream<Item = Event>,
+{
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+
+        match this.stream.poll_next(cx) {
+            Poll::Ready(Some(event)) => {
+                // Any event resets the idle timer.
+                this.idle.set(None);
+                return Poll::Ready(Some(Ok(Frame::data(event.encode()))));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+
+        let Some(keep_alive) = this.keep_alive else {
+            return Poll::Pending;
+        };
+        if this.idle.is_none() {
+            this.idle.set(Some(tokio::time::sleep(keep_alive.interval)));
+        }
+        let mut idle = this.idle.as_mut().as_pin_mut().expect("idle timer is set");
+        if idle.as_mut().poll(cx).is_ready() {
+            idle.reset(tokio::time::Instant::now() + keep_alive.interval);
+            return Poll::Ready(Some(Ok(Frame::data(keep_alive.comment.clone()))));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn encodes_every_field() {
+        let event = Event::new()
+            .comment("checkpoint feed")
+            .event("checkpoint")
+            .id("42")
+            .retry(Duration::from_secs(3))
+            .data("{\"sequence\":42}");
+        assert_eq!(
+            event.encode(),
+            ": checkpoint feed\nevent: checkpoint\nid: 42\nretry: 3000\ndata: {\"sequence\":42}\n\n"
+        );
+        assert_eq!(Event::new().encode(), "\n");
+    }
+
+    #[test]
+    fn splits_data_into_lines() {
+        let event = Event::new().data("first\r\nsecond\n\nlast\n");
+        assert_eq!(
+            event.encode(),
+            "data: first\ndata: second\ndata\ndata: last\ndata\n\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "line breaks")]
+    fn rejects_multiline_event_types() {
+        let _ = Event::new().event("check\npoint");
+    }
+
+    #[tokio::test]
+    async fn streams_events_then_ends() {
+        let events = futures::stream::iter([
+            Event::new().data("one"),
+            Event::new().event("done").data("two"),
+        ]);
+        let body = Sse::new(events).into_response().into_body();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(bytes, "data: one\n\nevent: done\ndata: two\n\n");
+    }
+
+    #[tokio::test]
+    async fn sends_keep_alives_while_idle() {
+        let events = futures::stream::pending::<Event>();
+        let mut body = Box::pin(
+            Sse::new(events).keep_alive(
+                KeepAlive::new()
+                    .interval(Duration::from_millis(20))
+                    .text("ping"),
+            ),
+        );
+
+        for _ in 0..2 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("no keep-alive was sent")
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.into_data().unwrap(), ": ping\n\n");
+        }
+    }
+}
@@ -6,10 +6,27 @@ use std::time::Duration;
 // Matches hyper's default.
 const DEFAULT_HTTP1_HEADER_READ_TIMEOUT_SECS: u64 = 30;
 const DEFAULT_HTTP2_KEEPALIVE_TIMEOUT_SECS: u64 = 20;
+// hyper panics on smaller HTTP/1 read buffers.
+const MIN_HTTP1_MAX_BUF_SIZE: usize = 8192;
 // Matches hyper's post-Rapid-Reset (CVE-2023-44487) hardened default.
 const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 200;
+#[cfg(feature = "tls")]
 const DEFAULT_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 const DEFAULT_MAX_PENDING_CONNECTIONS: usize = 4096;
+const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+const DEFAULT_PROXY_PROTOCOL_TIMEOUT: Duration = Duration::from_secs(5);
+const GRPC_HTTP2_KEEPALIVE_INTERVAL_SECS: u64 = 30;
+const GRPC_TCP_KEEPALIVE_SECS: u64 = 60;
+
+fn default_drain_reconnect_trailers() -> http::HeaderMap {
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", http::HeaderValue::from_static("14"));
+    trailers.insert(
+        "grpc-message",
+        http::HeaderValue::from_static("server is draining, please reconnect"),
+    );
+    trailers
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -18,6 +35,12 @@ pub struct Config {
     max_concurrent_streams: Option<u32>,
     pub(crate) tcp_keepalive: Option<Duration>,
     pub(crate) tcp_nodelay: bool,
+    pub(crate) reuse_address: bool,
+    pub(crate) reuse_port: bool,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) listen_backlog: Option<u32>,
+    pub(crate) unix_socket_lock_file: bool,
     http2_keepalive_interval: Option<Duration>,
     http2_keepalive_timeout: Option<Duration>,
     http2_adaptive_window: Option<bool>,
@@ -25,12 +48,32 @@ pub struct Config {
     http2_max_header_list_size: Option<u32>,
     max_frame_size: Option<u32>,
     http1_header_read_timeout: Option<Duration>,
+    http1_max_headers: Option<usize>,
+    http1_max_buf_size: Option<usize>,
+    http1_half_close: bool,
+    http1_keep_alive: bool,
     pub(crate) accept_http1: bool,
+    pub(crate) accept_http2: bool,
+    #[cfg(feature = "tls")]
+    alpn_protocols: Option<Vec<Vec<u8>>>,
     enable_connect_protocol: bool,
     pub(crate) max_connection_age: Option<Duration>,
     pub(crate) max_connection_age_grace: Option<Duration>,
+    pub(crate) max_connection_age_jitter: f64,
+    pub(crate) max_requests_per_connection: Option<u64>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) shutdown_grace_period: Duration,
+    pub(crate) shutdown_connection_grace: Option<Duration>,
+    pub(crate) drain_reconnect_after: Option<Duration>,
+    pub(crate) drain_reconnect_trailers: http::HeaderMap,
+    #[cfg(feature = "tls")]
     pub(crate) tls_handshake_timeout: Duration,
     pub(crate) max_pending_connections: usize,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_connection_request_rate: Option<u32>,
+    pub(crate) proxy_protocol: bool,
+    pub(crate) proxy_protocol_timeout: Duration,
+    pub(crate) accept_error_policy: crate::AcceptErrorPolicy,
 }
 
 impl Default for Config {
@@ -41,6 +84,12 @@ impl Default for Config {
             max_concurrent_streams: Some(DEFAULT_MAX_CONCURRENT_STREAMS),
             tcp_keepalive: None,
             tcp_nodelay: true,
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            listen_backlog: None,
+            unix_socket_lock_file: false,
             http2_keepalive_interval: None,
             http2_keepalive_timeout: None,
             http2_adaptive_window: None,
@@ -50,17 +99,51 @@ impl Default for Config {
             http1_header_read_timeout: Some(Duration::from_secs(
                 DEFAULT_HTTP1_HEADER_READ_TIMEOUT_SECS,
             )),
+            http1_max_headers: None,
+            http1_max_buf_size: None,
+            http1_half_close: false,
+            http1_keep_alive: true,
             accept_http1: true,
+            accept_http2: true,
+            #[cfg(feature = "tls")]
+            alpn_protocols: None,
             enable_connect_protocol: true,
             max_connection_age: None,
             max_connection_age_grace: None,
+            max_connection_age_jitter: 0.0,
+            max_requests_per_connection: None,
+            idle_timeout: None,
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            shutdown_connection_grace: None,
+            drain_reconnect_after: None,
+            drain_reconnect_trailers: default_drain_reconnect_trailers(),
+            #[cfg(feature = "tls")]
             tls_handshake_timeout: DEFAULT_TLS_HANDSHAKE_TIMEOUT,
             max_pending_connections: DEFAULT_MAX_PENDING_CONNECTIONS,
+            max_connections: None,
+            max_connection_request_rate: None,
+            proxy_protocol: false,
+            proxy_protocol_timeout: DEFAULT_PROXY_PROTOCOL_TIMEOUT,
+            accept_error_policy: crate::AcceptErrorPolicy::default(),
         }
     }
 }
 
 impl Config {
+    /// A preset for serving gRPC.
+    ///
+    /// Starts from the defaults and enables HTTP/2 keepalive pings every
+    /// 30 seconds plus TCP keepalive, so that long-lived streaming RPCs
+    /// notice (and release) peers that silently went away. HTTP/1 stays
+    /// enabled for gRPC-Web and plain HTTP health checks.
+    pub fn grpc() -> Self {
+        Self::default()
+            .http2_keepalive_interval(Some(Duration::from_secs(
+                GRPC_HTTP2_KEEPALIVE_INTERVAL_SECS,
+            )))
+            .tcp_keepalive(Some(Duration::from_secs(GRPC_TCP_KEEPALIVE_SECS)))
+    }
+
     /// Sets the [`SETTINGS_INITIAL_WINDOW_SIZE`][spec] option for HTTP2
     /// stream-level flow control.
     ///
@@ -124,12 +207,97 @@ impl Config {
         }
     }
 
+    /// Randomizes each connection's [`Config::max_connection_age`] by up
+    /// to `fraction` of it in either direction.
+    ///
+    /// Connections opened together, e.g. when clients reconnect after a
+    /// deploy, would otherwise all reach their maximum age at once and
+    /// reconnect in a thundering herd. grpc-go uses a jitter of 0.1 (±10%).
+    ///
+    /// Default is 0 (no jitter).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not in `0.0..=1.0`.
+    pub fn max_connection_age_jitter(self, fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "max connection age jitter must be in 0.0..=1.0, got {fraction}"
+        );
+        Self {
+            max_connection_age_jitter: fraction,
+            ..self
+        }
+    }
+
+    /// Sets the number of requests after which a connection is shut down
+    /// gracefully.
+    ///
+    /// Once a connection has received this many requests it is closed the
+    /// same way as on reaching [`Config::max_connection_age`]: HTTP/1
+    /// connections answer the last request with `Connection: close`, and
+    /// HTTP/2 connections are sent a GOAWAY while their in-flight requests
+    /// complete. Together with the age limit this makes long-lived clients
+    /// reconnect regularly, so an L4 load balancer can spread them across
+    /// backends.
+    ///
+    /// Default is no limit (`None`).
+    pub fn max_requests_per_connection(self, max: impl Into<Option<u64>>) -> Self {
+        Self {
+            max_requests_per_connection: max.into(),
+            ..self
+        }
+    }
+
+    /// Sets how long a connection may go without any request in flight
+    /// before it is shut down gracefully.
+    ///
+    /// A request is in flight from when it is received until its response
+    /// body has been sent, so long-running streams keep their connection
+    /// open. Idle HTTP/1 connections are closed and idle HTTP/2 connections
+    /// are sent a GOAWAY first. Unlike [`Config::http2_keepalive_interval`],
+    /// which only closes connections whose peer stopped answering pings,
+    /// this also reclaims connections a healthy client keeps open but no
+    /// longer uses, such as those browsers leave behind.
+    ///
+    /// Default is no limit (`None`).
+    pub fn idle_timeout(self, idle_timeout: impl Into<Option<Duration>>) -> Self {
+        Self {
+            idle_timeout: idle_timeout.into(),
+            ..self
+        }
+    }
+
+    /// The deadlines of a new connection, with the configured jitter
+    /// applied to its maximum age.
+    pub(crate) fn connection_deadlines(&self) -> crate::connection_handler::Deadlines {
+        crate::connection_handler::Deadlines {
+            max_connection_age: self.jittered_max_connection_age(),
+            max_connection_age_grace: self.max_connection_age_grace,
+            shutdown_connection_grace: self.shutdown_connection_grace,
+            drain_reconnect_after: self.drain_reconnect_after,
+            idle_timeout: self.idle_timeout,
+        }
+    }
+
+    /// The maximum age of a new connection, with the configured jitter
+    /// applied.
+    fn jittered_max_connection_age(&self) -> Option<Duration> {
+        self.max_connection_age.map(|age| {
+            let jitter = self.max_connection_age_jitter;
+            let factor = 1.0 + jitter * (2.0 * crate::middleware::sampling::random_unit() - 1.0);
+            age.mul_f64(factor)
+        })
+    }
+
     /// Sets the grace period allowed after a graceful shutdown of a
     /// connection is initiated before the connection is forcefully closed.
     ///
     /// The grace period applies however the graceful shutdown was
     /// triggered: [`Config::max_connection_age`] expiring,
-    /// `ConnectionInfo::close`, or `ServerHandle::trigger_shutdown`.
+    /// `ConnectionInfo::close`, or `ServerHandle::trigger_shutdown`, unless
+    /// [`Config::shutdown_connection_grace`] sets another one for server
+    /// shutdowns.
     ///
     /// A graceful shutdown waits for in-flight requests to complete, but a
     /// stream that can make no progress -- for example, a response wedged
@@ -150,6 +318,83 @@ impl Config {
         }
     }
 
+    /// Sets how long a server shutdown waits for connections to drain
+    /// before forcefully closing the ones that remain.
+    ///
+    /// On shutdown every connection is asked to close gracefully: HTTP/2
+    /// connections are sent a GOAWAY and HTTP/1 connections answer their
+    /// in-flight request with `Connection: close`. In-flight requests keep
+    /// running until this deadline, after which their connections are
+    /// dropped and counted in `ShutdownReport::connections_aborted`. A
+    /// shorter [`Config::shutdown_connection_grace`] still closes individual
+    /// connections earlier. `ServerHandle::graceful_shutdown` picks a
+    /// deadline for a single shutdown instead.
+    ///
+    /// Default is 1 second.
+    pub fn shutdown_grace_period(self, grace_period: Duration) -> Self {
+        Self {
+            shutdown_grace_period: grace_period,
+            ..self
+        }
+    }
+
+    /// Sets how long each connection may keep draining after a server
+    /// shutdown asked it to close, before it is forcefully closed.
+    ///
+    /// This bounds every connection separately, within the overall
+    /// [`Config::shutdown_grace_period`]: e.g. with a 10 second connection
+    /// grace and a 60 second shutdown grace period, unary calls finish
+    /// quickly, a long-running streaming RPC is reset 10 seconds after its
+    /// connection received the GOAWAY, and the shutdown still completes
+    /// within a minute. The requests that were reset are listed in
+    /// `ShutdownReport::requests_aborted`. Connections that started closing
+    /// before the shutdown, e.g. on reaching their maximum age, keep their
+    /// own deadline.
+    ///
+    /// Default is [`Config::max_connection_age_grace`].
+    pub fn shutdown_connection_grace(self, grace: impl Into<Option<Duration>>) -> Self {
+        Self {
+            shutdown_connection_grace: grace.into(),
+            ..self
+        }
+    }
+
+    /// Ends response bodies that are still streaming this long after their
+    /// connection started draining, sending the
+    /// [reconnect trailers](Config::drain_reconnect_trailers) so clients
+    /// know to resume on a new connection.
+    ///
+    /// Without this, a long-lived stream either keeps a draining connection
+    /// open or is reset once [`Config::max_connection_age_grace`] (or the
+    /// server's shutdown deadline) expires. Handlers can use the
+    /// `DrainSignal` request extension to checkpoint their stream state
+    /// as soon as the drain starts. Should be shorter than the grace
+    /// period to have any effect.
+    ///
+    /// Response bodies are sent without a `content-length` while this is
+    /// set, as they may be cut short.
+    ///
+    /// Default is `None`.
+    pub fn drain_reconnect_after(self, after: impl Into<Option<Duration>>) -> Self {
+        Self {
+            drain_reconnect_after: after.into(),
+            ..self
+        }
+    }
+
+    /// Sets the trailers sent when [`Config::drain_reconnect_after`] ends a
+    /// stream.
+    ///
+    /// Default is `grpc-status: 14` (`UNAVAILABLE`) with a `grpc-message`
+    /// asking the client to reconnect, which gRPC clients treat as
+    /// retryable.
+    pub fn drain_reconnect_trailers(self, trailers: http::HeaderMap) -> Self {
+        Self {
+            drain_reconnect_trailers: trailers,
+            ..self
+        }
+    }
+
     /// Set whether HTTP2 Ping frames are enabled on accepted connections.
     ///
     /// If `None` is specified, HTTP2 keepalive is disabled, otherwise the duration
@@ -157,6 +402,11 @@ impl Config {
     /// The timeout for receiving an acknowledgement of the keepalive ping
     /// can be set with [`Config::http2_keepalive_timeout`].
     ///
+    /// Pings are sent whether or not any stream is open, so idle
+    /// connections to a peer that silently went away (e.g. behind a NAT or
+    /// load balancer that dropped the flow) are closed too; hyper's server
+    /// has no option to skip idle connections.
+    ///
     /// Default is no HTTP2 keepalive (`None`)
     pub fn http2_keepalive_interval(self, http2_keepalive_interval: Option<Duration>) -> Self {
         Self {
@@ -230,6 +480,89 @@ impl Config {
         }
     }
 
+    /// Set the `SO_REUSEADDR` option on the listening socket.
+    ///
+    /// Default is `true` on Unix and `false` elsewhere, matching
+    /// `std::net::TcpListener::bind`.
+    pub fn reuse_address(self, enabled: bool) -> Self {
+        Self {
+            reuse_address: enabled,
+            ..self
+        }
+    }
+
+    /// Set the `SO_REUSEPORT` option on the listening socket, allowing
+    /// several processes (or listeners) to bind the same address and have
+    /// the kernel balance incoming connections between them.
+    ///
+    /// Only supported on Unix; binding fails elsewhere when enabled.
+    ///
+    /// Default is `false`.
+    pub fn reuse_port(self, enabled: bool) -> Self {
+        Self {
+            reuse_port: enabled,
+            ..self
+        }
+    }
+
+    /// Set the `SO_SNDBUF` size of the listening socket, inherited by
+    /// accepted connections.
+    ///
+    /// Default is the operating system's default (`None`).
+    pub fn send_buffer_size(self, size: impl Into<Option<usize>>) -> Self {
+        Self {
+            send_buffer_size: size.into(),
+            ..self
+        }
+    }
+
+    /// Set the `SO_RCVBUF` size of the listening socket, inherited by
+    /// accepted connections.
+    ///
+    /// This is applied before `listen` so that the TCP window scale
+    /// negotiated with clients accounts for it.
+    ///
+    /// Default is the operating system's default (`None`).
+    pub fn recv_buffer_size(self, size: impl Into<Option<usize>>) -> Self {
+        Self {
+            recv_buffer_size: size.into(),
+            ..self
+        }
+    }
+
+    /// Set the maximum length of the queue of connections waiting to be
+    /// accepted.
+    ///
+    /// The kernel may silently cap this value (e.g. by
+    /// `net.core.somaxconn` on Linux).
+    ///
+    /// Default is the same backlog `std::net::TcpListener::bind` uses
+    /// (`None`).
+    pub fn listen_backlog(self, backlog: impl Into<Option<u32>>) -> Self {
+        Self {
+            listen_backlog: backlog.into(),
+            ..self
+        }
+    }
+
+    /// Guard Unix domain sockets bound by `Builder::serve_unix` with a lock
+    /// file.
+    ///
+    /// When enabled, an exclusive lock is taken on `<socket path>.lock`, and
+    /// the process id written to it, before the socket is bound. A second
+    /// server started on the same path fails instead of unlinking the
+    /// socket of a live server that is briefly unresponsive. The lock is
+    /// released when the process exits, so the lock file of a crashed
+    /// server does not block a restart.
+    ///
+    /// Default is `false`.
+    pub fn unix_socket_lock_file(self, enabled: bool) -> Self {
+        Self {
+            unix_socket_lock_file: enabled,
+            ..self
+        }
+    }
+
     /// Sets the max size of received header frames.
     ///
     /// This will default to whatever the default in hyper is. As of v1.4.1, it is 16 KiB.
@@ -272,14 +605,70 @@ impl Config {
         }
     }
 
+    /// Sets the maximum number of headers accepted on an HTTP/1 request.
+    ///
+    /// Requests with more headers are rejected with `431 Request Header
+    /// Fields Too Large`. Setting this moves hyper's header parsing buffer
+    /// from the stack to the heap, which costs a small amount of throughput.
+    ///
+    /// If `None` is specified, hyper's default is used (currently 100).
+    pub fn http1_max_headers(self, max: impl Into<Option<usize>>) -> Self {
+        Self {
+            http1_max_headers: max.into(),
+            ..self
+        }
+    }
+
+    /// Sets the maximum size of the read buffer of an HTTP/1 connection,
+    /// which bounds the total size of a request's header block.
+    ///
+    /// Requests whose header block does not fit are rejected with `431
+    /// Request Header Fields Too Large`. The minimum is 8192 bytes; smaller
+    /// values are rejected when the server starts.
+    ///
+    /// If `None` is specified, hyper's default is used (currently ~400 KiB).
+    pub fn http1_max_buf_size(self, max: impl Into<Option<usize>>) -> Self {
+        Self {
+            http1_max_buf_size: max.into(),
+            ..self
+        }
+    }
+
+    /// Sets whether HTTP/1 connections support half-closure.
+    ///
+    /// When enabled, a client that shuts down its write side after sending
+    /// a request still receives the response; otherwise the connection is
+    /// closed as soon as the EOF is read.
+    ///
+    /// Default is `false`.
+    pub fn http1_half_close(self, enabled: bool) -> Self {
+        Self {
+            http1_half_close: enabled,
+            ..self
+        }
+    }
+
+    /// Sets whether HTTP/1 connections are kept alive between requests.
+    ///
+    /// When disabled, every response is sent with `Connection: close` and
+    /// the connection is closed once it has been written.
+    ///
+    /// Default is `true`.
+    pub fn http1_keep_alive(self, enabled: bool) -> Self {
+        Self {
+            http1_keep_alive: enabled,
+            ..self
+        }
+    }
+
     /// Allow this accepting http1 requests.
     ///
     /// When `false`, plain-text connections are served in HTTP/2-only
     /// (prior knowledge) mode: the protocol sniff is skipped and anything
     /// that is not an HTTP/2 preface is rejected at the transport level.
-    /// TLS connections additionally stop advertising `http/1.1` via ALPN.
-    /// hyper's HTTP/1 upgrade mechanism is unavailable in this mode;
-    /// HTTP/2 extended CONNECT is unaffected.
+    /// TLS connections additionally stop advertising `http/1.1` via ALPN,
+    /// and HTTP/1 upgrades are unavailable; HTTP/2 extended CONNECT is
+    /// unaffected.
     ///
     /// Default is `true`.
     pub fn accept_http1(self, accept_http1: bool) -> Self {
@@ -289,11 +678,54 @@ impl Config {
         }
     }
 
+    /// Allow accepting HTTP/2 requests.
+    ///
+    /// When `false`, connections are served in HTTP/1-only mode: the
+    /// protocol sniff is skipped, an HTTP/2 preface is rejected at the
+    /// transport level, and TLS connections stop advertising `h2` via ALPN.
+    /// HTTP/1 upgrades, such as WebSocket handshakes, keep working.
+    ///
+    /// At least one of HTTP/1 and HTTP/2 must be accepted; serving with
+    /// both disabled fails.
+    ///
+    /// Default is `true`.
+    pub fn accept_http2(self, accept_http2: bool) -> Self {
+        Config {
+            accept_http2,
+            ..self
+        }
+    }
+
+    /// Sets the list of protocols offered via ALPN during the TLS
+    /// handshake, in order of preference.
+    ///
+    /// By default the list is derived from [`Config::accept_http1`] and
+    /// [`Config::accept_http2`] (`h2` followed by `http/1.1`) and appended
+    /// to any protocols already present in the `rustls::ServerConfig`.
+    /// Setting it explicitly replaces both. Offering a protocol that is not
+    /// accepted by this config causes connections negotiating it to fail.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
+    pub fn alpn_protocols<I, P>(self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<Vec<u8>>,
+    {
+        Config {
+            alpn_protocols: Some(protocols.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
     /// Sets the timeout for TLS handshakes on incoming connections.
     ///
-    /// Connections that do not complete the TLS handshake within this duration are dropped.
+    /// Connections that do not complete the TLS handshake within this duration are dropped,
+    /// and reported to the connection handler as
+    /// [`CloseReason::HandshakeTimedOut`](crate::CloseReason::HandshakeTimedOut).
     ///
     /// Default is 5 seconds.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
     pub fn tls_handshake_timeout(self, timeout: Duration) -> Self {
         Config {
             tls_handshake_timeout: timeout,
@@ -314,9 +746,132 @@ impl Config {
         }
     }
 
-    pub(crate) fn connection_builder(
-        &self,
-    ) -> hyper_util::server::conn::auto::Builder<hyper_util::rt::TokioExecutor> {
+    /// Sets the maximum number of connections the server handles at once,
+    /// including connections still completing their TLS handshake.
+    ///
+    /// Once the limit is reached the server stops calling `accept` until a
+    /// connection closes, rather than accepting and immediately dropping
+    /// new connections. Further connection attempts queue in the kernel's
+    /// listen backlog (and are refused by the kernel once it is full), so a
+    /// connection flood cannot make the server spawn unbounded tasks.
+    ///
+    /// Default is no limit (`None`).
+    pub fn max_connections(self, max: impl Into<Option<usize>>) -> Self {
+        Config {
+            max_connections: max.into(),
+            ..self
+        }
+    }
+
+    /// Sets the maximum rate, in requests per second, at which requests on
+    /// a single connection are handed to the service.
+    ///
+    /// Each connection may start a burst of up to this many requests at
+    /// once; further requests are delayed until the connection is back
+    /// under the rate. Since delayed HTTP/2 streams still count towards
+    /// [`Config::max_concurrent_streams`], this stops one multiplexed
+    /// connection from opening thousands of streams in an instant,
+    /// independently of any global rate limit.
+    ///
+    /// Default is no limit (`None`).
+    pub fn max_connection_request_rate(self, rate: impl Into<Option<u32>>) -> Self {
+        Config {
+            max_connection_request_rate: rate.into(),
+            ..self
+        }
+    }
+
+    /// Require a [PROXY protocol] (v1 or v2) header on every accepted
+    /// connection.
+    ///
+    /// The header is read before any TLS or HTTP processing and the source
+    /// address it carries replaces the peer address reported through
+    /// `ConnectionInfo` and the `ConnectInfo` request extension. Only
+    /// enable this when every client connects through a proxy that sends
+    /// the header: connections without a valid header are dropped, and any
+    /// client able to reach the listener directly could otherwise spoof
+    /// its address.
+    ///
+    /// At most [`Config::max_pending_connections`] headers are read
+    /// concurrently; see [`Config::proxy_protocol_timeout`] for bounding
+    /// how long a peer may take to send one.
+    ///
+    /// Default is `false`.
+    ///
+    /// [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+    pub fn proxy_protocol(self, enabled: bool) -> Self {
+        Config {
+            proxy_protocol: enabled,
+            ..self
+        }
+    }
+
+    /// Sets the timeout for receiving the PROXY protocol header on incoming
+    /// connections when [`Config::proxy_protocol`] is enabled.
+    ///
+    /// Connections that do not send a complete header within this duration
+    /// are dropped.
+    ///
+    /// Default is 5 seconds.
+    pub fn proxy_protocol_timeout(self, timeout: Duration) -> Self {
+        Config {
+            proxy_protocol_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Sets how errors accepting connections are handled; see
+    /// [`AcceptErrorPolicy`](crate::AcceptErrorPolicy).
+    ///
+    /// Applies to the listeners bound by the `Builder`, not to custom
+    /// [`Listener`](crate::Listener) implementations.
+    ///
+    /// Default is exponential backoff from 5ms up to 1 second.
+    pub fn accept_error_policy(self, policy: crate::AcceptErrorPolicy) -> Self {
+        Config {
+            accept_error_policy: policy,
+            ..self
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), crate::BoxError> {
+        if !self.accept_http1 && !self.accept_http2 {
+            return Err("at least one of HTTP/1 and HTTP/2 must be accepted".into());
+        }
+
+        if let Some(max) = self.http1_max_buf_size
+            && max < MIN_HTTP1_MAX_BUF_SIZE
+        {
+            return Err(format!(
+                "http1_max_buf_size must be at least {MIN_HTTP1_MAX_BUF_SIZE} bytes, got {max}"
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Configures the ALPN protocols offered by `tls_config`.
+    #[cfg(feature = "tls")]
+    pub(crate) fn apply_alpn_protocols(&self, tls_config: &mut tokio_rustls::rustls::ServerConfig) {
+        if let Some(protocols) = &self.alpn_protocols {
+            tls_config.alpn_protocols = protocols.clone();
+            return;
+        }
+
+        if self.accept_http2 {
+            tls_config.alpn_protocols.push(crate::ALPN_H2.into());
+        }
+        if self.accept_http1 {
+            tls_config.alpn_protocols.push(crate::ALPN_H1.into());
+        }
+    }
+
+    pub(crate) fn connection_builder(&self) -> crate::connection_handler::ConnectionBuilder {
+        if !self.accept_http2 {
+            return crate::connection_handler::ConnectionBuilder::Http1(self.http1_builder());
+        }
+
         let mut builder =
             hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
 
@@ -338,7 +893,17 @@ impl Config {
         builder
             .http1()
             .timer(hyper_util::rt::TokioTimer::new())
-            .header_read_timeout(self.http1_header_read_timeout);
+            .header_read_timeout(self.http1_header_read_timeout)
+            .half_close(self.http1_half_close)
+            .keep_alive(self.http1_keep_alive);
+
+        if let Some(max_headers) = self.http1_max_headers {
+            builder.http1().max_headers(max_headers);
+        }
+
+        if let Some(max_buf_size) = self.http1_max_buf_size {
+            builder.http1().max_buf_size(max_buf_size);
+        }
 
         builder
             .http2()
@@ -356,6 +921,27 @@ impl Config {
             builder.http2().max_header_list_size(max_header_list_size);
         }
 
+        crate::connection_handler::ConnectionBuilder::Auto(builder)
+    }
+
+    /// The builder for HTTP/1-only connections, which unlike hyper-util's
+    /// auto builder pinned to HTTP/1 keeps hyper's upgrade support.
+    fn http1_builder(&self) -> hyper::server::conn::http1::Builder {
+        let mut builder = hyper::server::conn::http1::Builder::new();
+        builder
+            .timer(hyper_util::rt::TokioTimer::new())
+            .header_read_timeout(self.http1_header_read_timeout)
+            .half_close(self.http1_half_close)
+            .keep_alive(self.http1_keep_alive);
+
+        if let Some(max_headers) = self.http1_max_headers {
+            builder.max_headers(max_headers);
+        }
+
+        if let Some(max_buf_size) = self.http1_max_buf_size {
+            builder.max_buf_size(max_buf_size);
+        }
+
         builder
     }
 }
@@ -374,6 +960,61 @@ mod tests {
         assert_eq!(config.max_concurrent_streams, Some(200));
     }
 
+    #[cfg(feature = "tls")]
+    #[test]
+    fn alpn_protocols_follow_accepted_versions() {
+        let alpn = |config: Config| {
+            let mut tls = tokio_rustls::rustls::ServerConfig::builder()
+                .with_no_client_auth()
+                .with_cert_resolver(std::sync::Arc::new(
+                    tokio_rustls::rustls::server::ResolvesServerCertUsingSni::new(),
+                ));
+            config.apply_alpn_protocols(&mut tls);
+            tls.alpn_protocols
+        };
+
+        assert_eq!(
+            alpn(Config::default()),
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+        assert_eq!(
+            alpn(Config::default().accept_http1(false)),
+            vec![b"h2".to_vec()]
+        );
+        assert_eq!(
+            alpn(Config::default().accept_http2(false)),
+            vec![b"http/1.1".to_vec()]
+        );
+        assert_eq!(
+            alpn(Config::default().alpn_protocols(["http/1.1", "h2"])),
+            vec![b"http/1.1".to_vec(), b"h2".to_vec()]
+        );
+    }
+
+    #[test]
+    fn rejects_no_accepted_versions() {
+        let config = Config::default().accept_http1(false).accept_http2(false);
+        assert!(config.validate().is_err());
+    }
+
+    /// hyper panics on read buffers below its minimum; surface that as a
+    /// configuration error instead.
+    #[test]
+    fn rejects_tiny_http1_buffers() {
+        assert!(
+            Config::default()
+                .http1_max_buf_size(4096)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            Config::default()
+                .http1_max_buf_size(8192)
+                .validate()
+                .is_ok()
+        );
+    }
+
     /// The header read timeout is the slowloris defense for HTTP/1
     /// connections; pin the default so it cannot silently regress to
     /// disabled.
@@ -385,4 +1026,21 @@ mod tests {
             Some(Duration::from_secs(30))
         );
     }
+
+    #[test]
+    fn jitters_the_max_connection_age() {
+        let age = Duration::from_secs(100);
+        let config = Config::default().max_connection_age(age);
+        assert_eq!(config.jittered_max_connection_age(), Some(age));
+
+        let config = config.max_connection_age_jitter(0.1);
+        let ages = (0..100)
+            .map(|_| config.jittered_max_connection_age().unwrap())
+            .collect::<Vec<_>>();
+        assert!(
+            ages.iter()
+                .all(|age| (Duration::from_secs(90)..=Duration::from_secs(110)).contains(age))
+        );
+        assert!(ages.iter().any(|jittered| *jittered != age));
+    }
 }
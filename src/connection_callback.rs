@@ -0,0 +1,186 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::AlpnProtocol;
+use crate::io::ByteCounters;
+
+/// Factory for per-connection lifecycle handlers.
+///
+/// The connection-level counterpart of the request-level
+/// `middleware::callback::MakeCallbackHandler`: the server calls
+/// [`MakeConnectionHandler::on_accept`] for every accepted connection,
+/// before any TLS handshake or request, and reports the rest of the
+/// connection's life to the returned [`ConnectionHandler`]. Install one
+/// with `Builder::connection_handler`.
+pub trait MakeConnectionHandler: Send + Sync + 'static {
+    /// Handler observing a single connection.
+    type Handler: ConnectionHandler;
+
+    /// Called when a connection is accepted, to build its handler.
+    fn on_accept(&self, connection: &AcceptedConnection<'_>) -> Self::Handler;
+}
+
+/// Observes a single connection after it was accepted.
+pub trait ConnectionHandler: Send + 'static {
+    /// Called once the TLS handshake of a TLS connection completes.
+    fn on_tls_handshake(&mut self, _handshake: &TlsHandshake) {
+        // do nothing
+    }
+
+    /// Called exactly once when the connection closes, however it ends:
+    /// this includes connections that fail their TLS handshake and
+    /// connections the server drops before serving them.
+    fn on_close(&mut self, close: &ConnectionClosed);
+}
+
+/// A connection that was just accepted; see
+/// [`MakeConnectionHandler::on_accept`].
+#[derive(Debug)]
+pub struct AcceptedConnection<'a> {
+    remote_addr: &'a (dyn Any + Send + Sync),
+    local_addr: &'a (dyn Any + Send + Sync),
+}
+
+impl<'a> AcceptedConnection<'a> {
+    pub(crate) fn new<A: Send + Sync + 'static>(remote_addr: &'a A, local_addr: &'a A) -> Self {
+        Self {
+            remote_addr,
+            local_addr,
+        }
+    }
+
+    /// The peer's address, if the listener's addresses are `A`s (e.g.
+    /// `std::net::SocketAddr` for TCP listeners).
+    pub fn remote_addr<A: 'static>(&self) -> Option<&'a A> {
+        self.remote_addr.downcast_ref()
+    }
+
+    /// The local address the connection was accepted on, if the
+    /// listener's addresses are `A`s.
+    pub fn local_addr<A: 'static>(&self) -> Option<&'a A> {
+        self.local_addr.downcast_ref()
+    }
+}
+
+/// A completed TLS handshake; see [`ConnectionHandler::on_tls_handshake`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TlsHandshake {
+    /// Time from accepting the connection until the handshake completed.
+    pub duration: Duration,
+    /// The protocol negotiated through ALPN, if any.
+    pub alpn_protocol: Option<AlpnProtocol>,
+}
+
+/// A closed connection; see [`ConnectionHandler::on_close`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionClosed {
+    /// Time from accepting the connection until it closed.
+    pub duration: Duration,
+    /// Bytes read from the socket, including any TLS overhead.
+    pub bytes_read: u64,
+    /// Bytes written to the socket, including any TLS overhead.
+    pub bytes_written: u64,
+    /// Why the connection closed.
+    pub reason: CloseReason,
+}
+
+/// Why a connection closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// The peer closed the connection, or it finished a graceful shutdown.
+    Completed,
+    /// Serving the connection failed with an I/O or protocol error.
+    Error,
+    /// The TLS handshake failed.
+    HandshakeFailed,
+    /// The TLS handshake did not complete within
+    /// `Config::tls_handshake_timeout`, e.g. because the client never
+    /// spoke.
+    HandshakeTimedOut,
+    /// The client's certificate was rejected by
+    /// `Builder::client_identity`.
+    ClientRejected,
+    /// The server dropped the connection: it exceeded a shutdown grace
+    /// period, or was still pending when the server shut down or hit
+    /// `Config::max_pending_connections`.
+    Aborted,
+}
+
+/// A [`MakeConnectionHandler`] with its handler type erased, so the
+/// server does not need to be generic over it.
+pub(crate) type SharedMakeConnectionHandler = Arc<dyn MakeBoxedConnectionHandler>;
+
+pub(crate) trait MakeBoxedConnectionHandler: Send + Sync {
+    fn on_accept(&self, connection: &AcceptedConnection<'_>) -> Box<dyn ConnectionHandler>;
+}
+
+impl<M: MakeConnectionHandler> MakeBoxedConnectionHandler for M {
+    fn on_accept(&self, connection: &AcceptedConnection<'_>) -> Box<dyn ConnectionHandler> {
+        Box::new(MakeConnectionHandler::on_accept(self, connection))
+    }
+}
+
+/// Drives a connection's [`ConnectionHandler`], reporting the close when
+/// dropped.
+///
+/// The guard travels with the connection from accept through the TLS
+/// handshake to the connection task; if any of those is dropped early the
+/// connection is reported as [`CloseReason::Aborted`].
+pub(crate) struct ConnectionGuard {
+    handler: Box<dyn ConnectionHandler>,
+    accepted_at: Instant,
+    counters: Arc<ByteCounters>,
+    reason: CloseReason,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn new(
+        make_handler: &dyn MakeBoxedConnectionHandler,
+        connection: &AcceptedConnection<'_>,
+        accepted_at: Instant,
+    ) -> Self {
+        Self {
+            handler: make_handler.on_accept(connection),
+            accepted_at,
+            counters: Arc::default(),
+            reason: CloseReason::Aborted,
+        }
+    }
+
+    /// The counters the connection's IO should record its traffic in.
+    pub(crate) fn counters(&self) -> Arc<ByteCounters> {
+        self.counters.clone()
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn on_tls_handshake(&mut self, alpn_protocol: Option<AlpnProtocol>) {
+        self.handler.on_tls_handshake(&TlsHandshake {
+            duration: self.accepted_at.elapsed(),
+            alpn_protocol,
+        });
+    }
+
+    pub(crate) fn close(mut self, reason: CloseReason) {
+        self.reason = reason;
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let (bytes_read, bytes_written) = self.counters.get();
+        self.handler.on_close(&ConnectionClosed {
+            duration: self.accepted_at.elapsed(),
+            bytes_read,
+            bytes_written,
+            reason: self.reason,
+        });
+    }
+}
@@ -1,34 +1,54 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::pin::pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
 use std::time::Duration;
+use std::time::Instant;
 
 use http::Request;
 use http::Response;
 use hyper_util::rt::TokioExecutor;
 use hyper_util::server::conn::auto;
+use pin_project_lite::pin_project;
 use tracing::debug;
 use tracing::trace;
 
 use crate::ActiveConnections;
 use crate::BoxError;
 use crate::ConnectionId;
+use crate::body::BoxBody;
+use crate::connection_callback::ConnectionGuard;
+use crate::drain::ConnectionDrain;
 use crate::fuse::Fuse;
 
 // This is moved to its own function as a way to get around
 // https://github.com/rust-lang/rust/issues/102211
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_connection<IO, S, B, C>(
     hyper_io: IO,
     hyper_svc: S,
-    builder: auto::Builder<TokioExecutor>,
+    builder: ConnectionBuilder,
     graceful_shutdown_token: tokio_util::sync::CancellationToken,
-    max_connection_age: Option<Duration>,
-    max_connection_age_grace: Option<Duration>,
+    server_shutdown_token: tokio_util::sync::CancellationToken,
+    deadlines: Deadlines,
+    drain: ConnectionDrain,
+    in_flight: Arc<InFlight>,
+    connection_id: ConnectionId,
     on_connection_close: C,
-) where
+    guard: Option<ConnectionGuard>,
+) -> (ConnectionClose, Vec<crate::AbortedRequest>)
+where
     B: http_body::Body + Send + 'static,
     B::Data: Send,
     B::Error: Into<BoxError>,
@@ -38,39 +58,100 @@ pub async fn serve_connection<IO, S, B, C>(
     S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
     // `serve_connection_with_upgrades` always sniffs the protocol from the
-    // first bytes and silently ignores `http2_only`, so an HTTP/2-only
-    // builder must use `serve_connection`, where the pinned version is
-    // honored, the sniff is skipped, and anything that is not an HTTP/2
-    // preface is rejected. The upgrades variant differs only in its HTTP/1
-    // arm (hyper's `with_upgrades` wrapper); HTTP/2 extended CONNECT
-    // behaves identically on both paths.
-    if builder.is_http1_available() {
-        let conn = pin!(builder.serve_connection_with_upgrades(hyper_io, hyper_svc));
-        drive_connection(
-            conn,
-            graceful_shutdown_token,
-            max_connection_age,
-            max_connection_age_grace,
-        )
-        .await;
-    } else {
-        let conn = pin!(builder.serve_connection(hyper_io, hyper_svc));
-        drive_connection(
-            conn,
-            graceful_shutdown_token,
-            max_connection_age,
-            max_connection_age_grace,
-        )
-        .await;
-    }
+    // first bytes and silently ignores `http2_only`/`http1_only`, so a
+    // builder pinned to a single version cannot use it. HTTP/2-only
+    // connections use `serve_connection`, where the pinned version is
+    // honored and anything speaking HTTP/1 is rejected; HTTP/2 extended
+    // CONNECT behaves identically on both paths. HTTP/1-only connections
+    // are served by hyper's own HTTP/1 builder, which keeps upgrades.
+    let close = match builder {
+        ConnectionBuilder::Auto(builder)
+            if builder.is_http1_available() && builder.is_http2_available() =>
+        {
+            let conn = pin!(builder.serve_connection_with_upgrades(hyper_io, hyper_svc));
+            drive_connection(
+                conn,
+                graceful_shutdown_token,
+                server_shutdown_token,
+                deadlines,
+                &drain,
+                &in_flight,
+            )
+            .await
+        }
+        ConnectionBuilder::Auto(builder) => {
+            let conn = pin!(builder.serve_connection(hyper_io, hyper_svc));
+            drive_connection(
+                conn,
+                graceful_shutdown_token,
+                server_shutdown_token,
+                deadlines,
+                &drain,
+                &in_flight,
+            )
+            .await
+        }
+        ConnectionBuilder::Http1(builder) => {
+            let conn = pin!(
+                builder
+                    .serve_connection(hyper_io, hyper_svc)
+                    .with_upgrades()
+            );
+            drive_connection(
+                conn,
+                graceful_shutdown_token,
+                server_shutdown_token,
+                deadlines,
+                &drain,
+                &in_flight,
+            )
+            .await
+        }
+    };
 
     trace!("connection closed");
     drop(on_connection_close);
+    if let Some(guard) = guard {
+        guard.close(close.into());
+    }
+    (close, in_flight.take_aborted(connection_id))
+}
+
+/// Builds the connections of a server; see `Config::connection_builder`.
+pub(crate) enum ConnectionBuilder {
+    /// Serves HTTP/2, and HTTP/1 unless it is disabled.
+    Auto(auto::Builder<TokioExecutor>),
+    /// Serves HTTP/1 only.
+    Http1(hyper::server::conn::http1::Builder),
+}
+
+/// How a connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionClose {
+    /// The connection completed on its own or finished a graceful shutdown.
+    Completed,
+    /// Serving the connection failed with an I/O or protocol error.
+    Failed,
+    /// The connection was dropped with streams still in flight because a
+    /// graceful shutdown exceeded the grace period.
+    Forced,
+}
+
+impl From<ConnectionClose> for crate::CloseReason {
+    fn from(close: ConnectionClose) -> Self {
+        match close {
+            ConnectionClose::Completed => Self::Completed,
+            ConnectionClose::Failed => Self::Error,
+            ConnectionClose::Forced => Self::Aborted,
+        }
+    }
 }
 
-/// The connection future types produced by hyper-util's auto builder,
-/// unified so [`drive_connection`] can drive either.
-trait GracefulConnection: Future<Output = Result<(), BoxError>> {
+/// The connection future types produced by the [`ConnectionBuilder`]s,
+/// unified so [`drive_connection`] can drive any of them.
+trait GracefulConnection: Future<Output = Result<(), Self::Error>> {
+    type Error: std::fmt::Display;
+
     fn graceful_shutdown(self: Pin<&mut Self>);
 }
 
@@ -84,6 +165,8 @@ where
     S::Future: Send + 'static,
     S::Error: Into<BoxError>,
 {
+    type Error = BoxError;
+
     fn graceful_shutdown(self: Pin<&mut Self>) {
         auto::Connection::graceful_shutdown(self)
     }
@@ -99,24 +182,56 @@ where
     S::Future: Send + 'static,
     S::Error: Into<BoxError>,
 {
+    type Error = BoxError;
+
     fn graceful_shutdown(self: Pin<&mut Self>) {
         auto::UpgradeableConnection::graceful_shutdown(self)
     }
 }
 
+impl<IO, S, B> GracefulConnection for hyper::server::conn::http1::UpgradeableConnection<IO, S>
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    IO: hyper::rt::Read + hyper::rt::Write + Send + Unpin + 'static,
+    S: hyper::service::HttpService<hyper::body::Incoming, ResBody = B>,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Error = hyper::Error;
+
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        hyper::server::conn::http1::UpgradeableConnection::graceful_shutdown(self)
+    }
+}
+
 async fn drive_connection<C>(
     mut conn: Pin<&mut C>,
     graceful_shutdown_token: tokio_util::sync::CancellationToken,
-    max_connection_age: Option<Duration>,
-    max_connection_age_grace: Option<Duration>,
-) where
+    server_shutdown_token: tokio_util::sync::CancellationToken,
+    deadlines: Deadlines,
+    drain: &ConnectionDrain,
+    in_flight: &Arc<InFlight>,
+) -> ConnectionClose
+where
     C: GracefulConnection,
 {
-    let mut sig = pin!(Fuse::new(graceful_shutdown_token.cancelled_owned()));
+    let Deadlines {
+        max_connection_age,
+        max_connection_age_grace,
+        shutdown_connection_grace,
+        drain_reconnect_after,
+        idle_timeout,
+    } = deadlines;
+    let mut idle = pin!(Fuse::new(wait_idle(in_flight.clone(), idle_timeout)));
+    let mut sig = pin!(Fuse::new(graceful_shutdown_token.clone().cancelled_owned()));
 
     let sleep = sleep_or_pending(max_connection_age);
     tokio::pin!(sleep);
     let mut in_grace_period = false;
+    let reconnect = sleep_or_pending(None);
+    tokio::pin!(reconnect);
 
     loop {
         tokio::select! {
@@ -129,14 +244,22 @@ async fn drive_connection<C>(
                 // earlier deadline.
                 if !in_grace_period {
                     in_grace_period = true;
-                    sleep.set(sleep_or_pending(max_connection_age_grace));
+                    let grace = if server_shutdown_token.is_cancelled() {
+                        shutdown_connection_grace.or(max_connection_age_grace)
+                    } else {
+                        max_connection_age_grace
+                    };
+                    sleep.set(sleep_or_pending(grace));
+                    drain.start();
+                    reconnect.set(sleep_or_pending(drain_reconnect_after));
                 }
             }
             rv = &mut conn => {
                 if let Err(err) = rv {
                     debug!("failed serving connection: {:#}", err);
+                    return ConnectionClose::Failed;
                 }
-                break;
+                return ConnectionClose::Completed;
             },
             _ = &mut sleep  => {
                 if in_grace_period {
@@ -145,12 +268,26 @@ async fn drive_connection<C>(
                     // stalled peer that never reopens its receive window)
                     // can never complete a graceful shutdown, so dropping
                     // the connection is the only way to reclaim it.
-                    debug!("max connection age grace period expired, closing connection");
-                    break;
+                    debug!("connection grace period expired, closing connection");
+                    in_flight.abort();
+                    return ConnectionClose::Forced;
                 }
                 conn.as_mut().graceful_shutdown();
                 in_grace_period = true;
                 sleep.set(sleep_or_pending(max_connection_age_grace));
+                drain.start();
+                reconnect.set(sleep_or_pending(drain_reconnect_after));
+            },
+            _ = &mut idle => {
+                // Shut down the same way as when the connection is closed
+                // explicitly; the branch above handles it.
+                debug!("connection idle, closing connection");
+                graceful_shutdown_token.cancel();
+            },
+            _ = &mut reconnect => {
+                debug!("drain reconnect deadline reached, ending in-flight streams");
+                drain.request_reconnect();
+                reconnect.set(sleep_or_pending(None));
             },
         }
     }
@@ -163,6 +300,25 @@ async fn sleep_or_pending(wait_for: Option<Duration>) {
     };
 }
 
+/// Resolves once the connection has had no request in flight for
+/// `idle_timeout`, or never if there is none.
+async fn wait_idle(in_flight: Arc<InFlight>, idle_timeout: Option<Duration>) {
+    let Some(idle_timeout) = idle_timeout else {
+        return std::future::pending().await;
+    };
+    let mut active = in_flight.active.subscribe();
+    loop {
+        // The sender is owned by `in_flight`, so these never fail.
+        let _ = active.wait_for(|active| *active == 0).await;
+        tokio::select! {
+            _ = tokio::time::sleep(idle_timeout) => return,
+            // Any request restarts the timer, even one that has already
+            // completed by the time this wakes up.
+            _ = active.changed() => {}
+        }
+    }
+}
+
 pub(crate) struct OnConnectionClose<A> {
     id: ConnectionId,
     active_connections: ActiveConnections<A>,
@@ -182,3 +338,269 @@ impl<A> Drop for OnConnectionClose<A> {
         self.active_connections.write().unwrap().remove(&self.id);
     }
 }
+
+/// Counts the requests served on a connection and shuts it down gracefully
+/// once `Config::max_requests_per_connection` is reached.
+pub(crate) struct RequestLimit {
+    max: u64,
+    received: AtomicU64,
+    close_http1: AtomicBool,
+    graceful_shutdown_token: tokio_util::sync::CancellationToken,
+}
+
+impl RequestLimit {
+    pub(crate) fn new(
+        max: u64,
+        graceful_shutdown_token: tokio_util::sync::CancellationToken,
+    ) -> Self {
+        Self {
+            max,
+            received: AtomicU64::new(0),
+            close_http1: AtomicBool::new(false),
+            graceful_shutdown_token,
+        }
+    }
+
+    pub(crate) fn on_request<B>(&self, request: &Request<B>) {
+        if self.received.fetch_add(1, Ordering::Relaxed) + 1 != self.max {
+            return;
+        }
+
+        trace!("max requests per connection reached, closing connection");
+        // HTTP/1 serves one request at a time, so the response to this
+        // request is the next one written and can announce the close itself.
+        // HTTP/2 connections are sent a GOAWAY instead.
+        if request.version() < http::Version::HTTP_2 {
+            self.close_http1.store(true, Ordering::Relaxed);
+        }
+        self.graceful_shutdown_token.cancel();
+    }
+
+    pub(crate) fn on_response<B>(&self, response: &mut Response<B>) {
+        if self.close_http1.swap(false, Ordering::Relaxed) {
+            response.headers_mut().insert(
+                http::header::CONNECTION,
+                http::HeaderValue::from_static("close"),
+            );
+        }
+    }
+}
+
+/// The timeouts that end a connection; see `Config::connection_deadlines`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Deadlines {
+    pub(crate) max_connection_age: Option<Duration>,
+    pub(crate) max_connection_age_grace: Option<Duration>,
+    pub(crate) shutdown_connection_grace: Option<Duration>,
+    pub(crate) drain_reconnect_after: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+/// Tracks the requests in flight on a connection, for
+/// `Config::idle_timeout` and to report the requests a forced close
+/// aborts.
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    requests: Mutex<InFlightRequests>,
+    active: tokio::sync::watch::Sender<usize>,
+}
+
+#[derive(Debug, Default)]
+struct InFlightRequests {
+    next_id: u64,
+    requests: BTreeMap<u64, InFlightRequest>,
+    /// The requests in flight when a forced close aborted them, and when.
+    aborted: Option<(Instant, BTreeMap<u64, InFlightRequest>)>,
+}
+
+#[derive(Debug)]
+struct InFlightRequest {
+    method: http::Method,
+    uri: http::Uri,
+    received: Instant,
+}
+
+impl InFlightRequest {
+    fn aborted(&self, connection_id: ConnectionId, at: Instant) -> crate::AbortedRequest {
+        crate::AbortedRequest {
+            connection_id,
+            method: self.method.clone(),
+            uri: self.uri.clone(),
+            elapsed: at.saturating_duration_since(self.received),
+        }
+    }
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self {
+            requests: Mutex::default(),
+            active: tokio::sync::watch::Sender::new(0),
+        }
+    }
+}
+
+impl InFlight {
+    /// Marks `request` as in flight until the returned guard is dropped.
+    pub(crate) fn on_request<B>(self: &Arc<Self>, request: &Request<B>) -> ActiveRequest {
+        let mut requests = self.requests.lock().unwrap();
+        let id = requests.next_id;
+        requests.next_id += 1;
+        requests.requests.insert(
+            id,
+            InFlightRequest {
+                method: request.method().clone(),
+                uri: request.uri().clone(),
+                received: Instant::now(),
+            },
+        );
+        drop(requests);
+        self.active.send_modify(|active| *active += 1);
+        ActiveRequest {
+            in_flight: self.clone(),
+            id,
+        }
+    }
+
+    /// The requests in flight, in the order they were received, as if they
+    /// were aborted now.
+    pub(crate) fn abort_all(&self, connection_id: ConnectionId) -> Vec<crate::AbortedRequest> {
+        let now = Instant::now();
+        let requests = self.requests.lock().unwrap();
+        requests
+            .requests
+            .values()
+            .map(|request| request.aborted(connection_id, now))
+            .collect()
+    }
+
+    /// Records the requests in flight as aborted by a forced close, before
+    /// the connection is dropped along with them.
+    fn abort(&self) {
+        let mut requests = self.requests.lock().unwrap();
+        let aborted = std::mem::take(&mut requests.requests);
+        requests.aborted = Some((Instant::now(), aborted));
+    }
+
+    /// The requests a forced close of the connection aborted.
+    pub(crate) fn take_aborted(&self, connection_id: ConnectionId) -> Vec<crate::AbortedRequest> {
+        match self.requests.lock().unwrap().aborted.take() {
+            Some((at, aborted)) => aborted
+                .values()
+                .map(|request| request.aborted(connection_id, at))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// A request in flight on a connection; see [`InFlight::on_request`].
+pub(crate) struct ActiveRequest {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.in_flight
+            .requests
+            .lock()
+            .unwrap()
+            .requests
+            .remove(&self.id);
+        self.in_flight.active.send_modify(|active| *active -= 1);
+    }
+}
+
+/// Service marking each request in flight until its response body is
+/// dropped; see [`InFlight`].
+#[derive(Clone)]
+pub(crate) struct TrackInFlight<S> {
+    inner: S,
+    in_flight: Arc<InFlight>,
+}
+
+impl<S> TrackInFlight<S> {
+    pub(crate) fn new(inner: S, in_flight: Arc<InFlight>) -> Self {
+        Self { inner, in_flight }
+    }
+}
+
+impl<S, B> tower::Service<Request<B>> for TrackInFlight<S>
+where
+    S: tower::Service<Request<B>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ActiveFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let active = self.in_flight.on_request(&request);
+        ActiveFuture {
+            inner: self.inner.call(request),
+            active: Some(active),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future that keeps its request in flight until the response
+    /// body is dropped.
+    pub(crate) struct ActiveFuture<F> {
+        #[pin]
+        inner: F,
+        active: Option<ActiveRequest>,
+    }
+}
+
+impl<F, E> Future for ActiveFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let active = this.active.take().expect("polled after completion");
+        Poll::Ready(Ok(response.map(|body| {
+            crate::body::boxed(ActiveBody {
+                inner: body,
+                active,
+            })
+        })))
+    }
+}
+
+pin_project! {
+    /// Response body that keeps its request in flight until it is dropped.
+    struct ActiveBody<B> {
+        #[pin]
+        inner: B,
+        active: ActiveRequest,
+    }
+}
+
+impl<B: http_body::Body> http_body::Body for ActiveBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        self.project().inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
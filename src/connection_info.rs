@@ -4,6 +4,7 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
+#[cfg(feature = "tls")]
 use tokio_rustls::rustls::pki_types::CertificateDer;
 
 pub(crate) type ActiveConnections<A = std::net::SocketAddr> =
@@ -14,29 +15,150 @@ pub type ConnectionId = usize;
 #[derive(Debug)]
 pub struct ConnectionInfo<A>(Arc<Inner<A>>);
 
+/// The certificate chain a TLS client presented.
+///
+/// Without the `tls` feature no connection has one, and this type cannot be
+/// constructed.
 #[derive(Clone, Debug)]
-pub struct PeerCertificates(Arc<Vec<tokio_rustls::rustls::pki_types::CertificateDer<'static>>>);
+pub struct PeerCertificates(
+    #[cfg(feature = "tls")] Arc<Vec<CertificateDer<'static>>>,
+    #[cfg(not(feature = "tls"))] std::convert::Infallible,
+);
 
+#[cfg(feature = "tls")]
 impl PeerCertificates {
-    pub fn peer_certs(&self) -> &[tokio_rustls::rustls::pki_types::CertificateDer<'static>] {
+    pub(crate) fn new(certs: Vec<CertificateDer<'static>>) -> Self {
+        Self(Arc::new(certs))
+    }
+
+    pub fn peer_certs(&self) -> &[CertificateDer<'static>] {
         self.0.as_ref()
     }
 }
 
+/// Maps a client's certificate chain to the extensions added to each of
+/// its requests; see `Builder::client_identity`.
+#[cfg(feature = "tls")]
+pub(crate) type ClientIdentityFn =
+    dyn Fn(&PeerCertificates) -> Result<http::Extensions, crate::BoxError> + Send + Sync;
+
+/// The application protocol a TLS client and the server agreed on through
+/// ALPN, e.g. `h2` or `http/1.1`.
+///
+/// The server inserts it into the extensions of every request on a TLS
+/// connection that negotiated a protocol; see `router::AlpnRouter` for
+/// dispatching on it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AlpnProtocol(bytes::Bytes);
+
+impl AlpnProtocol {
+    #[cfg(any(feature = "tls", test))]
+    pub(crate) fn new(protocol: &[u8]) -> Self {
+        Self(bytes::Bytes::copy_from_slice(protocol))
+    }
+
+    /// The protocol's ALPN identifier.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The parameters a TLS connection was established with: the protocol
+/// version, cipher suite, ALPN protocol and SNI host name.
+///
+/// The server inserts it into the extensions of every request on a TLS
+/// connection, e.g. to log which clients still use deprecated protocol
+/// versions. It is also reported to
+/// `ConnectionHandler::on_tls_handshake` and available from
+/// [`ConnectionInfo::tls_info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsInfo(Arc<TlsInfoInner>);
+
+#[derive(Debug, PartialEq, Eq)]
+struct TlsInfoInner {
+    protocol_version: u16,
+    cipher_suite: u16,
+    cipher_suite_name: Option<&'static str>,
+    alpn_protocol: Option<AlpnProtocol>,
+    server_name: Option<String>,
+}
+
+impl TlsInfo {
+    #[cfg(feature = "tls")]
+    pub(crate) fn new(connection: &tokio_rustls::rustls::ServerConnection) -> Self {
+        let cipher_suite = connection
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite());
+        Self(Arc::new(TlsInfoInner {
+            protocol_version: connection.protocol_version().map_or(0, u16::from),
+            cipher_suite: cipher_suite.map_or(0, u16::from),
+            cipher_suite_name: cipher_suite.and_then(|suite| suite.as_str()),
+            alpn_protocol: connection.alpn_protocol().map(AlpnProtocol::new),
+            server_name: connection.server_name().map(str::to_owned),
+        }))
+    }
+
+    /// The negotiated protocol version, e.g. `TLSv1.3`.
+    pub fn protocol_version(&self) -> &'static str {
+        match self.0.protocol_version {
+            0x0300 => "SSLv3",
+            0x0301 => "TLSv1.0",
+            0x0302 => "TLSv1.1",
+            0x0303 => "TLSv1.2",
+            0x0304 => "TLSv1.3",
+            _ => "unknown",
+        }
+    }
+
+    /// The IANA code of the negotiated protocol version, e.g. `0x0304`
+    /// for TLS 1.3.
+    pub fn protocol_version_code(&self) -> u16 {
+        self.0.protocol_version
+    }
+
+    /// The IANA name of the negotiated cipher suite, e.g.
+    /// `TLS13_AES_128_GCM_SHA256`.
+    pub fn cipher_suite(&self) -> &'static str {
+        self.0.cipher_suite_name.unwrap_or("unknown")
+    }
+
+    /// The IANA code of the negotiated cipher suite.
+    pub fn cipher_suite_code(&self) -> u16 {
+        self.0.cipher_suite
+    }
+
+    /// The protocol negotiated through ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&AlpnProtocol> {
+        self.0.alpn_protocol.as_ref()
+    }
+
+    /// The host name the client asked for through SNI, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.0.server_name.as_deref()
+    }
+}
+
 impl<A> ConnectionInfo<A> {
     pub(crate) fn new(
         address: A,
-        peer_certificates: Option<Arc<Vec<CertificateDer<'static>>>>,
+        peer_certificates: Option<PeerCertificates>,
+        tls_info: Option<TlsInfo>,
         graceful_shutdown_token: tokio_util::sync::CancellationToken,
     ) -> Self {
         Self(Arc::new(Inner {
             address,
             time_established: std::time::Instant::now(),
-            peer_certificates: peer_certificates.map(PeerCertificates),
+            peer_certificates,
+            tls_info,
             graceful_shutdown_token,
+            in_flight: Default::default(),
         }))
     }
 
+    pub(crate) fn in_flight(&self) -> &Arc<crate::connection_handler::InFlight> {
+        &self.0.in_flight
+    }
+
     /// The peer's remote address
     pub fn remote_address(&self) -> &A {
         &self.0.address
@@ -51,6 +173,11 @@ impl<A> ConnectionInfo<A> {
         self.0.peer_certificates.as_ref()
     }
 
+    /// The parameters of the TLS session, for TLS connections.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.0.tls_info.as_ref()
+    }
+
     /// A stable identifier for this connection
     pub fn id(&self) -> ConnectionId {
         &*self.0 as *const _ as usize
@@ -76,15 +203,23 @@ struct Inner<A = std::net::SocketAddr> {
     time_established: std::time::Instant,
 
     peer_certificates: Option<PeerCertificates>,
+    tls_info: Option<TlsInfo>,
     graceful_shutdown_token: tokio_util::sync::CancellationToken,
+    in_flight: Arc<crate::connection_handler::InFlight>,
 }
 
+/// Details about the connection a request arrived on.
+///
+/// The server inserts a `ConnectInfo` into the extensions of every request
+/// it serves, so handlers and middleware can learn who connected and how.
 #[derive(Debug, Clone)]
 pub struct ConnectInfo<A = std::net::SocketAddr> {
     /// Returns the local address of this connection.
     pub local_addr: A,
     /// Returns the remote (peer) address of this connection.
     pub remote_addr: A,
+    /// Whether the connection is secured with TLS.
+    pub tls: bool,
 }
 
 impl<A> ConnectInfo<A> {
@@ -97,4 +232,9 @@ impl<A> ConnectInfo<A> {
     pub fn remote_addr(&self) -> &A {
         &self.remote_addr
     }
+
+    /// Returns `true` if the connection is secured with TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
 }
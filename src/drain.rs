@@ -0,0 +1,135 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Notifying long-lived streams that their connection is draining.
+//!
+//! A connection drains when the server shuts down, when it reaches
+//! `Config::max_connection_age`, or when `ConnectionInfo::close` is called.
+//! Every request carries a [`DrainSignal`] extension that fires when its
+//! connection starts draining, giving handlers a chance to checkpoint a
+//! stream and end it on their own terms. When
+//! `Config::drain_reconnect_after` is set, response bodies still streaming
+//! that long after the drain started are ended with the configured
+//! "please reconnect" trailers instead of being reset once the grace period
+//! runs out.
+
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use tokio_util::sync::CancellationToken;
+use tokio_util::sync::WaitForCancellationFutureOwned;
+
+use crate::BoxError;
+
+/// Request extension that fires when the request's connection starts
+/// draining.
+#[derive(Debug, Clone)]
+pub struct DrainSignal(CancellationToken);
+
+impl DrainSignal {
+    /// Returns `true` once the connection has started draining.
+    pub fn is_draining(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Completes once the connection starts draining.
+    pub async fn draining(&self) {
+        self.0.cancelled().await
+    }
+}
+
+/// The drain state of a single connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionDrain {
+    draining: CancellationToken,
+    reconnect: CancellationToken,
+}
+
+impl ConnectionDrain {
+    pub(crate) fn signal(&self) -> DrainSignal {
+        DrainSignal(self.draining.clone())
+    }
+
+    pub(crate) fn start(&self) {
+        self.draining.cancel();
+    }
+
+    pub(crate) fn request_reconnect(&self) {
+        self.reconnect.cancel();
+    }
+
+    pub(crate) fn reconnect_body<B>(&self, inner: B, trailers: HeaderMap) -> ReconnectBody<B> {
+        ReconnectBody {
+            inner,
+            reconnect: self.reconnect.clone().cancelled_owned(),
+            trailers: Some(trailers),
+        }
+    }
+}
+
+pin_project! {
+    /// Body that ends with the reconnect trailers once the connection
+    /// requests it, even if the inner body has more to send.
+    pub(crate) struct ReconnectBody<B> {
+        #[pin]
+        inner: B,
+        #[pin]
+        reconnect: WaitForCancellationFutureOwned,
+        trailers: Option<HeaderMap>,
+    }
+}
+
+impl<B> Body for ReconnectBody<B>
+where
+    B: Body<Data = Bytes, Error = BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        // Once the inner body finishes (or the trailers were sent) there is
+        // nothing left to cut short.
+        let Some(trailers) = this.trailers.as_ref() else {
+            return Poll::Ready(None);
+        };
+
+        if this.reconnect.poll(cx).is_ready() {
+            tracing::debug!("connection draining, ending response stream with reconnect trailers");
+            let trailers = trailers.clone();
+            *this.trailers = None;
+            return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+        }
+
+        let frame = std::task::ready!(this.inner.poll_frame(cx));
+        if frame.as_ref().is_none_or(|frame| match frame {
+            Ok(frame) => frame.is_trailers(),
+            Err(_) => true,
+        }) {
+            *this.trailers = None;
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        // The body may be cut short, so never promise an upper bound that
+        // would be sent as a `content-length`.
+        let mut hint = http_body::SizeHint::new();
+        hint.set_lower(self.inner.size_hint().lower());
+        hint
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Zero-downtime restarts by handing listening sockets to a new process.
+//!
+//! The running process duplicates its listening sockets with
+//! [`ServerHandle::listener_fds`](crate::ServerHandle::listener_fds) and
+//! starts the new binary with [`spawn`], which passes them on. The new
+//! process adopts them with [`Builder::from_inherited_fds`] and starts
+//! accepting, after which the old process shuts down, draining its
+//! in-flight connections. Both processes accept from the same sockets in
+//! the meantime, so no connection is refused.
+//!
+//! ```no_run
+//! # async fn upgrade(handle: sui_http::ServerHandle) -> std::io::Result<()> {
+//! let fds = handle.listener_fds()?;
+//! let _child = sui_http::handover::spawn(
+//!     &mut std::process::Command::new(std::env::current_exe()?),
+//!     &fds,
+//! )?;
+//! // Wait until the new process reports that it is serving, then drain.
+//! handle.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Builder::from_inherited_fds`]: crate::Builder::from_inherited_fds
+
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Lists the inherited descriptors, separated by commas.
+const LISTEN_FDS_ENV: &str = "SUI_HTTP_LISTEN_FDS";
+/// The pid of the process that passed the descriptors, so that processes
+/// spawned by the new one do not mistake them for their own.
+const LISTEN_PARENT_ENV: &str = "SUI_HTTP_LISTEN_PARENT";
+
+/// Whether the inherited file descriptors have been taken, so that each is
+/// owned at most once.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Spawns `command` with `fds` left open in the child, to be adopted with
+/// [`Builder::from_inherited_fds`](crate::Builder::from_inherited_fds).
+///
+/// The descriptors are passed as they are, so they should be duplicates
+/// owned by the caller, such as those returned by
+/// [`ServerHandle::listener_fds`](crate::ServerHandle::listener_fds).
+/// Processes spawned concurrently by other threads may inherit them too.
+pub fn spawn(
+    command: &mut std::process::Command,
+    fds: &[OwnedFd],
+) -> std::io::Result<std::process::Child> {
+    for fd in fds {
+        socket2::SockRef::from(fd).set_cloexec(false)?;
+    }
+    let listen_fds = fds
+        .iter()
+        .map(|fd| fd.as_raw_fd().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let child = command
+        .env(LISTEN_FDS_ENV, listen_fds)
+        .env(LISTEN_PARENT_ENV, std::process::id().to_string())
+        .spawn();
+    for fd in fds {
+        socket2::SockRef::from(fd).set_cloexec(true)?;
+    }
+    child
+}
+
+/// Takes the listening sockets passed by the parent process with [`spawn`].
+///
+/// Returns no sockets if this process was not started by [`spawn`], and on
+/// every call after the first, since the sockets can only be owned once.
+pub fn inherited_fds() -> std::io::Result<Vec<OwnedFd>> {
+    let inherited = parse_env(
+        std::env::var(LISTEN_FDS_ENV).ok().as_deref(),
+        std::env::var(LISTEN_PARENT_ENV).ok().as_deref(),
+        std::os::unix::process::parent_id(),
+    )?;
+    if inherited.is_empty() || TAKEN.swap(true, Ordering::AcqRel) {
+        return Ok(Vec::new());
+    }
+
+    inherited
+        .into_iter()
+        .map(|raw_fd| {
+            // SAFETY: our parent passed us these descriptors, and `TAKEN`
+            // ensures they are only taken once.
+            let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+            socket2::SockRef::from(&fd).set_cloexec(true)?;
+            Ok(fd)
+        })
+        .collect()
+}
+
+fn parse_env(
+    listen_fds: Option<&str>,
+    listen_parent: Option<&str>,
+    parent_pid: u32,
+) -> std::io::Result<Vec<RawFd>> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let (Some(listen_fds), Some(listen_parent)) = (listen_fds, listen_parent) else {
+        return Ok(Vec::new());
+    };
+    let listen_parent = listen_parent.parse::<u32>().map_err(|e| {
+        invalid(format!(
+            "invalid {LISTEN_PARENT_ENV} {listen_parent:?}: {e}"
+        ))
+    })?;
+    if listen_parent != parent_pid {
+        return Ok(Vec::new());
+    }
+
+    listen_fds
+        .split(',')
+        .filter(|fd| !fd.is_empty())
+        .map(|fd| {
+            fd.parse::<RawFd>()
+                .ok()
+                .filter(|fd| *fd >= 0)
+                .ok_or_else(|| invalid(format!("invalid {LISTEN_FDS_ENV} {listen_fds:?}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_trusts_the_parent() {
+        assert_eq!(parse_env(Some("5,6"), Some("42"), 42).unwrap(), [5, 6]);
+        assert!(parse_env(Some("5,6"), Some("7"), 42).unwrap().is_empty());
+        assert!(parse_env(Some("5,6"), None, 42).unwrap().is_empty());
+        assert!(parse_env(Some(""), Some("42"), 42).unwrap().is_empty());
+        assert!(parse_env(Some("5,-1"), Some("42"), 42).is_err());
+    }
+}
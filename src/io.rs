@@ -4,15 +4,24 @@
 use std::io;
 use std::io::IoSlice;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::task::Context;
 use std::task::Poll;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
 use tokio::io::ReadBuf;
+#[cfg(feature = "tls")]
 use tokio_rustls::server::TlsStream;
 
+use crate::AlpnProtocol;
+use crate::PeerCertificates;
+use crate::TlsInfo;
+
 pub(crate) enum ServerIo<IO> {
     Io(IO),
+    #[cfg(feature = "tls")]
     TlsIo(Box<TlsStream<IO>>),
 }
 
@@ -21,21 +30,50 @@ impl<IO> ServerIo<IO> {
         Self::Io(io)
     }
 
+    #[cfg(feature = "tls")]
     pub(crate) fn new_tls_io(io: TlsStream<IO>) -> Self {
         Self::TlsIo(Box::new(io))
     }
 
-    pub(crate) fn peer_certs(
-        &self,
-    ) -> Option<std::sync::Arc<Vec<tokio_rustls::rustls::pki_types::CertificateDer<'static>>>> {
+    pub(crate) fn is_tls(&self) -> bool {
+        match self {
+            Self::Io(_) => false,
+            #[cfg(feature = "tls")]
+            Self::TlsIo(_) => true,
+        }
+    }
+
+    pub(crate) fn alpn_protocol(&self) -> Option<AlpnProtocol> {
         match self {
             Self::Io(_) => None,
+            #[cfg(feature = "tls")]
+            Self::TlsIo(io) => {
+                let (_inner, session) = io.get_ref();
+
+                session.alpn_protocol().map(AlpnProtocol::new)
+            }
+        }
+    }
+
+    pub(crate) fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            Self::Io(_) => None,
+            #[cfg(feature = "tls")]
+            Self::TlsIo(io) => Some(TlsInfo::new(io.get_ref().1)),
+        }
+    }
+
+    pub(crate) fn peer_certs(&self) -> Option<PeerCertificates> {
+        match self {
+            Self::Io(_) => None,
+            #[cfg(feature = "tls")]
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => {
                 let (_inner, session) = io.get_ref();
 
                 session
                     .peer_certificates()
-                    .map(|certs| certs.to_owned().into())
+                    .map(|certs| PeerCertificates::new(certs.to_owned()))
             }
         }
     }
@@ -52,6 +90,7 @@ where
     ) -> Poll<io::Result<()>> {
         match &mut *self {
             Self::Io(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => Pin::new(io).poll_read(cx, buf),
         }
     }
@@ -68,6 +107,7 @@ where
     ) -> Poll<io::Result<usize>> {
         match &mut *self {
             Self::Io(io) => Pin::new(io).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => Pin::new(io).poll_write(cx, buf),
         }
     }
@@ -75,6 +115,7 @@ where
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         match &mut *self {
             Self::Io(io) => Pin::new(io).poll_flush(cx),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => Pin::new(io).poll_flush(cx),
         }
     }
@@ -82,6 +123,7 @@ where
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         match &mut *self {
             Self::Io(io) => Pin::new(io).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => Pin::new(io).poll_shutdown(cx),
         }
     }
@@ -93,6 +135,7 @@ where
     ) -> Poll<Result<usize, io::Error>> {
         match &mut *self {
             Self::Io(io) => Pin::new(io).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => Pin::new(io).poll_write_vectored(cx, bufs),
         }
     }
@@ -100,7 +143,102 @@ where
     fn is_write_vectored(&self) -> bool {
         match self {
             Self::Io(io) => io.is_write_vectored(),
+            #[cfg(feature = "tls")]
             Self::TlsIo(io) => io.is_write_vectored(),
         }
     }
 }
+
+/// Bytes read from and written to a connection's socket.
+#[derive(Debug, Default)]
+pub(crate) struct ByteCounters {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl ByteCounters {
+    /// Returns the bytes read and written so far.
+    pub(crate) fn get(&self) -> (u64, u64) {
+        (
+            self.read.load(Ordering::Relaxed),
+            self.written.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// IO recording its traffic in [`ByteCounters`], when there are any.
+pub(crate) struct CountingIo<IO> {
+    inner: IO,
+    counters: Option<Arc<ByteCounters>>,
+}
+
+impl<IO> CountingIo<IO> {
+    pub(crate) fn new(inner: IO, counters: Option<Arc<ByteCounters>>) -> Self {
+        Self { inner, counters }
+    }
+
+    fn record_written(&self, result: &Poll<io::Result<usize>>) {
+        if let (Some(counters), Poll::Ready(Ok(written))) = (&self.counters, result) {
+            counters
+                .written
+                .fetch_add(*written as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<IO> AsyncRead for CountingIo<IO>
+where
+    IO: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let (Some(counters), Poll::Ready(Ok(()))) = (&self.counters, &result) {
+            counters
+                .read
+                .fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<IO> AsyncWrite for CountingIo<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.record_written(&result);
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.record_written(&result);
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -1,55 +1,132 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+#![cfg_attr(doc_cfg, feature(doc_cfg))]
+
 use http::Request;
 use http::Response;
 use hyper_util::service::TowerToHyperService;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
 use tokio::task::JoinSet;
+#[cfg(feature = "tls")]
 use tokio_rustls::TlsAcceptor;
+use tower::Layer;
 use tower::Service;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 use tracing::trace;
 
 use self::body::BoxBody;
+use self::connection_callback::ConnectionGuard;
+use self::connection_callback::SharedMakeConnectionHandler;
 use self::connection_info::ActiveConnections;
+use self::io::CountingIo;
 use self::io::ServerIo;
 
 pub use bytes;
 pub use http;
+#[cfg(feature = "tls")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
 pub use tokio_rustls::rustls;
 
+#[cfg(feature = "acme")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "acme")))]
+pub mod acme;
 pub mod body;
 mod config;
+mod connection_callback;
 mod connection_handler;
 mod connection_info;
+mod drain;
 mod fuse;
+#[cfg(unix)]
+#[cfg_attr(doc_cfg, doc(cfg(unix)))]
+pub mod handover;
 mod io;
 mod listener;
 pub mod middleware;
+mod pacing;
+mod proxy_protocol;
+pub mod router;
+#[cfg(feature = "self-signed")]
+mod self_signed;
+#[cfg(feature = "serde")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "serde")))]
+pub mod server_config;
+#[cfg(unix)]
+#[cfg_attr(doc_cfg, doc(cfg(unix)))]
+pub mod systemd;
+#[cfg(feature = "test-util")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "test-util")))]
+pub mod test_util;
+mod timing;
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+mod vsock;
 
 pub use config::Config;
+pub use connection_callback::AcceptedConnection;
+pub use connection_callback::CloseReason;
+pub use connection_callback::ConnectionClosed;
+pub use connection_callback::ConnectionHandler;
+pub use connection_callback::MakeConnectionHandler;
+pub use connection_callback::TlsHandshake;
+pub use listener::AcceptErrorPolicy;
 pub use listener::Listener;
 pub use listener::ListenerExt;
-
+#[cfg(feature = "self-signed")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "self-signed")))]
+pub use self_signed::SelfSignedCertificate;
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+pub use vsock::VsockAddr;
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+pub use vsock::VsockListener;
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+pub use vsock::VsockStream;
+
+pub use connection_info::AlpnProtocol;
 pub use connection_info::ConnectInfo;
 pub use connection_info::ConnectionId;
 pub use connection_info::ConnectionInfo;
 pub use connection_info::PeerCertificates;
+pub use drain::DrainSignal;
+pub use timing::RequestTiming;
 
 pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
 /// h2 alpn in plain format for rustls.
+#[cfg(feature = "tls")]
 const ALPN_H2: &[u8] = b"h2";
 /// h1 alpn in plain format for rustls.
+#[cfg(feature = "tls")]
 const ALPN_H1: &[u8] = b"http/1.1";
 
+/// A [`Builder::layer`] layer, applied to the boxed service.
+type BoxLayer =
+    Arc<dyn Fn(middleware::stack::StackService) -> middleware::stack::StackService + Send + Sync>;
+
 #[derive(Default)]
 pub struct Builder {
     config: Config,
+    #[cfg(feature = "tls")]
     tls_config: Option<rustls::ServerConfig>,
+    #[cfg(feature = "tls")]
+    client_identity: Option<Arc<connection_info::ClientIdentityFn>>,
+    #[cfg(feature = "acme")]
+    acme: Option<acme::AcmeConfig>,
+    connection_handler: Option<SharedMakeConnectionHandler>,
+    middleware: Option<middleware::stack::MiddlewareStack>,
+    /// Layers added with [`Builder::layer`], outermost first.
+    layers: Vec<BoxLayer>,
+    error_handler: middleware::error::ErrorHandler,
+    /// Sockets handed over by a previous process, served in place of
+    /// binding new ones.
+    #[cfg(unix)]
+    inherited_fds: Vec<std::os::fd::OwnedFd>,
+    /// Shuts the server down; created up front so that listeners can
+    /// trigger a shutdown too.
+    graceful_shutdown_token: tokio_util::sync::CancellationToken,
 }
 
 impl Builder {
@@ -57,15 +134,130 @@ impl Builder {
         Self::default()
     }
 
+    /// A builder preset for serving gRPC, using [`Config::grpc`].
+    ///
+    /// Pair it with [`Builder::serve_grpc`].
+    pub fn grpc() -> Self {
+        Self::new().config(Config::grpc())
+    }
+
+    /// A builder that serves on the listening sockets handed over by the
+    /// previous process with [`handover::spawn`], for zero-downtime
+    /// restarts.
+    ///
+    /// [`Builder::serve`] and [`Builder::serve_addrs`] then serve on the
+    /// inherited sockets instead of binding the given addresses, which are
+    /// only bound when nothing was inherited, such as on first start.
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn from_inherited_fds() -> std::io::Result<Self> {
+        Ok(Self {
+            inherited_fds: handover::inherited_fds()?,
+            ..Self::default()
+        })
+    }
+
     pub fn config(mut self, config: Config) -> Self {
         self.config = config;
         self
     }
 
+    /// Observe the lifecycle of every accepted connection: acceptance, TLS
+    /// handshake completion and close. See [`MakeConnectionHandler`].
+    ///
+    /// With the `metrics` feature, `middleware::metrics::Metrics` is a
+    /// handler exporting connection counters and handshake latencies.
+    pub fn connection_handler<M: MakeConnectionHandler>(mut self, make_handler: M) -> Self {
+        self.connection_handler = Some(Arc::new(make_handler));
+        self
+    }
+
+    /// Wrap every served service in `stack`, such as
+    /// [`MiddlewareStack::rest_defaults`] or
+    /// [`MiddlewareStack::grpc_defaults`], which orders request IDs,
+    /// logging, metrics, compression and timeouts correctly.
+    ///
+    /// [`Builder::serve_grpc`] already enforces gRPC deadlines, so pair it
+    /// with a stack without a timeout.
+    ///
+    /// [`MiddlewareStack::rest_defaults`]: middleware::stack::MiddlewareStack::rest_defaults
+    /// [`MiddlewareStack::grpc_defaults`]: middleware::stack::MiddlewareStack::grpc_defaults
+    pub fn middleware(mut self, stack: middleware::stack::MiddlewareStack) -> Self {
+        self.middleware = Some(stack);
+        self
+    }
+
+    /// Wrap every served service in `layer`, as with
+    /// [`ServiceBuilder::layer`]: layers added first are outermost, and all
+    /// of them sit inside the [`Builder::middleware`] stack.
+    ///
+    /// The layer wraps a [`LayerService`], which takes requests with any
+    /// body, and may change the request and response bodies and the error
+    /// type, which are boxed again around it. So layers like
+    /// [`CallbackLayer`] or [`LoggingLayer`] can be added as they are:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use sui_http::middleware::logging::LoggingLayer;
+    /// use sui_http::middleware::timeout::TimeoutLayer;
+    ///
+    /// let builder = sui_http::Builder::new()
+    ///     .layer(LoggingLayer::new())
+    ///     .layer(TimeoutLayer::new(Duration::from_secs(10)));
+    /// # let _ = builder;
+    /// ```
+    ///
+    /// [`LayerService`]: middleware::stack::LayerService
+    /// [`CallbackLayer`]: middleware::callback::CallbackLayer
+    /// [`LoggingLayer`]: middleware::logging::LoggingLayer
+    pub fn layer<L, ResponseBody>(mut self, layer: L) -> Self
+    where
+        L: Layer<middleware::stack::LayerService> + Send + Sync + 'static,
+        L::Service: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        self.layers.push(Arc::new(move |service| {
+            middleware::stack::boxed(layer.layer(middleware::stack::LayerService::new(service)))
+        }));
+        self
+    }
+
+    /// Turn errors returned by the service, or by any layer or middleware
+    /// around it, into responses with `handler`.
+    ///
+    /// Errors never tear down the connection, so the other requests on an
+    /// HTTP/2 connection are unaffected. By default they become an empty
+    /// `500 Internal Server Error`, or an `INTERNAL` status for gRPC
+    /// requests.
+    ///
+    /// ```
+    /// use sui_http::middleware::error::ErrorHandler;
+    ///
+    /// let builder = sui_http::Builder::new().error_handler(ErrorHandler::new(|error, _parts| {
+    ///     let mut response = http::Response::new(sui_http::body::full(error.to_string()));
+    ///     *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+    ///     response
+    /// }));
+    /// # let _ = builder;
+    /// ```
+    pub fn error_handler(mut self, handler: middleware::error::ErrorHandler) -> Self {
+        self.error_handler = handler;
+        self
+    }
+
     // Convenience method for configuring TLS with a single server cert
     //
     // Attempts to load PEM formatted files for the certificate chain and private key material from
     // the provided file system paths.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
     pub fn tls_single_cert(
         self,
         cert_file: impl AsRef<std::path::Path>,
@@ -84,11 +276,59 @@ impl Builder {
         Ok(self.tls_config(tls_config))
     }
 
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
     pub fn tls_config(mut self, tls_config: rustls::ServerConfig) -> Self {
         self.tls_config = Some(tls_config);
         self
     }
 
+    /// Map the certificate chain of every TLS client to an identity of the
+    /// application's choosing, inserted into the extensions of each request
+    /// on the connection.
+    ///
+    /// `f` runs once per connection, right after the TLS handshake, so that
+    /// authorization middleware can work with a typed principal instead of
+    /// parsing certificates on every request. If it fails the connection is
+    /// closed with [`CloseReason::ClientRejected`] before any request is
+    /// served. Clients that present no certificate, which the TLS config's
+    /// client verifier may allow, are served without an identity.
+    ///
+    /// ```
+    /// #[derive(Clone)]
+    /// struct Peer(Vec<u8>);
+    ///
+    /// let builder = sui_http::Builder::new().client_identity(|certs| {
+    ///     let leaf = certs.peer_certs().first().ok_or("empty chain")?;
+    ///     Ok(Peer(leaf.to_vec()))
+    /// });
+    /// # let _ = builder;
+    /// ```
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
+    pub fn client_identity<F, T>(mut self, f: F) -> Self
+    where
+        F: Fn(&PeerCertificates) -> Result<T, BoxError> + Send + Sync + 'static,
+        T: Clone + Send + Sync + 'static,
+    {
+        self.client_identity = Some(Arc::new(move |certs| {
+            let mut extensions = http::Extensions::new();
+            extensions.insert(f(certs)?);
+            Ok(extensions)
+        }));
+        self
+    }
+
+    /// Serve TLS with a certificate obtained, and kept renewed, from an
+    /// ACME CA such as Let's Encrypt, in place of any
+    /// [`Builder::tls_config`]; see the [`acme`] module.
+    #[cfg(feature = "acme")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "acme")))]
+    pub fn acme(mut self, config: acme::AcmeConfig) -> Self {
+        self.acme = Some(config);
+        self
+    }
+
     pub fn serve<A, S, ResponseBody>(
         self,
         addr: A,
@@ -106,15 +346,402 @@ impl Builder {
             + 'static,
         ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
     {
-        let listener = listener::TcpListenerWithOptions::new(
-            addr,
+        #[cfg(unix)]
+        if !self.inherited_fds.is_empty() {
+            return Self::serve_inherited(self, service);
+        }
+
+        let listener = listener::TcpListenerWithOptions::new(addr, &self.config)?
+            .with_accept_errors(self.accept_errors());
+
+        Self::serve_tcp(self, listener, service)
+    }
+
+    /// Serve `service` on every address in `addrs`.
+    ///
+    /// All listeners feed the same service and share one [`ServerHandle`]:
+    /// [`ServerHandle::local_addrs`] reports each bound address, in order,
+    /// and shutting the handle down stops them all. Every listener uses the
+    /// same TLS and [`Config`] options; serve separately to mix plaintext
+    /// and TLS ports.
+    pub fn serve_addrs<I, A, S, ResponseBody>(
+        self,
+        addrs: I,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        I: IntoIterator<Item = A>,
+        A: std::net::ToSocketAddrs,
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        #[cfg(unix)]
+        if !self.inherited_fds.is_empty() {
+            return Self::serve_inherited(self, service);
+        }
+
+        let listener = listener::TcpListeners::bind(addrs, &self.config, &self.accept_errors())?;
+
+        Self::serve_tcp(self, listener, service)
+    }
+
+    /// Serve an axum [`Router`](axum::Router) on `addr`.
+    ///
+    /// Behaves like [`Builder::serve`], and additionally provides the peer
+    /// address through axum's own [`ConnectInfo`](axum::extract::ConnectInfo)
+    /// extractor (as `ConnectInfo<SocketAddr>`), so handlers can use it
+    /// without `into_make_service_with_connect_info`.
+    #[cfg(feature = "axum")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "axum")))]
+    pub fn serve_axum<A>(
+        self,
+        addr: A,
+        router: axum::Router,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        A: std::net::ToSocketAddrs,
+    {
+        let service = ServiceBuilder::new()
+            .map_request(|mut request: Request<BoxBody>| {
+                if let Some(connect_info) = request.extensions().get::<ConnectInfo>() {
+                    let remote_addr = connect_info.remote_addr;
+                    request
+                        .extensions_mut()
+                        .insert(axum::extract::ConnectInfo(remote_addr));
+                }
+                request
+            })
+            .service(router);
+
+        Self::serve(self, addr, service)
+    }
+
+    /// Serve a gRPC `service`, such as tonic's `Routes`, on `addr`.
+    ///
+    /// The service is wrapped in [`GrpcTimeout`], enforcing the deadline a
+    /// client sends in the `grpc-timeout` header (capped at
+    /// `server_timeout`, if set) by responding with `DEADLINE_EXCEEDED`.
+    /// Message compression is negotiated by the gRPC framework itself
+    /// through `grpc-encoding`, so no HTTP-level compression is applied.
+    ///
+    /// [`GrpcTimeout`]: middleware::grpc_timeout::GrpcTimeout
+    pub fn serve_grpc<A, S, ResponseBody>(
+        self,
+        addr: A,
+        server_timeout: Option<Duration>,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        A: std::net::ToSocketAddrs,
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let service = middleware::grpc_timeout::GrpcTimeout::new(service, server_timeout);
+
+        Self::serve(self, addr, service)
+    }
+
+    /// Serve `service` on an already bound `std::net::TcpListener`.
+    ///
+    /// This allows binding privileged ports before dropping privileges, or
+    /// reusing a socket created by a process supervisor. The listening
+    /// socket options on [`Config`] (such as [`Config::reuse_port`]) only
+    /// apply to sockets bound by [`Builder::serve`] and are ignored here;
+    /// per-connection options like [`Config::tcp_nodelay`] still apply.
+    pub fn serve_listener<S, ResponseBody>(
+        self,
+        listener: std::net::TcpListener,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        listener.set_nonblocking(true)?;
+        let listener = listener::TcpListenerWithOptions::from_listener(
+            tokio::net::TcpListener::from_std(listener)?,
             self.config.tcp_nodelay,
             self.config.tcp_keepalive,
-        )?;
+        )
+        .with_accept_errors(self.accept_errors());
+
+        Self::serve_tcp(self, listener, service)
+    }
+
+    /// Serve `service` on an inherited file descriptor of a bound and
+    /// listening TCP socket, e.g. one passed by systemd socket activation.
+    ///
+    /// See [`Builder::serve_listener`] for which options apply.
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn serve_fd<S, ResponseBody>(
+        self,
+        fd: std::os::fd::OwnedFd,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        Self::serve_listener(self, std::net::TcpListener::from(fd), service)
+    }
+
+    /// Serve `service` on every socket passed by systemd socket activation;
+    /// see [`systemd::listen_fds`].
+    ///
+    /// Fails if the process was not socket activated, or if any passed
+    /// socket is not a TCP stream socket. To serve only some of them, pick
+    /// them by name and use [`Builder::serve_listen_fds`].
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn serve_systemd<S, ResponseBody>(
+        self,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let fds = systemd::listen_fds()?;
+        if fds.is_empty() {
+            return Err("no sockets were passed by systemd".into());
+        }
+
+        Self::serve_listen_fds(self, fds, service)
+    }
+
+    /// Serve `service` on each of the TCP sockets in `fds`, sharing one
+    /// [`ServerHandle`] as with [`Builder::serve_addrs`].
+    ///
+    /// See [`Builder::serve_listener`] for which options apply.
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn serve_listen_fds<I, S, ResponseBody>(
+        self,
+        fds: I,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        I: IntoIterator<Item = systemd::ListenFd>,
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let listeners = fds
+            .into_iter()
+            .map(|fd| {
+                let listener = fd.into_tcp_listener()?;
+                listener.set_nonblocking(true)?;
+                let listener = listener::TcpListenerWithOptions::from_listener(
+                    tokio::net::TcpListener::from_std(listener)?,
+                    self.config.tcp_nodelay,
+                    self.config.tcp_keepalive,
+                )
+                .with_accept_errors(self.accept_errors());
+                Ok(listener)
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        let listener = listener::TcpListeners::from_listeners(listeners)?;
+
+        Self::serve_tcp(self, listener, service)
+    }
+
+    #[cfg(unix)]
+    fn serve_inherited<S, ResponseBody>(
+        mut self,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let fds = std::mem::take(&mut self.inherited_fds);
+        Self::serve_listen_fds(self, fds.into_iter().map(systemd::ListenFd::from), service)
+    }
+
+    /// Serve `service` on a Unix domain socket bound at `path`.
+    ///
+    /// A socket file left at `path` by a server that crashed is detected
+    /// (nothing accepts connections on it) and removed before binding,
+    /// while a socket with a live server behind it makes this fail with
+    /// `AddrInUse`. See [`Config::unix_socket_lock_file`] for guarding the
+    /// path with a lock file as well. The socket file is removed once the
+    /// server shuts down.
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn serve_unix<P, S, ResponseBody>(
+        self,
+        path: P,
+        service: S,
+    ) -> Result<ServerHandle<std::os::unix::net::SocketAddr>, BoxError>
+    where
+        P: AsRef<std::path::Path>,
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let listener =
+            listener::UnixSocketListener::bind(path.as_ref(), &self.config, self.accept_errors())?;
 
         Self::serve_with_listener(self, listener, service)
     }
 
+    fn serve_tcp<L, S, ResponseBody>(
+        self,
+        listener: L,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        L: Listener<Addr = std::net::SocketAddr>,
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        if self.config.proxy_protocol {
+            let listener = proxy_protocol::ProxyProtocolListener::new(
+                listener,
+                self.config.proxy_protocol_timeout,
+                self.config.max_pending_connections,
+            );
+            return Self::serve_with_listener(self, listener, service);
+        }
+
+        Self::serve_with_listener(self, listener, service)
+    }
+
+    /// Serve `service` over `AF_VSOCK` on the given port, accepting
+    /// connections on any CID of the local machine.
+    ///
+    /// The peer's CID and port are available through
+    /// [`ConnectionInfo::remote_address`] and the [`ConnectInfo`] request
+    /// extension.
+    #[cfg(all(feature = "vsock", target_os = "linux"))]
+    #[cfg_attr(doc_cfg, doc(cfg(all(feature = "vsock", target_os = "linux"))))]
+    pub fn serve_vsock<S, ResponseBody>(
+        self,
+        port: u32,
+        service: S,
+    ) -> Result<ServerHandle<VsockAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        let listener = VsockListener::bind(VsockAddr::new(VsockAddr::CID_ANY, port))?
+            .with_accept_errors(self.accept_errors());
+
+        Self::serve_with_listener(self, listener, service)
+    }
+
+    /// Serve `service` over in-memory connections, returning a
+    /// [`TestClient`](test_util::TestClient) that connects to it.
+    ///
+    /// Everything except the transport behaves as with [`Builder::serve`].
+    /// TLS is not supported.
+    #[cfg(feature = "test-util")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "test-util")))]
+    pub fn serve_in_memory<S, ResponseBody>(
+        self,
+        service: S,
+    ) -> Result<(ServerHandle<test_util::MemoryAddr>, test_util::TestClient), BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        #[cfg(feature = "tls")]
+        if self.tls_config.is_some() {
+            return Err("TLS is not supported by the in-memory transport".into());
+        }
+        #[cfg(feature = "acme")]
+        if self.acme.is_some() {
+            return Err("TLS is not supported by the in-memory transport".into());
+        }
+
+        let (listener, client) = test_util::pair();
+        let handle = Self::serve_with_listener(self, listener, service)?;
+        Ok((handle, client))
+    }
+
+    fn accept_errors(&self) -> listener::AcceptErrors {
+        listener::AcceptErrors::new(
+            self.config.accept_error_policy.clone(),
+            self.graceful_shutdown_token.clone(),
+        )
+    }
+
     fn serve_with_listener<L, S, ResponseBody>(
         self,
         listener: L,
@@ -132,41 +759,93 @@ impl Builder {
             + 'static,
         ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
     {
-        let local_addr = listener.local_addr()?;
-        let graceful_shutdown_token = tokio_util::sync::CancellationToken::new();
+        self.config.validate()?;
+        let local_addrs = listener.local_addrs()?;
+        let local_addr = local_addrs
+            .first()
+            .cloned()
+            .ok_or("listener is not bound to any address")?;
+        let graceful_shutdown_token = self.graceful_shutdown_token;
         let connections = ActiveConnections::default();
-
-        let tls_config = self.tls_config.map(|mut tls| {
-            tls.alpn_protocols.push(ALPN_H2.into());
-            if self.config.accept_http1 {
-                tls.alpn_protocols.push(ALPN_H1.into());
+        #[cfg(unix)]
+        let listener_fds = Arc::new(std::sync::Mutex::new(listener.try_clone_fds().ok()));
+
+        #[cfg(feature = "acme")]
+        let (acme, tls_config) = match self.acme {
+            Some(config) => {
+                let acme = acme::Acme::new(config);
+                let tls_config = acme.server_config()?;
+                (Some(acme), Some(tls_config))
+            }
+            None => (None, self.tls_config),
+        };
+        #[cfg(all(feature = "tls", not(feature = "acme")))]
+        let tls_config = self.tls_config;
+        #[cfg(feature = "tls")]
+        let tls_config = tls_config.map(|mut tls| {
+            self.config.apply_alpn_protocols(&mut tls);
+            #[cfg(feature = "acme")]
+            if acme.is_some() {
+                tls.alpn_protocols.push(acme::ACME_TLS_ALPN.to_vec());
             }
             Arc::new(tls)
         });
 
+        let service: tower::util::BoxCloneService<_, _, _> = ServiceBuilder::new()
+            .layer(tower::util::BoxCloneService::layer())
+            .map_response(|response: Response<ResponseBody>| response.map(body::boxed))
+            .map_err(Into::into)
+            .service(service);
+        let service = self
+            .layers
+            .iter()
+            .rev()
+            .fold(service, |service, layer| layer(service));
+        let service = match &self.middleware {
+            Some(stack) => stack.layer(service),
+            None => service,
+        };
+        let service = middleware::stack::boxed(self.error_handler.layer(service));
+
         let (watch_sender, watch_reciever) = tokio::sync::watch::channel(());
+        let shutdown_report = Arc::new(std::sync::OnceLock::new());
+        let shutdown_control = Arc::new(ShutdownControl::default());
         let server = Server {
             config: self.config,
+            #[cfg(feature = "tls")]
             tls_config,
+            #[cfg(feature = "tls")]
+            client_identity: self.client_identity,
             listener,
             local_addr: local_addr.clone(),
-            service: ServiceBuilder::new()
-                .layer(tower::util::BoxCloneService::layer())
-                .map_response(|response: Response<ResponseBody>| response.map(body::boxed))
-                .map_err(Into::into)
-                .service(service),
+            connection_handler: self.connection_handler,
+            service,
             pending_connections: JoinSet::new(),
             connection_handlers: JoinSet::new(),
             connections: connections.clone(),
             graceful_shutdown_token: graceful_shutdown_token.clone(),
+            #[cfg(unix)]
+            listener_fds: listener_fds.clone(),
+            shutdown_control: shutdown_control.clone(),
+            shutdown_report: shutdown_report.clone(),
+            report: ShutdownReport::default(),
             _watch_reciever: watch_reciever,
         };
 
+        #[cfg(feature = "acme")]
+        if let Some(acme) = acme {
+            tokio::spawn(acme.run(graceful_shutdown_token.clone()));
+        }
+
         let handle = ServerHandle(Arc::new(HandleInner {
-            local_addr,
+            local_addrs,
             connections,
             graceful_shutdown_token,
+            #[cfg(unix)]
+            listener_fds,
             watch_sender,
+            shutdown_control,
+            shutdown_report,
         }));
 
         tokio::spawn(server.serve());
@@ -180,37 +859,161 @@ pub struct ServerHandle<A = std::net::SocketAddr>(Arc<HandleInner<A>>);
 
 #[derive(Debug)]
 struct HandleInner<A = std::net::SocketAddr> {
-    /// The local address of the server.
-    local_addr: A,
+    /// The local addresses of the server, never empty.
+    local_addrs: Vec<A>,
     connections: ActiveConnections<A>,
     graceful_shutdown_token: tokio_util::sync::CancellationToken,
+    #[cfg(unix)]
+    listener_fds: ListenerFds,
     watch_sender: tokio::sync::watch::Sender<()>,
+    shutdown_control: Arc<ShutdownControl>,
+    shutdown_report: Arc<std::sync::OnceLock<ShutdownReport>>,
+}
+
+/// How a [`ServerHandle`] asked the server to shut down, beyond triggering
+/// the graceful shutdown itself.
+#[derive(Debug, Default)]
+struct ShutdownControl {
+    /// Overrides `Config::shutdown_grace_period`, if set before the
+    /// shutdown starts.
+    grace_period: std::sync::OnceLock<Duration>,
+    /// Aborts the connections that are still draining.
+    force: tokio_util::sync::CancellationToken,
+}
+
+/// Duplicates of the server's listening sockets, kept for handover until
+/// the server stops listening; `None` if the listener does not support
+/// handover or has been closed.
+#[cfg(unix)]
+type ListenerFds = Arc<std::sync::Mutex<Option<Vec<std::os::fd::OwnedFd>>>>;
+
+/// Summary of a completed server shutdown.
+///
+/// Connection counts only include connections that were still open when
+/// the shutdown started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ShutdownReport {
+    /// How long the shutdown took, from the shutdown signal until every
+    /// connection was closed.
+    pub duration: Duration,
+    /// Connections that finished their in-flight requests and closed
+    /// gracefully.
+    pub connections_drained: usize,
+    /// Connections that were forcefully closed with requests still in
+    /// flight, because they did not drain within the grace period.
+    pub connections_aborted: usize,
+    /// Connections dropped while still completing their TLS handshake.
+    pub handshakes_aborted: usize,
+    /// Listeners that stopped accepting connections.
+    pub listeners_closed: usize,
+    /// The requests, or HTTP/2 streams, that were still in flight on the
+    /// aborted connections, and were reset along with them.
+    pub requests_aborted: Vec<AbortedRequest>,
+}
+
+/// A request that a shutdown aborted, because its connection did not drain
+/// in time; see [`ShutdownReport::requests_aborted`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AbortedRequest {
+    /// The connection the request arrived on.
+    pub connection_id: ConnectionId,
+    pub method: http::Method,
+    pub uri: http::Uri,
+    /// How long the request had been in flight when it was aborted.
+    pub elapsed: Duration,
+}
+
+impl ShutdownReport {
+    /// Returns `true` if no connection had to be forcefully closed.
+    pub fn is_clean(&self) -> bool {
+        self.connections_aborted == 0
+    }
+
+    fn record(&mut self, close: connection_handler::ConnectionClose, aborted: Vec<AbortedRequest>) {
+        match close {
+            connection_handler::ConnectionClose::Completed
+            | connection_handler::ConnectionClose::Failed => self.connections_drained += 1,
+            connection_handler::ConnectionClose::Forced => self.connections_aborted += 1,
+        }
+        self.requests_aborted.extend(aborted);
+    }
 }
 
 impl<A> ServerHandle<A> {
     /// Returns the local address of the server
+    ///
+    /// The address is resolved when the server is bound, so binding to
+    /// port 0 reports the port the OS picked before any connection is
+    /// accepted. For servers listening on several addresses this is the
+    /// first of [`ServerHandle::local_addrs`].
     pub fn local_addr(&self) -> &A {
-        &self.0.local_addr
+        &self.0.local_addrs[0]
+    }
+
+    /// Returns every local address the server accepts connections on.
+    pub fn local_addrs(&self) -> &[A] {
+        &self.0.local_addrs
     }
 
     /// Trigger a graceful shutdown of the server, but don't wait till the server has completed
     /// shutting down
+    ///
+    /// Connections get [`Config::shutdown_grace_period`] to drain; see
+    /// [`ServerHandle::graceful_shutdown`] to pick another deadline.
     pub fn trigger_shutdown(&self) {
         self.0.graceful_shutdown_token.cancel();
     }
 
-    /// Completes once the network has been shutdown.
+    /// Trigger a graceful shutdown of the server, giving connections
+    /// `deadline` to finish their in-flight requests before they are
+    /// forcefully closed, in place of [`Config::shutdown_grace_period`].
+    ///
+    /// The server stops accepting connections right away. The deadline only
+    /// applies if the shutdown has not been triggered yet; use
+    /// [`ServerHandle::force_shutdown`] to cut a shutdown short instead.
+    /// Use [`ServerHandle::wait_for_completion`] to wait for it to finish.
+    pub fn graceful_shutdown(&self, deadline: Duration) {
+        let _ = self.0.shutdown_control.grace_period.set(deadline);
+        self.trigger_shutdown();
+    }
+
+    /// Shut the server down without waiting for connections to drain,
+    /// closing every connection with requests still in flight.
+    ///
+    /// May be called while a graceful shutdown is in progress, to abort the
+    /// connections that have not drained yet.
+    pub fn force_shutdown(&self) {
+        self.0.shutdown_control.force.cancel();
+        self.trigger_shutdown();
+    }
+
+    /// Completes once the server has shut down, returning a report of how
+    /// many connections drained and how many were aborted.
+    ///
+    /// This does not trigger a shutdown, see
+    /// [`ServerHandle::graceful_shutdown`] or
+    /// [`ServerHandle::force_shutdown`].
+    pub async fn wait_for_completion(&self) -> ShutdownReport {
+        self.0.watch_sender.closed().await;
+        // The report is only missing if the server task panicked.
+        self.0.shutdown_report.get().cloned().unwrap_or_default()
+    }
+
+    /// Completes once the network has been shutdown, returning a report of
+    /// how the shutdown went.
     ///
     /// This explicitly *does not* trigger the network to shutdown, see `trigger_shutdown` or
     /// `shutdown` if you want to trigger shutting down the server.
-    pub async fn wait_for_shutdown(&self) {
-        self.0.watch_sender.closed().await
+    pub async fn wait_for_shutdown(&self) -> ShutdownReport {
+        self.wait_for_completion().await
     }
 
     /// Triggers a shutdown of the server and waits for it to complete shutting down.
-    pub async fn shutdown(&self) {
+    pub async fn shutdown(&self) -> ShutdownReport {
         self.trigger_shutdown();
-        self.wait_for_shutdown().await;
+        self.wait_for_completion().await
     }
 
     /// Checks if the Server has been shutdown.
@@ -228,22 +1031,62 @@ impl<A> ServerHandle<A> {
     pub fn number_of_connections(&self) -> usize {
         self.connections().len()
     }
+
+    /// Duplicates the server's listening sockets, to hand them over to a
+    /// new process with [`handover::spawn`].
+    ///
+    /// Fails once the server has stopped listening, and for listeners that
+    /// do not support handover, such as Unix domain sockets, which are
+    /// removed when the server shuts down.
+    #[cfg(unix)]
+    #[cfg_attr(doc_cfg, doc(cfg(unix)))]
+    pub fn listener_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        let listener_fds = self.0.listener_fds.lock().unwrap();
+        let fds = listener_fds.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "server has no listening sockets to hand over",
+            )
+        })?;
+        fds.iter().map(|fd| fd.try_clone()).collect()
+    }
 }
 
-type ConnectingOutput<Io, Addr> = Result<(ServerIo<Io>, Addr), crate::BoxError>;
+type ConnectingOutput<Io, Addr> =
+    Result<(ServerIo<CountingIo<Io>>, Accepted<Addr>), crate::BoxError>;
+
+/// Where and when a connection was accepted.
+struct Accepted<A> {
+    remote_addr: A,
+    local_addr: A,
+    at: Instant,
+    /// Reports the connection's lifecycle, if a handler is installed.
+    guard: Option<ConnectionGuard>,
+    /// The client's identity, from `Builder::client_identity`.
+    client_identity: Option<http::Extensions>,
+}
 
 struct Server<L: Listener> {
     config: Config,
+    #[cfg(feature = "tls")]
     tls_config: Option<Arc<rustls::ServerConfig>>,
+    #[cfg(feature = "tls")]
+    client_identity: Option<Arc<connection_info::ClientIdentityFn>>,
 
     listener: L,
     local_addr: L::Addr,
+    connection_handler: Option<SharedMakeConnectionHandler>,
     service: tower::util::BoxCloneService<Request<BoxBody>, Response<BoxBody>, crate::BoxError>,
 
     pending_connections: JoinSet<ConnectingOutput<L::Io, L::Addr>>,
-    connection_handlers: JoinSet<()>,
+    connection_handlers: JoinSet<(connection_handler::ConnectionClose, Vec<AbortedRequest>)>,
     connections: ActiveConnections<L::Addr>,
     graceful_shutdown_token: tokio_util::sync::CancellationToken,
+    #[cfg(unix)]
+    listener_fds: ListenerFds,
+    shutdown_control: Arc<ShutdownControl>,
+    shutdown_report: Arc<std::sync::OnceLock<ShutdownReport>>,
+    report: ShutdownReport,
     // Used to signal to a ServerHandle when the server has completed shutting down
     _watch_reciever: tokio::sync::watch::Receiver<()>,
 }
@@ -259,15 +1102,29 @@ where
                     trace!("signal received, shutting down");
                     break;
                 },
-                (io, remote_addr) = self.listener.accept() => {
-                    self.handle_incomming(io, remote_addr);
+                (io, remote_addr) = self.listener.accept(), if self.has_connection_capacity() => {
+                    let local_addr = self
+                        .listener
+                        .accepted_local_addr(&io)
+                        .unwrap_or_else(|| self.local_addr.clone());
+                    let at = Instant::now();
+                    let guard = self.connection_handler.as_ref().map(|make_handler| {
+                        let connection = AcceptedConnection::new(&remote_addr, &local_addr);
+                        ConnectionGuard::new(make_handler.as_ref(), &connection, at)
+                    });
+                    let accepted = Accepted {
+                        remote_addr,
+                        local_addr,
+                        at,
+                        guard,
+                        client_identity: None,
+                    };
+                    self.handle_incomming(io, accepted);
                 },
                 Some(maybe_connection) = self.pending_connections.join_next() => {
                     // If a task panics, just propagate it
-                    let (io, remote_addr) = match maybe_connection.unwrap() {
-                        Ok((io, remote_addr)) => {
-                            (io, remote_addr)
-                        }
+                    let (io, accepted) = match maybe_connection.unwrap() {
+                        Ok((io, accepted)) => (io, accepted),
                         Err(e) => {
                             tracing::debug!(error = %e, "error accepting connection");
                             continue;
@@ -275,11 +1132,16 @@ where
                     };
 
                     trace!("connection accepted");
-                    self.handle_connection(io, remote_addr);
+                    self.handle_connection(io, accepted);
                 },
                 Some(connection_handler_output) = self.connection_handlers.join_next() => {
                     // If a task panics, just propagate it
-                    let _: () = connection_handler_output.unwrap();
+                    let (close, aborted) = connection_handler_output.unwrap();
+                    // Connections may finish draining before the shutdown
+                    // signal itself is observed by this loop.
+                    if self.graceful_shutdown_token.is_cancelled() {
+                        self.report.record(close, aborted);
+                    }
                 },
             }
         }
@@ -290,7 +1152,18 @@ where
         Ok(())
     }
 
-    fn handle_incomming(&mut self, io: L::Io, remote_addr: L::Addr) {
+    /// Whether another connection may be accepted without exceeding
+    /// `Config::max_connections`.
+    fn has_connection_capacity(&self) -> bool {
+        self.config
+            .max_connections
+            .is_none_or(|max| self.pending_connections.len() + self.connection_handlers.len() < max)
+    }
+
+    fn handle_incomming(&mut self, io: L::Io, accepted: Accepted<L::Addr>) {
+        let io = CountingIo::new(io, accepted.guard.as_ref().map(ConnectionGuard::counters));
+
+        #[cfg(feature = "tls")]
         if let Some(tls) = self.tls_config.clone() {
             if self.pending_connections.len() >= self.config.max_pending_connections {
                 tracing::warn!(
@@ -302,45 +1175,138 @@ where
 
             let tls_acceptor = TlsAcceptor::from(tls);
             let timeout_duration = self.config.tls_handshake_timeout;
+            let client_identity = self.client_identity.clone();
             self.pending_connections.spawn(async move {
                 tracing::trace!("accepting TLS connection");
-                let io = tokio::time::timeout(timeout_duration, tls_acceptor.accept(io))
-                    .await
-                    .map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timed out")
-                    })??;
-                Ok((ServerIo::new_tls_io(io), remote_addr))
+                let handshake =
+                    tokio::time::timeout(timeout_duration, tls_acceptor.accept(io)).await;
+                let mut accepted = accepted;
+                match handshake {
+                    Ok(Ok(io)) => {
+                        let io = ServerIo::new_tls_io(io);
+                        if let Some(guard) = &mut accepted.guard {
+                            guard.on_tls_handshake(io.alpn_protocol());
+                        }
+                        // An ACME CA validating a challenge only needs the
+                        // handshake; there are no requests to serve.
+                        #[cfg(feature = "acme")]
+                        if io
+                            .alpn_protocol()
+                            .is_some_and(|alpn| alpn.as_bytes() == acme::ACME_TLS_ALPN)
+                        {
+                            if let Some(guard) = accepted.guard.take() {
+                                guard.close(CloseReason::Completed);
+                            }
+                            return Err("served ACME challenge".into());
+                        }
+                        if let (Some(client_identity), Some(certs)) =
+                            (&client_identity, io.peer_certs())
+                        {
+                            match client_identity(&certs) {
+                                Ok(identity) => accepted.client_identity = Some(identity),
+                                Err(e) => {
+                                    if let Some(guard) = accepted.guard.take() {
+                                        guard.close(CloseReason::ClientRejected);
+                                    }
+                                    return Err(format!("client rejected: {e}").into());
+                                }
+                            }
+                        }
+                        Ok((io, accepted))
+                    }
+                    Ok(Err(e)) => {
+                        if let Some(guard) = accepted.guard.take() {
+                            guard.close(CloseReason::HandshakeFailed);
+                        }
+                        Err(e.into())
+                    }
+                    Err(_) => {
+                        if let Some(guard) = accepted.guard.take() {
+                            guard.close(CloseReason::HandshakeTimedOut);
+                        }
+                        Err("TLS handshake timed out".into())
+                    }
+                }
             });
-        } else {
-            self.handle_connection(ServerIo::new_io(io), remote_addr);
+            return;
         }
+
+        self.handle_connection(ServerIo::new_io(io), accepted);
     }
 
-    fn handle_connection(&mut self, io: ServerIo<L::Io>, remote_addr: L::Addr) {
+    fn handle_connection(&mut self, io: ServerIo<CountingIo<L::Io>>, accepted: Accepted<L::Addr>) {
         let connection_shutdown_token = self.graceful_shutdown_token.child_token();
         let connection_info = ConnectionInfo::new(
-            remote_addr,
+            accepted.remote_addr,
             io.peer_certs(),
             connection_shutdown_token.clone(),
         );
         let connection_id = connection_info.id();
         let connect_info = connection_info::ConnectInfo {
-            local_addr: self.local_addr.clone(),
+            local_addr: accepted.local_addr,
             remote_addr: connection_info.remote_address().clone(),
+            tls: io.is_tls(),
         };
         let peer_certificates = connection_info.peer_certificates().cloned();
+        let client_identity = accepted.client_identity;
+        let alpn_protocol = io.alpn_protocol();
         let hyper_io = hyper_util::rt::TokioIo::new(io);
-
-        let hyper_svc = TowerToHyperService::new(self.service.clone().map_request(
-            move |mut request: Request<hyper::body::Incoming>| {
-                request.extensions_mut().insert(connect_info.clone());
-                if let Some(peer_certificates) = peer_certificates.clone() {
-                    request.extensions_mut().insert(peer_certificates);
-                }
-
-                request.map(body::boxed)
-            },
-        ));
+        let drain = drain::ConnectionDrain::default();
+        let drain_signal = drain.signal();
+        let timing = timing::ConnectionTiming::new(accepted.at);
+        let reconnect = self
+            .config
+            .drain_reconnect_after
+            .map(|_| (drain.clone(), self.config.drain_reconnect_trailers.clone()));
+
+        let service = match self.config.max_connection_request_rate {
+            Some(rate) => tower::util::Either::Left(pacing::Paced::new(self.service.clone(), rate)),
+            None => tower::util::Either::Right(self.service.clone()),
+        };
+        let in_flight = connection_info.in_flight().clone();
+        let service = connection_handler::TrackInFlight::new(service, in_flight.clone());
+
+        let request_limit = self.config.max_requests_per_connection.map(|max| {
+            Arc::new(connection_handler::RequestLimit::new(
+                max,
+                connection_shutdown_token.clone(),
+            ))
+        });
+        let response_limit = request_limit.clone();
+
+        let hyper_svc = TowerToHyperService::new(
+            service
+                .map_request(move |mut request: Request<hyper::body::Incoming>| {
+                    if let Some(request_limit) = &request_limit {
+                        request_limit.on_request(&request);
+                    }
+
+                    request.extensions_mut().insert(connect_info.clone());
+                    request.extensions_mut().insert(drain_signal.clone());
+                    request.extensions_mut().insert(timing.request_received());
+                    if let Some(peer_certificates) = peer_certificates.clone() {
+                        request.extensions_mut().insert(peer_certificates);
+                    }
+                    if let Some(client_identity) = client_identity.clone() {
+                        request.extensions_mut().extend(client_identity);
+                    }
+                    if let Some(alpn_protocol) = alpn_protocol.clone() {
+                        request.extensions_mut().insert(alpn_protocol);
+                    }
+
+                    request.map(body::boxed)
+                })
+                .map_response(move |mut response: Response<BoxBody>| {
+                    if let Some(request_limit) = &response_limit {
+                        request_limit.on_response(&mut response);
+                    }
+                    match &reconnect {
+                        Some((drain, trailers)) => response
+                            .map(|body| body::boxed(drain.reconnect_body(body, trailers.clone()))),
+                        None => response,
+                    }
+                }),
+        );
 
         self.connections
             .write()
@@ -349,27 +1315,47 @@ where
         let on_connection_close =
             connection_handler::OnConnectionClose::new(connection_id, self.connections.clone());
 
-        self.connection_handlers
-            .spawn(connection_handler::serve_connection(
-                hyper_io,
-                hyper_svc,
-                self.config.connection_builder(),
-                connection_shutdown_token,
-                self.config.max_connection_age,
-                self.config.max_connection_age_grace,
-                on_connection_close,
-            ));
+        let serve_connection = connection_handler::serve_connection(
+            hyper_io,
+            hyper_svc,
+            self.config.connection_builder(),
+            connection_shutdown_token,
+            self.graceful_shutdown_token.clone(),
+            self.config.connection_deadlines(),
+            drain,
+            in_flight,
+            connection_id,
+            on_connection_close,
+            accepted.guard,
+        );
+        self.connection_handlers.spawn(serve_connection);
     }
 
     async fn shutdown(mut self) {
         // The time we are willing to wait for a connection to get gracefully shutdown before we
         // attempt to forcefully shutdown all active connections
-        const CONNECTION_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+        let grace_period = self
+            .shutdown_control
+            .grace_period
+            .get()
+            .copied()
+            .unwrap_or(self.config.shutdown_grace_period);
+
+        let start = std::time::Instant::now();
+        let mut report = std::mem::take(&mut self.report);
+        report.listeners_closed = self.listener.local_addrs().map_or(1, |addrs| addrs.len());
 
         // Just to be careful make sure the token is canceled
         self.graceful_shutdown_token.cancel();
 
+        // Stop listening right away; this also cleans up listeners such as
+        // Unix sockets before the shutdown is reported as complete.
+        drop(self.listener);
+        #[cfg(unix)]
+        self.listener_fds.lock().unwrap().take();
+
         // Terminate any in-progress pending connections
+        report.handshakes_aborted = self.pending_connections.len();
         self.pending_connections.shutdown().await;
 
         // Wait for all connection handlers to terminate
@@ -378,19 +1364,43 @@ where
             self.connection_handlers.len()
         );
 
-        let graceful_shutdown =
-            async { while self.connection_handlers.join_next().await.is_some() {} };
+        let graceful_shutdown = async {
+            while let Some(output) = self.connection_handlers.join_next().await {
+                // If a task panics, just propagate it
+                let (close, aborted) = output.unwrap();
+                report.record(close, aborted);
+            }
+        };
 
-        if tokio::time::timeout(CONNECTION_SHUTDOWN_GRACE_PERIOD, graceful_shutdown)
-            .await
-            .is_err()
-        {
-            tracing::warn!(
-                "Failed to stop all connection handlers in {:?}. Forcing shutdown.",
-                CONNECTION_SHUTDOWN_GRACE_PERIOD
-            );
+        let drained = tokio::select! {
+            biased;
+            drained = tokio::time::timeout(grace_period, graceful_shutdown) => {
+                if drained.is_err() {
+                    tracing::warn!(
+                        "Failed to stop all connection handlers in {:?}. Forcing shutdown.",
+                        grace_period
+                    );
+                }
+                drained.is_ok()
+            }
+            _ = self.shutdown_control.force.cancelled() => {
+                tracing::debug!("forced shutdown requested, aborting connection handlers");
+                false
+            }
+        };
+        if !drained {
+            report.connections_aborted += self.connection_handlers.len();
+            for connection in self.connections.read().unwrap().values() {
+                report
+                    .requests_aborted
+                    .extend(connection.in_flight().abort_all(connection.id()));
+            }
             self.connection_handlers.shutdown().await;
         }
+
+        report.duration = start.elapsed();
+        tracing::debug!(?report, "server shut down");
+        let _ = self.shutdown_report.set(report);
     }
 }
 
@@ -440,4 +1450,18 @@ mod tests {
         // Now that the network has been shutdown there should be zero connections
         assert_eq!(handle.connections().len(), 0);
     }
+
+    #[tokio::test]
+    async fn reports_bound_addresses_before_accepting() {
+        let app = Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        let handle = Builder::new().serve(("127.0.0.1", 0), app).unwrap();
+
+        let addr = *handle.local_addr();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(handle.local_addrs(), [addr]);
+        assert_eq!(handle.number_of_connections(), 0);
+
+        // The reported port is the one actually accepting connections.
+        tokio::net::TcpStream::connect(addr).await.unwrap();
+    }
 }
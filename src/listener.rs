@@ -1,6 +1,7 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Types that can listen for connections.
@@ -20,6 +21,36 @@ pub trait Listener: Send + 'static {
 
     /// Returns the local address that this listener is bound to.
     fn local_addr(&self) -> std::io::Result<Self::Addr>;
+
+    /// Returns every local address that this listener accepts connections
+    /// on, starting with [`Listener::local_addr`].
+    ///
+    /// Listeners bound to several addresses must override this; the
+    /// default is just [`Listener::local_addr`].
+    fn local_addrs(&self) -> std::io::Result<Vec<Self::Addr>> {
+        Ok(vec![self.local_addr()?])
+    }
+
+    /// Returns the local address `io` was accepted on.
+    ///
+    /// Listeners bound to several addresses should override this; `None`,
+    /// the default, means the connection arrived on
+    /// [`Listener::local_addr`].
+    fn accepted_local_addr(&self, _io: &Self::Io) -> Option<Self::Addr> {
+        None
+    }
+
+    /// Duplicates the sockets this listener accepts connections on, to
+    /// hand them over to another process; see [`crate::handover`].
+    ///
+    /// The default reports that handover is unsupported.
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "listener does not support handover",
+        ))
+    }
 }
 
 /// Extensions to [`Listener`].
@@ -61,7 +92,8 @@ impl Listener for tokio::net::TcpListener {
     type Addr = std::net::SocketAddr;
 
     async fn accept(&mut self) -> (Self::Io, Self::Addr) {
-        let mut backoff = AcceptBackoff::new();
+        let errors = AcceptErrors::default();
+        let mut backoff = errors.backoff();
         loop {
             match Self::accept(self).await {
                 Ok(tup) => return tup,
@@ -74,6 +106,13 @@ impl Listener for tokio::net::TcpListener {
     fn local_addr(&self) -> std::io::Result<Self::Addr> {
         Self::local_addr(self)
     }
+
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        use std::os::fd::AsFd;
+
+        Ok(vec![self.as_fd().try_clone_to_owned()?])
+    }
 }
 
 #[derive(Debug)]
@@ -81,19 +120,39 @@ pub struct TcpListenerWithOptions {
     inner: tokio::net::TcpListener,
     nodelay: bool,
     keepalive: Option<Duration>,
+    accept_errors: AcceptErrors,
 }
 
 impl TcpListenerWithOptions {
+    /// Binds a listener to the first of `addr`'s addresses that succeeds,
+    /// applying the socket options from `config`.
     pub fn new<A: std::net::ToSocketAddrs>(
         addr: A,
-        nodelay: bool,
-        keepalive: Option<Duration>,
+        config: &crate::Config,
     ) -> Result<Self, crate::BoxError> {
-        let std_listener = std::net::TcpListener::bind(addr)?;
-        std_listener.set_nonblocking(true)?;
-        let listener = tokio::net::TcpListener::from_std(std_listener)?;
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match bind_socket(addr, config) {
+                Ok(std_listener) => {
+                    let listener = tokio::net::TcpListener::from_std(std_listener)?;
+                    return Ok(Self::from_listener(
+                        listener,
+                        config.tcp_nodelay,
+                        config.tcp_keepalive,
+                    ));
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
 
-        Ok(Self::from_listener(listener, nodelay, keepalive))
+        Err(last_err
+            .unwrap_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "could not resolve to any addresses",
+                )
+            })
+            .into())
     }
 
     /// Creates a new `TcpIncoming` from an existing `tokio::net::TcpListener`.
@@ -106,6 +165,15 @@ impl TcpListenerWithOptions {
             inner: listener,
             nodelay,
             keepalive,
+            accept_errors: AcceptErrors::default(),
+        }
+    }
+
+    /// Handles accept errors according to `accept_errors`.
+    pub(crate) fn with_accept_errors(self, accept_errors: AcceptErrors) -> Self {
+        Self {
+            accept_errors,
+            ..self
         }
     }
 
@@ -128,14 +196,256 @@ impl TcpListenerWithOptions {
     }
 }
 
+// Matches the backlog used by `std::net::TcpListener::bind`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const DEFAULT_LISTEN_BACKLOG: i32 = -1;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const DEFAULT_LISTEN_BACKLOG: i32 = 128;
+
+fn bind_socket(
+    addr: std::net::SocketAddr,
+    config: &crate::Config,
+) -> std::io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+
+    if config.reuse_address {
+        socket.set_reuse_address(true)?;
+    }
+    if config.reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not supported on this platform",
+        ));
+    }
+    if let Some(size) = config.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = config.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    let backlog = config
+        .listen_backlog
+        .map(|backlog| backlog.min(i32::MAX as u32) as i32)
+        .unwrap_or(DEFAULT_LISTEN_BACKLOG);
+    socket.listen(backlog)?;
+
+    Ok(socket.into())
+}
+
 impl Listener for TcpListenerWithOptions {
     type Io = tokio::net::TcpStream;
     type Addr = std::net::SocketAddr;
 
     async fn accept(&mut self) -> (Self::Io, Self::Addr) {
-        let (io, addr) = Listener::accept(&mut self.inner).await;
-        self.set_accepted_socket_options(&io);
-        (io, addr)
+        let mut backoff = self.accept_errors.backoff();
+        loop {
+            match self.inner.accept().await {
+                Ok((io, addr)) => {
+                    self.set_accepted_socket_options(&io);
+                    return (io, addr);
+                }
+                Err(e) => backoff.handle_accept_error(e).await,
+            }
+        }
+    }
+
+    #[inline]
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Listener::local_addr(&self.inner)
+    }
+
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        self.inner.try_clone_fds()
+    }
+}
+
+/// TCP listeners bound to several addresses, accepting connections from
+/// all of them.
+#[derive(Debug)]
+pub(crate) struct TcpListeners {
+    listeners: Vec<TcpListenerWithOptions>,
+    /// The listener polled first on the next accept, rotated so a busy
+    /// listener cannot starve the others.
+    next: usize,
+}
+
+impl TcpListeners {
+    /// Binds a listener to each of `addrs`, applying the socket options
+    /// from `config`.
+    pub fn bind<A, I>(
+        addrs: I,
+        config: &crate::Config,
+        accept_errors: &AcceptErrors,
+    ) -> Result<Self, crate::BoxError>
+    where
+        A: std::net::ToSocketAddrs,
+        I: IntoIterator<Item = A>,
+    {
+        let listeners = addrs
+            .into_iter()
+            .map(|addr| {
+                TcpListenerWithOptions::new(addr, config)
+                    .map(|listener| listener.with_accept_errors(accept_errors.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_listeners(listeners)
+    }
+
+    /// Accepts connections from each of the already bound `listeners`.
+    pub fn from_listeners(listeners: Vec<TcpListenerWithOptions>) -> Result<Self, crate::BoxError> {
+        if listeners.is_empty() {
+            return Err("no addresses to listen on".into());
+        }
+
+        Ok(Self { listeners, next: 0 })
+    }
+}
+
+impl Listener for TcpListeners {
+    type Io = tokio::net::TcpStream;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let start = self.next;
+        self.next = (start + 1) % self.listeners.len();
+
+        let (head, tail) = self.listeners.split_at_mut(start);
+        let mut accepts = tail
+            .iter_mut()
+            .chain(head)
+            .map(|listener| Box::pin(listener.accept()))
+            .collect::<Vec<_>>();
+        std::future::poll_fn(|cx| {
+            accepts
+                .iter_mut()
+                .find_map(|accept| match accept.as_mut().poll(cx) {
+                    std::task::Poll::Ready(accepted) => Some(accepted),
+                    std::task::Poll::Pending => None,
+                })
+                .map_or(std::task::Poll::Pending, std::task::Poll::Ready)
+        })
+        .await
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.listeners[0].local_addr()
+    }
+
+    fn local_addrs(&self) -> std::io::Result<Vec<Self::Addr>> {
+        self.listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect()
+    }
+
+    fn accepted_local_addr(&self, io: &Self::Io) -> Option<Self::Addr> {
+        io.local_addr().ok()
+    }
+
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        let mut fds = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            fds.extend(listener.try_clone_fds()?);
+        }
+        Ok(fds)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+    type Addr = std::os::unix::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let errors = AcceptErrors::default();
+        let mut backoff = errors.backoff();
+        loop {
+            match Self::accept(self).await {
+                Ok((io, addr)) => return (io, addr.into()),
+                Err(e) => backoff.handle_accept_error(e).await,
+            }
+        }
+    }
+
+    #[inline]
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Self::local_addr(self).map(Into::into)
+    }
+}
+
+/// A Unix domain socket bound by `Builder::serve_unix`.
+///
+/// The socket file is removed when the listener is dropped, and the lock
+/// file, if any, stays locked until then.
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct UnixSocketListener {
+    inner: tokio::net::UnixListener,
+    path: std::path::PathBuf,
+    _lock: Option<std::fs::File>,
+    accept_errors: AcceptErrors,
+}
+
+#[cfg(unix)]
+impl UnixSocketListener {
+    /// Binds `path`, first removing a stale socket file left behind by a
+    /// server that crashed.
+    pub(crate) fn bind(
+        path: &std::path::Path,
+        config: &crate::Config,
+        accept_errors: AcceptErrors,
+    ) -> std::io::Result<Self> {
+        let lock = config
+            .unix_socket_lock_file
+            .then(|| lock_unix_socket(path))
+            .transpose()?;
+        remove_stale_unix_socket(path)?;
+
+        Ok(Self {
+            inner: tokio::net::UnixListener::bind(path)?,
+            path: path.to_owned(),
+            _lock: lock,
+            accept_errors,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::debug!(path = %self.path.display(), "error removing unix socket: {e}");
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixSocketListener {
+    type Io = tokio::net::UnixStream;
+    type Addr = std::os::unix::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let mut backoff = self.accept_errors.backoff();
+        loop {
+            match self.inner.accept().await {
+                Ok((io, addr)) => return (io, addr.into()),
+                Err(e) => backoff.handle_accept_error(e).await,
+            }
+        }
     }
 
     #[inline]
@@ -144,26 +454,70 @@ impl Listener for TcpListenerWithOptions {
     }
 }
 
-// Uncomment once we update tokio to >=1.41.0
-// #[cfg(unix)]
-// impl Listener for tokio::net::UnixListener {
-//     type Io = tokio::net::UnixStream;
-//     type Addr = std::os::unix::net::SocketAddr;
-
-//     async fn accept(&mut self) -> (Self::Io, Self::Addr) {
-//         loop {
-//             match Self::accept(self).await {
-//                 Ok((io, addr)) => return (io, addr.into()),
-//                 Err(e) => handle_accept_error(e).await,
-//             }
-//         }
-//     }
-
-//     #[inline]
-//     fn local_addr(&self) -> std::io::Result<Self::Addr> {
-//         Self::local_addr(self).map(Into::into)
-//     }
-// }
+/// Takes an exclusive lock on `<path>.lock` and records our pid in it.
+#[cfg(unix)]
+fn lock_unix_socket(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    use std::io::Write;
+
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(std::fs::TryLockError::WouldBlock) => {
+            let pid = std::fs::read_to_string(&lock_path).unwrap_or_default();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!(
+                    "{} is locked by a running server (pid {})",
+                    path.display(),
+                    pid.trim()
+                ),
+            ));
+        }
+        Err(std::fs::TryLockError::Error(e)) => return Err(e),
+    }
+
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(file)
+}
+
+/// Removes the socket file at `path` if no server is accepting connections
+/// on it.
+#[cfg(unix)]
+fn remove_stale_unix_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        // Binding reports `AddrInUse` for anything else at the path; never
+        // delete a file that is not a socket.
+        _ => return Ok(()),
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("{} is in use by a running server", path.display()),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            tracing::info!(path = %path.display(), "removing stale unix socket");
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        }
+        // Let the bind itself report any other problem.
+        Err(_) => Ok(()),
+    }
+}
 
 /// Return type of [`ListenerExt::tap_io`].
 ///
@@ -201,42 +555,168 @@ where
     fn local_addr(&self) -> std::io::Result<Self::Addr> {
         self.listener.local_addr()
     }
+
+    fn local_addrs(&self) -> std::io::Result<Vec<Self::Addr>> {
+        self.listener.local_addrs()
+    }
+
+    fn accepted_local_addr(&self, io: &Self::Io) -> Option<Self::Addr> {
+        self.listener.accepted_local_addr(io)
+    }
+
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> std::io::Result<Vec<std::os::fd::OwnedFd>> {
+        self.listener.try_clone_fds()
+    }
 }
 
-/// Exponential backoff for recoverable `accept()` errors.
+/// How a server reacts to errors accepting connections.
 ///
-/// Certain errors (notably `EMFILE`/`ENFILE`, when the process has exhausted its
-/// file descriptor limit) leave the listener in a persistently-readable state,
-/// causing `accept()` to return immediately on retry. Without backoff the serve
-/// loop would spin a CPU core and flood logs.
+/// Errors tied to a single connection (such as `ECONNABORTED` or
+/// `ECONNRESET`) are retried immediately. Other errors (notably
+/// `EMFILE`/`ENFILE`, when the process has exhausted its file descriptor
+/// limit) leave the listener in a persistently-readable state, causing
+/// `accept()` to fail again immediately on retry; without backoff the
+/// accept loop would spin a CPU core and flood logs.
 ///
-/// A fixed 1 second sleep (as in hyper 0.14 and still in axum today) avoids the
-/// spin but delays recovery once descriptors free up. Instead we follow Go's
-/// `net/http` and HashiCorp Vault: start at 5ms and double on each consecutive
-/// error, capped at 1 second. Reset-on-success is implicit because a fresh
-/// `AcceptBackoff` is constructed per call to `accept()`.
-struct AcceptBackoff {
-    next_delay: Duration,
+/// A fixed 1 second sleep (as in hyper 0.14 and still in axum today) avoids
+/// the spin but delays recovery once descriptors free up. Instead the
+/// default follows Go's `net/http` and HashiCorp Vault: start at 5ms and
+/// double on each consecutive error, capped at 1 second, resetting once a
+/// connection is accepted. Alternatively, [`AcceptErrorPolicy::shutdown`]
+/// gracefully shuts the server down on the first such error, for
+/// deployments that would rather restart. Either way every error,
+/// including per-connection ones, is reported to the
+/// [`AcceptErrorPolicy::on_error`] callback.
+///
+/// Set it with `Config::accept_error_policy`.
+#[derive(Clone)]
+pub struct AcceptErrorPolicy {
+    min_backoff: Duration,
+    max_backoff: Duration,
+    shutdown: bool,
+    on_error: Option<OnAcceptError>,
 }
 
-impl AcceptBackoff {
-    const MIN: Duration = Duration::from_millis(5);
-    const MAX: Duration = Duration::from_secs(1);
+type OnAcceptError = Arc<dyn Fn(&std::io::Error) + Send + Sync>;
+
+impl Default for AcceptErrorPolicy {
+    fn default() -> Self {
+        Self::backoff(Duration::from_millis(5), Duration::from_secs(1))
+    }
+}
 
-    fn new() -> Self {
+impl std::fmt::Debug for AcceptErrorPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptErrorPolicy")
+            .field("min_backoff", &self.min_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("shutdown", &self.shutdown)
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+impl AcceptErrorPolicy {
+    /// Sleep after each error, starting at `min` and doubling on each
+    /// consecutive error up to `max`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is zero or greater than `max`.
+    pub fn backoff(min: Duration, max: Duration) -> Self {
+        assert!(
+            !min.is_zero() && min <= max,
+            "invalid accept backoff: {min:?}..{max:?}"
+        );
+        Self {
+            min_backoff: min,
+            max_backoff: max,
+            shutdown: false,
+            on_error: None,
+        }
+    }
+
+    /// Gracefully shut the server down on the first error that is not tied
+    /// to a single connection.
+    pub fn shutdown() -> Self {
+        Self {
+            shutdown: true,
+            ..Self::default()
+        }
+    }
+
+    /// Call `on_error` with every accept error, e.g. to count them.
+    pub fn on_error<F>(self, on_error: F) -> Self
+    where
+        F: Fn(&std::io::Error) + Send + Sync + 'static,
+    {
         Self {
-            next_delay: Self::MIN,
+            on_error: Some(Arc::new(on_error)),
+            ..self
         }
     }
+}
+
+/// An [`AcceptErrorPolicy`] bound to the server it applies to.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AcceptErrors {
+    policy: AcceptErrorPolicy,
+    /// The server's shutdown token, cancelled by a fatal policy.
+    shutdown_token: Option<tokio_util::sync::CancellationToken>,
+}
+
+impl AcceptErrors {
+    pub(crate) fn new(
+        policy: AcceptErrorPolicy,
+        shutdown_token: tokio_util::sync::CancellationToken,
+    ) -> Self {
+        Self {
+            policy,
+            shutdown_token: Some(shutdown_token),
+        }
+    }
+
+    /// Starts handling the errors of one `accept()` call; reset-on-success
+    /// is implicit because a fresh [`AcceptBackoff`] is used per call.
+    pub(crate) fn backoff(&self) -> AcceptBackoff<'_> {
+        AcceptBackoff {
+            errors: self,
+            next_delay: self.policy.min_backoff,
+        }
+    }
+}
+
+/// Applies an [`AcceptErrorPolicy`] to the consecutive errors of a single
+/// `accept()` call.
+pub(crate) struct AcceptBackoff<'a> {
+    errors: &'a AcceptErrors,
+    next_delay: Duration,
+}
+
+impl AcceptBackoff<'_> {
+    pub(crate) async fn handle_accept_error(&mut self, e: std::io::Error) {
+        let policy = &self.errors.policy;
+        if let Some(on_error) = &policy.on_error {
+            on_error(&e);
+        }
 
-    async fn handle_accept_error(&mut self, e: std::io::Error) {
         if is_connection_error(&e) {
             return;
         }
 
+        if policy.shutdown
+            && let Some(shutdown_token) = &self.errors.shutdown_token
+        {
+            tracing::error!("accept error, shutting down: {e}");
+            shutdown_token.cancel();
+            // The server stops accepting once it observes the shutdown.
+            std::future::pending::<()>().await;
+        }
+
         tracing::error!(backoff = ?self.next_delay, "accept error: {e}");
         tokio::time::sleep(self.next_delay).await;
-        self.next_delay = (self.next_delay * 2).min(Self::MAX);
+        self.next_delay = (self.next_delay * 2).min(policy.max_backoff);
     }
 }
 
@@ -254,3 +734,72 @@ fn is_connection_error(e: &std::io::Error) -> bool {
             | ErrorKind::TimedOut
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    /// An error that is not tied to a single connection, like `EMFILE`.
+    fn fd_exhaustion() -> std::io::Error {
+        std::io::Error::other("too many open files")
+    }
+
+    #[tokio::test]
+    async fn backs_off_exponentially_up_to_the_cap() {
+        let errors = AcceptErrors::new(
+            AcceptErrorPolicy::backoff(Duration::from_millis(1), Duration::from_millis(4)),
+            tokio_util::sync::CancellationToken::new(),
+        );
+        let mut backoff = errors.backoff();
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            delays.push(backoff.next_delay.as_millis());
+            backoff.handle_accept_error(fd_exhaustion()).await;
+        }
+        assert_eq!(delays, [1, 2, 4, 4]);
+    }
+
+    #[tokio::test]
+    async fn reports_every_error() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let policy = AcceptErrorPolicy::default().on_error({
+            let seen = seen.clone();
+            move |_| {
+                seen.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let errors = AcceptErrors::new(policy, tokio_util::sync::CancellationToken::new());
+        let mut backoff = errors.backoff();
+
+        backoff
+            .handle_accept_error(std::io::ErrorKind::ConnectionAborted.into())
+            .await;
+        // Per-connection errors are retried without backing off.
+        assert_eq!(backoff.next_delay, Duration::from_millis(5));
+        backoff.handle_accept_error(fd_exhaustion()).await;
+        assert_eq!(seen.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_policy_shuts_the_server_down() {
+        let shutdown_token = tokio_util::sync::CancellationToken::new();
+        let errors = AcceptErrors::new(AcceptErrorPolicy::shutdown(), shutdown_token.clone());
+        let mut backoff = errors.backoff();
+
+        backoff
+            .handle_accept_error(std::io::ErrorKind::ConnectionReset.into())
+            .await;
+        assert!(!shutdown_token.is_cancelled());
+
+        // The failing accept parks once it has triggered the shutdown.
+        let parked = tokio::time::timeout(
+            Duration::from_millis(50),
+            backoff.handle_accept_error(fd_exhaustion()),
+        )
+        .await;
+        assert!(parked.is_err());
+        assert!(shutdown_token.is_cancelled());
+    }
+}
@@ -0,0 +1,837 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Machine-parseable access logs, written to a sink of your choosing.
+//!
+//! Where [`LoggingLayer`](super::logging::LoggingLayer) emits `tracing`
+//! events alongside the application's own, [`AccessLogLayer`] writes one
+//! line per completed request to a dedicated [`AccessLogSink`]: any
+//! [`io::Write`], or a closure that can forward lines to a file rotator, a
+//! channel drained by an async writer, and the like.
+//!
+//! A request's line is written once it is over: when the response body has
+//! finished streaming, when the service or the body fails, or when the
+//! client abandons the request. Lines come in one of two [`Format`]s:
+//!
+//! * [`Format::Common`], the Common Log Format understood by most log
+//!   tooling:
+//!
+//!   ```text
+//!   127.0.0.1 - - [16/Oct/2026:04:20:03 +0000] "GET /path?q=1 HTTP/1.1" 200 2
+//!   ```
+//!
+//! * [`Format::Json`], one JSON object per line holding the configured
+//!   [`Field`]s, which include the latency and gRPC status Common Log
+//!   Format has no room for:
+//!
+//!   ```text
+//!   {"time":"2026-10-16T04:20:03.123Z","method":"GET","uri":"/path?q=1","status":200,...}
+//!   ```
+//!
+//! Sinks are called on the task serving the request, so writing should be
+//! cheap: wrap files in an [`io::BufWriter`], or hand lines to another
+//! task.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::access_log::AccessLogLayer;
+//! use sui_http::middleware::access_log::Field;
+//! use sui_http::middleware::access_log::Format;
+//!
+//! let layer = AccessLogLayer::new(std::io::stdout())
+//!     .format(Format::Json)
+//!     .fields([Field::Time, Field::Method, Field::Uri, Field::Status, Field::Latency]);
+//! # let _ = layer;
+//! ```
+
+use bytes::Buf;
+use http::HeaderMap;
+use http::HeaderName;
+use http::Request;
+use http::Response;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::fmt;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Instant;
+use std::time::SystemTime;
+use tower::Layer;
+use tower::Service;
+
+use super::callback::Classification;
+use crate::ConnectInfo;
+
+/// Destination of access log lines.
+///
+/// Implemented for closures taking each line, and by [`WriterSink`] for
+/// any [`io::Write`].
+pub trait AccessLogSink: Send + Sync + 'static {
+    /// Writes one line, without its trailing newline.
+    fn write_line(&self, line: &str);
+}
+
+impl<F> AccessLogSink for F
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    fn write_line(&self, line: &str) {
+        self(line)
+    }
+}
+
+/// An [`AccessLogSink`] writing newline-terminated lines to an
+/// [`io::Write`]. Write errors are dropped.
+#[derive(Debug)]
+pub struct WriterSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W> AccessLogSink for WriterSink<W>
+where
+    W: io::Write + Send + 'static,
+{
+    fn write_line(&self, line: &str) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(error) = writer
+            .write_all(line.as_bytes())
+            .and_then(|()| writer.write_all(b"\n"))
+        {
+            tracing::debug!("failed to write access log: {error}");
+        }
+    }
+}
+
+/// The format of access log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Common Log Format: `remote - - [time] "request line" status bytes`.
+    /// Ignores [`AccessLogLayer::fields`].
+    Common,
+    /// A JSON object holding the configured [`Field`]s.
+    Json,
+}
+
+/// A field of [`Format::Json`] lines, named after its JSON key. Fields
+/// that are unknown for a request, such as the status of a request
+/// abandoned before its response, are `null`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Field {
+    /// `remote_addr`: the client's address.
+    RemoteAddr,
+    /// `time`: when the request was received, in RFC 3339 format (UTC).
+    Time,
+    /// `method`: the request method.
+    Method,
+    /// `uri`: the request path and query.
+    Uri,
+    /// `protocol`: the HTTP version, e.g. `HTTP/2.0`.
+    Protocol,
+    /// `status`: the response status code.
+    Status,
+    /// `grpc_status`: the gRPC status code of gRPC responses.
+    GrpcStatus,
+    /// `bytes_sent`: the size of the response body sent.
+    BytesSent,
+    /// `latency_ms`: time from receiving the request until it was over,
+    /// in fractional milliseconds.
+    Latency,
+    /// `completed`: whether the response was sent in full, as opposed to
+    /// failing or being abandoned by the client.
+    Completed,
+    /// `user_agent`: the `user-agent` request header.
+    UserAgent,
+    /// `referer`: the `referer` request header.
+    Referer,
+    /// A request header, keyed by its name. Avoid headers carrying
+    /// credentials.
+    RequestHeader(HeaderName),
+}
+
+impl Field {
+    /// The fields of [`Format::Json`] lines unless configured otherwise.
+    pub const DEFAULT: &[Field] = &[
+        Field::RemoteAddr,
+        Field::Time,
+        Field::Method,
+        Field::Uri,
+        Field::Protocol,
+        Field::Status,
+        Field::GrpcStatus,
+        Field::BytesSent,
+        Field::Latency,
+        Field::Completed,
+        Field::UserAgent,
+        Field::Referer,
+    ];
+}
+
+/// [`Layer`] that writes an access log line for every request; see the
+/// [module docs](self).
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    sink: Arc<dyn AccessLogSink>,
+    format: Format,
+    fields: Arc<[Field]>,
+}
+
+impl fmt::Debug for AccessLogLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLogLayer")
+            .field("format", &self.format)
+            .field("fields", &self.fields)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AccessLogLayer {
+    /// Write Common Log Format lines to `writer`.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: io::Write + Send + 'static,
+    {
+        Self::with_sink(WriterSink::new(writer))
+    }
+
+    /// Write Common Log Format lines to `sink`.
+    pub fn with_sink<S: AccessLogSink>(sink: S) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            format: Format::Common,
+            fields: Field::DEFAULT.into(),
+        }
+    }
+
+    /// The format of the lines. Defaults to [`Format::Common`].
+    pub fn format(self, format: Format) -> Self {
+        Self { format, ..self }
+    }
+
+    /// The fields of [`Format::Json`] lines, in order. Defaults to
+    /// [`Field::DEFAULT`].
+    pub fn fields<I>(self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = Field>,
+    {
+        Self {
+            fields: fields.into_iter().collect(),
+            ..self
+        }
+    }
+
+    fn entry<B>(&self, request: &Request<B>) -> Entry {
+        let headers = request.headers();
+        let header = |name: &HeaderName| {
+            headers
+                .get(name)
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        };
+        let request_headers = self
+            .fields
+            .iter()
+            .filter_map(|field| match field {
+                Field::RequestHeader(name) => Some((name.clone(), header(name))),
+                _ => None,
+            })
+            .collect();
+
+        Entry {
+            layer: self.clone(),
+            time: SystemTime::now(),
+            start: Instant::now(),
+            remote_addr: request
+                .extensions()
+                .get::<ConnectInfo>()
+                .map(|connect_info| *connect_info.remote_addr()),
+            method: request.method().to_string(),
+            uri: request
+                .uri()
+                .path_and_query()
+                .map_or_else(|| request.uri().path().to_owned(), |pq| pq.to_string()),
+            protocol: request.version(),
+            user_agent: header(&header::USER_AGENT),
+            referer: header(&header::REFERER),
+            request_headers,
+            status: None,
+            grpc_status: None,
+            bytes_sent: 0,
+        }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`AccessLogLayer`].
+#[derive(Debug, Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    layer: AccessLogLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for AccessLog<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Body,
+{
+    type Response = Response<AccessLogBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let entry = self.layer.entry(&request);
+        ResponseFuture {
+            inner: self.inner.call(request),
+            entry: Some(entry),
+        }
+    }
+}
+
+/// What is known about a request, written out once it is over.
+struct Entry {
+    layer: AccessLogLayer,
+    time: SystemTime,
+    start: Instant,
+    remote_addr: Option<SocketAddr>,
+    method: String,
+    uri: String,
+    protocol: http::Version,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    request_headers: Vec<(HeaderName, Option<String>)>,
+    status: Option<u16>,
+    grpc_status: Option<i32>,
+    bytes_sent: u64,
+}
+
+impl Entry {
+    fn classify(&mut self, classification: Option<Classification>) {
+        if let Some(Classification::Grpc { code, .. }) = classification {
+            self.grpc_status = Some(code);
+        }
+    }
+
+    fn finish(self, completed: bool) {
+        let line = match self.layer.format {
+            Format::Common => self.common_line(),
+            Format::Json => self.json_line(completed),
+        };
+        self.layer.sink.write_line(&line);
+    }
+
+    fn common_line(&self) -> String {
+        let mut line = String::new();
+        match self.remote_addr {
+            Some(addr) => write!(line, "{}", addr.ip()),
+            None => write!(line, "-"),
+        }
+        .unwrap();
+        write!(
+            line,
+            " - - [{}] \"{} {} {:?}\" ",
+            CommonTime(self.time),
+            self.method,
+            self.uri,
+            self.protocol
+        )
+        .unwrap();
+        match self.status {
+            Some(status) => write!(line, "{status} "),
+            None => write!(line, "- "),
+        }
+        .unwrap();
+        // The Common Log Format writes `-` for empty responses.
+        if self.bytes_sent == 0 {
+            line.push('-');
+        } else {
+            write!(line, "{}", self.bytes_sent).unwrap();
+        }
+        line
+    }
+
+    fn json_line(&self, completed: bool) -> String {
+        let mut line = String::from("{");
+        for (i, field) in self.layer.fields.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            match field {
+                Field::RemoteAddr => {
+                    json_key(&mut line, "remote_addr");
+                    json_opt_str(
+                        &mut line,
+                        self.remote_addr.map(|a| a.to_string()).as_deref(),
+                    );
+                }
+                Field::Time => {
+                    json_key(&mut line, "time");
+                    json_str(&mut line, &Rfc3339(self.time).to_string());
+                }
+                Field::Method => {
+                    json_key(&mut line, "method");
+                    json_str(&mut line, &self.method);
+                }
+                Field::Uri => {
+                    json_key(&mut line, "uri");
+                    json_str(&mut line, &self.uri);
+                }
+                Field::Protocol => {
+                    json_key(&mut line, "protocol");
+                    json_str(&mut line, &format!("{:?}", self.protocol));
+                }
+                Field::Status => {
+                    json_key(&mut line, "status");
+                    json_opt_number(&mut line, self.status);
+                }
+                Field::GrpcStatus => {
+                    json_key(&mut line, "grpc_status");
+                    json_opt_number(&mut line, self.grpc_status);
+                }
+                Field::BytesSent => {
+                    json_key(&mut line, "bytes_sent");
+                    write!(line, "{}", self.bytes_sent).unwrap();
+                }
+                Field::Latency => {
+                    json_key(&mut line, "latency_ms");
+                    let latency = self.start.elapsed();
+                    write!(line, "{:.3}", latency.as_secs_f64() * 1000.0).unwrap();
+                }
+                Field::Completed => {
+                    json_key(&mut line, "completed");
+                    write!(line, "{completed}").unwrap();
+                }
+                Field::UserAgent => {
+                    json_key(&mut line, "user_agent");
+                    json_opt_str(&mut line, self.user_agent.as_deref());
+                }
+                Field::Referer => {
+                    json_key(&mut line, "referer");
+                    json_opt_str(&mut line, self.referer.as_deref());
+                }
+                Field::RequestHeader(name) => {
+                    let value = self
+                        .request_headers
+                        .iter()
+                        .find(|(header, _)| header == name)
+                        .and_then(|(_, value)| value.as_deref());
+                    json_key(&mut line, name.as_str());
+                    json_opt_str(&mut line, value);
+                }
+            }
+        }
+        line.push('}');
+        line
+    }
+}
+
+fn json_key(line: &mut String, key: &str) {
+    json_str(line, key);
+    line.push(':');
+}
+
+fn json_opt_str(line: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => json_str(line, value),
+        None => line.push_str("null"),
+    }
+}
+
+fn json_opt_number<N: fmt::Display>(line: &mut String, value: Option<N>) {
+    match value {
+        Some(value) => write!(line, "{value}").unwrap(),
+        None => line.push_str("null"),
+    }
+}
+
+/// Writes `value` as a JSON string.
+fn json_str(line: &mut String, value: &str) {
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => write!(line, "\\u{:04x}", c as u32).unwrap(),
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+/// UTC calendar date and time of `time`, as `(year, month, day, hour,
+/// minute, second, millisecond)`.
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64, u32) {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+/// Formats a time as Common Log Format does: `16/Oct/2026:04:20:03 +0000`.
+struct CommonTime(SystemTime);
+
+impl fmt::Display for CommonTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let (year, month, day, hour, minute, second, _) = utc(self.0);
+        write!(
+            f,
+            "{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000",
+            MONTHS[month as usize - 1]
+        )
+    }
+}
+
+/// Formats a time in RFC 3339 format: `2026-10-16T04:20:03.123Z`.
+struct Rfc3339(SystemTime);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second, millis) = utc(self.0);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z"
+        )
+    }
+}
+
+pin_project! {
+    /// Response future for [`AccessLog`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        entry: Option<Entry>,
+    }
+
+    impl<F> PinnedDrop for ResponseFuture<F> {
+        fn drop(this: Pin<&mut Self>) {
+            // Dropped before the inner service produced a response.
+            if let Some(entry) = this.project().entry.take() {
+                entry.finish(false);
+            }
+        }
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<AccessLogBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let mut entry = this.entry.take().expect("polled after completion");
+
+        match result {
+            Ok(response) => {
+                let (head, body) = response.into_parts();
+                entry.status = Some(head.status.as_u16());
+                let classification = Classification::from_response(&head);
+                let classify_trailers = classification.is_none();
+                entry.classify(classification);
+                Poll::Ready(Ok(Response::from_parts(
+                    head,
+                    AccessLogBody {
+                        inner: body,
+                        entry: Some(entry),
+                        classify_trailers,
+                    },
+                )))
+            }
+            Err(error) => {
+                entry.finish(false);
+                Poll::Ready(Err(error))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`AccessLog`]. Writes the request's line once the
+    /// body ends, fails, or is dropped.
+    pub struct AccessLogBody<B>
+    where
+        B: Body,
+    {
+        #[pin]
+        inner: B,
+        entry: Option<Entry>,
+        classify_trailers: bool,
+    }
+
+    impl<B> PinnedDrop for AccessLogBody<B>
+    where
+        B: Body,
+    {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(entry) = this.entry.take() {
+                // Bodies known to be empty may be dropped without a poll.
+                entry.finish(this.inner.is_end_stream());
+            }
+        }
+    }
+}
+
+impl<B> Body for AccessLogBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let result = ready!(this.inner.poll_frame(cx));
+
+        match &result {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref()
+                    && let Some(entry) = this.entry.as_mut()
+                {
+                    entry.bytes_sent += data.remaining() as u64;
+                }
+                if let Some(trailers) = frame.trailers_ref()
+                    && let Some(entry) = this.entry.take()
+                {
+                    end_of_stream(entry, *this.classify_trailers, Some(trailers));
+                }
+            }
+            Some(Err(_)) => {
+                if let Some(entry) = this.entry.take() {
+                    entry.finish(false);
+                }
+            }
+            None => {
+                if let Some(entry) = this.entry.take() {
+                    end_of_stream(entry, *this.classify_trailers, None);
+                }
+            }
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn end_of_stream(mut entry: Entry, classify_trailers: bool, trailers: Option<&HeaderMap>) {
+    if classify_trailers {
+        entry.classify(Classification::from_trailers(trailers));
+    }
+    entry.finish(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use http_body_util::StreamBody;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// Serves a request answered with `response` through `layer`, and
+    /// returns the lines written to its sink.
+    async fn log<B>(
+        layer: impl FnOnce(Arc<Mutex<Vec<String>>>) -> AccessLogLayer,
+        response: Response<B>,
+    ) -> Vec<String>
+    where
+        B: Body<Data = Bytes, Error = Infallible> + Send + 'static,
+    {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut response = Some(response);
+        let svc = layer(lines.clone()).layer(tower::service_fn(move |_: Request<()>| {
+            std::future::ready(Ok::<_, Infallible>(response.take().unwrap()))
+        }));
+        let mut request = Request::get("/path?q=1")
+            .header(header::USER_AGENT, "curl/8.0")
+            .header("x-request-id", "abc")
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo {
+            local_addr: SocketAddr::from(([127, 0, 0, 1], 80)),
+            remote_addr: SocketAddr::from(([10, 0, 0, 1], 1234)),
+            tls: false,
+        });
+        let response = svc.oneshot(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+        std::mem::take(&mut *lines.lock().unwrap())
+    }
+
+    fn recording(lines: Arc<Mutex<Vec<String>>>) -> AccessLogLayer {
+        AccessLogLayer::with_sink(move |line: &str| lines.lock().unwrap().push(line.to_owned()))
+    }
+
+    #[tokio::test]
+    async fn writes_common_log_format() {
+        let lines = log(
+            recording,
+            Response::new(Full::new(Bytes::from_static(b"ok"))),
+        )
+        .await;
+        let [line] = &lines[..] else {
+            panic!("expected one line: {lines:?}");
+        };
+        let (prefix, rest) = line.split_once(" [").unwrap();
+        assert_eq!(prefix, "10.0.0.1 - -");
+        let (_, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(rest, "\"GET /path?q=1 HTTP/1.1\" 200 2");
+    }
+
+    #[tokio::test]
+    async fn writes_the_configured_json_fields() {
+        let trailers = {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "5".parse().unwrap());
+            trailers
+        };
+        let body = StreamBody::new(futures::stream::iter([
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"abc"))),
+            Ok(Frame::data(Bytes::from_static(b"de"))),
+            Ok(Frame::trailers(trailers)),
+        ]));
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(body)
+            .unwrap();
+
+        let layer = |lines| {
+            recording(lines).format(Format::Json).fields([
+                Field::RemoteAddr,
+                Field::Method,
+                Field::Uri,
+                Field::Status,
+                Field::GrpcStatus,
+                Field::BytesSent,
+                Field::Completed,
+                Field::Referer,
+                Field::RequestHeader(HeaderName::from_static("x-request-id")),
+            ])
+        };
+        let lines = log(layer, response).await;
+        assert_eq!(
+            lines,
+            [concat!(
+                r#"{"remote_addr":"10.0.0.1:1234","method":"GET","uri":"/path?q=1","#,
+                r#""status":200,"grpc_status":5,"bytes_sent":5,"completed":true,"#,
+                r#""referer":null,"x-request-id":"abc"}"#
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_abandoned_requests() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let svc = recording(lines.clone())
+            .format(Format::Json)
+            .fields([Field::Status, Field::Completed])
+            .layer(tower::service_fn(|_: Request<()>| async {
+                std::future::pending::<Result<Response<Full<Bytes>>, Infallible>>().await
+            }));
+        let result =
+            tokio::time::timeout(Duration::from_millis(10), svc.oneshot(Request::new(()))).await;
+        assert!(result.is_err());
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [r#"{"status":null,"completed":false}"#]
+        );
+    }
+
+    #[test]
+    fn formats_times() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_792_124_403_123);
+        assert_eq!(CommonTime(time).to_string(), "16/Oct/2026:04:20:03 +0000");
+        assert_eq!(Rfc3339(time).to_string(), "2026-10-16T04:20:03.123Z");
+        let leap_day = SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(Rfc3339(leap_day).to_string(), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let mut line = String::new();
+        json_str(&mut line, "a\"b\\c\n\u{1}");
+        assert_eq!(line, r#""a\"b\\c\n\u0001""#);
+    }
+
+    #[test]
+    fn writer_sink_terminates_lines() {
+        let sink = WriterSink::new(Vec::new());
+        sink.write_line("one");
+        sink.write_line("two");
+        assert_eq!(sink.writer.into_inner().unwrap(), b"one\ntwo\n");
+    }
+}
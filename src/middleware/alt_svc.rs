@@ -0,0 +1,223 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Advertising alternative services to clients.
+//!
+//! [`AltSvcLayer`] sets the `Alt-Svc` header ([RFC 7838]) on every response,
+//! telling clients where else they can reach the server, such as an HTTP/3
+//! endpoint or another port. Responses that already carry an `Alt-Svc`
+//! header are left untouched.
+//!
+//! [RFC 7838]: https://www.rfc-editor.org/rfc/rfc7838
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::alt_svc::AltSvcLayer;
+//!
+//! // alt-svc: h3=":443"; ma=3600, h2="backup.example.com:8443"; ma=3600
+//! let layer = AltSvcLayer::new()
+//!     .h3(443)
+//!     .alternative("h2", "backup.example.com:8443")
+//!     .max_age(Duration::from_secs(3600));
+//! # let _ = layer;
+//! ```
+
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::header::ALT_SVC;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tower::Layer;
+use tower::Service;
+
+/// [`Layer`] that advertises alternative services; see the
+/// [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct AltSvcLayer {
+    alternatives: Vec<(String, String)>,
+    max_age: Option<Duration>,
+}
+
+impl AltSvcLayer {
+    /// A layer advertising no alternatives, which sends `alt-svc: clear` to
+    /// make clients forget alternatives advertised earlier.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advertise the service at `authority` (`host:port`, or `:port` for
+    /// the same host) over the protocol with ALPN id `protocol`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `protocol` is not a valid token or `authority` contains
+    /// quotes, backslashes or characters not allowed in header values.
+    pub fn alternative(mut self, protocol: &str, authority: &str) -> Self {
+        assert!(
+            !protocol.is_empty()
+                && protocol
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)),
+            "invalid alt-svc protocol id {protocol:?}"
+        );
+        assert!(
+            authority
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\'),
+            "invalid alt-svc authority {authority:?}"
+        );
+        self.alternatives
+            .push((protocol.to_owned(), authority.to_owned()));
+        self
+    }
+
+    /// Advertise HTTP/3 on `port` of the same host.
+    pub fn h3(self, port: u16) -> Self {
+        self.alternative("h3", &format!(":{port}"))
+    }
+
+    /// How long clients may use the alternatives for. Clients assume 24
+    /// hours when it is not set.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        if self.alternatives.is_empty() {
+            return HeaderValue::from_static("clear");
+        }
+
+        let value = self
+            .alternatives
+            .iter()
+            .map(|(protocol, authority)| match self.max_age {
+                Some(max_age) => format!("{protocol}=\"{authority}\"; ma={}", max_age.as_secs()),
+                None => format!("{protocol}=\"{authority}\""),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&value).expect("alternatives are validated when added")
+    }
+}
+
+impl<S> Layer<S> for AltSvcLayer {
+    type Service = AltSvc<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AltSvc {
+            inner,
+            value: self.header_value(),
+        }
+    }
+}
+
+/// Service returned by [`AltSvcLayer`].
+#[derive(Debug, Clone)]
+pub struct AltSvc<S> {
+    inner: S,
+    value: HeaderValue,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for AltSvc<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(request),
+            value: Some(self.value.clone()),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`AltSvc`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        value: Option<HeaderValue>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        if let Some(value) = this.value.take() {
+            response.headers_mut().entry(ALT_SVC).or_insert(value);
+        }
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    #[test]
+    fn formats_alternatives() {
+        assert_eq!(AltSvcLayer::new().header_value(), "clear");
+        assert_eq!(AltSvcLayer::new().h3(443).header_value(), "h3=\":443\"");
+        assert_eq!(
+            AltSvcLayer::new()
+                .h3(443)
+                .alternative("h2", "alt.example.com:8443")
+                .max_age(Duration::from_secs(60))
+                .header_value(),
+            "h3=\":443\"; ma=60, h2=\"alt.example.com:8443\"; ma=60"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid alt-svc authority")]
+    fn rejects_quoted_authority() {
+        let _ = AltSvcLayer::new().alternative("h3", "\":443");
+    }
+
+    #[tokio::test]
+    async fn keeps_header_set_by_service() {
+        let layer = AltSvcLayer::new().h3(443);
+        let svc = layer.layer(tower::service_fn(|request: Request<()>| async move {
+            let mut response = Response::new(());
+            if request.uri().path() == "/custom" {
+                response
+                    .headers_mut()
+                    .insert(ALT_SVC, HeaderValue::from_static("h3=\":8443\""));
+            }
+            Ok::<_, Infallible>(response)
+        }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.headers()[ALT_SVC], "h3=\":443\"");
+
+        let request = Request::builder().uri("/custom").body(()).unwrap();
+        let response = svc.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[ALT_SVC], "h3=\":8443\"");
+    }
+}
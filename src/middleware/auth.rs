@@ -0,0 +1,569 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authenticating requests with an async token validator.
+//!
+//! [`AuthLayer`] extracts a [`Credential`] from every request — a bearer
+//! token from the `authorization` header, or an API key from a configured
+//! header — and passes it to a [`Validator`], which may call out to an
+//! identity service or a key store. Requests it accepts reach the inner
+//! service with the validator's principal in their extensions; requests
+//! without a credential, or whose credential the validator rejects, are
+//! answered right away:
+//!
+//! * HTTP requests get `401 Unauthorized` with a `www-authenticate`
+//!   challenge when bearer tokens are accepted, and the rejection message
+//!   as a plain text body;
+//! * gRPC requests get a trailers-only response with `grpc-status` 16
+//!   (`UNAUTHENTICATED`) and the rejection message as `grpc-message`.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::auth::AuthLayer;
+//! use sui_http::middleware::auth::Credential;
+//! use sui_http::middleware::auth::Unauthenticated;
+//!
+//! #[derive(Clone)]
+//! struct User(String);
+//!
+//! let layer = AuthLayer::new(|credential: Credential| async move {
+//!     match credential.secret() {
+//!         "let-me-in" => Ok(User("alice".to_owned())),
+//!         _ => Err(Unauthenticated::new("unknown token")),
+//!     }
+//! })
+//! .api_key_header(http::HeaderName::from_static("x-api-key"));
+//!
+//! // Handlers read the principal back with
+//! // `request.extensions().get::<User>()`.
+//! # let _ = layer;
+//! ```
+
+use bytes::Bytes;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+use super::trailers::grpc_trailers_only;
+use crate::middleware::callback::is_grpc;
+
+const GRPC_UNAUTHENTICATED: i32 = 16;
+
+/// A credential presented by a client.
+///
+/// Its `Debug` output redacts the secret.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Credential {
+    /// A token from an `authorization: Bearer <token>` header.
+    Bearer(String),
+    /// A key from one of the configured API key headers.
+    ApiKey { header: HeaderName, key: String },
+}
+
+impl Credential {
+    /// The token or key.
+    pub fn secret(&self) -> &str {
+        match self {
+            Credential::Bearer(token) => token,
+            Credential::ApiKey { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Bearer(_) => f.write_str("Bearer(<redacted>)"),
+            Credential::ApiKey { header, .. } => f
+                .debug_struct("ApiKey")
+                .field("header", header)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Why a request was not authenticated, sent back to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unauthenticated {
+    message: Cow<'static, str>,
+}
+
+impl Unauthenticated {
+    pub fn new(message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Unauthenticated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Unauthenticated {}
+
+/// Checks the credential of a request.
+///
+/// Implemented for closures taking a [`Credential`] and returning a future
+/// of the result.
+pub trait Validator: Send + Sync + 'static {
+    /// Who or what a valid credential identifies; inserted into the
+    /// extensions of authenticated requests.
+    type Principal: Clone + Send + Sync + 'static;
+
+    /// Returns the principal `credential` identifies, or why it was
+    /// rejected.
+    fn validate(
+        &self,
+        credential: Credential,
+    ) -> impl Future<Output = Result<Self::Principal, Unauthenticated>> + Send + 'static;
+}
+
+impl<F, Fut, P> Validator for F
+where
+    F: Fn(Credential) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<P, Unauthenticated>> + Send + 'static,
+    P: Clone + Send + Sync + 'static,
+{
+    type Principal = P;
+
+    fn validate(
+        &self,
+        credential: Credential,
+    ) -> impl Future<Output = Result<P, Unauthenticated>> + Send + 'static {
+        self(credential)
+    }
+}
+
+/// [`Layer`] that authenticates requests; see the [module docs](self).
+pub struct AuthLayer<V> {
+    validator: Arc<V>,
+    bearer: bool,
+    api_key_headers: Arc<[HeaderName]>,
+}
+
+impl<V> Clone for AuthLayer<V> {
+    fn clone(&self) -> Self {
+        Self {
+            validator: self.validator.clone(),
+            bearer: self.bearer,
+            api_key_headers: self.api_key_headers.clone(),
+        }
+    }
+}
+
+impl<V> fmt::Debug for AuthLayer<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthLayer")
+            .field("bearer", &self.bearer)
+            .field("api_key_headers", &self.api_key_headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<V: Validator> AuthLayer<V> {
+    /// Authenticate requests by their bearer token with `validator`.
+    pub fn new(validator: V) -> Self {
+        Self {
+            validator: Arc::new(validator),
+            bearer: true,
+            api_key_headers: Arc::new([]),
+        }
+    }
+
+    /// Whether to accept bearer tokens from the `authorization` header.
+    /// Defaults to `true`.
+    pub fn bearer(self, bearer: bool) -> Self {
+        Self { bearer, ..self }
+    }
+
+    /// Also accept API keys from `header`. Headers are tried after the
+    /// bearer token, in the order they were added.
+    pub fn api_key_header(self, header: HeaderName) -> Self {
+        let mut api_key_headers = self.api_key_headers.to_vec();
+        api_key_headers.push(header);
+        Self {
+            api_key_headers: api_key_headers.into(),
+            ..self
+        }
+    }
+
+    fn credential<B>(&self, request: &Request<B>) -> Option<Credential> {
+        let headers = request.headers();
+        if self.bearer
+            && let Some(token) = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split_once(' '))
+                .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                .map(|(_, token)| token.trim())
+                .filter(|token| !token.is_empty())
+        {
+            return Some(Credential::Bearer(token.to_owned()));
+        }
+        self.api_key_headers.iter().find_map(|name| {
+            let key = headers.get(name)?.to_str().ok()?;
+            Some(Credential::ApiKey {
+                header: name.clone(),
+                key: key.to_owned(),
+            })
+        })
+    }
+}
+
+impl<S, V> Layer<S> for AuthLayer<V> {
+    type Service = Auth<S, V>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`AuthLayer`].
+pub struct Auth<S, V> {
+    inner: S,
+    layer: AuthLayer<V>,
+}
+
+impl<S: Clone, V> Clone for Auth<S, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, V> fmt::Debug for Auth<S, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, V, RequestBody, ResponseBody> Service<Request<RequestBody>> for Auth<S, V>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    V: Validator,
+    ResponseBody: Body<Data = Bytes>,
+{
+    type Response = Response<AuthBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, RequestBody, V::Principal>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let rejection = |message| Rejection {
+            grpc: is_grpc(request.headers()),
+            bearer: self.layer.bearer,
+            message,
+        };
+        let Some(credential) = self.layer.credential(&request) else {
+            return ResponseFuture::Rejected {
+                rejection: Some(rejection(Unauthenticated::new("missing credentials"))),
+            };
+        };
+
+        ResponseFuture::Validating {
+            validation: Box::pin(self.layer.validator.validate(credential)),
+            grpc: is_grpc(request.headers()),
+            bearer: self.layer.bearer,
+            service: Some(self.inner.clone()),
+            request: Some(request),
+        }
+    }
+}
+
+type Validation<P> = Pin<Box<dyn Future<Output = Result<P, Unauthenticated>> + Send>>;
+
+/// How to answer an unauthenticated request.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct Rejection {
+    grpc: bool,
+    bearer: bool,
+    message: Unauthenticated,
+}
+
+impl Rejection {
+    fn into_response<B>(self) -> Response<AuthBody<B>> {
+        if self.grpc {
+            return grpc_trailers_only(GRPC_UNAUTHENTICATED, Some(self.message.message()));
+        }
+
+        let mut response = Response::new(AuthBody {
+            inner: None,
+            rejection: Some(Bytes::from(self.message.message().to_owned())),
+        });
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        if self.bearer {
+            headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+pin_project! {
+    /// Response future for [`Auth`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<S, B, P>
+    where
+        S: Service<Request<B>>,
+    {
+        Validating {
+            validation: Validation<P>,
+            grpc: bool,
+            bearer: bool,
+            service: Option<S>,
+            request: Option<Request<B>>,
+        },
+        Inner {
+            #[pin]
+            future: Oneshot<S, Request<B>>,
+        },
+        Rejected {
+            rejection: Option<Rejection>,
+        },
+    }
+}
+
+impl<S, B, P, ResponseBody> Future for ResponseFuture<S, B, P>
+where
+    S: Service<Request<B>, Response = Response<ResponseBody>>,
+    P: Clone + Send + Sync + 'static,
+{
+    type Output = Result<Response<AuthBody<ResponseBody>>, S::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.as_mut().project() {
+                ResponseFutureProj::Validating {
+                    validation,
+                    grpc,
+                    bearer,
+                    service,
+                    request,
+                } => {
+                    let result = ready!(validation.as_mut().poll(cx));
+                    let next = match result {
+                        Ok(principal) => {
+                            let mut request = request.take().expect("polled after completion");
+                            request.extensions_mut().insert(principal);
+                            let service = service.take().expect("polled after completion");
+                            ResponseFuture::Inner {
+                                future: service.oneshot(request),
+                            }
+                        }
+                        Err(message) => ResponseFuture::Rejected {
+                            rejection: Some(Rejection {
+                                grpc: *grpc,
+                                bearer: *bearer,
+                                message,
+                            }),
+                        },
+                    };
+                    self.set(next);
+                }
+                ResponseFutureProj::Inner { future } => {
+                    let response = ready!(future.poll(cx))?;
+                    return Poll::Ready(Ok(response.map(|inner| AuthBody {
+                        inner: Some(inner),
+                        rejection: None,
+                    })));
+                }
+                ResponseFutureProj::Rejected { rejection } => {
+                    let rejection = rejection.take().expect("polled after completion");
+                    return Poll::Ready(Ok(rejection.into_response()));
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`Auth`]: the inner service's body, or the
+    /// message of a rejection.
+    pub struct AuthBody<B> {
+        #[pin]
+        inner: Option<B>,
+        rejection: Option<Bytes>,
+    }
+}
+
+impl<B> Default for AuthBody<B> {
+    fn default() -> Self {
+        Self {
+            inner: None,
+            rejection: None,
+        }
+    }
+}
+
+impl<B> Body for AuthBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        match this.inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(this.rejection.take().map(|body| Ok(Frame::data(body)))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => inner.is_end_stream(),
+            None => self.rejection.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match &self.inner {
+            Some(inner) => inner.size_hint(),
+            None => http_body::SizeHint::with_exact(
+                self.rejection.as_ref().map_or(0, |body| body.len() as u64),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct User(&'static str);
+
+    fn layer() -> AuthLayer<impl Validator<Principal = User>> {
+        AuthLayer::new(|credential: Credential| async move {
+            tokio::task::yield_now().await;
+            match credential.secret() {
+                "alice-token" => Ok(User("alice")),
+                _ => Err(Unauthenticated::new("unknown token")),
+            }
+        })
+        .api_key_header(HeaderName::from_static("x-api-key"))
+    }
+
+    async fn call(request: Request<()>) -> (http::response::Parts, String) {
+        let svc = layer().layer(tower::service_fn(|request: Request<()>| async move {
+            let user = request.extensions().get::<User>().unwrap();
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(user.0))))
+        }));
+        let response = svc.oneshot(request).await.unwrap();
+        let (head, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (head, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn request(header: Option<(&'static str, &'static str)>) -> Request<()> {
+        let mut request = Request::get("/");
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn injects_the_principal_of_valid_credentials() {
+        for header in [
+            ("authorization", "Bearer alice-token"),
+            ("authorization", "bearer  alice-token"),
+            ("x-api-key", "alice-token"),
+        ] {
+            let (head, body) = call(request(Some(header))).await;
+            assert_eq!(head.status, StatusCode::OK, "{header:?}");
+            assert_eq!(body, "alice");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_and_invalid_credentials() {
+        let (head, body) = call(request(None)).await;
+        assert_eq!(head.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(head.headers[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(body, "missing credentials");
+
+        let (head, body) = call(request(Some(("authorization", "Basic alice-token")))).await;
+        assert_eq!(head.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "missing credentials");
+
+        let (head, body) = call(request(Some(("x-api-key", "mallory")))).await;
+        assert_eq!(head.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "unknown token");
+    }
+
+    #[tokio::test]
+    async fn rejects_grpc_requests_with_unauthenticated() {
+        let request = Request::post("/pkg.Service/Method")
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .header("authorization", "Bearer mallory")
+            .body(())
+            .unwrap();
+        let (head, body) = call(request).await;
+        assert_eq!(head.status, StatusCode::OK);
+        assert_eq!(head.headers["grpc-status"], "16");
+        assert_eq!(head.headers["grpc-message"], "unknown%20token");
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let bearer = Credential::Bearer("secret".to_owned());
+        assert_eq!(format!("{bearer:?}"), "Bearer(<redacted>)");
+        let api_key = Credential::ApiKey {
+            header: HeaderName::from_static("x-api-key"),
+            key: "secret".to_owned(),
+        };
+        assert!(!format!("{api_key:?}").contains("secret"));
+    }
+}
@@ -0,0 +1,767 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caching responses to repeated requests.
+//!
+//! [`CacheLayer`] answers a `GET` or `HEAD` request from its cache when an
+//! identical request was answered recently, without calling the inner
+//! service. Requests are identical when they have the same method, path
+//! and query, and the same values for the headers configured with
+//! [`CacheLayer::key_headers`].
+//!
+//! Only `200 OK` responses are stored, and only if their `cache-control`
+//! allows it: responses marked `no-store`, `no-cache` or `private` are not,
+//! and `s-maxage` or `max-age` set how long a response stays fresh, in
+//! place of [`CacheLayer::ttl`]. Responses setting cookies, varying on
+//! headers outside the key, or with bodies larger than
+//! [`CacheLayer::max_entry_size`] are not stored either, and neither are
+//! responses to requests carrying an `authorization` header.
+//!
+//! Responses stream to the client as usual while they are copied into the
+//! cache, which happens once their body has ended. Responses served from
+//! the cache carry an `age` header with their age in seconds and an
+//! `x-cache: HIT` header; responses to cacheable requests that missed carry
+//! `x-cache: MISS`.
+//!
+//! Entries are kept in a [`CacheStore`]; [`MemoryStore`], the default, is
+//! an in-memory store evicting the least recently used entries past its
+//! capacity.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::cache::CacheLayer;
+//! use sui_http::middleware::cache::MemoryStore;
+//!
+//! let layer = CacheLayer::with_store(MemoryStore::new(256 << 20))
+//!     .ttl(Duration::from_secs(5))
+//!     .key_headers([http::header::ACCEPT_ENCODING]);
+//! # let _ = layer;
+//! ```
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use http_body::SizeHint;
+use pin_project_lite::pin_project;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use std::time::Instant;
+use tower::Layer;
+use tower::Service;
+
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+const HIT: HeaderValue = HeaderValue::from_static("HIT");
+const MISS: HeaderValue = HeaderValue::from_static("MISS");
+
+const DEFAULT_CAPACITY: usize = 64 << 20;
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_ENTRY_SIZE: usize = 1 << 20;
+
+/// Storage for cached responses.
+///
+/// Stores need not check freshness: expired entries they return are
+/// ignored, and may be replaced through [`CacheStore::insert`].
+pub trait CacheStore: Send + Sync + 'static {
+    /// Returns the response stored for `key`, if any.
+    fn get(&self, key: &CacheKey) -> Option<CachedResponse>;
+
+    /// Stores `response` for `key`, replacing any previous entry.
+    fn insert(&self, key: CacheKey, response: CachedResponse);
+}
+
+/// What identifies a cached response: the request's method, path and
+/// query, and the values of the configured key headers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: Method,
+    uri: String,
+    headers: Vec<Option<HeaderValue>>,
+}
+
+impl CacheKey {
+    /// The request method.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The request path and query.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+}
+
+/// A response held in a [`CacheStore`].
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+}
+
+impl CachedResponse {
+    /// The response's headers.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The response's body.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Approximate memory used by the response, in bytes.
+    pub fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        headers + self.body.len()
+    }
+
+    /// Time since the response was stored.
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+
+    /// Whether the response is no longer fresh.
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+/// An in-memory [`CacheStore`] holding up to a number of bytes of
+/// responses, evicting the least recently used ones first.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    // Each entry with the tick of its last use.
+    entries: HashMap<CacheKey, (CachedResponse, u64)>,
+    // Keys by the tick of their last use, least recent first.
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    size: usize,
+}
+
+impl MemoryStore {
+    /// A store holding up to `capacity` bytes of responses, as measured by
+    /// [`CachedResponse::size`].
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Default::default(),
+        }
+    }
+}
+
+impl Lru {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((response, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.size -= response.size();
+        }
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut lru = self.lru.lock().unwrap();
+        let lru = &mut *lru;
+        lru.tick += 1;
+        let (response, tick) = lru.entries.get_mut(key)?;
+        let response = response.clone();
+        lru.order.remove(tick);
+        *tick = lru.tick;
+        lru.order.insert(lru.tick, key.clone());
+        Some(response)
+    }
+
+    fn insert(&self, key: CacheKey, response: CachedResponse) {
+        let size = response.size();
+        if size > self.capacity {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(&key);
+        while lru.size + size > self.capacity {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            lru.remove(&oldest);
+        }
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.order.insert(tick, key.clone());
+        lru.entries.insert(key, (response, tick));
+        lru.size += size;
+    }
+}
+
+/// [`Layer`] that caches responses; see the [module docs](self).
+///
+/// All services produced by the layer (and their clones) share its store.
+#[derive(Clone)]
+pub struct CacheLayer {
+    store: Arc<dyn CacheStore>,
+    ttl: Duration,
+    max_entry_size: usize,
+    key_headers: Arc<[HeaderName]>,
+}
+
+impl fmt::Debug for CacheLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheLayer")
+            .field("ttl", &self.ttl)
+            .field("max_entry_size", &self.max_entry_size)
+            .field("key_headers", &self.key_headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CacheLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheLayer {
+    /// Cache responses in a 64 MiB [`MemoryStore`], for 60 seconds unless
+    /// their `cache-control` says otherwise.
+    pub fn new() -> Self {
+        Self::with_store(MemoryStore::new(DEFAULT_CAPACITY))
+    }
+
+    /// Cache responses in `store`.
+    pub fn with_store<S: CacheStore>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: DEFAULT_TTL,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            key_headers: Arc::new([]),
+        }
+    }
+
+    /// How long responses without an `s-maxage` or `max-age` directive stay
+    /// fresh. Defaults to 60 seconds.
+    pub fn ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// The largest response body to cache, in bytes. Defaults to 1 MiB.
+    pub fn max_entry_size(self, max_entry_size: usize) -> Self {
+        Self {
+            max_entry_size,
+            ..self
+        }
+    }
+
+    /// Request headers whose values are part of the cache key, e.g.
+    /// `accept-encoding` in front of a compression layer. None by default.
+    pub fn key_headers<I>(self, key_headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        Self {
+            key_headers: key_headers.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// The cache key for `request`, or `None` if its response must not be
+    /// cached.
+    fn key<B>(&self, request: &Request<B>) -> Option<CacheKey> {
+        if !matches!(*request.method(), Method::GET | Method::HEAD)
+            || request.headers().contains_key(header::AUTHORIZATION)
+        {
+            return None;
+        }
+        let uri = request
+            .uri()
+            .path_and_query()
+            .map_or_else(|| request.uri().path().to_owned(), |pq| pq.to_string());
+        let headers = self
+            .key_headers
+            .iter()
+            .map(|name| request.headers().get(name).cloned())
+            .collect();
+        Some(CacheKey {
+            method: request.method().clone(),
+            uri,
+            headers,
+        })
+    }
+
+    /// How long `response` stays fresh, or `None` if it must not be
+    /// cached.
+    fn freshness(&self, response: &http::response::Parts) -> Option<Duration> {
+        let headers = &response.headers;
+        if response.status != StatusCode::OK || headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+        let vary_outside_key = headers
+            .get_all(header::VARY)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or("*").split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .any(|name| {
+                !self
+                    .key_headers
+                    .iter()
+                    .any(|key| key.as_str().eq_ignore_ascii_case(name))
+            });
+        if vary_outside_key {
+            return None;
+        }
+        if let Some(length) = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+            && length > self.max_entry_size
+        {
+            return None;
+        }
+
+        let mut max_age = None;
+        let mut s_maxage = None;
+        for directive in headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or("no-store").split(','))
+        {
+            let (name, value) = directive
+                .trim()
+                .split_once('=')
+                .map_or((directive.trim(), None), |(name, value)| {
+                    (name, Some(value.trim_matches('"')))
+                });
+            let seconds = || value?.parse().ok().map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" | "no-cache" | "private" => return None,
+                "max-age" => max_age = seconds(),
+                "s-maxage" => s_maxage = seconds(),
+                _ => {}
+            }
+        }
+        Some(s_maxage.or(max_age).unwrap_or(self.ttl)).filter(|ttl| !ttl.is_zero())
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = Cache<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cache {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`CacheLayer`].
+#[derive(Debug, Clone)]
+pub struct Cache<S> {
+    inner: S,
+    layer: CacheLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Cache<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Body<Data = Bytes>,
+{
+    type Response = Response<CacheBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let key = self.layer.key(&request);
+        if let Some(key) = &key
+            && let Some(cached) = self.layer.store.get(key)
+            && !cached.is_expired()
+        {
+            return ResponseFuture::Hit {
+                cached: Some(cached),
+            };
+        }
+
+        ResponseFuture::Miss {
+            future: self.inner.call(request),
+            key,
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Cache`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Hit {
+            cached: Option<CachedResponse>,
+        },
+        Miss {
+            #[pin]
+            future: F,
+            key: Option<CacheKey>,
+            layer: CacheLayer,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body<Data = Bytes>,
+{
+    type Output = Result<Response<CacheBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Hit { cached } => {
+                let cached = cached.take().expect("polled after completion");
+                let age = HeaderValue::from(cached.age().as_secs());
+                let mut response = Response::new(CacheBody {
+                    inner: None,
+                    cached: Some(cached.body).filter(|body| !body.is_empty()),
+                    recording: None,
+                });
+                *response.headers_mut() = cached.headers;
+                response.headers_mut().insert(header::AGE, age);
+                response.headers_mut().insert(X_CACHE, HIT);
+                Poll::Ready(Ok(response))
+            }
+            ResponseFutureProj::Miss { future, key, layer } => {
+                let response = ready!(future.poll(cx))?;
+                let (mut head, body) = response.into_parts();
+                let recording = key.take().and_then(|key| {
+                    head.headers.insert(X_CACHE, MISS);
+                    let ttl = layer.freshness(&head)?;
+                    let mut headers = head.headers.clone();
+                    headers.remove(X_CACHE);
+                    Some(Recording {
+                        store: layer.store.clone(),
+                        key,
+                        headers,
+                        ttl,
+                        max_size: layer.max_entry_size,
+                        body: BytesMut::new(),
+                    })
+                });
+                Poll::Ready(Ok(Response::from_parts(
+                    head,
+                    CacheBody {
+                        inner: Some(body),
+                        cached: None,
+                        recording,
+                    },
+                )))
+            }
+        }
+    }
+}
+
+/// A response being copied into the cache as its body streams.
+struct Recording {
+    store: Arc<dyn CacheStore>,
+    key: CacheKey,
+    headers: HeaderMap,
+    ttl: Duration,
+    max_size: usize,
+    body: BytesMut,
+}
+
+impl Recording {
+    fn finish(self) {
+        let stored_at = Instant::now();
+        self.store.insert(
+            self.key,
+            CachedResponse {
+                headers: self.headers,
+                body: self.body.freeze(),
+                stored_at,
+                expires_at: stored_at + self.ttl,
+            },
+        );
+    }
+}
+
+pin_project! {
+    /// Response body for [`Cache`]: either a body served from the cache,
+    /// or the inner service's body, copied into the cache if cacheable.
+    pub struct CacheBody<B> {
+        #[pin]
+        inner: Option<B>,
+        cached: Option<Bytes>,
+        recording: Option<Recording>,
+    }
+}
+
+impl<B> Body for CacheBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let Some(inner) = this.inner.as_pin_mut() else {
+            return Poll::Ready(this.cached.take().map(|body| Ok(Frame::data(body))));
+        };
+
+        let result = ready!(inner.poll_frame(cx));
+        match &result {
+            Some(Ok(frame)) => match frame.data_ref() {
+                Some(data) => {
+                    if let Some(recording) = this.recording {
+                        if recording.body.len() + data.len() > recording.max_size {
+                            *this.recording = None;
+                        } else {
+                            recording.body.extend_from_slice(data);
+                        }
+                    }
+                }
+                // Trailers are not cached, and neither are responses with
+                // them.
+                None => *this.recording = None,
+            },
+            Some(Err(_)) => *this.recording = None,
+            None => {
+                if let Some(recording) = this.recording.take() {
+                    recording.finish();
+                }
+            }
+        }
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => inner.is_end_stream(),
+            None => self.cached.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.inner {
+            Some(inner) => inner.size_hint(),
+            None => SizeHint::with_exact(self.cached.as_ref().map_or(0, |body| body.len() as u64)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    trait CachedService:
+        Service<Request<()>, Response = Response<CacheBody<Full<Bytes>>>, Error = Infallible>
+        + Clone
+    {
+    }
+
+    impl<S> CachedService for S where
+        S: Service<Request<()>, Response = Response<CacheBody<Full<Bytes>>>, Error = Infallible>
+            + Clone
+    {
+    }
+
+    /// A service answering with `headers` and a body counting its calls.
+    fn counting(
+        layer: CacheLayer,
+        headers: &'static [(&'static str, &'static str)],
+    ) -> (impl CachedService, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let svc = layer.layer(tower::service_fn(move |_: Request<()>| {
+            let call = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            let mut response = Response::new(Full::new(Bytes::from(format!("call {call}"))));
+            for (name, value) in headers {
+                response
+                    .headers_mut()
+                    .insert(*name, HeaderValue::from_static(value));
+            }
+            std::future::ready(Ok(response))
+        }));
+        (svc, calls)
+    }
+
+    async fn get(svc: &impl CachedService, request: Request<()>) -> (HeaderMap, String) {
+        let response = svc.clone().oneshot(request).await.unwrap();
+        let (head, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (head.headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn request(path: &str) -> Request<()> {
+        Request::get(path).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_repeated_requests_from_the_cache() {
+        let (svc, calls) = counting(CacheLayer::new(), &[("content-type", "application/json")]);
+
+        let (headers, body) = get(&svc, request("/a?x=1")).await;
+        assert_eq!(body, "call 1");
+        assert_eq!(headers[X_CACHE], "MISS");
+
+        let (headers, body) = get(&svc, request("/a?x=1")).await;
+        assert_eq!(body, "call 1");
+        assert_eq!(headers[X_CACHE], "HIT");
+        assert_eq!(headers[header::AGE], "0");
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+
+        let (_, body) = get(&svc, request("/a?x=2")).await;
+        assert_eq!(body, "call 2");
+        let (headers, body) = get(&svc, Request::post("/a?x=1").body(()).unwrap()).await;
+        assert_eq!(body, "call 3");
+        assert!(!headers.contains_key(X_CACHE));
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn keys_on_the_configured_headers() {
+        let layer = CacheLayer::new().key_headers([header::ACCEPT_ENCODING]);
+        let (svc, calls) = counting(layer, &[("vary", "accept-encoding")]);
+        let with_encoding = |encoding| {
+            Request::get("/")
+                .header(header::ACCEPT_ENCODING, encoding)
+                .body(())
+                .unwrap()
+        };
+
+        get(&svc, with_encoding("gzip")).await;
+        get(&svc, with_encoding("br")).await;
+        let (headers, body) = get(&svc, with_encoding("gzip")).await;
+        assert_eq!(headers[X_CACHE], "HIT");
+        assert_eq!(body, "call 1");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn honors_cache_control() {
+        for headers in [
+            &[("cache-control", "no-store")][..],
+            &[("cache-control", "private, max-age=60")],
+            &[("cache-control", "max-age=0")],
+            &[("set-cookie", "session=1")],
+            &[("vary", "cookie")],
+        ] {
+            let (svc, calls) = counting(CacheLayer::new(), headers);
+            get(&svc, request("/")).await;
+            get(&svc, request("/")).await;
+            assert_eq!(calls.load(Ordering::Relaxed), 2, "{headers:?}");
+        }
+
+        let layer = CacheLayer::new().ttl(Duration::from_millis(1));
+        let (svc, calls) = counting(layer, &[("cache-control", "public, max-age=60")]);
+        get(&svc, request("/")).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        get(&svc, request("/")).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn expires_entries_after_their_ttl() {
+        let layer = CacheLayer::new().ttl(Duration::from_millis(10));
+        let (svc, calls) = counting(layer, &[]);
+        get(&svc, request("/")).await;
+        get(&svc, request("/")).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let (headers, body) = get(&svc, request("/")).await;
+        assert_eq!(headers[X_CACHE], "MISS");
+        assert_eq!(body, "call 2");
+    }
+
+    #[tokio::test]
+    async fn skips_large_and_authorized_responses() {
+        let (svc, calls) = counting(CacheLayer::new().max_entry_size(4), &[]);
+        get(&svc, request("/")).await;
+        get(&svc, request("/")).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        let (svc, calls) = counting(CacheLayer::new(), &[]);
+        let authorized = || {
+            Request::get("/")
+                .header(header::AUTHORIZATION, "Bearer token")
+                .body(())
+                .unwrap()
+        };
+        get(&svc, authorized()).await;
+        get(&svc, authorized()).await;
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn memory_store_evicts_the_least_recently_used() {
+        let entry = |body: &'static str| {
+            let stored_at = Instant::now();
+            CachedResponse {
+                headers: HeaderMap::new(),
+                body: Bytes::from_static(body.as_bytes()),
+                stored_at,
+                expires_at: stored_at + DEFAULT_TTL,
+            }
+        };
+        let key = |uri: &str| CacheKey {
+            method: Method::GET,
+            uri: uri.to_owned(),
+            headers: Vec::new(),
+        };
+
+        let store = MemoryStore::new(10);
+        store.insert(key("a"), entry("aaaa"));
+        store.insert(key("b"), entry("bbbb"));
+        assert!(store.get(&key("a")).is_some());
+        store.insert(key("c"), entry("cccc"));
+        assert!(store.get(&key("a")).is_some());
+        assert!(store.get(&key("b")).is_none());
+        assert!(store.get(&key("c")).is_some());
+
+        store.insert(key("d"), entry("far too large"));
+        assert!(store.get(&key("d")).is_none());
+        assert_eq!(store.lru.lock().unwrap().size, 8);
+    }
+}
@@ -1,8 +1,15 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use super::Classification;
+use super::ClassifiedError;
+use super::Classifier;
 use super::RequestHandler;
 use super::ResponseHandler;
+use super::ServerErrorsAsFailures;
+use crate::body::Either;
+use crate::body::Empty;
+use http::HeaderMap;
 use http_body::Body;
 use pin_project_lite::pin_project;
 use std::fmt;
@@ -10,6 +17,7 @@ use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
 use std::task::ready;
+use std::time::Instant;
 
 pin_project! {
     /// Request body wrapper for [`Callback`].
@@ -92,19 +100,67 @@ pin_project! {
     /// `on_body_chunk`, `on_end_of_stream`, or `on_body_error`.
     ///
     /// [`Callback`]: super::Callback
-    pub struct ResponseBody<B, H> {
+    pub struct ResponseBody<B, H, C = ServerErrorsAsFailures>
+    where
+        B: Body,
+        H: ResponseHandler,
+    {
+        // Empty for responses from `MakeCallbackHandler::intercept`.
         #[pin]
-        pub(crate) inner: B,
+        pub(crate) inner: Either<B, Empty<B::Data, B::Error>>,
         pub(crate) handler: H,
+        // Set once the stream ended or failed; dropping the body before
+        // then cancels the request.
         pub(crate) ended: bool,
+        // Set once the first data frame was polled.
+        pub(crate) first_chunk: bool,
+        // Set for gRPC responses whose status arrives in the trailers.
+        pub(crate) classifier: Option<C>,
+        pub(crate) start: Instant,
+    }
+
+    impl<B, H, C> PinnedDrop for ResponseBody<B, H, C>
+    where
+        B: Body,
+        H: ResponseHandler,
+    {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if !*this.ended && !this.inner.is_end_stream() {
+                this.handler.on_cancel(this.start.elapsed());
+            }
+        }
     }
 }
 
-impl<B, H> Body for ResponseBody<B, H>
+impl<B, H, C> ResponseBody<B, H, C>
+where
+    B: Body,
+    H: ResponseHandler,
+    C: Classifier,
+{
+    fn end_of_stream(
+        handler: &mut H,
+        classifier: Option<&C>,
+        start: Instant,
+        trailers: Option<&HeaderMap>,
+    ) {
+        handler.on_end_of_stream(trailers, start.elapsed());
+        if let Some(classifier) = classifier
+            && let Some(classification) = Classification::from_trailers(trailers)
+            && classifier.is_failure(&classification)
+        {
+            handler.on_failure(&classification);
+        }
+    }
+}
+
+impl<B, H, C> Body for ResponseBody<B, H, C>
 where
     B: Body,
     B::Error: fmt::Display + 'static,
     H: ResponseHandler,
+    C: Classifier,
 {
     type Data = B::Data;
     type Error = B::Error;
@@ -119,11 +175,20 @@ where
         match result {
             Some(Ok(frame)) => {
                 if let Some(chunk) = frame.data_ref() {
+                    if !*this.first_chunk {
+                        *this.first_chunk = true;
+                        this.handler.on_first_body_chunk(this.start.elapsed());
+                    }
                     this.handler.on_body_chunk(chunk);
                 } else if let Some(trailers) = frame.trailers_ref()
                     && !*this.ended
                 {
-                    this.handler.on_end_of_stream(Some(trailers));
+                    Self::end_of_stream(
+                        this.handler,
+                        this.classifier.as_ref(),
+                        *this.start,
+                        Some(trailers),
+                    );
                     *this.ended = true;
                 }
 
@@ -131,12 +196,14 @@ where
             }
             Some(Err(err)) => {
                 this.handler.on_body_error(&err);
+                this.handler.on_error(&ClassifiedError::new(&err));
+                *this.ended = true;
 
                 Poll::Ready(Some(Err(err)))
             }
             None => {
                 if !*this.ended {
-                    this.handler.on_end_of_stream(None);
+                    Self::end_of_stream(this.handler, this.classifier.as_ref(), *this.start, None);
                     *this.ended = true;
                 }
 
@@ -0,0 +1,509 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::HeaderMap;
+use http::StatusCode;
+use http::response;
+use std::any::Any;
+use std::error::Error;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+const GRPC_STATUS_HEADER: &str = "grpc-status";
+const GRPC_MESSAGE_HEADER: &str = "grpc-message";
+
+/// The outcome of a response, as passed to [`ResponseHandler::on_failure`].
+///
+/// Plain HTTP responses are classified by their status code. gRPC
+/// responses (those with an `application/grpc` content type) are
+/// classified by the `grpc-status` trailer, or by the `grpc-status`
+/// header of a trailers-only response.
+///
+/// [`ResponseHandler::on_failure`]: super::ResponseHandler::on_failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Classification {
+    /// The HTTP status of a non-gRPC response, or of a gRPC response
+    /// whose HTTP status is not `200 OK`.
+    Http(StatusCode),
+    /// The `grpc-status` code of a gRPC response, with its percent-decoded
+    /// `grpc-message`, if any.
+    Grpc { code: i32, message: Option<String> },
+}
+
+impl Classification {
+    /// Whether this outcome counts as a failure: a `5xx` HTTP status or a
+    /// gRPC status other than `OK`.
+    pub fn is_failure(&self) -> bool {
+        match self {
+            Classification::Http(status) => status.is_server_error(),
+            Classification::Grpc { code, .. } => *code != 0,
+        }
+    }
+
+    /// Classifies a response from its head.
+    ///
+    /// Returns `None` for a gRPC response whose status will only be known
+    /// once its trailers arrive.
+    pub(crate) fn from_response(response: &response::Parts) -> Option<Self> {
+        if !is_grpc(&response.headers) || response.status != StatusCode::OK {
+            return Some(Classification::Http(response.status));
+        }
+        Self::from_grpc_headers(&response.headers)
+    }
+
+    /// Classifies a gRPC response from its trailers. Returns `None` if the
+    /// stream ended without a `grpc-status`.
+    pub(crate) fn from_trailers(trailers: Option<&HeaderMap>) -> Option<Self> {
+        trailers.and_then(Self::from_grpc_headers)
+    }
+
+    fn from_grpc_headers(headers: &HeaderMap) -> Option<Self> {
+        let code = headers
+            .get(GRPC_STATUS_HEADER)?
+            .to_str()
+            .ok()
+            .and_then(|code| code.parse().ok())
+            // An unparseable status is reported by clients as UNKNOWN.
+            .unwrap_or(2);
+        let message = headers
+            .get(GRPC_MESSAGE_HEADER)
+            .map(|message| percent_decode(message.as_bytes()));
+
+        Some(Classification::Grpc { code, message })
+    }
+}
+
+/// Decides which [`Classification`]s count as failures, to be reported to
+/// [`ResponseHandler::on_failure`].
+///
+/// Set on a [`CallbackLayer`] with [`CallbackLayer::classifier`]; the
+/// default is [`ServerErrorsAsFailures`]. Closures taking a
+/// `&Classification` and returning whether it is a failure are
+/// classifiers too.
+///
+/// [`ResponseHandler::on_failure`]: super::ResponseHandler::on_failure
+/// [`CallbackLayer`]: super::CallbackLayer
+/// [`CallbackLayer::classifier`]: super::CallbackLayer::classifier
+pub trait Classifier {
+    /// Whether `classification` counts as a failure.
+    fn is_failure(&self, classification: &Classification) -> bool;
+}
+
+impl<F> Classifier for F
+where
+    F: Fn(&Classification) -> bool,
+{
+    fn is_failure(&self, classification: &Classification) -> bool {
+        self(classification)
+    }
+}
+
+/// Classifies `5xx` statuses and gRPC statuses other than `OK` as
+/// failures; see [`Classification::is_failure`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServerErrorsAsFailures;
+
+impl Classifier for ServerErrorsAsFailures {
+    fn is_failure(&self, classification: &Classification) -> bool {
+        classification.is_failure()
+    }
+}
+
+/// Classifies HTTP statuses within a range as failures, e.g. `400..=599`
+/// to count client errors too. gRPC statuses other than `OK` are failures.
+#[derive(Debug, Clone)]
+pub struct StatusInRangeAsFailures {
+    range: RangeInclusive<u16>,
+}
+
+impl StatusInRangeAsFailures {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self { range }
+    }
+
+    /// Classifies every `4xx` and `5xx` status as a failure.
+    pub fn client_and_server_errors() -> Self {
+        Self::new(400..=599)
+    }
+}
+
+impl Classifier for StatusInRangeAsFailures {
+    fn is_failure(&self, classification: &Classification) -> bool {
+        match classification {
+            Classification::Http(status) => self.range.contains(&status.as_u16()),
+            Classification::Grpc { .. } => classification.is_failure(),
+        }
+    }
+}
+
+/// Classifies gRPC statuses other than `OK` as failures, except those
+/// marked as successes, e.g. `NOT_FOUND` (5) for lookups that are expected
+/// to miss. `5xx` HTTP statuses are failures.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcErrorsAsFailures {
+    successes: Vec<i32>,
+}
+
+impl GrpcErrorsAsFailures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts gRPC status `code` as a success.
+    pub fn with_success(mut self, code: i32) -> Self {
+        self.successes.push(code);
+        self
+    }
+}
+
+impl Classifier for GrpcErrorsAsFailures {
+    fn is_failure(&self, classification: &Classification) -> bool {
+        match classification {
+            Classification::Grpc { code, .. } => {
+                classification.is_failure() && !self.successes.contains(code)
+            }
+            Classification::Http(_) => classification.is_failure(),
+        }
+    }
+}
+
+/// What kind of error was reported to [`ResponseHandler::on_error`], as
+/// far as can be told from its type.
+///
+/// [`ResponseHandler::on_error`]: super::ResponseHandler::on_error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An operation timed out.
+    Timeout,
+    /// The request was cancelled, e.g. by the client resetting its HTTP/2
+    /// stream with `CANCEL`.
+    Cancelled,
+    /// The HTTP/2 stream was reset, or the connection torn down, for
+    /// another reason.
+    Reset,
+    /// The connection was closed or broke.
+    Connection,
+    /// A body went past its length limit.
+    BodyTooLarge,
+    /// Any other error.
+    Other,
+}
+
+impl ErrorKind {
+    /// The gRPC status code clients see for an error of this kind:
+    /// `DEADLINE_EXCEEDED` (4) for timeouts, `CANCELLED` (1),
+    /// `UNAVAILABLE` (14) for resets and broken connections,
+    /// `RESOURCE_EXHAUSTED` (8) for bodies too large and `UNKNOWN` (2)
+    /// otherwise.
+    pub fn grpc_code(self) -> i32 {
+        match self {
+            ErrorKind::Timeout => 4,
+            ErrorKind::Cancelled => 1,
+            ErrorKind::Reset | ErrorKind::Connection => 14,
+            ErrorKind::BodyTooLarge => 8,
+            ErrorKind::Other => 2,
+        }
+    }
+}
+
+/// Tells the [`ErrorKind`] of an error.
+///
+/// Implemented for every error the callback handlers observe: the error
+/// is downcast to the types that say what went wrong (`hyper`, `h2` and
+/// I/O errors, `tokio` timeouts and body length limits, boxed or not),
+/// and its [`source`](Error::source) chain walked until one does.
+pub trait ClassifyError {
+    /// The kind of this error, [`ErrorKind::Other`] if it cannot be told.
+    fn error_kind(&self) -> ErrorKind;
+}
+
+impl<E> ClassifyError for E
+where
+    E: Display + 'static,
+{
+    fn error_kind(&self) -> ErrorKind {
+        let error = self as &dyn Any;
+        let error: &(dyn Error + 'static) =
+            if let Some(error) = error.downcast_ref::<crate::BoxError>() {
+                &**error
+            } else if let Some(error) = error.downcast_ref::<Box<dyn Error + Send>>() {
+                &**error
+            } else if let Some(error) = error.downcast_ref::<Box<dyn Error>>() {
+                &**error
+            } else if let Some(error) = error.downcast_ref::<hyper::Error>() {
+                error
+            } else if let Some(error) = error.downcast_ref::<h2::Error>() {
+                error
+            } else if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                error
+            } else if let Some(error) = error.downcast_ref::<tokio::time::error::Elapsed>() {
+                error
+            } else if let Some(error) = error.downcast_ref::<crate::body::LengthLimitExceeded>() {
+                error
+            } else if let Some(error) = error.downcast_ref::<http_body_util::LengthLimitError>() {
+                error
+            } else {
+                return ErrorKind::Other;
+            };
+
+        let mut next = Some(error);
+        while let Some(error) = next {
+            if let Some(kind) = kind_of(error) {
+                return kind;
+            }
+            next = error.source();
+        }
+        ErrorKind::Other
+    }
+}
+
+/// The kind of `error` itself, if its type tells; its sources are not
+/// looked at.
+fn kind_of(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    if let Some(error) = error.downcast_ref::<hyper::Error>() {
+        if error.is_timeout() {
+            Some(ErrorKind::Timeout)
+        } else if error.is_canceled() {
+            Some(ErrorKind::Cancelled)
+        } else if error.is_incomplete_message() || error.is_closed() {
+            Some(ErrorKind::Connection)
+        } else {
+            None
+        }
+    } else if let Some(error) = error.downcast_ref::<h2::Error>() {
+        // I/O errors carried by an `h2::Error` are its source.
+        let reason = error.reason()?;
+        if reason == h2::Reason::CANCEL {
+            Some(ErrorKind::Cancelled)
+        } else {
+            Some(ErrorKind::Reset)
+        }
+    } else if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        use std::io::ErrorKind as Io;
+        match error.kind() {
+            Io::TimedOut => Some(ErrorKind::Timeout),
+            Io::ConnectionReset
+            | Io::ConnectionAborted
+            | Io::BrokenPipe
+            | Io::NotConnected
+            | Io::UnexpectedEof => Some(ErrorKind::Connection),
+            // Errors wrapped in an I/O error are reachable through
+            // `get_ref`, not always through `source`.
+            _ => error
+                .get_ref()
+                .and_then(|inner| kind_of(inner as &(dyn Error + 'static))),
+        }
+    } else if error.is::<tokio::time::error::Elapsed>() {
+        Some(ErrorKind::Timeout)
+    } else if error.is::<crate::body::LengthLimitExceeded>()
+        || error.is::<http_body_util::LengthLimitError>()
+    {
+        Some(ErrorKind::BodyTooLarge)
+    } else {
+        None
+    }
+}
+
+/// An error passed to [`ResponseHandler::on_error`]: its message and what
+/// kind of error it is.
+///
+/// [`ResponseHandler::on_error`]: super::ResponseHandler::on_error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassifiedError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl ClassifiedError {
+    /// Classifies `error` with [`ClassifyError::error_kind`].
+    pub fn new<E>(error: &E) -> Self
+    where
+        E: Display + 'static,
+    {
+        Self {
+            kind: error.error_kind(),
+            message: error.to_string(),
+        }
+    }
+
+    /// The kind of error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The gRPC status code for the error; see [`ErrorKind::grpc_code`].
+    pub fn grpc_code(&self) -> i32 {
+        self.kind.grpc_code()
+    }
+
+    /// The error's message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+pub(crate) fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(http::header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"))
+}
+
+fn percent_decode(input: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%'
+            && let Some(byte) = input
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(input[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Response;
+
+    fn parts(status: StatusCode, headers: &[(&'static str, &'static str)]) -> response::Parts {
+        let mut builder = Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn classifies_http_responses_by_status() {
+        let ok = Classification::from_response(&parts(StatusCode::OK, &[])).unwrap();
+        assert_eq!(ok, Classification::Http(StatusCode::OK));
+        assert!(!ok.is_failure());
+
+        let not_found = Classification::from_response(&parts(StatusCode::NOT_FOUND, &[])).unwrap();
+        assert!(!not_found.is_failure());
+
+        let unavailable =
+            Classification::from_response(&parts(StatusCode::SERVICE_UNAVAILABLE, &[])).unwrap();
+        assert!(unavailable.is_failure());
+    }
+
+    #[test]
+    fn grpc_responses_wait_for_trailers() {
+        let head = parts(
+            StatusCode::OK,
+            &[("content-type", "application/grpc+proto")],
+        );
+        assert_eq!(Classification::from_response(&head), None);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(GRPC_STATUS_HEADER, "0".parse().unwrap());
+        let ok = Classification::from_trailers(Some(&trailers)).unwrap();
+        assert!(!ok.is_failure());
+
+        trailers.insert(GRPC_STATUS_HEADER, "14".parse().unwrap());
+        trailers.insert(GRPC_MESSAGE_HEADER, "try%20again".parse().unwrap());
+        assert_eq!(
+            Classification::from_trailers(Some(&trailers)).unwrap(),
+            Classification::Grpc {
+                code: 14,
+                message: Some("try again".to_owned()),
+            }
+        );
+
+        assert_eq!(Classification::from_trailers(None), None);
+    }
+
+    #[test]
+    fn grpc_trailers_only_responses() {
+        let head = parts(
+            StatusCode::OK,
+            &[("content-type", "application/grpc"), ("grpc-status", "5")],
+        );
+        let classification = Classification::from_response(&head).unwrap();
+        assert_eq!(
+            classification,
+            Classification::Grpc {
+                code: 5,
+                message: None,
+            }
+        );
+        assert!(classification.is_failure());
+    }
+
+    #[test]
+    fn stock_classifiers() {
+        let not_found = Classification::Http(StatusCode::NOT_FOUND);
+        let unavailable = Classification::Http(StatusCode::SERVICE_UNAVAILABLE);
+        let grpc = |code| Classification::Grpc {
+            code,
+            message: None,
+        };
+
+        let classifier = StatusInRangeAsFailures::client_and_server_errors();
+        assert!(classifier.is_failure(&not_found));
+        assert!(classifier.is_failure(&unavailable));
+        assert!(!classifier.is_failure(&Classification::Http(StatusCode::OK)));
+        assert!(classifier.is_failure(&grpc(13)));
+
+        let classifier = GrpcErrorsAsFailures::new().with_success(5);
+        assert!(!classifier.is_failure(&grpc(0)));
+        assert!(!classifier.is_failure(&grpc(5)));
+        assert!(classifier.is_failure(&grpc(13)));
+        assert!(!classifier.is_failure(&not_found));
+        assert!(classifier.is_failure(&unavailable));
+
+        let classifier = |classification: &Classification| {
+            matches!(classification, Classification::Grpc { code: 14, .. })
+        };
+        assert!(classifier.is_failure(&grpc(14)));
+        assert!(!classifier.is_failure(&unavailable));
+    }
+
+    #[tokio::test]
+    async fn classifies_errors_by_type() {
+        let io = |kind| std::io::Error::from(kind);
+        assert_eq!(
+            io(std::io::ErrorKind::TimedOut).error_kind(),
+            ErrorKind::Timeout
+        );
+        assert_eq!(
+            io(std::io::ErrorKind::BrokenPipe).error_kind(),
+            ErrorKind::Connection
+        );
+        assert_eq!(
+            h2::Error::from(h2::Reason::CANCEL).error_kind(),
+            ErrorKind::Cancelled
+        );
+        assert_eq!(
+            h2::Error::from(h2::Reason::REFUSED_STREAM).error_kind(),
+            ErrorKind::Reset
+        );
+        assert_eq!("oops".error_kind(), ErrorKind::Other);
+
+        // Boxed errors, and the errors they wrap, are looked into.
+        let body = crate::body::full("hello");
+        let error = crate::body::collect_to_bytes(body, 2).await.unwrap_err();
+        assert_eq!(error.error_kind(), ErrorKind::BodyTooLarge);
+        let error: crate::BoxError = Box::new(std::io::Error::other(error));
+        assert_eq!(error.error_kind(), ErrorKind::BodyTooLarge);
+
+        let classified = ClassifiedError::new(&h2::Error::from(h2::Reason::CANCEL));
+        assert_eq!(classified.kind(), ErrorKind::Cancelled);
+        assert_eq!(classified.grpc_code(), 1);
+        assert!(!classified.message().is_empty());
+    }
+
+    #[test]
+    fn decodes_grpc_message() {
+        assert_eq!(percent_decode(b"a%20b%zz%2"), "a b%zz%2");
+    }
+}
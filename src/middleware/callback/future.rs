@@ -1,55 +1,103 @@
 // Copyright (c) Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use super::Classification;
+use super::ClassifiedError;
+use super::Classifier;
 use super::ResponseBody;
 use super::ResponseHandler;
+use super::ServerErrorsAsFailures;
+use crate::body::Either;
 use http::Response;
 use pin_project_lite::pin_project;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Instant;
 
 pin_project! {
     /// Response future for [`Callback`].
     ///
     /// [`Callback`]: super::Callback
-    pub struct ResponseFuture<F, ResponseHandler> {
+    pub struct ResponseFuture<F, H, C = ServerErrorsAsFailures>
+    where
+        H: ResponseHandler,
+    {
+        // `None` if the request was intercepted.
         #[pin]
-        pub(crate) inner: F,
-        pub(crate) handler: Option<ResponseHandler>,
+        pub(crate) inner: Option<F>,
+        pub(crate) intercepted: Option<Response<()>>,
+        pub(crate) handler: Option<H>,
+        pub(crate) classifier: Option<C>,
+        pub(crate) start: Instant,
+    }
+
+    impl<F, H, C> PinnedDrop for ResponseFuture<F, H, C>
+    where
+        H: ResponseHandler,
+    {
+        fn drop(this: Pin<&mut Self>) {
+            // Dropped before the inner service produced a response.
+            let this = this.project();
+            if let Some(mut handler) = this.handler.take() {
+                handler.on_cancel(this.start.elapsed());
+            }
+        }
     }
 }
 
-impl<Fut, B, E, ResponseHandlerT> Future for ResponseFuture<Fut, ResponseHandlerT>
+impl<Fut, B, E, ResponseHandlerT, C> Future for ResponseFuture<Fut, ResponseHandlerT, C>
 where
     Fut: Future<Output = Result<Response<B>, E>>,
     B: http_body::Body<Error: std::fmt::Display + 'static>,
     E: std::fmt::Display + 'static,
     ResponseHandlerT: ResponseHandler,
+    C: Classifier,
 {
-    type Output = Result<Response<ResponseBody<B, ResponseHandlerT>>, E>;
+    type Output = Result<Response<ResponseBody<B, ResponseHandlerT, C>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let result = futures_core::ready!(this.inner.poll(cx));
+        let result = match this.inner.as_pin_mut() {
+            Some(inner) => {
+                futures_core::ready!(inner.poll(cx)).map(|response| response.map(Either::left))
+            }
+            None => {
+                let response = this.intercepted.take().expect("polled after completion");
+                Ok(response.map(|()| Either::default()))
+            }
+        };
         let mut handler = this.handler.take().unwrap();
+        let classifier = this.classifier.take().unwrap();
 
         let result = match result {
             Ok(response) => {
                 let (head, body) = response.into_parts();
-                handler.on_response(&head);
+                handler.on_response(&head, this.start.elapsed());
+                let classification = Classification::from_response(&head);
+                if let Some(classification) = &classification
+                    && classifier.is_failure(classification)
+                {
+                    handler.on_failure(classification);
+                }
                 Ok(Response::from_parts(
                     head,
                     ResponseBody {
                         inner: body,
                         handler,
                         ended: false,
+                        first_chunk: false,
+                        // Set for gRPC responses whose status arrives in
+                        // the trailers.
+                        classifier: classification.is_none().then_some(classifier),
+                        start: *this.start,
                     },
                 ))
             }
             Err(error) => {
-                handler.on_service_error(&error);
+                handler.on_service_error(&error, this.start.elapsed());
+                handler.on_error(&ClassifiedError::new(&error));
                 Err(error)
             }
         };
@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::Callback;
+use super::Classifier;
 use super::MakeCallbackHandler;
+use super::ServerErrorsAsFailures;
 use tower::Layer;
 
 /// [`Layer`] that adds callbacks to a [`Service`].
@@ -12,8 +14,9 @@ use tower::Layer;
 /// [`Layer`]: tower::layer::Layer
 /// [`Service`]: tower::Service
 #[derive(Debug, Copy, Clone)]
-pub struct CallbackLayer<M> {
+pub struct CallbackLayer<M, C = ServerErrorsAsFailures> {
     pub(crate) make_handler: M,
+    pub(crate) classifier: C,
 }
 
 impl<M> CallbackLayer<M> {
@@ -22,20 +25,36 @@ impl<M> CallbackLayer<M> {
     where
         M: MakeCallbackHandler,
     {
-        Self { make_handler }
+        Self {
+            make_handler,
+            classifier: ServerErrorsAsFailures,
+        }
+    }
+}
+
+impl<M, C> CallbackLayer<M, C> {
+    /// Use `classifier` to decide which responses are reported to
+    /// `ResponseHandler::on_failure`. Defaults to [`ServerErrorsAsFailures`].
+    pub fn classifier<T: Classifier>(self, classifier: T) -> CallbackLayer<M, T> {
+        CallbackLayer {
+            make_handler: self.make_handler,
+            classifier,
+        }
     }
 }
 
-impl<S, M> Layer<S> for CallbackLayer<M>
+impl<S, M, C> Layer<S> for CallbackLayer<M, C>
 where
     M: Clone,
+    C: Clone,
 {
-    type Service = Callback<S, M>;
+    type Service = Callback<S, M, C>;
 
     fn layer(&self, inner: S) -> Self::Service {
         Callback {
             inner,
             make_callback_handler: self.make_handler.clone(),
+            classifier: self.classifier.clone(),
         }
     }
 }
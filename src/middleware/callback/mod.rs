@@ -16,11 +16,52 @@
 //! Either side can be a no-op by using the unit type `()`, which has a
 //! blanket [`RequestHandler`] impl provided by this crate.
 //!
+//! The middleware records when each request enters the [`Callback`]
+//! service, and passes the time elapsed since then to
+//! [`ResponseHandler::on_response`] (when the response head was produced),
+//! [`ResponseHandler::on_first_body_chunk`] (when the first data frame was
+//! polled, the time to first byte of a streaming response),
+//! [`ResponseHandler::on_service_error`] and
+//! [`ResponseHandler::on_end_of_stream`] (the total duration). Requests served by this
+//! crate's server also carry a [`RequestTiming`] extension, which
+//! [`MakeCallbackHandler::make_handler`] can read from the request parts to
+//! separate the time spent before the request reached the service from the
+//! handler latency.
+//!
+//! Responses are classified as they complete: by their status, or for gRPC
+//! by the `grpc-status` in the trailers (or in the headers of a
+//! trailers-only response). Those the layer's [`Classifier`] counts as
+//! failures are reported to [`ResponseHandler::on_failure`] as a typed
+//! [`Classification`]. By default ([`ServerErrorsAsFailures`]) these are
+//! `5xx` statuses and non-`OK` gRPC statuses; set
+//! [`CallbackLayer::classifier`] to, say, count client errors too with
+//! [`StatusInRangeAsFailures`], or expected gRPC codes as successes with
+//! [`GrpcErrorsAsFailures`].
+//!
+//! Service and response body errors are also reported to
+//! [`ResponseHandler::on_error`] as a [`ClassifiedError`]: the message
+//! along with an [`ErrorKind`], such as a timeout or an HTTP/2 stream
+//! reset, told by downcasting the error, and the matching gRPC code.
+//!
+//! Requests abandoned by the client before they complete are reported to
+//! [`ResponseHandler::on_cancel`]: the inner service's future (and with it
+//! the handler's work) is dropped as soon as the server notices the client
+//! went away.
+//!
+//! [`MakeCallbackHandler::intercept`] can also answer a request on its own,
+//! e.g. rejecting it, without calling the inner service; the response
+//! handler still observes that response.
+//!
+//! Handlers whose work is asynchronous can implement
+//! [`AsyncResponseHandler`] instead and be wrapped in a [`SpawnHandler`],
+//! which spawns the returned futures in event order.
+//!
 //! # Example
 //!
 //! ```
 //! use http::request;
 //! use http::response;
+//! use std::time::Duration;
 //! use sui_http::middleware::callback::CallbackLayer;
 //! use sui_http::middleware::callback::MakeCallbackHandler;
 //! use sui_http::middleware::callback::RequestHandler;
@@ -39,8 +80,8 @@
 //! }
 //!
 //! impl ResponseHandler for ByteCounter {
-//!     fn on_response(&mut self, _parts: &response::Parts) {}
-//!     fn on_service_error<E: std::fmt::Display + 'static>(&mut self, _error: &E) {}
+//!     fn on_response(&mut self, _parts: &response::Parts, _latency: Duration) {}
+//!     fn on_service_error<E: std::fmt::Display + 'static>(&mut self, _error: &E, _latency: Duration) {}
 //!     fn on_body_chunk<B: bytes::Buf>(&mut self, chunk: &B) {
 //!         self.bytes += chunk.remaining();
 //!     }
@@ -83,21 +124,37 @@
 //! ```
 //!
 //! [`Callback`]: self::Callback
+//! [`RequestTiming`]: crate::RequestTiming
 
 use http::HeaderMap;
+use http::Response;
 use http::request;
 use http::response;
+use std::time::Duration;
 
 mod body;
+mod classify;
 mod future;
 mod layer;
 mod service;
+mod spawn;
 
 pub use self::body::RequestBody;
 pub use self::body::ResponseBody;
+pub use self::classify::Classification;
+pub use self::classify::ClassifiedError;
+pub use self::classify::Classifier;
+pub use self::classify::ClassifyError;
+pub use self::classify::ErrorKind;
+pub use self::classify::GrpcErrorsAsFailures;
+pub use self::classify::ServerErrorsAsFailures;
+pub use self::classify::StatusInRangeAsFailures;
+pub(crate) use self::classify::is_grpc;
 pub use self::future::ResponseFuture;
 pub use self::layer::CallbackLayer;
 pub use self::service::Callback;
+pub use self::spawn::AsyncResponseHandler;
+pub use self::spawn::SpawnHandler;
 
 /// Factory for per-request callback handler pairs.
 ///
@@ -117,6 +174,18 @@ pub trait MakeCallbackHandler {
         &self,
         request: &request::Parts,
     ) -> (Self::RequestHandler, Self::ResponseHandler);
+
+    /// Answer the request right away instead of calling the inner service,
+    /// e.g. to reject tenants over their quota or to turn traffic away
+    /// during maintenance.
+    ///
+    /// Called after [`Self::make_handler`]. If it returns a response, that
+    /// response is served with an empty body, and the request's
+    /// [`ResponseHandler`] observes it like any other. Defaults to calling
+    /// the inner service for every request.
+    fn intercept(&self, _request: &request::Parts) -> Option<Response<()>> {
+        None
+    }
 }
 
 /// Observes the request body as it is polled by the inner service.
@@ -160,15 +229,30 @@ impl RequestHandler for () {}
 /// Body-level methods default to no-ops.
 pub trait ResponseHandler {
     /// Called exactly once when the inner service produces a response.
-    fn on_response(&mut self, response: &response::Parts);
+    ///
+    /// `latency` is the time since the request reached the middleware.
+    fn on_response(&mut self, response: &response::Parts, latency: Duration);
 
     /// Called when the inner service's future resolves to `Err` (no
     /// response is produced). Response body errors are reported
     /// separately through [`Self::on_body_error`].
-    fn on_service_error<E>(&mut self, error: &E)
+    ///
+    /// `latency` is the time since the request reached the middleware.
+    fn on_service_error<E>(&mut self, error: &E, latency: Duration)
     where
         E: std::fmt::Display + 'static;
 
+    /// Called at most once, when the response body yields its first data
+    /// frame and before that frame is passed to `on_body_chunk`. Not
+    /// called for responses without a body.
+    ///
+    /// `latency` is the time since the request reached the middleware:
+    /// the time to first byte, which for streaming responses says more
+    /// than the latency of the response head.
+    fn on_first_body_chunk(&mut self, _latency: Duration) {
+        // do nothing
+    }
+
     /// Called once per data frame yielded by the response body.
     fn on_body_chunk<B>(&mut self, _chunk: &B)
     where
@@ -178,7 +262,10 @@ pub trait ResponseHandler {
     }
 
     /// Called at most once when the response body stream ends.
-    fn on_end_of_stream(&mut self, _trailers: Option<&HeaderMap>) {
+    ///
+    /// `latency` is the time since the request reached the middleware,
+    /// i.e. the total time taken to serve it.
+    fn on_end_of_stream(&mut self, _trailers: Option<&HeaderMap>, _latency: Duration) {
         // do nothing
     }
 
@@ -189,6 +276,33 @@ pub trait ResponseHandler {
     {
         // do nothing
     }
+
+    /// Called after [`Self::on_service_error`] and the response's
+    /// [`Self::on_body_error`] with the error classified, e.g. to count
+    /// errors by kind or report them with a gRPC code.
+    fn on_error(&mut self, _error: &ClassifiedError) {
+        // do nothing
+    }
+
+    /// Called at most once when the response is classified as a failure.
+    ///
+    /// For plain HTTP responses this follows `on_response`; for gRPC
+    /// responses whose status is carried in the trailers it follows
+    /// `on_end_of_stream`.
+    fn on_failure(&mut self, _classification: &Classification) {
+        // do nothing
+    }
+
+    /// Called at most once when the request is abandoned before it
+    /// completes: the response future is dropped before the inner service
+    /// produced a response, or the response body is dropped before its end
+    /// of stream. The server drops both as soon as the client resets the
+    /// HTTP/2 stream or the connection goes away.
+    ///
+    /// `latency` is the time since the request reached the middleware.
+    fn on_cancel(&mut self, _latency: Duration) {
+        // do nothing
+    }
 }
 
 #[cfg(test)]
@@ -196,6 +310,7 @@ mod tests {
     use super::*;
     use bytes::Buf;
     use bytes::Bytes;
+    use futures::StreamExt;
     use futures::stream;
     use http::Request;
     use http::Response;
@@ -222,6 +337,10 @@ mod tests {
         response_end_trailers: Vec<Option<HeaderMap>>,
         response_body_errors: Vec<String>,
         response_service_errors: Vec<String>,
+        response_errors: Vec<ClassifiedError>,
+        response_failures: Vec<Classification>,
+        response_cancels: u32,
+        latencies: Vec<Duration>,
     }
 
     #[derive(Clone, Default)]
@@ -255,16 +374,25 @@ mod tests {
     }
 
     impl ResponseHandler for RespH {
-        fn on_response(&mut self, _parts: &response::Parts) {
-            self.0.lock().unwrap().response_seen += 1;
+        fn on_response(&mut self, _parts: &response::Parts, latency: Duration) {
+            let mut events = self.0.lock().unwrap();
+            events.response_seen += 1;
+            events.latencies.push(latency);
         }
-        fn on_service_error<E: std::fmt::Display + 'static>(&mut self, error: &E) {
+        fn on_service_error<E: std::fmt::Display + 'static>(
+            &mut self,
+            error: &E,
+            _latency: Duration,
+        ) {
             self.0
                 .lock()
                 .unwrap()
                 .response_service_errors
                 .push(error.to_string());
         }
+        fn on_first_body_chunk(&mut self, latency: Duration) {
+            self.0.lock().unwrap().latencies.push(latency);
+        }
         fn on_body_chunk<B: Buf>(&mut self, chunk: &B) {
             self.0
                 .lock()
@@ -272,12 +400,10 @@ mod tests {
                 .response_chunks
                 .push(chunk.chunk().to_vec());
         }
-        fn on_end_of_stream(&mut self, trailers: Option<&HeaderMap>) {
-            self.0
-                .lock()
-                .unwrap()
-                .response_end_trailers
-                .push(trailers.cloned());
+        fn on_end_of_stream(&mut self, trailers: Option<&HeaderMap>, latency: Duration) {
+            let mut events = self.0.lock().unwrap();
+            events.response_end_trailers.push(trailers.cloned());
+            events.latencies.push(latency);
         }
         fn on_body_error<E: std::fmt::Display + 'static>(&mut self, error: &E) {
             self.0
@@ -286,6 +412,19 @@ mod tests {
                 .response_body_errors
                 .push(error.to_string());
         }
+        fn on_error(&mut self, error: &ClassifiedError) {
+            self.0.lock().unwrap().response_errors.push(error.clone());
+        }
+        fn on_failure(&mut self, classification: &Classification) {
+            self.0
+                .lock()
+                .unwrap()
+                .response_failures
+                .push(classification.clone());
+        }
+        fn on_cancel(&mut self, _latency: Duration) {
+            self.0.lock().unwrap().response_cancels += 1;
+        }
     }
 
     impl MakeCallbackHandler for Recorder {
@@ -336,6 +475,7 @@ mod tests {
         assert_eq!(events.response_end_trailers, vec![None]);
         assert!(events.response_body_errors.is_empty());
         assert!(events.response_service_errors.is_empty());
+        assert_eq!(events.response_cancels, 0);
     }
 
     #[tokio::test]
@@ -426,10 +566,15 @@ mod tests {
 
         struct CountResp(Arc<Mutex<u32>>);
         impl ResponseHandler for CountResp {
-            fn on_response(&mut self, _parts: &response::Parts) {
+            fn on_response(&mut self, _parts: &response::Parts, _latency: Duration) {
                 *self.0.lock().unwrap() += 1;
             }
-            fn on_service_error<E: std::fmt::Display + 'static>(&mut self, _error: &E) {}
+            fn on_service_error<E: std::fmt::Display + 'static>(
+                &mut self,
+                _error: &E,
+                _latency: Duration,
+            ) {
+            }
         }
 
         impl MakeCallbackHandler for MakeResponseOnly {
@@ -550,6 +695,9 @@ mod tests {
         assert_eq!(events.response_chunks, vec![b"partial".to_vec()]);
         assert_eq!(events.response_body_errors, vec!["body-boom".to_string()]);
         assert!(events.response_service_errors.is_empty());
+        assert_eq!(events.response_errors.len(), 1);
+        assert_eq!(events.response_errors[0].kind(), ErrorKind::Other);
+        assert_eq!(events.response_errors[0].message(), "body-boom");
         // An error terminates the stream; no clean end-of-stream fires.
         assert!(events.response_end_trailers.is_empty());
     }
@@ -592,5 +740,282 @@ mod tests {
         assert!(events.response_body_errors.is_empty());
         // Service error routed to the response handler.
         assert_eq!(events.response_service_errors, vec!["svc-boom".to_string()]);
+        assert_eq!(events.response_errors[0].grpc_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn classifies_service_errors() {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+
+        let inner = tower::service_fn(|_req: Request<RequestBody<Full<Bytes>, ReqH>>| async move {
+            let error: crate::BoxError = Box::new(h2::Error::from(h2::Reason::CANCEL));
+            Err::<Response<Full<Bytes>>, _>(error)
+        });
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(recorder))
+            .service(inner);
+
+        let _ = svc.oneshot(Request::new(Full::new(Bytes::new()))).await;
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.response_errors.len(), 1);
+        let error = &events.response_errors[0];
+        assert_eq!(error.kind(), ErrorKind::Cancelled);
+        assert_eq!(error.grpc_code(), 1);
+        assert_eq!(error.message(), events.response_service_errors[0]);
+    }
+
+    #[tokio::test]
+    async fn measures_latency_from_the_request() {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(recorder))
+            .service_fn(|_: Request<RequestBody<Full<Bytes>, ReqH>>| async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                // A streaming body whose first chunk takes a while.
+                let frames = stream::once(async {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, Infallible>(http_body::Frame::data(Bytes::from_static(b"ok")))
+                })
+                .chain(stream::once(async {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok(http_body::Frame::data(Bytes::from_static(b"ok")))
+                }));
+                Ok::<_, Infallible>(Response::new(StreamBody::new(Box::pin(frames))))
+            });
+
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        drain(response.into_body()).await.unwrap();
+
+        let events = events.lock().unwrap();
+        let [on_response, on_first_chunk, on_end] = events.latencies[..] else {
+            panic!("unexpected latencies: {:?}", events.latencies);
+        };
+        assert!(on_response >= Duration::from_millis(20));
+        assert!(on_first_chunk >= on_response + Duration::from_millis(20));
+        assert!(on_end >= on_first_chunk + Duration::from_millis(20));
+        assert_eq!(events.response_chunks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn observes_cancelled_requests() {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(recorder))
+            .service_fn(
+                |request: Request<RequestBody<Full<Bytes>, ReqH>>| async move {
+                    if request.uri().path() == "/slow" {
+                        std::future::pending::<()>().await;
+                    }
+                    let frames: Vec<Result<http_body::Frame<Bytes>, Infallible>> =
+                        vec![Ok(http_body::Frame::data(Bytes::from_static(b"part")))];
+                    let body = StreamBody::new(stream::iter(frames).chain(stream::pending()));
+                    Ok::<_, Infallible>(Response::new(body))
+                },
+            );
+
+        // Dropped before a response was produced.
+        let request = Request::get("/slow").body(Full::default()).unwrap();
+        let result =
+            tokio::time::timeout(Duration::from_millis(10), svc.clone().oneshot(request)).await;
+        assert!(result.is_err());
+        assert_eq!(events.lock().unwrap().response_cancels, 1);
+
+        // Dropped partway through the response body.
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        let mut body = response.into_body();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.response_cancels, 2);
+        assert!(events.response_end_trailers.is_empty());
+    }
+
+    async fn failures_for(response: Response<StreamBody<FrameStream>>) -> Vec<Classification> {
+        classified_failures_for(ServerErrorsAsFailures, response).await
+    }
+
+    async fn classified_failures_for<C>(
+        classifier: C,
+        response: Response<StreamBody<FrameStream>>,
+    ) -> Vec<Classification>
+    where
+        C: Classifier + Clone,
+    {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+        let response = std::sync::Mutex::new(Some(response));
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(recorder).classifier(classifier))
+            .service_fn(move |_: Request<RequestBody<Full<Bytes>, ReqH>>| {
+                let response = response.lock().unwrap().take().unwrap();
+                async move { Ok::<_, Infallible>(response) }
+            });
+
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        drain(response.into_body()).await.unwrap();
+
+        std::mem::take(&mut events.lock().unwrap().response_failures)
+    }
+
+    type FrameStream =
+        stream::Iter<std::vec::IntoIter<Result<http_body::Frame<Bytes>, Infallible>>>;
+
+    fn grpc_response(
+        headers: &[(&'static str, &'static str)],
+        trailers: Option<HeaderMap>,
+    ) -> Response<StreamBody<FrameStream>> {
+        let frames: Vec<_> = trailers
+            .into_iter()
+            .map(|trailers| Ok(http_body::Frame::trailers(trailers)))
+            .collect();
+        let mut response = Response::new(StreamBody::new(stream::iter(frames)));
+        response
+            .headers_mut()
+            .insert("content-type", "application/grpc".parse().unwrap());
+        for (name, value) in headers {
+            response.headers_mut().insert(*name, value.parse().unwrap());
+        }
+        response
+    }
+
+    #[tokio::test]
+    async fn reports_http_server_errors() {
+        let mut response = Response::new(StreamBody::new(stream::iter(Vec::new())));
+        *response.status_mut() = http::StatusCode::BAD_GATEWAY;
+        assert_eq!(
+            failures_for(response).await,
+            vec![Classification::Http(http::StatusCode::BAD_GATEWAY)]
+        );
+
+        let mut response = Response::new(StreamBody::new(stream::iter(Vec::new())));
+        *response.status_mut() = http::StatusCode::NOT_FOUND;
+        assert!(failures_for(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reports_grpc_failures_from_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        assert!(
+            failures_for(grpc_response(&[], Some(trailers.clone())))
+                .await
+                .is_empty()
+        );
+
+        trailers.insert("grpc-status", "13".parse().unwrap());
+        trailers.insert("grpc-message", "oops".parse().unwrap());
+        assert_eq!(
+            failures_for(grpc_response(&[], Some(trailers))).await,
+            vec![Classification::Grpc {
+                code: 13,
+                message: Some("oops".to_owned()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_grpc_trailers_only_failures() {
+        assert_eq!(
+            failures_for(grpc_response(&[("grpc-status", "7")], None)).await,
+            vec![Classification::Grpc {
+                code: 7,
+                message: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn consults_the_configured_classifier() {
+        let mut response = Response::new(StreamBody::new(stream::iter(Vec::new())));
+        *response.status_mut() = http::StatusCode::NOT_FOUND;
+        assert_eq!(
+            classified_failures_for(
+                StatusInRangeAsFailures::client_and_server_errors(),
+                response
+            )
+            .await,
+            vec![Classification::Http(http::StatusCode::NOT_FOUND)]
+        );
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "5".parse().unwrap());
+        assert!(
+            classified_failures_for(
+                GrpcErrorsAsFailures::new().with_success(5),
+                grpc_response(&[], Some(trailers))
+            )
+            .await
+            .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn intercepted_requests_skip_the_inner_service() {
+        /// Rejects requests to `/maintenance`.
+        #[derive(Clone)]
+        struct Maintenance(Recorder);
+
+        impl MakeCallbackHandler for Maintenance {
+            type RequestHandler = ReqH;
+            type ResponseHandler = RespH;
+
+            fn make_handler(
+                &self,
+                request: &request::Parts,
+            ) -> (Self::RequestHandler, Self::ResponseHandler) {
+                self.0.make_handler(request)
+            }
+
+            fn intercept(&self, request: &request::Parts) -> Option<Response<()>> {
+                (request.uri.path() == "/maintenance").then(|| {
+                    let mut response = Response::new(());
+                    *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+                    response
+                })
+            }
+        }
+
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+        let calls = Arc::new(Mutex::new(0));
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(Maintenance(recorder)))
+            .service_fn({
+                let calls = calls.clone();
+                move |_: Request<RequestBody<Full<Bytes>, ReqH>>| {
+                    *calls.lock().unwrap() += 1;
+                    async {
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+                    }
+                }
+            });
+
+        let request = Request::get("/maintenance").body(Full::default()).unwrap();
+        let response = svc.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "");
+        assert_eq!(*calls.lock().unwrap(), 0);
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events.response_seen, 1);
+            assert_eq!(events.response_end_trailers, vec![None]);
+            assert_eq!(
+                events.response_failures,
+                vec![Classification::Http(http::StatusCode::SERVICE_UNAVAILABLE)]
+            );
+        }
+
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        assert_eq!(
+            response.into_body().collect().await.unwrap().to_bytes(),
+            "ok"
+        );
+        assert_eq!(*calls.lock().unwrap(), 1);
     }
 }
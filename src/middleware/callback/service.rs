@@ -2,14 +2,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::CallbackLayer;
+use super::Classifier;
 use super::MakeCallbackHandler;
 use super::RequestBody;
 use super::ResponseBody;
 use super::ResponseFuture;
+use super::ServerErrorsAsFailures;
 use http::Request;
 use http::Response;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Instant;
 use tower::Service;
 
 /// Middleware that adds callbacks to a [`Service`].
@@ -18,9 +21,10 @@ use tower::Service;
 ///
 /// [`Service`]: tower::Service
 #[derive(Debug, Clone, Copy)]
-pub struct Callback<S, M> {
+pub struct Callback<S, M, C = ServerErrorsAsFailures> {
     pub(crate) inner: S,
     pub(crate) make_callback_handler: M,
+    pub(crate) classifier: C,
 }
 
 impl<S, M> Callback<S, M> {
@@ -29,6 +33,7 @@ impl<S, M> Callback<S, M> {
         Self {
             inner,
             make_callback_handler,
+            classifier: ServerErrorsAsFailures,
         }
     }
 
@@ -41,7 +46,9 @@ impl<S, M> Callback<S, M> {
     {
         CallbackLayer::new(make_handler)
     }
+}
 
+impl<S, M, C> Callback<S, M, C> {
     /// Gets a reference to the underlying service.
     pub fn inner(&self) -> &S {
         &self.inner
@@ -58,7 +65,7 @@ impl<S, M> Callback<S, M> {
     }
 }
 
-impl<S, M, ReqBody, ResponseBodyT> Service<Request<ReqBody>> for Callback<S, M>
+impl<S, M, C, ReqBody, ResponseBodyT> Service<Request<ReqBody>> for Callback<S, M, C>
 where
     S: Service<
             Request<RequestBody<ReqBody, M::RequestHandler>>,
@@ -66,18 +73,20 @@ where
             Error: std::fmt::Display + 'static,
         >,
     M: MakeCallbackHandler,
+    C: Classifier + Clone,
     ReqBody: http_body::Body<Error: std::fmt::Display + 'static>,
     ResponseBodyT: http_body::Body<Error: std::fmt::Display + 'static>,
 {
-    type Response = Response<ResponseBody<ResponseBodyT, M::ResponseHandler>>;
+    type Response = Response<ResponseBody<ResponseBodyT, M::ResponseHandler, C>>;
     type Error = S::Error;
-    type Future = ResponseFuture<S::Future, M::ResponseHandler>;
+    type Future = ResponseFuture<S::Future, M::ResponseHandler, C>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
     }
 
     fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let start = Instant::now();
         let (head, body) = request.into_parts();
         let (req_handler, resp_handler) = self.make_callback_handler.make_handler(&head);
         let wrapped_body = RequestBody {
@@ -85,11 +94,17 @@ where
             handler: req_handler,
             ended: false,
         };
-        let request = Request::from_parts(head, wrapped_body);
+        let intercepted = self.make_callback_handler.intercept(&head);
+        let inner = intercepted
+            .is_none()
+            .then(|| self.inner.call(Request::from_parts(head, wrapped_body)));
 
         ResponseFuture {
-            inner: self.inner.call(request),
+            inner,
+            intercepted,
             handler: Some(resp_handler),
+            classifier: Some(self.classifier.clone()),
+            start,
         }
     }
 }
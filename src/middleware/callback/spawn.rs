@@ -0,0 +1,271 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::future::Future;
+use std::time::Duration;
+
+use http::HeaderMap;
+use http::response;
+use tokio::task::JoinHandle;
+
+use super::Classification;
+use super::ClassifiedError;
+use super::ResponseHandler;
+
+/// Like [`ResponseHandler`], but the response and end-of-stream events
+/// return futures, for handlers that flush to a channel, write to an audit
+/// sink or call an external system.
+///
+/// Use it through [`SpawnHandler`], which runs the returned futures on the
+/// tokio runtime so they never block the response path.
+pub trait AsyncResponseHandler {
+    /// Called exactly once when the inner service produces a response.
+    fn on_response(
+        &mut self,
+        response: &response::Parts,
+        latency: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static;
+
+    /// Called when the inner service's future resolves to `Err`.
+    fn on_service_error<E>(
+        &mut self,
+        error: &E,
+        latency: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static
+    where
+        E: std::fmt::Display + 'static;
+
+    /// Called at most once, when the response body yields its first data
+    /// frame.
+    fn on_first_body_chunk(
+        &mut self,
+        _latency: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        std::future::ready(())
+    }
+
+    /// Called once per data frame yielded by the response body.
+    ///
+    /// This runs inline on every chunk and therefore stays synchronous.
+    fn on_body_chunk<B>(&mut self, _chunk: &B)
+    where
+        B: bytes::Buf,
+    {
+        // do nothing
+    }
+
+    /// Called at most once when the response body stream ends.
+    fn on_end_of_stream(
+        &mut self,
+        _trailers: Option<&HeaderMap>,
+        _latency: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        std::future::ready(())
+    }
+
+    /// Called when polling the response body yields an error.
+    fn on_body_error<E>(&mut self, _error: &E) -> impl Future<Output = ()> + Send + 'static
+    where
+        E: std::fmt::Display + 'static,
+    {
+        std::future::ready(())
+    }
+
+    /// Called after `on_service_error` and `on_body_error` with the error
+    /// classified.
+    fn on_error(&mut self, _error: &ClassifiedError) -> impl Future<Output = ()> + Send + 'static {
+        std::future::ready(())
+    }
+
+    /// Called at most once when the response is classified as a failure.
+    fn on_failure(
+        &mut self,
+        _classification: &Classification,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        std::future::ready(())
+    }
+
+    /// Called at most once when the client abandons the request.
+    fn on_cancel(&mut self, _latency: Duration) -> impl Future<Output = ()> + Send + 'static {
+        std::future::ready(())
+    }
+}
+
+/// Adapts an [`AsyncResponseHandler`] into a [`ResponseHandler`] by
+/// spawning the futures it returns.
+///
+/// The futures of a single handler run one after another in the order
+/// their events occurred, so e.g. the work for `on_response` completes
+/// before the work for `on_end_of_stream` starts. Must be used within a
+/// tokio runtime.
+#[derive(Debug)]
+pub struct SpawnHandler<H> {
+    handler: H,
+    previous: Option<JoinHandle<()>>,
+}
+
+impl<H> SpawnHandler<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            previous: None,
+        }
+    }
+
+    fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let previous = self.previous.take();
+        self.previous = Some(tokio::spawn(async move {
+            if let Some(previous) = previous {
+                // A panic in an earlier callback must not suppress later ones.
+                let _ = previous.await;
+            }
+            future.await
+        }));
+    }
+}
+
+impl<H: AsyncResponseHandler> ResponseHandler for SpawnHandler<H> {
+    fn on_response(&mut self, response: &response::Parts, latency: Duration) {
+        let future = self.handler.on_response(response, latency);
+        self.spawn(future);
+    }
+
+    fn on_service_error<E>(&mut self, error: &E, latency: Duration)
+    where
+        E: std::fmt::Display + 'static,
+    {
+        let future = self.handler.on_service_error(error, latency);
+        self.spawn(future);
+    }
+
+    fn on_first_body_chunk(&mut self, latency: Duration) {
+        let future = self.handler.on_first_body_chunk(latency);
+        self.spawn(future);
+    }
+
+    fn on_body_chunk<B>(&mut self, chunk: &B)
+    where
+        B: bytes::Buf,
+    {
+        self.handler.on_body_chunk(chunk);
+    }
+
+    fn on_end_of_stream(&mut self, trailers: Option<&HeaderMap>, latency: Duration) {
+        let future = self.handler.on_end_of_stream(trailers, latency);
+        self.spawn(future);
+    }
+
+    fn on_body_error<E>(&mut self, error: &E)
+    where
+        E: std::fmt::Display + 'static,
+    {
+        let future = self.handler.on_body_error(error);
+        self.spawn(future);
+    }
+
+    fn on_error(&mut self, error: &ClassifiedError) {
+        let future = self.handler.on_error(error);
+        self.spawn(future);
+    }
+
+    fn on_failure(&mut self, classification: &Classification) {
+        let future = self.handler.on_failure(classification);
+        self.spawn(future);
+    }
+
+    fn on_cancel(&mut self, latency: Duration) {
+        let future = self.handler.on_cancel(latency);
+        self.spawn(future);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::callback::CallbackLayer;
+    use crate::middleware::callback::MakeCallbackHandler;
+    use crate::middleware::callback::RequestBody;
+    use bytes::Bytes;
+    use http::Request;
+    use http::Response;
+    use http::request;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use tokio::sync::mpsc;
+    use tower::ServiceBuilder;
+    use tower::ServiceExt;
+
+    struct Audit(mpsc::UnboundedSender<String>);
+
+    impl AsyncResponseHandler for Audit {
+        fn on_response(
+            &mut self,
+            response: &response::Parts,
+            _latency: Duration,
+        ) -> impl Future<Output = ()> + Send + 'static {
+            let sender = self.0.clone();
+            let status = response.status;
+            async move {
+                // Slower than the end-of-stream callback, which must still
+                // be observed second.
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                sender.send(format!("response {status}")).unwrap();
+            }
+        }
+
+        fn on_service_error<E>(
+            &mut self,
+            error: &E,
+            _latency: Duration,
+        ) -> impl Future<Output = ()> + Send + 'static
+        where
+            E: std::fmt::Display + 'static,
+        {
+            let _ = self.0.send(format!("error {error}"));
+            std::future::ready(())
+        }
+
+        fn on_end_of_stream(
+            &mut self,
+            _trailers: Option<&HeaderMap>,
+            _latency: Duration,
+        ) -> impl Future<Output = ()> + Send + 'static {
+            let sender = self.0.clone();
+            async move {
+                sender.send("end".to_owned()).unwrap();
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct MakeAudit(mpsc::UnboundedSender<String>);
+
+    impl MakeCallbackHandler for MakeAudit {
+        type RequestHandler = ();
+        type ResponseHandler = SpawnHandler<Audit>;
+
+        fn make_handler(&self, _request: &request::Parts) -> ((), SpawnHandler<Audit>) {
+            ((), SpawnHandler::new(Audit(self.0.clone())))
+        }
+    }
+
+    #[tokio::test]
+    async fn spawned_callbacks_run_in_order() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let svc = ServiceBuilder::new()
+            .layer(CallbackLayer::new(MakeAudit(sender)))
+            .service_fn(|_: Request<RequestBody<Full<Bytes>, ()>>| async {
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+            });
+
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        assert_eq!(receiver.recv().await.unwrap(), "response 200 OK");
+        assert_eq!(receiver.recv().await.unwrap(), "end");
+    }
+}
@@ -0,0 +1,420 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Turning panics in services into error responses.
+//!
+//! Without this layer a panicking handler unwinds through the connection
+//! task, which takes down the whole connection, including every other
+//! request multiplexed on it over HTTP/2. [`CatchPanicLayer`] instead
+//! catches panics raised while calling the inner service, while polling its
+//! response future and while polling the response body:
+//!
+//! * before the response has been sent, the panic becomes an empty
+//!   `500 Internal Server Error`, or for gRPC requests a trailers-only
+//!   response with `grpc-status` 13 (`INTERNAL`);
+//! * once the body is streaming, a gRPC body ends with `grpc-status` 13
+//!   trailers, and any other body ends with a [`Panicked`] error, which
+//!   resets the stream (or closes the HTTP/1 connection) so the client
+//!   does not mistake the truncated body for a complete one.
+//!
+//! Every caught panic is passed to a hook, which by default logs it. The
+//! panic message is never sent to the client.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::catch_panic::CatchPanicLayer;
+//! use sui_http::middleware::catch_panic::panic_message;
+//!
+//! let layer = CatchPanicLayer::new().on_panic(|payload| {
+//!     tracing::error!(message = panic_message(payload), "handler panicked");
+//! });
+//! # let _ = layer;
+//! ```
+
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::panic::catch_unwind;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use tower::Layer;
+use tower::Service;
+
+use crate::BoxError;
+use crate::middleware::callback::is_grpc;
+
+const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_MESSAGE_HEADER: HeaderName = HeaderName::from_static("grpc-message");
+
+const GRPC_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/grpc");
+const GRPC_INTERNAL_CODE: HeaderValue = HeaderValue::from_static("13");
+const GRPC_INTERNAL_MESSAGE: HeaderValue = HeaderValue::from_static("internal%20error");
+
+type PanicHook = dyn Fn(&(dyn Any + Send)) + Send + Sync;
+
+/// The message of a panic payload, for payloads created by `panic!` with a
+/// message; `"Box<dyn Any>"` otherwise, as in the standard panic hook.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+fn log_panic(payload: &(dyn Any + Send)) {
+    tracing::error!(panic = panic_message(payload), "service panicked");
+}
+
+/// The error a response body ends with when polling it panicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panicked(());
+
+impl std::fmt::Display for Panicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("response body panicked")
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+/// [`Layer`] that catches panics in the inner service; see the
+/// [module docs](self).
+#[derive(Clone)]
+pub struct CatchPanicLayer {
+    on_panic: Arc<PanicHook>,
+}
+
+impl std::fmt::Debug for CatchPanicLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CatchPanicLayer").finish_non_exhaustive()
+    }
+}
+
+impl Default for CatchPanicLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CatchPanicLayer {
+    pub fn new() -> Self {
+        Self {
+            on_panic: Arc::new(log_panic),
+        }
+    }
+
+    /// Call `on_panic` with the payload of every caught panic, instead of
+    /// logging it at the error level.
+    pub fn on_panic<F>(self, on_panic: F) -> Self
+    where
+        F: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        Self {
+            on_panic: Arc::new(on_panic),
+        }
+    }
+}
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanic {
+            inner,
+            on_panic: self.on_panic.clone(),
+        }
+    }
+}
+
+/// Service returned by [`CatchPanicLayer`].
+#[derive(Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+    on_panic: Arc<PanicHook>,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for CatchPanic<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CatchPanic")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for CatchPanic<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Body,
+    ResponseBody::Error: Into<BoxError>,
+{
+    type Response = Response<CatchPanicBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let grpc = is_grpc(request.headers());
+        match catch_unwind(AssertUnwindSafe(|| self.inner.call(request))) {
+            Ok(future) => ResponseFuture::Inner {
+                future,
+                grpc,
+                on_panic: self.on_panic.clone(),
+            },
+            Err(payload) => {
+                (self.on_panic)(&*payload);
+                ResponseFuture::Panicked {
+                    grpc,
+                    on_panic: self.on_panic.clone(),
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`CatchPanic`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+            grpc: bool,
+            on_panic: Arc<PanicHook>,
+        },
+        Panicked {
+            grpc: bool,
+            on_panic: Arc<PanicHook>,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<CatchPanicBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner {
+                future,
+                grpc,
+                on_panic,
+            } => match catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+                Ok(Poll::Ready(result)) => Poll::Ready(result.map(|response| {
+                    let grpc = *grpc || is_grpc(response.headers());
+                    response.map(|body| CatchPanicBody {
+                        inner: Some(body),
+                        grpc,
+                        on_panic: on_panic.clone(),
+                    })
+                })),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(payload) => {
+                    on_panic(&*payload);
+                    Poll::Ready(Ok(panic_response(*grpc, on_panic)))
+                }
+            },
+            ResponseFutureProj::Panicked { grpc, on_panic } => {
+                Poll::Ready(Ok(panic_response(*grpc, on_panic)))
+            }
+        }
+    }
+}
+
+fn panic_response<B>(grpc: bool, on_panic: &Arc<PanicHook>) -> Response<CatchPanicBody<B>> {
+    let mut response = Response::new(CatchPanicBody {
+        inner: None,
+        grpc: false,
+        on_panic: on_panic.clone(),
+    });
+    let headers = response.headers_mut();
+    if grpc {
+        headers.insert(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+        headers.insert(GRPC_STATUS_HEADER, GRPC_INTERNAL_CODE);
+        headers.insert(GRPC_MESSAGE_HEADER, GRPC_INTERNAL_MESSAGE);
+    } else {
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    response
+}
+
+pin_project! {
+    /// Response body for [`CatchPanic`].
+    pub struct CatchPanicBody<B> {
+        // `None` once the body panicked, or for responses built after a
+        // panic.
+        #[pin]
+        inner: Option<B>,
+        grpc: bool,
+        on_panic: Arc<PanicHook>,
+    }
+}
+
+impl<B> Body for CatchPanicBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = B::Data;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let Some(inner) = this.inner.as_mut().as_pin_mut() else {
+            return Poll::Ready(None);
+        };
+        match catch_unwind(AssertUnwindSafe(|| inner.poll_frame(cx))) {
+            Ok(frame) => frame.map(|frame| frame.map(|frame| frame.map_err(Into::into))),
+            Err(payload) => {
+                (this.on_panic)(&*payload);
+                this.inner.set(None);
+                if *this.grpc {
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert(GRPC_STATUS_HEADER, GRPC_INTERNAL_CODE);
+                    trailers.insert(GRPC_MESSAGE_HEADER, GRPC_INTERNAL_MESSAGE);
+                    Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                } else {
+                    Poll::Ready(Some(Err(Panicked(()).into())))
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| http_body::SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    fn grpc_request() -> Request<()> {
+        Request::post("/pkg.Service/Method")
+            .header(http::header::CONTENT_TYPE, "application/grpc")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn converts_handler_panics_into_responses() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let layer = CatchPanicLayer::new().on_panic({
+            let panics = panics.clone();
+            move |payload| {
+                assert_eq!(panic_message(payload), "boom");
+                panics.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let svc = layer.layer(tower::service_fn(|_: Request<()>| async {
+            if true {
+                panic!("boom");
+            }
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+        }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .is_empty()
+        );
+
+        let response = svc.oneshot(grpc_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[GRPC_STATUS_HEADER], "13");
+        assert_eq!(panics.load(Ordering::Relaxed), 2);
+    }
+
+    /// A body that panics when polled.
+    struct PanickingBody;
+
+    impl Body for PanickingBody {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+            panic!("body panicked")
+        }
+    }
+
+    #[tokio::test]
+    async fn catches_body_panics() {
+        let svc = CatchPanicLayer::new()
+            .on_panic(|_| {})
+            .layer(tower::service_fn(|request: Request<()>| async move {
+                let mut response = Response::new(PanickingBody);
+                response.headers_mut().extend(
+                    request
+                        .headers()
+                        .get(http::header::CONTENT_TYPE)
+                        .map(|value| (http::header::CONTENT_TYPE, value.clone())),
+                );
+                Ok::<_, Infallible>(response)
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        let error = response.into_body().collect().await.unwrap_err();
+        assert!(error.is::<Panicked>());
+
+        let response = svc.oneshot(grpc_request()).await.unwrap();
+        let collected = response.into_body().collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()[GRPC_STATUS_HEADER], "13");
+    }
+
+    #[tokio::test]
+    async fn passes_responses_through() {
+        let svc = CatchPanicLayer::new().layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+        }));
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.into_body().collect().await.unwrap().to_bytes(),
+            "ok"
+        );
+    }
+}
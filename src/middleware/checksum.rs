@@ -0,0 +1,88 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Incremental checksums used by the upload and (de)compression
+//! middleware.
+//!
+//! Each function extends the checksum of the bytes seen so far with `data`;
+//! start from `0` for the CRCs and `1` for Adler-32.
+
+/// Builds the lookup table for a reflected CRC-32 polynomial.
+const fn crc_table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ polynomial
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+const CRC32_TABLE: [u32; 256] = crc_table(0xedb8_8320);
+const CRC32C_TABLE: [u32; 256] = crc_table(0x82f6_3b78);
+
+fn crc(table: &[u32; 256], crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = table[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// CRC-32 (IEEE), as used by gzip.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+pub(crate) fn crc32(crc32: u32, data: &[u8]) -> u32 {
+    crc(&CRC32_TABLE, crc32, data)
+}
+
+/// CRC-32C (Castagnoli).
+pub(crate) fn crc32c(crc32c: u32, data: &[u8]) -> u32 {
+    crc(&CRC32C_TABLE, crc32c, data)
+}
+
+/// Adler-32, as used by zlib.
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+pub(crate) fn adler32(adler32: u32, data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (adler32 & 0xffff, adler32 >> 16);
+    // 5552 bytes is the most that can be summed before `b` overflows.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32c(0, b"123456789"), 0xe306_9283);
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn incremental() {
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+        assert_eq!(crc32c(crc32c(0, b"1234"), b"56789"), 0xe306_9283);
+        assert_eq!(adler32(adler32(1, b"Wiki"), b"pedia"), 0x11e6_0398);
+    }
+}
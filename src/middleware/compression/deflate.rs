@@ -0,0 +1,332 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A streaming DEFLATE encoder (RFC 1951) producing gzip (RFC 1952) or zlib
+//! (RFC 1950) streams.
+//!
+//! Each chunk of input is compressed on its own, using LZ77 matching and the
+//! fixed Huffman code, and followed by a sync flush (an empty stored block).
+//! Every chunk of output is therefore decodable as soon as it is sent, which
+//! keeps streaming responses streaming.
+
+use bytes::Bytes;
+
+use crate::middleware::checksum;
+
+const WINDOW: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Gzip,
+    Zlib,
+}
+
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    format: Format,
+    /// How many earlier positions to try when looking for a match.
+    max_chain: usize,
+    bits: BitWriter,
+    header_written: bool,
+    checksum: u32,
+    size: u32,
+}
+
+impl Encoder {
+    pub(crate) fn new(format: Format, max_chain: usize) -> Self {
+        Self {
+            format,
+            max_chain,
+            bits: BitWriter::default(),
+            header_written: false,
+            checksum: match format {
+                Format::Gzip => 0,
+                Format::Zlib => 1,
+            },
+            size: 0,
+        }
+    }
+
+    /// Compresses `data`, returning output that decodes to everything
+    /// compressed so far.
+    pub(crate) fn compress(&mut self, data: &[u8]) -> Bytes {
+        self.write_header();
+        self.checksum = match self.format {
+            Format::Gzip => checksum::crc32(self.checksum, data),
+            Format::Zlib => checksum::adler32(self.checksum, data),
+        };
+        self.size = self.size.wrapping_add(data.len() as u32);
+
+        // A fixed Huffman block holding the chunk...
+        self.bits.write(0, 1);
+        self.bits.write(1, 2);
+        self.lz77(data);
+        self.bits.write_literal_length(256);
+
+        // ...followed by an empty stored block, which byte-aligns it.
+        self.bits.write(0, 1);
+        self.bits.write(0, 2);
+        self.bits.align();
+        self.bits.out.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+        self.bits.take()
+    }
+
+    /// Ends the stream, returning the final block and trailer.
+    pub(crate) fn finish(&mut self) -> Bytes {
+        self.write_header();
+        // An empty, final fixed Huffman block.
+        self.bits.write(1, 1);
+        self.bits.write(1, 2);
+        self.bits.write_literal_length(256);
+        self.bits.align();
+        match self.format {
+            Format::Gzip => {
+                self.bits
+                    .out
+                    .extend_from_slice(&self.checksum.to_le_bytes());
+                self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+            }
+            Format::Zlib => self
+                .bits
+                .out
+                .extend_from_slice(&self.checksum.to_be_bytes()),
+        }
+        self.bits.take()
+    }
+
+    fn write_header(&mut self) {
+        if std::mem::replace(&mut self.header_written, true) {
+            return;
+        }
+        match self.format {
+            // No mtime, no flags, unknown OS.
+            Format::Gzip => self
+                .bits
+                .out
+                .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]),
+            // 32 KiB window, no dictionary.
+            Format::Zlib => self.bits.out.extend_from_slice(&[0x78, 0x01]),
+        }
+    }
+
+    /// Writes `data` as literals and back-references found with hash
+    /// chains.
+    fn lz77(&mut self, data: &[u8]) {
+        let mut chains = Chains::new(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let (len, distance) = chains.longest_match(data, i, self.max_chain);
+            if len >= MIN_MATCH {
+                self.bits.write_match(len, distance);
+                for j in i..i + len {
+                    chains.insert(data, j);
+                }
+                i += len;
+            } else {
+                self.bits.write_literal_length(u16::from(data[i]));
+                chains.insert(data, i);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Hash chains linking each position to the previous position whose next
+/// three bytes hash the same.
+struct Chains {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl Chains {
+    const NONE: u32 = u32::MAX;
+
+    /// Chains for a chunk of `len` bytes.
+    fn new(len: usize) -> Self {
+        Self {
+            head: vec![Self::NONE; 1 << HASH_BITS],
+            prev: vec![Self::NONE; len.min(WINDOW)],
+        }
+    }
+
+    fn hash(data: &[u8], i: usize) -> usize {
+        let value = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let hash = Self::hash(data, i);
+            let slot = i % self.prev.len();
+            self.prev[slot] = self.head[hash];
+            self.head[hash] = i as u32;
+        }
+    }
+
+    /// The longest earlier match for the bytes at `i`, as a length and
+    /// distance, following at most `max_chain` links.
+    fn longest_match(&self, data: &[u8], i: usize, max_chain: usize) -> (usize, usize) {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + MIN_MATCH > data.len() {
+            return (best_len, best_distance);
+        }
+
+        let max_len = (data.len() - i).min(MAX_MATCH);
+        let mut candidate = self.head[Self::hash(data, i)];
+        let mut chain = max_chain;
+        while candidate != Self::NONE && chain > 0 && i - candidate as usize <= WINDOW {
+            let start = candidate as usize;
+            let len = data[start..]
+                .iter()
+                .zip(&data[i..i + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                (best_len, best_distance) = (len, i - start);
+                if len == max_len {
+                    break;
+                }
+            }
+            let next = self.prev[start % self.prev.len()];
+            // Slots are reused once the window wraps around.
+            if next == Self::NONE || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        (best_len, best_distance)
+    }
+}
+
+/// Packs bits least significant first, as DEFLATE expects.
+#[derive(Debug, Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, n: u32) {
+        self.buf |= u64::from(bits) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which DEFLATE packs most significant bit
+    /// first.
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    /// Writes a literal/length symbol with the fixed Huffman code.
+    fn write_literal_length(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, len: usize, distance: usize) {
+        let index = LENGTH_BASE.partition_point(|&base| usize::from(base) <= len) - 1;
+        self.write_literal_length(257 + index as u16);
+        self.write(
+            (len - usize::from(LENGTH_BASE[index])) as u32,
+            u32::from(LENGTH_EXTRA[index]),
+        );
+
+        let index = DISTANCE_BASE.partition_point(|&base| usize::from(base) <= distance) - 1;
+        self.write_code(index as u32, 5);
+        self.write(
+            (distance - usize::from(DISTANCE_BASE[index])) as u32,
+            u32::from(DISTANCE_EXTRA[index]),
+        );
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buf as u8);
+            self.buf = 0;
+            self.count = 0;
+        }
+    }
+
+    /// Takes the output written so far, which must be byte-aligned.
+    fn take(&mut self) -> Bytes {
+        debug_assert_eq!(self.count, 0);
+        std::mem::take(&mut self.out).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::decompression::inflate;
+
+    fn compress(format: Format, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut encoder = Encoder::new(format, 32);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend_from_slice(&encoder.compress(chunk));
+        }
+        out.extend_from_slice(&encoder.finish());
+        out
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+        let binary: Vec<u8> = (0..70_000u64).map(|i| (i * i % 251) as u8).collect();
+        let chunks: [&[u8]; 4] = [&text, b"", &binary, b"abcabcabcabcabcabc"];
+        let expected = chunks.concat();
+
+        let gzip = compress(Format::Gzip, &chunks);
+        assert_eq!(inflate::gzip(&gzip, usize::MAX).unwrap(), expected);
+        let zlib = compress(Format::Zlib, &chunks);
+        assert_eq!(inflate::zlib(&zlib, usize::MAX).unwrap(), expected);
+
+        // Repetitive input compresses well.
+        assert!(compress(Format::Gzip, &[&text]).len() < text.len() / 10);
+    }
+
+    #[test]
+    fn encodes_empty_streams() {
+        let gzip = compress(Format::Gzip, &[]);
+        assert_eq!(inflate::gzip(&gzip, 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn encodes_long_matches() {
+        let data = vec![b'a'; 1000];
+        let gzip = compress(Format::Gzip, &[&data]);
+        assert_eq!(inflate::gzip(&gzip, usize::MAX).unwrap(), data);
+        assert!(gzip.len() < 50);
+    }
+}
@@ -0,0 +1,699 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compression of response bodies.
+//!
+//! [`CompressionLayer`] compresses response bodies with the best coding the
+//! client lists in its `accept-encoding` header, among those enabled on the
+//! layer: `gzip` and `deflate`, both on by default and preferred in that
+//! order when the client has no preference. Responses are compressed as
+//! they stream, chunk by chunk, so every chunk sent can be decoded
+//! immediately.
+//!
+//! A response is left untouched when:
+//!
+//! * it is a gRPC response (a `content-type` of `application/grpc`, with
+//!   or without a suffix). gRPC compresses individual messages, negotiated
+//!   with `grpc-encoding`; compressing the body again would hide the
+//!   message framing from clients;
+//! * it already has a `content-encoding`, or is a `206 Partial Content`
+//!   (ranges refer to the uncompressed representation);
+//! * it sets `cache-control: no-transform`;
+//! * its body is known to be smaller than the [minimum
+//!   size](CompressionLayer::min_size);
+//! * its `content-type` is rejected by the [content-type
+//!   predicate](CompressionLayer::compress_when), which by default skips
+//!   images (except SVG), audio, video and already compressed archives.
+//!
+//! Compressible responses get a `vary: accept-encoding` header whether or
+//! not they are compressed, so caches keep the variants apart. Compressed
+//! responses lose their `content-length` and `accept-ranges` headers.
+//!
+//! Only the DEFLATE-based codings are implemented; `br` and `zstd` are not
+//! offered.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::compression::CompressionLayer;
+//! use sui_http::middleware::compression::Level;
+//!
+//! // Compress JSON and text responses of at least 1 KiB, favoring speed.
+//! let layer = CompressionLayer::new()
+//!     .deflate(false)
+//!     .level(Level::Fastest)
+//!     .min_size(1024)
+//!     .compress_when(|content_type| {
+//!         content_type.starts_with("application/json") || content_type.starts_with("text/")
+//!     });
+//! # let _ = layer;
+//! ```
+
+use bytes::Buf;
+use bytes::Bytes;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+
+mod deflate;
+
+/// The default minimum size of bodies to compress, in bytes.
+const DEFAULT_MIN_SIZE: u64 = 32;
+
+/// How hard to look for repeated input, trading speed for size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Level {
+    Fastest,
+    #[default]
+    Default,
+    Best,
+    /// A level from 1 (fastest) to 9 (best), as for `gzip -1` to
+    /// `gzip -9`. Other values are clamped to that range.
+    Precise(u32),
+}
+
+impl Level {
+    /// The number of earlier positions the encoder tries per match.
+    fn max_chain(self) -> usize {
+        let level = match self {
+            Self::Fastest => 1,
+            Self::Default => 6,
+            Self::Best => 9,
+            Self::Precise(level) => level.clamp(1, 9),
+        };
+        1 << (level - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn header_value(self) -> HeaderValue {
+        match self {
+            Self::Gzip => HeaderValue::from_static("gzip"),
+            Self::Deflate => HeaderValue::from_static("deflate"),
+        }
+    }
+
+    fn format(self) -> deflate::Format {
+        match self {
+            Self::Gzip => deflate::Format::Gzip,
+            Self::Deflate => deflate::Format::Zlib,
+        }
+    }
+}
+
+/// The enabled encoding the client prefers according to `accept-encoding`,
+/// if any. `gzip` wins ties.
+fn negotiate(headers: &HeaderMap, gzip: bool, deflate: bool) -> Option<Encoding> {
+    let (mut gzip_q, mut deflate_q, mut any_q) = (None, None, None);
+    for value in headers.get_all(header::ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else { continue };
+        for item in value.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let coding = params.next().unwrap_or_default();
+            let q = params
+                .find_map(|param| param.strip_prefix("q=").or(param.strip_prefix("Q=")))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                gzip_q = Some(q);
+            } else if coding.eq_ignore_ascii_case("deflate") {
+                deflate_q = Some(q);
+            } else if coding == "*" {
+                any_q = Some(q);
+            }
+        }
+    }
+
+    let gzip_q = if gzip { gzip_q.or(any_q) } else { None };
+    let deflate_q = if deflate { deflate_q.or(any_q) } else { None };
+    match (gzip_q.filter(|q| *q > 0.0), deflate_q.filter(|q| *q > 0.0)) {
+        (Some(gzip_q), Some(deflate_q)) if deflate_q > gzip_q => Some(Encoding::Deflate),
+        (Some(_), _) => Some(Encoding::Gzip),
+        (None, Some(_)) => Some(Encoding::Deflate),
+        (None, None) => None,
+    }
+}
+
+type Predicate = dyn Fn(&str) -> bool + Send + Sync;
+
+/// The default content-type predicate: skip media types that are usually
+/// compressed already.
+fn default_predicate(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if essence == "image/svg+xml" {
+        return true;
+    }
+    !(essence.starts_with("image/")
+        || essence.starts_with("audio/")
+        || essence.starts_with("video/")
+        || matches!(
+            essence.as_str(),
+            "application/gzip"
+                | "application/zip"
+                | "application/zstd"
+                | "application/x-bzip2"
+                | "application/x-xz"
+                | "application/x-7z-compressed"
+        ))
+}
+
+fn is_grpc(content_type: &str) -> bool {
+    content_type
+        .get(.."application/grpc".len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("application/grpc"))
+}
+
+/// [`Layer`] that compresses response bodies; see the
+/// [module docs](self).
+#[derive(Clone)]
+pub struct CompressionLayer {
+    gzip: bool,
+    deflate: bool,
+    level: Level,
+    min_size: u64,
+    predicate: Arc<Predicate>,
+}
+
+impl std::fmt::Debug for CompressionLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressionLayer")
+            .field("gzip", &self.gzip)
+            .field("deflate", &self.deflate)
+            .field("level", &self.level)
+            .field("min_size", &self.min_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CompressionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressionLayer {
+    pub fn new() -> Self {
+        Self {
+            gzip: true,
+            deflate: true,
+            level: Level::Default,
+            min_size: DEFAULT_MIN_SIZE,
+            predicate: Arc::new(default_predicate),
+        }
+    }
+
+    /// Whether to offer `gzip`. Defaults to `true`.
+    pub fn gzip(self, gzip: bool) -> Self {
+        Self { gzip, ..self }
+    }
+
+    /// Whether to offer `deflate`. Defaults to `true`.
+    pub fn deflate(self, deflate: bool) -> Self {
+        Self { deflate, ..self }
+    }
+
+    /// The compression level. Defaults to [`Level::Default`].
+    pub fn level(self, level: Level) -> Self {
+        Self { level, ..self }
+    }
+
+    /// Leave bodies whose size is known and smaller than `min_size` bytes
+    /// uncompressed. Defaults to 32 bytes, below which compression usually
+    /// grows the body.
+    pub fn min_size(self, min_size: u64) -> Self {
+        Self { min_size, ..self }
+    }
+
+    /// Only compress responses whose `content-type` satisfies `predicate`.
+    /// Responses without a `content-type` are checked against `""`.
+    ///
+    /// This replaces the default predicate, which skips images (except
+    /// SVG), audio, video and compressed archives. gRPC responses are never
+    /// compressed, whatever the predicate.
+    pub fn compress_when<F>(self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            predicate: Arc::new(predicate),
+            ..self
+        }
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Compression<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Compression {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`CompressionLayer`].
+#[derive(Debug, Clone)]
+pub struct Compression<S> {
+    inner: S,
+    layer: CompressionLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Compression<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Body,
+{
+    type Response = Response<CompressionBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let encoding = if request.method() == http::Method::HEAD {
+            None
+        } else {
+            negotiate(request.headers(), self.layer.gzip, self.layer.deflate)
+        };
+        ResponseFuture {
+            inner: self.inner.call(request),
+            encoding,
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Compression`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        encoding: Option<Encoding>,
+        layer: CompressionLayer,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<CompressionBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let (mut parts, body) = response.into_parts();
+
+        let content_type = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let no_transform = parts
+            .headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        let compressible = !is_grpc(content_type)
+            && !parts.headers.contains_key(header::CONTENT_ENCODING)
+            && parts.status != StatusCode::PARTIAL_CONTENT
+            && !no_transform
+            && (this.layer.predicate)(content_type);
+        if !compressible {
+            return Poll::Ready(Ok(Response::from_parts(
+                parts,
+                CompressionBody::identity(body),
+            )));
+        }
+
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        let too_small = body
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size < this.layer.min_size);
+        let encoding = match this.encoding.take() {
+            Some(encoding) if !too_small && !body.is_end_stream() => encoding,
+            _ => {
+                return Poll::Ready(Ok(Response::from_parts(
+                    parts,
+                    CompressionBody::identity(body),
+                )));
+            }
+        };
+
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, encoding.header_value());
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::ACCEPT_RANGES);
+        let encoder = deflate::Encoder::new(encoding.format(), this.layer.level.max_chain());
+        let body = CompressionBody::compressed(body, encoder);
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
+
+pin_project! {
+    /// Response body for [`Compression`]: either the original body or its
+    /// compressed form.
+    pub struct CompressionBody<B> {
+        #[pin]
+        state: BodyState<B>,
+    }
+}
+
+pin_project! {
+    #[project = BodyStateProj]
+    enum BodyState<B> {
+        Identity {
+            #[pin]
+            body: B,
+        },
+        Compressed {
+            #[pin]
+            body: B,
+            encoder: deflate::Encoder,
+            // Trailers of the inner body, held back until the end of the
+            // compressed stream has been sent.
+            trailers: Option<HeaderMap>,
+            finished: bool,
+        },
+    }
+}
+
+impl<B> CompressionBody<B> {
+    fn identity(body: B) -> Self {
+        Self {
+            state: BodyState::Identity { body },
+        }
+    }
+
+    fn compressed(body: B, encoder: deflate::Encoder) -> Self {
+        Self {
+            state: BodyState::Compressed {
+                body,
+                encoder,
+                trailers: None,
+                finished: false,
+            },
+        }
+    }
+}
+
+impl<B> Body for CompressionBody<B>
+where
+    B: Body,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().state.project() {
+            BodyStateProj::Identity { body } => body.poll_frame(cx).map(|frame| {
+                frame.map(|frame| {
+                    frame.map(|frame| {
+                        frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                    })
+                })
+            }),
+            BodyStateProj::Compressed {
+                mut body,
+                encoder,
+                trailers,
+                finished,
+            } => loop {
+                if *finished {
+                    return Poll::Ready(
+                        trailers
+                            .take()
+                            .map(|trailers| Ok(Frame::trailers(trailers))),
+                    );
+                }
+                match ready!(body.as_mut().poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(mut data) => {
+                            let data = data.copy_to_bytes(data.remaining());
+                            if !data.is_empty() {
+                                return Poll::Ready(Some(Ok(Frame::data(encoder.compress(&data)))));
+                            }
+                        }
+                        Err(frame) => {
+                            if let Ok(frame_trailers) = frame.into_trailers() {
+                                trailers.get_or_insert_default().extend(frame_trailers);
+                            }
+                        }
+                    },
+                    Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                    None => {
+                        *finished = true;
+                        return Poll::Ready(Some(Ok(Frame::data(encoder.finish()))));
+                    }
+                }
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.state {
+            BodyState::Identity { body } => body.is_end_stream(),
+            BodyState::Compressed {
+                trailers, finished, ..
+            } => *finished && trailers.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match &self.state {
+            BodyState::Identity { body } => body.size_hint(),
+            BodyState::Compressed { .. } => http_body::SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::decompression::inflate;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog. ";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    async fn respond(
+        layer: CompressionLayer,
+        accept_encoding: &'static str,
+        response_headers: &'static [(&'static str, &'static str)],
+        body: String,
+    ) -> Response<CompressionBody<Full<Bytes>>> {
+        let svc = layer.layer(tower::service_fn(move |_: Request<()>| {
+            let mut response = Response::new(Full::new(Bytes::from(body.clone())));
+            *response.headers_mut() = headers(response_headers);
+            async move { Ok::<_, Infallible>(response) }
+        }));
+        let request = Request::get("/")
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .unwrap();
+        svc.oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        let negotiate = |value, gzip, deflate| {
+            super::negotiate(&headers(&[("accept-encoding", value)]), gzip, deflate)
+        };
+        assert_eq!(
+            negotiate("gzip, deflate, br", true, true),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate", true, true),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            negotiate("gzip, deflate", false, true),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("*", true, true), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate("*, gzip;q=0", true, true),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("identity", true, true), None);
+        assert_eq!(negotiate("br, zstd", true, true), None);
+        assert_eq!(negotiate("gzip", true, true), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip", false, true), None);
+        assert_eq!(super::negotiate(&headers(&[]), true, true), None);
+    }
+
+    #[test]
+    fn default_predicate_skips_compressed_media() {
+        assert!(default_predicate("application/json"));
+        assert!(default_predicate("text/html; charset=utf-8"));
+        assert!(default_predicate("image/svg+xml"));
+        assert!(default_predicate(""));
+        assert!(!default_predicate("image/png"));
+        assert!(!default_predicate("video/mp4"));
+        assert!(!default_predicate("application/zip"));
+    }
+
+    #[tokio::test]
+    async fn compresses_responses() {
+        let body = TEXT.repeat(100);
+        for (accept_encoding, encoding) in [("gzip", "gzip"), ("deflate", "deflate")] {
+            let response = respond(
+                CompressionLayer::new(),
+                accept_encoding,
+                &[("content-type", "text/plain"), ("content-length", "4500")],
+                body.clone(),
+            )
+            .await;
+            assert_eq!(response.headers()[header::CONTENT_ENCODING], encoding);
+            assert_eq!(response.headers()[header::VARY], "accept-encoding");
+            assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+
+            let compressed = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(compressed.len() < body.len() / 10);
+            let decoded = match encoding {
+                "gzip" => inflate::gzip(&compressed, usize::MAX),
+                _ => inflate::zlib(&compressed, usize::MAX),
+            };
+            assert_eq!(decoded.unwrap(), body.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn leaves_excluded_responses_alone() {
+        let body = TEXT.repeat(100);
+        let cases: [(CompressionLayer, &'static [(&'static str, &'static str)]); 6] = [
+            (
+                CompressionLayer::new(),
+                &[("content-type", "application/grpc")],
+            ),
+            (
+                CompressionLayer::new(),
+                &[("content-type", "application/grpc+proto")],
+            ),
+            (CompressionLayer::new(), &[("content-encoding", "br")]),
+            (
+                CompressionLayer::new(),
+                &[("cache-control", "public, no-transform")],
+            ),
+            (CompressionLayer::new(), &[("content-type", "image/png")]),
+            (
+                CompressionLayer::new().compress_when(|content_type| content_type == "text/html"),
+                &[("content-type", "text/plain")],
+            ),
+        ];
+        for (layer, response_headers) in cases {
+            let response = respond(layer, "gzip", response_headers, body.clone()).await;
+            assert_ne!(
+                response
+                    .headers()
+                    .get(header::CONTENT_ENCODING)
+                    .map(|v| v.as_bytes()),
+                Some(&b"gzip"[..]),
+                "{response_headers:?}"
+            );
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(bytes, body.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_small_bodies_and_disabled_encodings() {
+        let response = respond(
+            CompressionLayer::new().min_size(1024),
+            "gzip",
+            &[],
+            TEXT.to_owned(),
+        )
+        .await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+
+        let response = respond(
+            CompressionLayer::new().gzip(false).deflate(false),
+            "gzip, deflate",
+            &[],
+            TEXT.repeat(10),
+        )
+        .await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn forwards_trailers_after_the_compressed_stream() {
+        let svc = CompressionLayer::new().layer(tower::service_fn(|_: Request<()>| async {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+            let body = Full::new(Bytes::from(TEXT.repeat(10)))
+                .with_trailers(async { Some(Ok::<_, Infallible>(trailers)) });
+            Ok::<_, Infallible>(Response::new(body))
+        }));
+        let request = Request::get("/")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .unwrap();
+        let collected = svc
+            .oneshot(request)
+            .await
+            .unwrap()
+            .into_body()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(collected.trailers().unwrap()["x-checksum"], "abc");
+        let decoded = inflate::gzip(&collected.to_bytes(), usize::MAX).unwrap();
+        assert_eq!(decoded, TEXT.repeat(10).as_bytes());
+    }
+}
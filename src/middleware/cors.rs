@@ -0,0 +1,487 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cross-origin resource sharing for browser clients, including gRPC-Web.
+//!
+//! [`CorsLayer`] only grants access to the origins, methods and headers it
+//! is configured with. Preflight `OPTIONS` requests are answered by the
+//! layer itself, without calling the inner service. Responses to
+//! cross-origin requests from allowed origins carry
+//! `access-control-allow-origin`.
+//!
+//! Out of the box the layer allows the methods and request headers that
+//! gRPC-Web clients send. It also exposes the `grpc-status`, `grpc-message`
+//! and `grpc-status-details-bin` response headers. Without them a browser
+//! hides the status of trailers-only gRPC-Web responses from the client.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use http::HeaderValue;
+//! use sui_http::middleware::cors::CorsLayer;
+//!
+//! let layer = CorsLayer::new()
+//!     .allow_origins([HeaderValue::from_static("https://explorer.sui.io")])
+//!     .allow_headers([http::header::AUTHORIZATION])
+//!     .max_age(Duration::from_secs(3600));
+//! # let _ = layer;
+//! ```
+
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tower::Layer;
+use tower::Service;
+
+/// Request headers sent by gRPC-Web clients, allowed by default.
+const GRPC_WEB_REQUEST_HEADERS: [HeaderName; 4] = [
+    header::CONTENT_TYPE,
+    HeaderName::from_static("x-grpc-web"),
+    HeaderName::from_static("x-user-agent"),
+    HeaderName::from_static("grpc-timeout"),
+];
+
+/// Response headers gRPC-Web clients read, always exposed.
+const GRPC_WEB_RESPONSE_HEADERS: [HeaderName; 3] = [
+    HeaderName::from_static("grpc-status"),
+    HeaderName::from_static("grpc-message"),
+    HeaderName::from_static("grpc-status-details-bin"),
+];
+
+/// [`Layer`] that applies a CORS policy; see the [module docs](self).
+///
+/// No origin is allowed until [`CorsLayer::allow_origins`] or
+/// [`CorsLayer::allow_any_origin`] is called.
+#[derive(Debug, Clone)]
+pub struct CorsLayer {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Option<Duration>,
+    allow_credentials: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Origins {
+    List(Vec<HeaderValue>),
+    Any,
+}
+
+impl Default for CorsLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CorsLayer {
+    /// A policy allowing `GET`, `POST` and the gRPC-Web request headers, but
+    /// no origins yet.
+    pub fn new() -> Self {
+        Self {
+            origins: Origins::List(Vec::new()),
+            methods: vec![Method::GET, Method::POST],
+            headers: GRPC_WEB_REQUEST_HEADERS.to_vec(),
+            expose_headers: GRPC_WEB_RESPONSE_HEADERS.to_vec(),
+            max_age: None,
+            allow_credentials: false,
+        }
+    }
+
+    /// Allow requests from `origins`, in addition to any already allowed.
+    ///
+    /// Origins are compared byte for byte with the `origin` request header,
+    /// e.g. `https://example.com` without a trailing slash.
+    pub fn allow_origins<I>(self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderValue>,
+    {
+        let origins = match self.origins {
+            Origins::List(mut list) => {
+                list.extend(origins);
+                Origins::List(list)
+            }
+            Origins::Any => Origins::Any,
+        };
+        Self { origins, ..self }
+    }
+
+    /// Allow requests from every origin.
+    pub fn allow_any_origin(self) -> Self {
+        Self {
+            origins: Origins::Any,
+            ..self
+        }
+    }
+
+    /// Allow `methods` in addition to `GET` and `POST`.
+    pub fn allow_methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        extend_unique(&mut self.methods, methods);
+        self
+    }
+
+    /// Allow the request headers `headers` in addition to the gRPC-Web
+    /// ones.
+    pub fn allow_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        extend_unique(&mut self.headers, headers);
+        self
+    }
+
+    /// Expose the response headers `headers` to scripts, in addition to the
+    /// gRPC-Web status headers.
+    pub fn expose_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        extend_unique(&mut self.expose_headers, headers);
+        self
+    }
+
+    /// Let browsers cache preflight responses for `max_age`.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Allow requests with credentials (cookies, client certificates).
+    ///
+    /// With credentials allowed the request's origin is echoed back even
+    /// when any origin is allowed, since browsers reject `*` for such
+    /// requests.
+    pub fn allow_credentials(self, allow_credentials: bool) -> Self {
+        Self {
+            allow_credentials,
+            ..self
+        }
+    }
+
+    fn policy(&self) -> Policy {
+        Policy {
+            origins: self.origins.clone(),
+            allow_credentials: self.allow_credentials,
+            methods: join(self.methods.iter().map(Method::as_str)),
+            headers: join(self.headers.iter().map(HeaderName::as_str)),
+            expose_headers: join(self.expose_headers.iter().map(HeaderName::as_str)),
+            max_age: self.max_age.map(|max_age| max_age.as_secs().into()),
+        }
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = Cors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cors {
+            inner,
+            policy: Arc::new(self.policy()),
+        }
+    }
+}
+
+/// The policy of a [`CorsLayer`] with its header values precomputed.
+#[derive(Debug)]
+struct Policy {
+    origins: Origins,
+    allow_credentials: bool,
+    methods: HeaderValue,
+    headers: HeaderValue,
+    expose_headers: HeaderValue,
+    max_age: Option<HeaderValue>,
+}
+
+impl Policy {
+    /// The `access-control-allow-origin` value for a request from `origin`,
+    /// or `None` if the origin is not allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.origins {
+            Origins::Any if !self.allow_credentials => Some(HeaderValue::from_static("*")),
+            Origins::Any => Some(origin.clone()),
+            Origins::List(list) => list.contains(origin).then(|| origin.clone()),
+        }
+    }
+
+    /// The headers shared by preflight and actual responses.
+    fn common_headers(&self, allow_origin: HeaderValue) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        headers
+    }
+
+    fn preflight_headers(&self, allow_origin: HeaderValue) -> HeaderMap {
+        let mut headers = self.common_headers(allow_origin);
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone());
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, self.headers.clone());
+        if let Some(max_age) = &self.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.clone());
+        }
+        headers
+    }
+
+    fn response_headers(&self, allow_origin: HeaderValue) -> HeaderMap {
+        let mut headers = self.common_headers(allow_origin);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            self.expose_headers.clone(),
+        );
+        headers
+    }
+}
+
+/// Service returned by [`CorsLayer`].
+#[derive(Debug, Clone)]
+pub struct Cors<S> {
+    inner: S,
+    policy: Arc<Policy>,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Cors<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let allow_origin = request
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|origin| self.policy.allow_origin(origin));
+
+        let is_preflight = request.method() == Method::OPTIONS
+            && request.headers().contains_key(header::ORIGIN)
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if is_preflight {
+            // Disallowed origins get a bare response, which browsers treat
+            // as a failed preflight.
+            let headers = match allow_origin {
+                Some(allow_origin) => self.policy.preflight_headers(allow_origin),
+                None => {
+                    tracing::debug!("rejecting CORS preflight from disallowed origin");
+                    HeaderMap::new()
+                }
+            };
+            return ResponseFuture::Preflight {
+                headers: Some(headers),
+            };
+        }
+
+        ResponseFuture::Inner {
+            future: self.inner.call(request),
+            headers: allow_origin.map(|allow_origin| self.policy.response_headers(allow_origin)),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Cors`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+            headers: Option<HeaderMap>,
+        },
+        Preflight {
+            headers: Option<HeaderMap>,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (mut response, headers) = match self.project() {
+            ResponseFutureProj::Inner { future, headers } => {
+                let response = ready!(future.poll(cx))?;
+                (response, headers.take())
+            }
+            ResponseFutureProj::Preflight { headers } => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = StatusCode::NO_CONTENT;
+                (response, headers.take())
+            }
+        };
+
+        // The response depends on the origin whether or not it was allowed,
+        // so caches must not serve it to other origins.
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("origin"));
+        response.headers_mut().extend(headers.unwrap_or_default());
+        Poll::Ready(Ok(response))
+    }
+}
+
+fn extend_unique<T: PartialEq>(list: &mut Vec<T>, items: impl IntoIterator<Item = T>) {
+    for item in items {
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+}
+
+fn join<'a>(items: impl Iterator<Item = &'a str>) -> HeaderValue {
+    HeaderValue::from_str(&items.collect::<Vec<_>>().join(", "))
+        .expect("methods and header names are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    const ORIGIN: &str = "https://example.com";
+
+    fn service(
+        layer: CorsLayer,
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<Request<()>, Response = Response<String>, Error = Infallible> + Clone {
+        layer.layer(tower::service_fn(move |_: Request<()>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Ok(Response::new(String::from("hello"))) }
+        }))
+    }
+
+    fn preflight(origin: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_without_calling_the_service() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = CorsLayer::new()
+            .allow_origins([HeaderValue::from_static(ORIGIN)])
+            .allow_methods([Method::PUT])
+            .max_age(Duration::from_secs(600));
+        let response = service(layer, calls.clone())
+            .oneshot(preflight(ORIGIN))
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST, PUT"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, x-grpc-web, x-user-agent, grpc-timeout"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[header::VARY], "origin");
+    }
+
+    #[tokio::test]
+    async fn rejects_preflight_from_disallowed_origin() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = CorsLayer::new().allow_origins([HeaderValue::from_static(ORIGIN)]);
+        let response = service(layer, calls.clone())
+            .oneshot(preflight("https://evil.example"))
+            .await
+            .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn exposes_grpc_status_headers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let svc = service(CorsLayer::new().allow_any_origin(), calls.clone());
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(header::ORIGIN, ORIGIN)
+            .body(())
+            .unwrap();
+        let response = svc.clone().oneshot(request).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(response.body(), "hello");
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            "grpc-status, grpc-message, grpc-status-details-bin"
+        );
+
+        // Same-origin requests carry no CORS headers.
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn echoes_origin_with_credentials() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = CorsLayer::new().allow_any_origin().allow_credentials(true);
+        let request = Request::builder()
+            .header(header::ORIGIN, ORIGIN)
+            .body(())
+            .unwrap();
+        let response = service(layer, calls).oneshot(request).await.unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+}
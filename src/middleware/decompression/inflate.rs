@@ -0,0 +1,445 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A bounded DEFLATE decoder (RFC 1951) with the zlib (RFC 1950) and gzip
+//! (RFC 1952) wrappers used by the `deflate` and `gzip` content codings.
+//!
+//! Decoding works on a fully buffered input and stops as soon as the output
+//! would exceed a limit, so a small compressed body cannot expand into an
+//! unbounded allocation.
+
+use crate::middleware::checksum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Error {
+    /// The input is not valid compressed data.
+    Invalid(&'static str),
+    /// The output would exceed the limit.
+    TooLarge,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Decodes a zlib stream, as sent with `content-encoding: deflate`.
+pub(crate) fn zlib(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let [cmf, flg, ..] = *data else {
+        return Err(Error::Invalid("truncated zlib header"));
+    };
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err(Error::Invalid("invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(Error::Invalid("zlib preset dictionaries are not supported"));
+    }
+
+    let mut out = Vec::new();
+    let consumed = 2 + inflate(&data[2..], &mut out, limit)?;
+    let trailer = data
+        .get(consumed..consumed + 4)
+        .ok_or(Error::Invalid("truncated zlib trailer"))?;
+    if u32::from_be_bytes(trailer.try_into().unwrap()) != checksum::adler32(1, &out) {
+        return Err(Error::Invalid("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+/// Decodes one or more concatenated gzip members.
+pub(crate) fn gzip(mut data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let mut out = Vec::new();
+    loop {
+        let [0x1f, 0x8b, 8, flags, _, _, _, _, _, _, ..] = *data else {
+            return Err(Error::Invalid("invalid gzip header"));
+        };
+        let mut pos = 10;
+        if flags & FEXTRA != 0 {
+            let len = data
+                .get(pos..pos + 2)
+                .ok_or(Error::Invalid("truncated gzip header"))?;
+            pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let len = data
+                    .get(pos..)
+                    .and_then(|rest| rest.iter().position(|&b| b == 0))
+                    .ok_or(Error::Invalid("truncated gzip header"))?;
+                pos += len + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+        let member = data
+            .get(pos..)
+            .ok_or(Error::Invalid("truncated gzip header"))?;
+
+        let start = out.len();
+        let consumed = pos + inflate(member, &mut out, limit)?;
+        let trailer = data
+            .get(consumed..consumed + 8)
+            .ok_or(Error::Invalid("truncated gzip trailer"))?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != checksum::crc32(0, &out[start..]) || size != (out.len() - start) as u32 {
+            return Err(Error::Invalid("gzip checksum mismatch"));
+        }
+
+        data = &data[consumed + 8..];
+        if data.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decodes a raw DEFLATE stream into `out`, returning the number of input
+/// bytes consumed.
+fn inflate(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<usize> {
+    let mut bits = Bits::new(data);
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => stored(&mut bits, out, limit)?,
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                codes(&mut bits, out, limit, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, out, limit, &lengths, &distances)?;
+            }
+            _ => return Err(Error::Invalid("invalid deflate block type")),
+        }
+        if last {
+            return Ok(bits.consumed());
+        }
+    }
+}
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(Error::Invalid("truncated deflate stream"))?;
+            self.pos += 1;
+            self.buf |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Discards the bits left in the current byte.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::Invalid("truncated deflate stream"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Input bytes consumed, counting a partially read byte as consumed.
+    fn consumed(&self) -> usize {
+        self.pos
+    }
+}
+
+fn stored(bits: &mut Bits<'_>, out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    bits.align();
+    let header = bits.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(Error::Invalid("invalid stored block length"));
+    }
+    let len = usize::from(len);
+    if out.len() + len > limit {
+        return Err(Error::TooLarge);
+    }
+    out.extend_from_slice(bits.bytes(len)?);
+    Ok(())
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+
+        // Reject over-subscribed codes; incomplete codes are allowed and
+        // fail only if an unused code is read.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(Error::Invalid("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Invalid("invalid huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (
+        Huffman::new(&lengths).expect("fixed code is valid"),
+        Huffman::new(&[5; 30]).expect("fixed code is valid"),
+    )
+}
+
+fn dynamic_codes(bits: &mut Bits<'_>) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let nlen = bits.read(5)? as usize + 257;
+    let ndist = bits.read(5)? as usize + 1;
+    let ncode = bits.read(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(Error::Invalid("too many huffman codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in &ORDER[..ncode] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (len, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..16 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or(Error::Invalid("repeated length with no previous length"))?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        if lengths.len() + repeat as usize > nlen + ndist {
+            return Err(Error::Invalid("too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(Error::Invalid("missing end-of-block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+fn codes(
+    bits: &mut Bits<'_>,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<()> {
+    const LENGTH_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    const DISTANCE_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    const DISTANCE_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13,
+    ];
+
+    loop {
+        let symbol = usize::from(lengths.decode(bits)?);
+        if symbol < 256 {
+            if out.len() >= limit {
+                return Err(Error::TooLarge);
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(Error::Invalid("invalid length symbol"));
+        }
+        let len =
+            usize::from(LENGTH_BASE[symbol]) + bits.read(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+        let symbol = usize::from(distances.decode(bits)?);
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(Error::Invalid("invalid distance symbol"));
+        }
+        let distance = usize::from(DISTANCE_BASE[symbol])
+            + bits.read(u32::from(DISTANCE_EXTRA[symbol]))? as usize;
+        if distance > out.len() {
+            return Err(Error::Invalid("distance too far back"));
+        }
+        if out.len() + len > limit {
+            return Err(Error::TooLarge);
+        }
+
+        // The copy may overlap the bytes it produces.
+        let start = out.len() - distance;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `printf 'hello hello hello hello\n' | gzip -9n`
+    const HELLO_GZIP: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00, 0x00, 0x88, 0x59, 0x0b, 0x18, 0x00, 0x00, 0x00,
+    ];
+    const HELLO: &[u8] = b"hello hello hello hello\n";
+
+    #[test]
+    fn decodes_gzip() {
+        assert_eq!(gzip(HELLO_GZIP, 1024).unwrap(), HELLO);
+
+        // Concatenated members decode to the concatenated contents.
+        let twice = [HELLO_GZIP, HELLO_GZIP].concat();
+        assert_eq!(gzip(&twice, 1024).unwrap(), [HELLO, HELLO].concat());
+    }
+
+    #[test]
+    fn decodes_zlib_dynamic_blocks() {
+        // Python's `zlib.compress(PANGRAMS, 9)`, a single dynamic block.
+        const PANGRAMS: &[u8] = b"The quick brown fox jumps over the lazy dog. \
+            The quick brown fox jumps over the lazy dog. \
+            The quick brown fox jumps over the lazy dog. \
+            Pack my box with five dozen liquor jugs! 0123456789 abcdefghijklmnopqrstuvwxyz";
+        const COMPRESSED: &[u8] = &[
+            0x78, 0xda, 0xb5, 0xcb, 0xd7, 0x19, 0x83, 0x20, 0x00, 0x45, 0xe1, 0x55, 0x6e, 0x16,
+            0xc8, 0x97, 0x62, 0xda, 0x16, 0x79, 0x70, 0x01, 0x50, 0x9a, 0x52, 0xa4, 0x89, 0x30,
+            0x7d, 0x58, 0x22, 0xcf, 0xe7, 0x3f, 0xa3, 0x64, 0xf0, 0x59, 0x4d, 0x2b, 0x68, 0x70,
+            0xc5, 0x82, 0xbb, 0x03, 0x4b, 0x36, 0x5b, 0x84, 0xdb, 0x59, 0x40, 0xea, 0x59, 0x93,
+            0x56, 0x31, 0x3b, 0x71, 0xc6, 0xf8, 0x37, 0xfc, 0x25, 0xdd, 0x99, 0x0a, 0xda, 0x51,
+            0x51, 0x49, 0x82, 0xab, 0x9d, 0xf5, 0xd4, 0x98, 0x85, 0x56, 0x3e, 0xbb, 0xd0, 0x5f,
+            0x11, 0x4f, 0xb8, 0x5c, 0x6f, 0xf7, 0xe1, 0xf1, 0x7c, 0xbd, 0x3f, 0x20, 0x74, 0x9a,
+            0x19, 0x17, 0x52, 0x2d, 0xab, 0x36, 0xd6, 0x6d, 0x3e, 0xc4, 0x94, 0xf7, 0x72, 0xd4,
+            0xf6, 0x03, 0xe8, 0x64, 0x4c, 0x6c,
+        ];
+        assert_eq!(zlib(COMPRESSED, 1024).unwrap(), PANGRAMS);
+    }
+
+    #[test]
+    fn decodes_zlib_stored_blocks() {
+        // A zlib stream with a single stored block.
+        let mut data = vec![0x78, 0x01, 0x01];
+        data.extend_from_slice(&(HELLO.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(HELLO.len() as u16)).to_le_bytes());
+        data.extend_from_slice(HELLO);
+        data.extend_from_slice(&checksum::adler32(1, HELLO).to_be_bytes());
+        assert_eq!(zlib(&data, 1024).unwrap(), HELLO);
+
+        *data.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            zlib(&data, 1024),
+            Err(Error::Invalid("zlib checksum mismatch"))
+        );
+    }
+
+    #[test]
+    fn enforces_the_limit() {
+        assert_eq!(gzip(HELLO_GZIP, HELLO.len()).unwrap(), HELLO);
+        assert_eq!(gzip(HELLO_GZIP, HELLO.len() - 1), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        assert!(gzip(&HELLO_GZIP[..20], 1024).is_err());
+        let mut corrupt = HELLO_GZIP.to_vec();
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert_eq!(
+            gzip(&corrupt, 1024),
+            Err(Error::Invalid("gzip checksum mismatch"))
+        );
+    }
+}
@@ -0,0 +1,493 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent decompression of request bodies.
+//!
+//! [`RequestDecompressionLayer`] decodes request bodies sent with a
+//! `gzip` or `deflate` `content-encoding`. The inner service then sees the
+//! plain body, with the `content-encoding` header removed and
+//! `content-length` set to the decoded length. Requests without a
+//! `content-encoding` (or with `identity`) are passed through untouched.
+//!
+//! Compressed bodies are buffered and decoded before the inner service is
+//! called. Both the compressed and the decoded body are capped at a
+//! configurable size, and decoding stops as soon as the output would exceed
+//! it. A small body that expands into gigabytes (a "decompression bomb")
+//! is therefore rejected with `413 Payload Too Large` after at most the cap
+//! has been allocated. Other rejections are:
+//!
+//! * `415 Unsupported Media Type`, with an `accept-encoding` header listing
+//!   the supported codings, for codings that cannot be decoded;
+//! * `400 Bad Request` for corrupt compressed data.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::decompression::RequestDecompressionLayer;
+//!
+//! // Accept compressed JSON uploads of up to 16 MiB once decompressed.
+//! let layer = RequestDecompressionLayer::new().max_size(16 << 20);
+//! # let _ = layer;
+//! ```
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http::request;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+pub(crate) mod inflate;
+
+/// The default cap on compressed and decoded request bodies: 8 MiB.
+const DEFAULT_MAX_SIZE: usize = 8 << 20;
+
+const ACCEPT_ENCODING: HeaderValue = HeaderValue::from_static("gzip, deflate");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn decode(self, data: &[u8], limit: usize) -> Result<Vec<u8>, inflate::Error> {
+        match self {
+            Self::Gzip => inflate::gzip(data, limit),
+            Self::Deflate => inflate::zlib(data, limit),
+        }
+    }
+}
+
+/// The codings applied to a body, in the order they were applied, or
+/// `None` if any of them is not supported.
+fn parse_encodings(value: &HeaderValue) -> Option<Vec<Encoding>> {
+    value
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"))
+        .map(|coding| {
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                Some(Encoding::Gzip)
+            } else if coding.eq_ignore_ascii_case("deflate") {
+                Some(Encoding::Deflate)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// [`Layer`] that decompresses request bodies; see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct RequestDecompressionLayer {
+    max_size: usize,
+}
+
+impl Default for RequestDecompressionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestDecompressionLayer {
+    pub fn new() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Reject compressed bodies larger than `max_size` bytes, either as sent
+    /// or once decoded. Defaults to 8 MiB.
+    pub fn max_size(self, max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+impl<S> Layer<S> for RequestDecompressionLayer {
+    type Service = RequestDecompression<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestDecompression {
+            inner,
+            max_size: self.max_size,
+        }
+    }
+}
+
+/// Service returned by [`RequestDecompressionLayer`].
+///
+/// The inner service must be `Clone`: compressed bodies are buffered before
+/// it is called, so it is driven to readiness inside the response future.
+#[derive(Debug, Clone)]
+pub struct RequestDecompression<S> {
+    inner: S,
+    max_size: usize,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for RequestDecompression<S>
+where
+    S: Service<Request<DecompressedBody<RequestBody>>, Response = Response<ResponseBody>> + Clone,
+    RequestBody: Body<Data = Bytes>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, RequestBody>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let Some(value) = request.headers().get(header::CONTENT_ENCODING) else {
+            return ResponseFuture::inner(self.inner.clone(), request.map(DecompressedBody::inner));
+        };
+        let Some(encodings) = parse_encodings(value) else {
+            tracing::debug!(?value, "unsupported request content-encoding");
+            return ResponseFuture::respond(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        };
+        if encodings.is_empty() {
+            return ResponseFuture::inner(self.inner.clone(), request.map(DecompressedBody::inner));
+        }
+
+        let (parts, body) = request.into_parts();
+        ResponseFuture {
+            state: State::Collect {
+                body,
+                buf: BytesMut::new(),
+                parts: Some(parts),
+                service: Some(self.inner.clone()),
+                encodings,
+                max_size: self.max_size,
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`RequestDecompression`].
+    pub struct ResponseFuture<S, B>
+    where
+        S: Service<Request<DecompressedBody<B>>>,
+    {
+        #[pin]
+        state: State<S, B>,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<S, B>
+    where
+        S: Service<Request<DecompressedBody<B>>>,
+    {
+        Collect {
+            #[pin]
+            body: B,
+            buf: BytesMut,
+            parts: Option<request::Parts>,
+            service: Option<S>,
+            encodings: Vec<Encoding>,
+            max_size: usize,
+        },
+        Inner {
+            #[pin]
+            future: Oneshot<S, Request<DecompressedBody<B>>>,
+        },
+        Respond {
+            status: StatusCode,
+        },
+    }
+}
+
+impl<S, B> ResponseFuture<S, B>
+where
+    S: Service<Request<DecompressedBody<B>>>,
+{
+    fn inner(service: S, request: Request<DecompressedBody<B>>) -> Self {
+        Self {
+            state: State::Inner {
+                future: service.oneshot(request),
+            },
+        }
+    }
+
+    fn respond(status: StatusCode) -> Self {
+        Self {
+            state: State::Respond { status },
+        }
+    }
+}
+
+impl<S, B, ResponseBody> Future for ResponseFuture<S, B>
+where
+    S: Service<Request<DecompressedBody<B>>, Response = Response<ResponseBody>>,
+    B: Body<Data = Bytes>,
+    ResponseBody: Default,
+{
+    type Output = Result<Response<ResponseBody>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            let next = match this.state.as_mut().project() {
+                StateProj::Collect {
+                    mut body,
+                    buf,
+                    parts,
+                    service,
+                    encodings,
+                    max_size,
+                } => match ready!(body.as_mut().poll_frame(cx)) {
+                    Some(Ok(frame)) => {
+                        // Trailers of compressed bodies are dropped.
+                        if let Ok(data) = frame.into_data() {
+                            if buf.len() + data.len() > *max_size {
+                                State::Respond {
+                                    status: StatusCode::PAYLOAD_TOO_LARGE,
+                                }
+                            } else {
+                                buf.extend_from_slice(&data);
+                                continue;
+                            }
+                        } else {
+                            continue;
+                        }
+                    }
+                    Some(Err(_)) => State::Respond {
+                        status: StatusCode::BAD_REQUEST,
+                    },
+                    None => match decode(buf, encodings, *max_size) {
+                        Ok(decoded) => {
+                            let mut parts = parts.take().expect("polled after completion");
+                            parts.headers.remove(header::CONTENT_ENCODING);
+                            parts
+                                .headers
+                                .insert(header::CONTENT_LENGTH, decoded.len().into());
+                            let request = Request::from_parts(
+                                parts,
+                                DecompressedBody::Decoded {
+                                    data: Some(decoded),
+                                },
+                            );
+                            let service = service.take().expect("polled after completion");
+                            State::Inner {
+                                future: service.oneshot(request),
+                            }
+                        }
+                        Err(status) => State::Respond { status },
+                    },
+                },
+                StateProj::Inner { future } => return future.poll(cx),
+                StateProj::Respond { status } => {
+                    let mut response = Response::new(ResponseBody::default());
+                    *response.status_mut() = *status;
+                    if *status == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                        response
+                            .headers_mut()
+                            .insert(header::ACCEPT_ENCODING, ACCEPT_ENCODING);
+                    }
+                    return Poll::Ready(Ok(response));
+                }
+            };
+            this.state.set(next);
+        }
+    }
+}
+
+/// Undoes `encodings`, last applied first.
+fn decode(
+    buf: &mut BytesMut,
+    encodings: &[Encoding],
+    max_size: usize,
+) -> Result<Bytes, StatusCode> {
+    let mut data = buf.split().freeze();
+    for encoding in encodings.iter().rev() {
+        data = match encoding.decode(&data, max_size) {
+            Ok(decoded) => decoded.into(),
+            Err(inflate::Error::TooLarge) => {
+                tracing::debug!(max_size, "decompressed request body too large");
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Err(inflate::Error::Invalid(reason)) => {
+                tracing::debug!(reason, "invalid compressed request body");
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        };
+    }
+    Ok(data)
+}
+
+pin_project! {
+    /// Request body for [`RequestDecompression`]: either the original body,
+    /// when it was not compressed, or the decoded body.
+    #[project = DecompressedBodyProj]
+    pub enum DecompressedBody<B> {
+        Inner {
+            #[pin]
+            body: B,
+        },
+        Decoded {
+            data: Option<Bytes>,
+        },
+    }
+}
+
+impl<B> DecompressedBody<B> {
+    fn inner(body: B) -> Self {
+        Self::Inner { body }
+    }
+}
+
+impl<B> Body for DecompressedBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
+            DecompressedBodyProj::Inner { body } => body.poll_frame(cx),
+            DecompressedBodyProj::Decoded { data } => {
+                Poll::Ready(data.take().map(|data| Ok(Frame::data(data))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Self::Inner { body } => body.is_end_stream(),
+            Self::Decoded { data } => data.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match self {
+            Self::Inner { body } => body.size_hint(),
+            Self::Decoded { data } => {
+                http_body::SizeHint::with_exact(data.as_ref().map_or(0, |data| data.len() as u64))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+
+    // `printf 'hello hello hello hello\n' | gzip -9n`
+    const HELLO_GZIP: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00, 0x00, 0x88, 0x59, 0x0b, 0x18, 0x00, 0x00, 0x00,
+    ];
+
+    /// A service echoing the body it receives and its content headers.
+    fn service(
+        layer: RequestDecompressionLayer,
+    ) -> impl Service<Request<Full<Bytes>>, Response = Response<String>, Error = Infallible> {
+        layer.layer(tower::service_fn(
+            |request: Request<DecompressedBody<Full<Bytes>>>| async move {
+                if let Some(encoding) = request.headers().get(header::CONTENT_ENCODING) {
+                    assert_eq!(encoding, "identity");
+                }
+                let length = request.headers().get(header::CONTENT_LENGTH).cloned();
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                if let Some(length) = length {
+                    assert_eq!(length, body.len().to_string().as_str());
+                }
+                Ok(Response::new(String::from_utf8(body.to_vec()).unwrap()))
+            },
+        ))
+    }
+
+    fn request(encoding: Option<&str>, body: &'static [u8]) -> Request<Full<Bytes>> {
+        let mut request = Request::post("/upload");
+        if let Some(encoding) = encoding {
+            request = request.header(header::CONTENT_ENCODING, encoding);
+        }
+        request.body(Full::new(Bytes::from_static(body))).unwrap()
+    }
+
+    #[test]
+    fn parses_content_encodings() {
+        let parse = |value| parse_encodings(&HeaderValue::from_static(value));
+        assert_eq!(parse("gzip"), Some(vec![Encoding::Gzip]));
+        assert_eq!(
+            parse("deflate, identity, X-GZIP"),
+            Some(vec![Encoding::Deflate, Encoding::Gzip])
+        );
+        assert_eq!(parse("identity"), Some(vec![]));
+        assert_eq!(parse("gzip, br"), None);
+    }
+
+    #[tokio::test]
+    async fn decompresses_gzip_bodies() {
+        let response = service(RequestDecompressionLayer::new())
+            .oneshot(request(Some("gzip"), HELLO_GZIP))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "hello hello hello hello\n");
+    }
+
+    #[tokio::test]
+    async fn passes_through_uncompressed_bodies() {
+        for encoding in [None, Some("identity")] {
+            let response = service(RequestDecompressionLayer::new())
+                .oneshot(request(encoding, b"plain"))
+                .await
+                .unwrap();
+            assert_eq!(response.body(), "plain");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_limit() {
+        let response = service(RequestDecompressionLayer::new().max_size(10))
+            .oneshot(request(Some("gzip"), HELLO_GZIP))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_and_corrupt_bodies() {
+        let response = service(RequestDecompressionLayer::new())
+            .oneshot(request(Some("br"), b"..."))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(response.headers()[header::ACCEPT_ENCODING], "gzip, deflate");
+
+        let response = service(RequestDecompressionLayer::new())
+            .oneshot(request(Some("gzip"), &HELLO_GZIP[..20]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,472 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adapters for bridging the error types of tower services.
+//!
+//! Third-party services and layers each come with their own error type,
+//! while this crate expects either a [`BoxError`] or an infallible service.
+//! [`MapErrLayer`] erases any error convertible into a [`BoxError`], and
+//! [`ErrIntoResponseLayer`] turns errors into responses so that the
+//! resulting service never fails.
+//!
+//! The server itself never lets a service error tear down a connection:
+//! errors that reach it are turned into responses by an [`ErrorHandler`],
+//! configured with [`Builder::error_handler`], so that the other requests
+//! on the connection carry on.
+//!
+//! [`BoxError`]: crate::BoxError
+//! [`Builder::error_handler`]: crate::Builder::error_handler
+
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::request;
+use pin_project_lite::pin_project;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+use crate::BoxError;
+use crate::body::BoxBody;
+use crate::middleware::callback::is_grpc;
+use crate::middleware::stack::StackService;
+use crate::middleware::trailers::grpc_trailers_only;
+
+const GRPC_INTERNAL_CODE: i32 = 13;
+
+type ErrorHandlerFn = dyn Fn(BoxError, &request::Parts) -> Response<BoxBody> + Send + Sync;
+
+/// [`Layer`] that converts the inner service's error into a [`BoxError`].
+///
+/// Unlike `tower::util::MapErrLayer` no conversion function is needed: any
+/// error implementing `Into<BoxError>` is boxed.
+///
+/// [`BoxError`]: crate::BoxError
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MapErrLayer;
+
+impl MapErrLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for MapErrLayer {
+    type Service = MapErr<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MapErr { inner }
+    }
+}
+
+/// Service returned by [`MapErrLayer`].
+#[derive(Debug, Clone, Copy)]
+pub struct MapErr<S> {
+    inner: S,
+}
+
+impl<S> MapErr<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, Request> Service<Request> for MapErr<S>
+where
+    S: Service<Request>,
+    S::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = MapErrFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        MapErrFuture {
+            inner: self.inner.call(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`MapErr`].
+    pub struct MapErrFuture<F> {
+        #[pin]
+        inner: F,
+    }
+}
+
+impl<F, T, E> Future for MapErrFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().inner.poll(cx).map_err(Into::into)
+    }
+}
+
+/// [`Layer`] that converts errors from the inner service into responses,
+/// producing a service that never fails.
+///
+/// By default an error becomes an empty `500 Internal Server Error`
+/// response; use [`ErrIntoResponseLayer::new`] to build the response
+/// yourself.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrIntoResponseLayer<F = DefaultErrorResponse> {
+    f: F,
+}
+
+impl ErrIntoResponseLayer {
+    /// Convert errors into empty `500 Internal Server Error` responses.
+    pub fn internal_server_error() -> Self {
+        Self {
+            f: DefaultErrorResponse,
+        }
+    }
+}
+
+impl Default for ErrIntoResponseLayer {
+    fn default() -> Self {
+        Self::internal_server_error()
+    }
+}
+
+impl<F> ErrIntoResponseLayer<F> {
+    /// Convert errors into responses using `f`.
+    pub fn new(f: F) -> Self {
+        Self { f }
+    }
+}
+
+impl<S, F: Clone> Layer<S> for ErrIntoResponseLayer<F> {
+    type Service = ErrIntoResponse<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ErrIntoResponse {
+            inner,
+            f: self.f.clone(),
+        }
+    }
+}
+
+/// Converts an error into a response.
+///
+/// Implemented for every `FnMut(E) -> Response<B>` closure.
+pub trait MakeErrorResponse<E, B> {
+    fn make_response(&mut self, error: E) -> Response<B>;
+}
+
+impl<F, E, B> MakeErrorResponse<E, B> for F
+where
+    F: FnMut(E) -> Response<B>,
+{
+    fn make_response(&mut self, error: E) -> Response<B> {
+        self(error)
+    }
+}
+
+/// The default [`MakeErrorResponse`] used by [`ErrIntoResponseLayer`]:
+/// logs the error and responds with an empty `500 Internal Server Error`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultErrorResponse;
+
+impl<E, B> MakeErrorResponse<E, B> for DefaultErrorResponse
+where
+    E: std::fmt::Display,
+    B: Default,
+{
+    fn make_response(&mut self, error: E) -> Response<B> {
+        tracing::debug!(%error, "service error converted into response");
+        let mut response = Response::new(B::default());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    }
+}
+
+/// Service returned by [`ErrIntoResponseLayer`].
+///
+/// The inner service must be `Clone`: it is driven to readiness inside the
+/// response future so that readiness errors become responses too.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrIntoResponse<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F> ErrIntoResponse<S, F> {
+    pub fn new(inner: S, f: F) -> Self {
+        Self { inner, f }
+    }
+}
+
+impl<S, F, Request, ResponseBody> Service<Request> for ErrIntoResponse<S, F>
+where
+    S: Service<Request, Response = Response<ResponseBody>> + Clone,
+    F: MakeErrorResponse<S::Error, ResponseBody> + Clone,
+{
+    type Response = Response<ResponseBody>;
+    type Error = Infallible;
+    type Future = ErrIntoResponseFuture<Oneshot<S, Request>, F>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Readiness is driven inside the response future so that an error
+        // from the inner service's `poll_ready` is converted into a
+        // response as well.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+
+        ErrIntoResponseFuture {
+            inner: inner.oneshot(request),
+            f: Some(self.f.clone()),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`ErrIntoResponse`].
+    pub struct ErrIntoResponseFuture<Fut, F> {
+        #[pin]
+        inner: Fut,
+        f: Option<F>,
+    }
+}
+
+impl<Fut, F, B, E> Future for ErrIntoResponseFuture<Fut, F>
+where
+    Fut: Future<Output = Result<Response<B>, E>>,
+    F: MakeErrorResponse<E, B>,
+{
+    type Output = Result<Response<B>, Infallible>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let response = result.unwrap_or_else(|error| {
+            this.f
+                .take()
+                .expect("polled after completion")
+                .make_response(error)
+        });
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Turns the errors of the served service into responses; see
+/// [`Builder::error_handler`](crate::Builder::error_handler).
+///
+/// The handler is given the error along with the head of the request that
+/// failed. By default the error is logged and answered with an empty
+/// `500 Internal Server Error`, or for gRPC requests a trailers-only
+/// response with `grpc-status` 13 (`INTERNAL`).
+#[derive(Clone)]
+pub struct ErrorHandler(Arc<ErrorHandlerFn>);
+
+impl ErrorHandler {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(BoxError, &request::Parts) -> Response<BoxBody> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// The default handler, an `INTERNAL` error for gRPC requests and a
+    /// `500 Internal Server Error` otherwise.
+    pub fn internal_error() -> Self {
+        Self::new(internal_error)
+    }
+
+    pub(crate) fn handle(&self, error: BoxError, parts: &request::Parts) -> Response<BoxBody> {
+        (self.0)(error, parts)
+    }
+
+    /// Wraps `service`, so that its errors are turned into responses.
+    pub(crate) fn layer(&self, service: StackService) -> HandleError {
+        HandleError {
+            inner: service,
+            handler: self.clone(),
+        }
+    }
+}
+
+impl Default for ErrorHandler {
+    fn default() -> Self {
+        Self::internal_error()
+    }
+}
+
+impl std::fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorHandler").finish_non_exhaustive()
+    }
+}
+
+fn internal_error(error: BoxError, parts: &request::Parts) -> Response<BoxBody> {
+    tracing::error!(
+        %error,
+        method = %parts.method,
+        uri = %parts.uri,
+        "service error converted into response"
+    );
+    if is_grpc(&parts.headers) {
+        grpc_trailers_only(GRPC_INTERNAL_CODE, Some("internal error"))
+    } else {
+        let mut response = Response::new(BoxBody::default());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    }
+}
+
+/// Copies the head of `request`, for responding once the request itself
+/// has been handed to the inner service.
+pub(crate) fn clone_parts<B>(request: &Request<B>) -> request::Parts {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.method = request.method().clone();
+    parts.uri = request.uri().clone();
+    parts.version = request.version();
+    parts.headers = request.headers().clone();
+    parts.extensions = request.extensions().clone();
+    parts
+}
+
+/// The served service, wrapped by [`ErrorHandler::layer`].
+#[derive(Clone)]
+pub(crate) struct HandleError {
+    inner: StackService,
+    handler: ErrorHandler,
+}
+
+impl Service<Request<BoxBody>> for HandleError {
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = HandleErrorFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // As in `ErrIntoResponse`, readiness is driven inside the response
+        // future so that readiness errors become responses too.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let parts = clone_parts(&request);
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+
+        HandleErrorFuture {
+            inner: inner.oneshot(request),
+            handler: self.handler.clone(),
+            parts: Some(Box::new(parts)),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`HandleError`].
+    pub(crate) struct HandleErrorFuture {
+        #[pin]
+        inner: Oneshot<StackService, Request<BoxBody>>,
+        handler: ErrorHandler,
+        parts: Option<Box<request::Parts>>,
+    }
+}
+
+impl Future for HandleErrorFuture {
+    type Output = Result<Response<BoxBody>, Infallible>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let response = result.unwrap_or_else(|error| {
+            let parts = this.parts.take().expect("polled after completion");
+            this.handler.handle(error, &parts)
+        });
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::Request;
+    use http_body_util::Full;
+    use tower::ServiceBuilder;
+
+    #[derive(Debug)]
+    struct TypedError;
+
+    impl std::fmt::Display for TypedError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("typed error")
+        }
+    }
+
+    impl std::error::Error for TypedError {}
+
+    fn failing()
+    -> impl Service<Request<()>, Response = Response<Full<Bytes>>, Error = TypedError, Future: Send>
+    + Clone {
+        tower::service_fn(|_: Request<()>| async { Err(TypedError) })
+    }
+
+    #[tokio::test]
+    async fn map_err_boxes_errors() {
+        let svc = ServiceBuilder::new().layer(MapErrLayer).service(failing());
+
+        let error: BoxError = svc.oneshot(Request::new(())).await.unwrap_err();
+        assert!(error.is::<TypedError>());
+    }
+
+    #[tokio::test]
+    async fn err_into_response_defaults_to_500() {
+        let svc = ServiceBuilder::new()
+            .layer(ErrIntoResponseLayer::default())
+            .service(failing());
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn err_into_response_uses_custom_response() {
+        let svc = ServiceBuilder::new()
+            .layer(ErrIntoResponseLayer::new(|error: TypedError| {
+                let mut response = Response::new(Full::new(Bytes::from(error.to_string())));
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response
+            }))
+            .service(failing());
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn err_into_response_passes_through_success() {
+        let svc = ServiceBuilder::new()
+            .layer(ErrIntoResponseLayer::default())
+            .service(tower::service_fn(|_: Request<()>| async {
+                Ok::<_, TypedError>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+            }));
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
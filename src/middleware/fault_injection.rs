@@ -0,0 +1,430 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fault injection for chaos testing.
+//!
+//! [`FaultInjectionLayer`] injects [`Fault`]s into a configurable fraction
+//! of the requests matching a predicate, so that clients' retry and timeout
+//! logic can be exercised against a real server. Never enable it in
+//! production.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::fault_injection::Fault;
+//! use sui_http::middleware::fault_injection::FaultInjectionLayer;
+//!
+//! let layer = FaultInjectionLayer::new()
+//!     .when(|parts| parts.uri.path().starts_with("/api/"))
+//!     .fault(0.05, Fault::Latency(Duration::from_secs(2)))
+//!     .fault(0.01, Fault::Error(http::StatusCode::SERVICE_UNAVAILABLE))
+//!     .fault(0.01, Fault::Truncate(1024));
+//! # let _ = layer;
+//! ```
+
+use bytes::Bytes;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tokio::time::Sleep;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+use crate::BoxError;
+
+/// A fault to inject into a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Delay the request by this long before handling it.
+    Latency(Duration),
+    /// Respond with an empty response with this status without calling the
+    /// inner service.
+    Error(StatusCode),
+    /// Fail the response body before sending any data, which resets the
+    /// stream (HTTP/2) or closes the connection (HTTP/1).
+    Abort,
+    /// End the response body cleanly after this many bytes, dropping the
+    /// rest of the data and any trailers.
+    Truncate(usize),
+}
+
+type Predicate = Arc<dyn Fn(&http::request::Parts) -> bool + Send + Sync>;
+
+/// [`Layer`] that injects [`Fault`]s into requests.
+///
+/// See the [module docs](self) for an example.
+#[derive(Clone, Default)]
+pub struct FaultInjectionLayer {
+    predicate: Option<Predicate>,
+    faults: Arc<Vec<(f64, Fault)>>,
+}
+
+impl std::fmt::Debug for FaultInjectionLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FaultInjectionLayer")
+            .field("faults", &self.faults)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FaultInjectionLayer {
+    /// A layer that injects no faults until some are added with
+    /// [`FaultInjectionLayer::fault`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only inject faults into requests for which `predicate` returns
+    /// `true`. By default every request is eligible.
+    pub fn when<F>(self, predicate: F) -> Self
+    where
+        F: Fn(&http::request::Parts) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            predicate: Some(Arc::new(predicate)),
+            ..self
+        }
+    }
+
+    /// Inject `fault` into the given fraction (between `0.0` and `1.0`) of
+    /// eligible requests.
+    ///
+    /// At most one fault is injected per request; the fractions of all
+    /// faults should add up to at most `1.0`.
+    pub fn fault(mut self, fraction: f64, fault: Fault) -> Self {
+        Arc::make_mut(&mut self.faults).push((fraction.clamp(0.0, 1.0), fault));
+        self
+    }
+}
+
+impl<S> Layer<S> for FaultInjectionLayer {
+    type Service = FaultInjection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FaultInjection {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`FaultInjectionLayer`].
+///
+/// The inner service must be `Clone`, as it is only driven to readiness
+/// once any injected latency has elapsed.
+#[derive(Debug, Clone)]
+pub struct FaultInjection<S> {
+    inner: S,
+    layer: FaultInjectionLayer,
+}
+
+impl<S> FaultInjection<S> {
+    fn choose_fault(&self, parts: &http::request::Parts) -> Option<Fault> {
+        if self.layer.faults.is_empty()
+            || !self
+                .layer
+                .predicate
+                .as_ref()
+                .is_none_or(|predicate| predicate(parts))
+        {
+            return None;
+        }
+
+        let mut roll = random_fraction();
+        for (fraction, fault) in self.layer.faults.iter() {
+            if roll < *fraction {
+                return Some(fault.clone());
+            }
+            roll -= fraction;
+        }
+        None
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for FaultInjection<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    ResponseBody: Body<Data = Bytes>,
+    ResponseBody::Error: Into<BoxError>,
+{
+    type Response = Response<FaultBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Request<RequestBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let (parts, body) = request.into_parts();
+        let fault = self.choose_fault(&parts);
+        if let Some(fault) = &fault {
+            tracing::debug!(?fault, path = parts.uri.path(), "injecting fault");
+        }
+        let request = Request::from_parts(parts, body);
+
+        let service = self.inner.clone();
+        let (sleep, body_fault) = match fault {
+            None => (None, BodyFault::None),
+            Some(Fault::Latency(delay)) => (Some(tokio::time::sleep(delay)), BodyFault::None),
+            Some(Fault::Error(status)) => return ResponseFuture::Respond { status },
+            Some(Fault::Abort) => (None, BodyFault::Abort),
+            Some(Fault::Truncate(bytes)) => (None, BodyFault::Truncate(bytes)),
+        };
+
+        ResponseFuture::Inner {
+            sleep,
+            future: service.oneshot(request),
+            body_fault,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`FaultInjection`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<S, Req>
+    where
+        S: Service<Req>,
+    {
+        Inner {
+            #[pin]
+            sleep: Option<Sleep>,
+            #[pin]
+            future: Oneshot<S, Req>,
+            body_fault: BodyFault,
+        },
+        Respond {
+            status: StatusCode,
+        },
+    }
+}
+
+impl<S, Req, B> Future for ResponseFuture<S, Req>
+where
+    S: Service<Req, Response = Response<B>>,
+{
+    type Output = Result<Response<FaultBody<B>>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner {
+                mut sleep,
+                future,
+                body_fault,
+            } => {
+                if let Some(delay) = sleep.as_mut().as_pin_mut() {
+                    ready!(delay.poll(cx));
+                    sleep.set(None);
+                }
+
+                let response = ready!(future.poll(cx))?;
+                let body_fault = *body_fault;
+                Poll::Ready(Ok(response.map(|inner| FaultBody {
+                    inner: Some(inner),
+                    fault: body_fault,
+                })))
+            }
+            ResponseFutureProj::Respond { status } => {
+                let mut response = Response::new(FaultBody {
+                    inner: None,
+                    fault: BodyFault::None,
+                });
+                *response.status_mut() = *status;
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum BodyFault {
+    None,
+    Abort,
+    Truncate(usize),
+}
+
+pin_project! {
+    /// Response body for [`FaultInjection`].
+    pub struct FaultBody<B> {
+        #[pin]
+        inner: Option<B>,
+        fault: BodyFault,
+    }
+}
+
+impl<B> Body for FaultBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let Some(inner) = this.inner.as_mut().as_pin_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match this.fault {
+            BodyFault::None => inner.poll_frame(cx).map_err(Into::into),
+            BodyFault::Abort => {
+                this.inner.set(None);
+                Poll::Ready(Some(Err("fault injection: response aborted".into())))
+            }
+            BodyFault::Truncate(remaining) => {
+                if *remaining == 0 {
+                    this.inner.set(None);
+                    return Poll::Ready(None);
+                }
+
+                match ready!(inner.poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(mut data) => {
+                            data.truncate(*remaining);
+                            *remaining -= data.len();
+                            Poll::Ready(Some(Ok(Frame::data(data))))
+                        }
+                        // Trailers would mark the body as complete.
+                        Err(_trailers) => {
+                            this.inner.set(None);
+                            Poll::Ready(None)
+                        }
+                    },
+                    Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
+                    None => Poll::Ready(None),
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => matches!(self.fault, BodyFault::None) && inner.is_end_stream(),
+            None => true,
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match (&self.inner, self.fault) {
+            (None, _) => http_body::SizeHint::with_exact(0),
+            (Some(inner), BodyFault::None) => inner.size_hint(),
+            // Never advertise a content-length the body will not honor.
+            (Some(_), _) => http_body::SizeHint::new(),
+        }
+    }
+}
+
+/// A uniformly distributed value in `[0, 1)`.
+///
+/// Seeded from the standard library's randomly keyed hasher, which is good
+/// enough for sampling requests and avoids a dependency on a RNG crate.
+fn random_fraction() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+
+    fn service(
+        layer: FaultInjectionLayer,
+    ) -> FaultInjection<
+        impl Service<Request<()>, Response = Response<Full<Bytes>>, Error = Infallible, Future: Send>
+        + Clone,
+    > {
+        layer.layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"hello world"))))
+        }))
+    }
+
+    #[tokio::test]
+    async fn no_faults_by_default() {
+        let response = service(FaultInjectionLayer::new())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello world");
+    }
+
+    #[tokio::test]
+    async fn error_fault() {
+        let layer = FaultInjectionLayer::new().fault(1.0, Fault::Error(StatusCode::BAD_GATEWAY));
+        let response = service(layer).oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn latency_fault() {
+        let layer =
+            FaultInjectionLayer::new().fault(1.0, Fault::Latency(Duration::from_millis(100)));
+        let start = tokio::time::Instant::now();
+        service(layer).oneshot(Request::new(())).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn abort_and_truncate_faults() {
+        let layer = FaultInjectionLayer::new().fault(1.0, Fault::Abort);
+        let response = service(layer).oneshot(Request::new(())).await.unwrap();
+        assert!(response.into_body().collect().await.is_err());
+
+        let layer = FaultInjectionLayer::new().fault(1.0, Fault::Truncate(5));
+        let response = service(layer).oneshot(Request::new(())).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+    }
+
+    #[tokio::test]
+    async fn predicate_limits_eligible_requests() {
+        let layer = FaultInjectionLayer::new()
+            .when(|parts| parts.uri.path() == "/chaos")
+            .fault(1.0, Fault::Error(StatusCode::BAD_GATEWAY));
+        let svc = service(layer);
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::get("/chaos").body(()).unwrap();
+        let response = svc.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn random_fraction_is_in_range() {
+        for _ in 0..1000 {
+            let value = random_fraction();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+}
@@ -9,28 +9,118 @@ use http::HeaderName;
 use http::HeaderValue;
 use http::Request;
 use http::Response;
+use http::StatusCode;
+use http::request;
 use pin_project_lite::pin_project;
+use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
 use std::task::ready;
 use std::time::Duration;
+use tokio::time::Instant;
 use tokio::time::Sleep;
 use tower::Service;
 
+use super::error::clone_parts;
+use super::trailers::grpc_trailers_only;
+use crate::body::Either;
+use crate::body::Empty;
+
 const GRPC_TIMEOUT_HEADER: HeaderName = HeaderName::from_static("grpc-timeout");
-const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
-const GRPC_MESSAGE_HEADER: HeaderName = HeaderName::from_static("grpc-message");
 
-const GRPC_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/grpc");
-const GRPC_DEADLINE_EXCEEDED_CODE: HeaderValue = HeaderValue::from_static("4");
-const GRPC_DEADLINE_EXCEEDED_MESSAGE: HeaderValue = HeaderValue::from_static("Timeout%20expired");
+const GRPC_DEADLINE_EXCEEDED_CODE: i32 = 4;
+
+/// Request extension holding the deadline [`GrpcTimeout`] enforces for the
+/// request: the shorter of the client's `grpc-timeout` and the server's
+/// timeout.
+///
+/// Handlers can check the remaining budget before starting expensive work,
+/// and pass it on to downstream calls with [`Deadline::grpc_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn new(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    pub fn instant(self) -> Instant {
+        self.0
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(self) -> bool {
+        self.0 <= Instant::now()
+    }
+
+    /// The remaining time as a `grpc-timeout` header value, for propagating
+    /// the deadline to downstream gRPC calls.
+    pub fn grpc_timeout(self) -> HeaderValue {
+        format_grpc_timeout(self.remaining())
+    }
+}
+
+/// What [`GrpcTimeout`] responds with once a request's deadline expires.
+///
+/// Defaults to [`OnTimeout::deadline_exceeded`].
+#[derive(Clone, Default)]
+pub struct OnTimeout(TimeoutResponse);
+
+#[derive(Clone, Default)]
+enum TimeoutResponse {
+    #[default]
+    DeadlineExceeded,
+    GatewayTimeout,
+    Custom(TimeoutResponseFn),
+}
+
+type TimeoutResponseFn = Arc<dyn Fn(&request::Parts) -> Response<()> + Send + Sync>;
+
+impl OnTimeout {
+    /// A gRPC trailers-only response with status `DEADLINE_EXCEEDED`.
+    pub fn deadline_exceeded() -> Self {
+        Self(TimeoutResponse::DeadlineExceeded)
+    }
+
+    /// An empty `504 Gateway Timeout` response, for plain HTTP clients
+    /// that would not understand a `200` with a `grpc-status`.
+    pub fn gateway_timeout() -> Self {
+        Self(TimeoutResponse::GatewayTimeout)
+    }
+
+    /// The response head `f` builds from the parts of the timed out
+    /// request, with an empty body.
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&request::Parts) -> Response<()> + Send + Sync + 'static,
+    {
+        Self(TimeoutResponse::Custom(Arc::new(f)))
+    }
+}
+
+impl fmt::Debug for OnTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let response = match self.0 {
+            TimeoutResponse::DeadlineExceeded => "deadline_exceeded",
+            TimeoutResponse::GatewayTimeout => "gateway_timeout",
+            TimeoutResponse::Custom(_) => "custom",
+        };
+        f.debug_tuple("OnTimeout").field(&response).finish()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct GrpcTimeout<S> {
     inner: S,
     server_timeout: Option<Duration>,
+    on_timeout: OnTimeout,
 }
 
 impl<S> GrpcTimeout<S> {
@@ -38,13 +128,21 @@ impl<S> GrpcTimeout<S> {
         Self {
             inner,
             server_timeout,
+            on_timeout: OnTimeout::default(),
         }
     }
+
+    /// Sets the response to requests whose deadline expired, by default a
+    /// gRPC `DEADLINE_EXCEEDED`.
+    pub fn on_timeout(self, on_timeout: OnTimeout) -> Self {
+        Self { on_timeout, ..self }
+    }
 }
 
 impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for GrpcTimeout<S>
 where
     S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: http_body::Body,
 {
     type Response = Response<MaybeEmptyBody<ResponseBody>>;
     type Error = S::Error;
@@ -54,7 +152,7 @@ where
         self.inner.poll_ready(cx).map_err(Into::into)
     }
 
-    fn call(&mut self, req: Request<RequestBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<RequestBody>) -> Self::Future {
         let client_timeout = try_parse_grpc_timeout(req.headers()).unwrap_or_else(|e| {
             tracing::trace!("Error parsing `grpc-timeout` header {:?}", e);
             None
@@ -71,9 +169,44 @@ where
             }
         };
 
+        let deadline = timeout_duration.map(|timeout| Deadline(Instant::now() + timeout));
+        if let Some(deadline) = deadline {
+            req.extensions_mut().insert(deadline);
+        }
+
+        let expired = deadline.map(|_| match &self.on_timeout.0 {
+            TimeoutResponse::DeadlineExceeded => Expired::DeadlineExceeded,
+            TimeoutResponse::GatewayTimeout => Expired::GatewayTimeout,
+            TimeoutResponse::Custom(f) => Expired::Custom(f.clone(), Box::new(clone_parts(&req))),
+        });
+
         ResponseFuture {
             inner: self.inner.call(req),
-            sleep: timeout_duration.map(tokio::time::sleep),
+            sleep: deadline.map(|deadline| tokio::time::sleep_until(deadline.0)),
+            expired,
+        }
+    }
+}
+
+/// The response [`ResponseFuture`] returns once the deadline expires.
+enum Expired {
+    DeadlineExceeded,
+    GatewayTimeout,
+    Custom(TimeoutResponseFn, Box<request::Parts>),
+}
+
+impl Expired {
+    fn into_response<B: Default>(self) -> Response<B> {
+        match self {
+            Self::DeadlineExceeded => {
+                grpc_trailers_only(GRPC_DEADLINE_EXCEEDED_CODE, Some("Timeout expired"))
+            }
+            Self::GatewayTimeout => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+                response
+            }
+            Self::Custom(f, parts) => f(&parts).map(|()| B::default()),
         }
     }
 }
@@ -84,12 +217,14 @@ pin_project! {
         inner: F,
         #[pin]
         sleep: Option<Sleep>,
+        expired: Option<Expired>,
     }
 }
 
 impl<F, ResponseBody, E> Future for ResponseFuture<F>
 where
     F: Future<Output = Result<Response<ResponseBody>, E>>,
+    ResponseBody: http_body::Body,
 {
     type Output = Result<Response<MaybeEmptyBody<ResponseBody>>, E>;
 
@@ -97,78 +232,24 @@ where
         let this = self.project();
 
         if let Poll::Ready(result) = this.inner.poll(cx) {
-            return Poll::Ready(result.map(|response| response.map(MaybeEmptyBody::full)));
+            return Poll::Ready(result.map(|response| response.map(Either::left)));
         }
 
         if let Some(sleep) = this.sleep.as_pin_mut() {
             ready!(sleep.poll(cx));
-            let mut response = Response::new(MaybeEmptyBody::empty());
-
-            response
-                .headers_mut()
-                .insert(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
-            response
-                .headers_mut()
-                .insert(GRPC_STATUS_HEADER, GRPC_DEADLINE_EXCEEDED_CODE);
-            response
-                .headers_mut()
-                .insert(GRPC_MESSAGE_HEADER, GRPC_DEADLINE_EXCEEDED_MESSAGE);
-
-            return Poll::Ready(Ok(response));
+            let expired = this.expired.take().expect("polled after completion");
+            return Poll::Ready(Ok(expired.into_response()));
         }
 
         Poll::Pending
     }
 }
 
-pin_project! {
-    pub struct MaybeEmptyBody<B> {
-        #[pin]
-        inner: Option<B>,
-    }
-}
-
-impl<B> MaybeEmptyBody<B> {
-    fn full(inner: B) -> Self {
-        Self { inner: Some(inner) }
-    }
-
-    fn empty() -> Self {
-        Self { inner: None }
-    }
-}
-
-impl<B> http_body::Body for MaybeEmptyBody<B>
-where
-    B: http_body::Body + Send,
-{
-    type Data = B::Data;
-    type Error = B::Error;
-
-    fn poll_frame(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
-        match self.project().inner.as_pin_mut() {
-            Some(b) => b.poll_frame(cx),
-            None => Poll::Ready(None),
-        }
-    }
-
-    fn is_end_stream(&self) -> bool {
-        match &self.inner {
-            Some(b) => b.is_end_stream(),
-            None => true,
-        }
-    }
-
-    fn size_hint(&self) -> http_body::SizeHint {
-        match &self.inner {
-            Some(body) => body.size_hint(),
-            None => http_body::SizeHint::with_exact(0),
-        }
-    }
-}
+/// Response body for [`GrpcTimeout`] and the HTTP
+/// [`Timeout`](super::timeout::Timeout): the inner service's body, or an
+/// empty one once the deadline expired.
+pub type MaybeEmptyBody<B> =
+    Either<B, Empty<<B as http_body::Body>::Data, <B as http_body::Body>::Error>>;
 
 const SECONDS_IN_HOUR: u64 = 60 * 60;
 const SECONDS_IN_MINUTE: u64 = 60;
@@ -222,6 +303,30 @@ fn try_parse_grpc_timeout(
     Ok(Some(duration))
 }
 
+/// Formats `duration` as a `grpc-timeout` value, in the finest unit that
+/// fits the spec's 8 digits.
+fn format_grpc_timeout(duration: Duration) -> HeaderValue {
+    const MAX_VALUE: u128 = 99_999_999;
+
+    let nanos = duration.as_nanos();
+    let value = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (SECONDS_IN_MINUTE as u128 * 1_000_000_000, 'M'),
+        (SECONDS_IN_HOUR as u128 * 1_000_000_000, 'H'),
+    ]
+    .into_iter()
+    .find_map(|(unit_nanos, unit)| {
+        let value = nanos / unit_nanos;
+        (value <= MAX_VALUE).then(|| format!("{value}{unit}"))
+    })
+    .unwrap_or_else(|| format!("{MAX_VALUE}H"));
+
+    HeaderValue::try_from(value).expect("valid header value")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,4 +404,98 @@ mod tests {
         // gRPC spec states TimeoutValue will be at most 8 digits
         setup_map_try_parse(Some("oneH")).unwrap().unwrap();
     }
+
+    #[test]
+    fn test_format_round_trips() {
+        for duration in [
+            Duration::ZERO,
+            Duration::from_nanos(82),
+            Duration::from_millis(13),
+            Duration::from_secs(42),
+            Duration::from_secs(3 * 60 * 60),
+        ] {
+            let mut hm = HeaderMap::new();
+            hm.insert(GRPC_TIMEOUT_HEADER, format_grpc_timeout(duration));
+            assert_eq!(try_parse_grpc_timeout(&hm).unwrap(), Some(duration));
+        }
+        // Rounded down to fit in 8 digits.
+        assert_eq!(
+            format_grpc_timeout(Duration::from_nanos(123_456_789)),
+            "123456u"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadline_extension() {
+        use std::convert::Infallible;
+        use tower::ServiceExt;
+
+        let svc = GrpcTimeout::new(
+            tower::service_fn(|req: Request<()>| async move {
+                let mut response = Response::new(http_body_util::Empty::<bytes::Bytes>::new());
+                if let Some(deadline) = req.extensions().get::<Deadline>() {
+                    response.extensions_mut().insert(*deadline);
+                }
+                Ok::<_, Infallible>(response)
+            }),
+            Some(Duration::from_secs(10)),
+        );
+
+        let request = Request::builder()
+            .header(GRPC_TIMEOUT_HEADER, "2S")
+            .body(())
+            .unwrap();
+        let response = svc.clone().oneshot(request).await.unwrap();
+        let remaining = response.extensions().get::<Deadline>().unwrap().remaining();
+        assert!(remaining <= Duration::from_secs(2));
+        assert!(remaining > Duration::from_secs(1));
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        let remaining = response.extensions().get::<Deadline>().unwrap().remaining();
+        assert!(remaining > Duration::from_secs(9));
+    }
+
+    #[tokio::test]
+    async fn test_on_timeout_responses() {
+        use std::convert::Infallible;
+        use tower::ServiceExt;
+
+        let svc = GrpcTimeout::new(
+            tower::service_fn(|_: Request<()>| async {
+                std::future::pending::<
+                    Result<Response<http_body_util::Empty<bytes::Bytes>>, Infallible>,
+                >()
+                .await
+            }),
+            Some(Duration::from_millis(10)),
+        );
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["grpc-status"], "4");
+
+        let response = svc
+            .clone()
+            .on_timeout(OnTimeout::gateway_timeout())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(!response.headers().contains_key("grpc-status"));
+
+        let response = svc
+            .on_timeout(OnTimeout::custom(|parts| {
+                let mut response = Response::new(());
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response
+                    .headers_mut()
+                    .insert("x-timed-out-path", parts.uri.path().parse().unwrap());
+                response
+            }))
+            .oneshot(Request::get("/slow").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-timed-out-path"], "/slow");
+    }
 }
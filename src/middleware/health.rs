@@ -0,0 +1,589 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Health checks answered before requests reach the application.
+//!
+//! A [`HealthReporter`] tracks whether each component of the server (a
+//! database connection, a sync task, a gRPC service...) is serving. The
+//! [`HealthLayer`] it creates answers health checks from that state and
+//! passes every other request through:
+//!
+//! * `GET /healthz` (liveness) always answers `200 OK`: the server is up
+//!   enough to answer;
+//! * `GET /readyz` (readiness) answers `200 OK` when every component is
+//!   serving and `503 Service Unavailable`, listing the others, otherwise;
+//! * the standard gRPC health check, `grpc.health.v1.Health/Check`, reports
+//!   the status of the requested component, or of the whole server for the
+//!   empty service name. Unknown components get `grpc-status` 5
+//!   (`NOT_FOUND`), as the health checking protocol specifies.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::health::HealthReporter;
+//!
+//! let health = HealthReporter::new();
+//! health.set_not_serving("state-sync");
+//!
+//! let app: axum::Router = axum::Router::new()
+//!     .route("/", axum::routing::get(|| async { "hello" }))
+//!     .layer(health.layer());
+//! # let _ = app;
+//!
+//! // Once caught up:
+//! health.set_serving("state-sync");
+//! ```
+
+use bytes::Buf;
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+
+const LIVENESS_PATH: &str = "/healthz";
+const READINESS_PATH: &str = "/readyz";
+const GRPC_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/grpc");
+const GRPC_OK_CODE: HeaderValue = HeaderValue::from_static("0");
+const GRPC_INVALID_ARGUMENT_CODE: HeaderValue = HeaderValue::from_static("3");
+const GRPC_NOT_FOUND_CODE: HeaderValue = HeaderValue::from_static("5");
+
+/// The largest `HealthCheckRequest` read; it only holds a service name.
+const MAX_GRPC_REQUEST_SIZE: usize = 4096;
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus` values.
+const GRPC_SERVING: u8 = 1;
+const GRPC_NOT_SERVING: u8 = 2;
+
+/// Tracks which components of the server are serving; see the
+/// [module docs](self).
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct HealthReporter {
+    components: Arc<RwLock<BTreeMap<String, bool>>>,
+}
+
+impl HealthReporter {
+    /// A reporter with no components, so the server starts out ready.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark `component` as serving, registering it if needed.
+    pub fn set_serving(&self, component: &str) {
+        self.set(component, true);
+    }
+
+    /// Mark `component` as not serving, registering it if needed.
+    pub fn set_not_serving(&self, component: &str) {
+        self.set(component, false);
+    }
+
+    fn set(&self, component: &str, serving: bool) {
+        let mut components = self.components.write().unwrap();
+        match components.get_mut(component) {
+            Some(status) => *status = serving,
+            None => {
+                components.insert(component.to_owned(), serving);
+            }
+        }
+    }
+
+    /// Forget `component`, so it no longer affects readiness.
+    pub fn remove(&self, component: &str) {
+        self.components.write().unwrap().remove(component);
+    }
+
+    /// Whether `component` is serving, or `None` if it is not registered.
+    pub fn is_serving(&self, component: &str) -> Option<bool> {
+        self.components.read().unwrap().get(component).copied()
+    }
+
+    /// Whether every component is serving.
+    pub fn is_ready(&self) -> bool {
+        self.components
+            .read()
+            .unwrap()
+            .values()
+            .all(|serving| *serving)
+    }
+
+    fn not_serving(&self) -> Vec<String> {
+        self.components
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, serving)| !**serving)
+            .map(|(component, _)| component.clone())
+            .collect()
+    }
+
+    /// A [`HealthLayer`] answering health checks from this reporter.
+    pub fn layer(&self) -> HealthLayer {
+        HealthLayer {
+            reporter: self.clone(),
+        }
+    }
+}
+
+/// [`Layer`] that answers health checks; see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct HealthLayer {
+    reporter: HealthReporter,
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = Health<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Health {
+            inner,
+            reporter: self.reporter.clone(),
+        }
+    }
+}
+
+/// Service returned by [`HealthLayer`].
+#[derive(Debug, Clone)]
+pub struct Health<S> {
+    inner: S,
+    reporter: HealthReporter,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Health<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    RequestBody: Body,
+    ResponseBody: Body<Data = Bytes>,
+{
+    type Response = Response<HealthBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, RequestBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let method = request.method();
+        let get = method == Method::GET || method == Method::HEAD;
+        let state = match request.uri().path() {
+            LIVENESS_PATH if get => State::Respond {
+                response: Some(text_response(StatusCode::OK, "ok\n".to_owned())),
+            },
+            READINESS_PATH if get => State::Respond {
+                response: Some(self.readiness()),
+            },
+            GRPC_CHECK_PATH if method == Method::POST => State::Collect {
+                body: request.into_body(),
+                buf: BytesMut::new(),
+                reporter: self.reporter.clone(),
+            },
+            _ => State::Inner {
+                future: self.inner.call(request),
+            },
+        };
+        ResponseFuture { state }
+    }
+}
+
+impl<S> Health<S> {
+    fn readiness(&self) -> HealthResponse {
+        let not_serving = self.reporter.not_serving();
+        if not_serving.is_empty() {
+            text_response(StatusCode::OK, "ok\n".to_owned())
+        } else {
+            text_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("not serving: {}\n", not_serving.join(", ")),
+            )
+        }
+    }
+}
+
+/// The parts of a response answered by the layer itself.
+struct HealthResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    trailers: Option<HeaderMap>,
+}
+
+fn text_response(status: StatusCode, body: String) -> HealthResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    HealthResponse {
+        status,
+        headers,
+        body: body.into(),
+        trailers: None,
+    }
+}
+
+/// Answers a gRPC health check for the length-prefixed request in `buf`.
+fn grpc_check(buf: &[u8], reporter: &HealthReporter) -> HealthResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+    let trailers_only = |mut headers: HeaderMap, code| {
+        headers.insert(GRPC_STATUS_HEADER, code);
+        HealthResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Bytes::new(),
+            trailers: None,
+        }
+    };
+
+    let Some(service) = decode_check_request(buf) else {
+        return trailers_only(headers, GRPC_INVALID_ARGUMENT_CODE);
+    };
+    let serving = if service.is_empty() {
+        reporter.is_ready()
+    } else {
+        match reporter.is_serving(&service) {
+            Some(serving) => serving,
+            None => return trailers_only(headers, GRPC_NOT_FOUND_CODE),
+        }
+    };
+
+    // A `HealthCheckResponse` with just its `status` field (1, varint).
+    let status = if serving {
+        GRPC_SERVING
+    } else {
+        GRPC_NOT_SERVING
+    };
+    let mut body = BytesMut::with_capacity(7);
+    body.put_u8(0);
+    body.put_u32(2);
+    body.put_slice(&[0x08, status]);
+    let mut trailers = HeaderMap::new();
+    trailers.insert(GRPC_STATUS_HEADER, GRPC_OK_CODE);
+    HealthResponse {
+        status: StatusCode::OK,
+        headers,
+        body: body.freeze(),
+        trailers: Some(trailers),
+    }
+}
+
+/// Decodes the `service` field of a length-prefixed, uncompressed
+/// `HealthCheckRequest`.
+fn decode_check_request(mut buf: &[u8]) -> Option<String> {
+    if buf.len() < 5 || buf[0] != 0 {
+        return None;
+    }
+    buf.advance(1);
+    let len = buf.get_u32() as usize;
+    let mut message = buf.get(..len)?;
+
+    let mut service = String::new();
+    while message.has_remaining() {
+        let key = read_varint(&mut message)?;
+        match key & 7 {
+            0 => {
+                read_varint(&mut message)?;
+            }
+            1 => message = message.get(8..)?,
+            2 => {
+                let len = read_varint(&mut message)? as usize;
+                let value = message.get(..len)?;
+                if key >> 3 == 1 {
+                    service = String::from_utf8(value.to_vec()).ok()?;
+                }
+                message = &message[len..];
+            }
+            5 => message = message.get(4..)?,
+            _ => return None,
+        }
+    }
+    Some(service)
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+pin_project! {
+    /// Response future for [`Health`].
+    pub struct ResponseFuture<F, B> {
+        #[pin]
+        state: State<F, B>,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<F, B> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Collect {
+            #[pin]
+            body: B,
+            buf: BytesMut,
+            reporter: HealthReporter,
+        },
+        Respond {
+            response: Option<HealthResponse>,
+        },
+    }
+}
+
+impl<F, B, ResponseBody, E> Future for ResponseFuture<F, B>
+where
+    F: Future<Output = Result<Response<ResponseBody>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<HealthBody<ResponseBody>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            let response = match this.state.as_mut().project() {
+                StateProj::Inner { future } => {
+                    let response = ready!(future.poll(cx))?;
+                    return Poll::Ready(Ok(response.map(|body| HealthBody::Inner { body })));
+                }
+                StateProj::Collect {
+                    mut body,
+                    buf,
+                    reporter,
+                } => match ready!(body.as_mut().poll_frame(cx)) {
+                    Some(Ok(frame)) => {
+                        if let Ok(mut data) = frame.into_data() {
+                            if buf.len() + data.remaining() > MAX_GRPC_REQUEST_SIZE {
+                                grpc_check(&[], reporter)
+                            } else {
+                                buf.put(&mut data);
+                                continue;
+                            }
+                        } else {
+                            continue;
+                        }
+                    }
+                    Some(Err(_)) | None => grpc_check(buf, reporter),
+                },
+                StateProj::Respond { response } => {
+                    response.take().expect("polled after completion")
+                }
+            };
+
+            let mut builder = Response::builder().status(response.status);
+            *builder.headers_mut().expect("valid response") = response.headers;
+            let body = HealthBody::Health {
+                data: Some(response.body).filter(|body| !body.is_empty()),
+                trailers: response.trailers,
+            };
+            return Poll::Ready(Ok(builder.body(body).expect("valid response")));
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`Health`]: either the inner service's body or a
+    /// health check answer.
+    #[project = HealthBodyProj]
+    pub enum HealthBody<B> {
+        Inner {
+            #[pin]
+            body: B,
+        },
+        Health {
+            data: Option<Bytes>,
+            trailers: Option<HeaderMap>,
+        },
+    }
+}
+
+impl<B> Body for HealthBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
+            HealthBodyProj::Inner { body } => body.poll_frame(cx),
+            HealthBodyProj::Health { data, trailers } => Poll::Ready(
+                data.take()
+                    .map(Frame::data)
+                    .or_else(|| trailers.take().map(Frame::trailers))
+                    .map(Ok),
+            ),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Self::Inner { body } => body.is_end_stream(),
+            Self::Health { data, trailers } => data.is_none() && trailers.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match self {
+            Self::Inner { body } => body.size_hint(),
+            Self::Health { data, .. } => {
+                http_body::SizeHint::with_exact(data.as_ref().map_or(0, |data| data.len() as u64))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn service(
+        reporter: &HealthReporter,
+    ) -> impl Service<
+        Request<Full<Bytes>>,
+        Response = Response<HealthBody<Full<Bytes>>>,
+        Error = Infallible,
+    > + Clone {
+        reporter
+            .layer()
+            .layer(tower::service_fn(|_: Request<Full<Bytes>>| async {
+                Ok(Response::new(Full::new(Bytes::from_static(b"app"))))
+            }))
+    }
+
+    async fn get(reporter: &HealthReporter, path: &str) -> (StatusCode, Bytes) {
+        let request = Request::get(path).body(Full::default()).unwrap();
+        let response = service(reporter).oneshot(request).await.unwrap();
+        let status = response.status();
+        (
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        )
+    }
+
+    /// A length-prefixed `HealthCheckRequest` for `service`.
+    fn check_request(service: &str) -> Request<Full<Bytes>> {
+        let mut message = vec![0x0a, service.len() as u8];
+        message.extend_from_slice(service.as_bytes());
+        let mut body = vec![0];
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(&message);
+        Request::post(GRPC_CHECK_PATH)
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Full::new(body.into()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_liveness_and_readiness() {
+        let reporter = HealthReporter::new();
+        assert_eq!(get(&reporter, "/healthz").await.0, StatusCode::OK);
+        assert_eq!(get(&reporter, "/readyz").await.0, StatusCode::OK);
+        assert_eq!(
+            get(&reporter, "/other").await,
+            (StatusCode::OK, "app".into())
+        );
+
+        reporter.set_not_serving("db");
+        reporter.set_not_serving("sync");
+        assert_eq!(get(&reporter, "/healthz").await.0, StatusCode::OK);
+        assert_eq!(
+            get(&reporter, "/readyz").await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "not serving: db, sync\n".into()
+            )
+        );
+
+        reporter.set_serving("db");
+        reporter.remove("sync");
+        assert_eq!(get(&reporter, "/readyz").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn answers_grpc_health_checks() {
+        let reporter = HealthReporter::new();
+        reporter.set_serving("sui.rpc.v2.LedgerService");
+        reporter.set_not_serving("sui.rpc.v2.SubscriptionService");
+
+        for (name, status) in [
+            ("", GRPC_NOT_SERVING),
+            ("sui.rpc.v2.LedgerService", GRPC_SERVING),
+            ("sui.rpc.v2.SubscriptionService", GRPC_NOT_SERVING),
+        ] {
+            let response = service(&reporter)
+                .oneshot(check_request(name))
+                .await
+                .unwrap();
+            let collected = response.into_body().collect().await.unwrap();
+            assert_eq!(collected.trailers().unwrap()[GRPC_STATUS_HEADER], "0");
+            assert_eq!(collected.to_bytes()[..], [0, 0, 0, 0, 2, 0x08, status]);
+        }
+
+        let response = service(&reporter)
+            .oneshot(check_request("unknown"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[GRPC_STATUS_HEADER], "5");
+    }
+
+    #[test]
+    fn decodes_check_requests() {
+        let frame = |message: &[u8]| {
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(message);
+            frame
+        };
+        assert_eq!(decode_check_request(&frame(b"")), Some(String::new()));
+        assert_eq!(decode_check_request(&frame(b"\x0a\x01a")), Some("a".into()));
+        // Unknown fields are skipped.
+        assert_eq!(
+            decode_check_request(&frame(b"\x10\x96\x01\x0a\x01a")),
+            Some("a".into())
+        );
+        assert_eq!(decode_check_request(&frame(b"\x0a\x05a")), None);
+        assert_eq!(decode_check_request(b"\x01\x00\x00\x00\x00"), None);
+    }
+}
@@ -0,0 +1,583 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Accepting or rejecting requests by client IP address.
+//!
+//! [`IpFilterLayer`] checks the client's address against a denylist and an
+//! allowlist of [`IpNet`] ranges. A client is rejected if it is in a denied
+//! range, or if there is an allowlist and it is in none of its ranges.
+//! Rejected requests are answered without calling the inner service:
+//!
+//! * HTTP requests get `403 Forbidden`;
+//! * gRPC requests get a trailers-only response with `grpc-status` 7
+//!   (`PERMISSION_DENIED`).
+//!
+//! The client's address is the peer address from the [`ConnectInfo`]
+//! request extension. When the peer is one of the
+//! [trusted proxies](IpFilterLayer::trusted_proxies), the address is taken
+//! from the `forwarded` header, or `x-forwarded-for` if there is none,
+//! instead: the header's addresses are walked from the closest hop back,
+//! skipping trusted proxies, and the first untrusted one is the client.
+//! Addresses from untrusted peers' headers are ignored, since clients can
+//! set the headers themselves. Requests whose client address is unknown,
+//! such as those on Unix sockets or with an obfuscated `forwarded` node,
+//! are rejected when there is an allowlist and accepted otherwise.
+//!
+//! The lists can be replaced at runtime through an [`IpFilterHandle`],
+//! which updates every service produced by the layer.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::ip_filter::IpFilterLayer;
+//! use sui_http::middleware::ip_filter::IpNet;
+//!
+//! let layer = IpFilterLayer::new()
+//!     .allow(["10.0.0.0/8", "fd00::/8"].map(|net| net.parse::<IpNet>().unwrap()))
+//!     .trusted_proxies(["10.1.0.0/16".parse().unwrap()]);
+//!
+//! // Block an address at runtime.
+//! let handle = layer.handle();
+//! handle.deny(["10.2.3.4".parse().unwrap()]);
+//! ```
+
+use http::HeaderMap;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use pin_project_lite::pin_project;
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::task::Context;
+use std::task::Poll;
+use tower::Layer;
+use tower::Service;
+
+use super::trailers::grpc_trailers_only;
+use crate::ConnectInfo;
+use crate::middleware::callback::is_grpc;
+
+const GRPC_PERMISSION_DENIED: i32 = 7;
+
+/// A range of IP addresses in CIDR notation, such as `10.0.0.0/8`.
+///
+/// Parses from `address/prefix-length`, or from a bare address for a range
+/// holding only that address. IPv4-mapped IPv6 addresses match the IPv4
+/// ranges they map to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// The range of addresses sharing the first `prefix_len` bits of `addr`.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, InvalidIpNet> {
+        let addr = addr.to_canonical();
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > bits {
+            return Err(InvalidIpNet(()));
+        }
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::from(Ipv4Addr::from(
+                u32::from(v4) & u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(v6) => IpAddr::from(Ipv6Addr::from(
+                u128::from(v6) & u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0),
+            )),
+        };
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// The first address of the range.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is in the range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        Self::new(ip, self.prefix_len).is_ok_and(|net| net.addr == self.addr)
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix_len }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = InvalidIpNet;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => Self::new(
+                addr.parse().map_err(|_| InvalidIpNet(()))?,
+                prefix_len.parse().map_err(|_| InvalidIpNet(()))?,
+            ),
+            None => Ok(s.parse::<IpAddr>().map_err(|_| InvalidIpNet(()))?.into()),
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Error returned when parsing or constructing an invalid [`IpNet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidIpNet(());
+
+impl fmt::Display for InvalidIpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid IP network")
+    }
+}
+
+impl std::error::Error for InvalidIpNet {}
+
+#[derive(Debug, Default)]
+struct Rules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl Rules {
+    fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// [`Layer`] that filters requests by client IP address; see the
+/// [module docs](self).
+///
+/// All services produced by the layer (and their clones) share one set of
+/// lists.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilterLayer {
+    rules: Arc<RwLock<Rules>>,
+    trusted_proxies: Arc<[IpNet]>,
+}
+
+impl IpFilterLayer {
+    /// A filter accepting every client until ranges are allowed or denied.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `nets` to the allowlist.
+    pub fn allow(self, nets: impl IntoIterator<Item = IpNet>) -> Self {
+        self.handle().allow(nets);
+        self
+    }
+
+    /// Add `nets` to the denylist.
+    pub fn deny(self, nets: impl IntoIterator<Item = IpNet>) -> Self {
+        self.handle().deny(nets);
+        self
+    }
+
+    /// Take the client address from the `forwarded` or `x-forwarded-for`
+    /// header of requests from peers in `nets`.
+    pub fn trusted_proxies(self, nets: impl IntoIterator<Item = IpNet>) -> Self {
+        Self {
+            trusted_proxies: nets.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// A handle for updating the lists at runtime.
+    pub fn handle(&self) -> IpFilterHandle {
+        IpFilterHandle {
+            rules: self.rules.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilter<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilter {
+            inner,
+            rules: self.rules.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+/// Updates the lists of an [`IpFilterLayer`] and its services at runtime.
+#[derive(Debug, Clone)]
+pub struct IpFilterHandle {
+    rules: Arc<RwLock<Rules>>,
+}
+
+impl IpFilterHandle {
+    /// Add `nets` to the allowlist.
+    pub fn allow(&self, nets: impl IntoIterator<Item = IpNet>) {
+        self.rules.write().unwrap().allow.extend(nets);
+    }
+
+    /// Add `nets` to the denylist.
+    pub fn deny(&self, nets: impl IntoIterator<Item = IpNet>) {
+        self.rules.write().unwrap().deny.extend(nets);
+    }
+
+    /// Replace the allowlist with `nets`; an empty allowlist accepts every
+    /// client that is not denied.
+    pub fn set_allow(&self, nets: impl IntoIterator<Item = IpNet>) {
+        self.rules.write().unwrap().allow = nets.into_iter().collect();
+    }
+
+    /// Replace the denylist with `nets`.
+    pub fn set_deny(&self, nets: impl IntoIterator<Item = IpNet>) {
+        self.rules.write().unwrap().deny = nets.into_iter().collect();
+    }
+
+    /// The current allowlist.
+    pub fn allowed(&self) -> Vec<IpNet> {
+        self.rules.read().unwrap().allow.clone()
+    }
+
+    /// The current denylist.
+    pub fn denied(&self) -> Vec<IpNet> {
+        self.rules.read().unwrap().deny.clone()
+    }
+}
+
+/// Service returned by [`IpFilterLayer`].
+#[derive(Debug, Clone)]
+pub struct IpFilter<S> {
+    inner: S,
+    rules: Arc<RwLock<Rules>>,
+    trusted_proxies: Arc<[IpNet]>,
+}
+
+impl<S> IpFilter<S> {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()?
+            .remote_addr
+            .ip();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+
+        let hops = forwarded_for(request.headers());
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            client = hop?;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The client addresses in the `forwarded` header, or `x-forwarded-for` if
+/// there is none, closest hop last. Nodes that are not IP addresses are
+/// `None`.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded = headers.get_all(header::FORWARDED);
+    if forwarded.iter().next().is_some() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+            })
+            .collect();
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Parses an address with an optional port, IPv6 addresses with a port
+/// being bracketed.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .ok_or(())
+                .and_then(|node| node.parse().map_err(drop))
+        })
+        .ok()
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for IpFilter<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let ip = self.client_ip(&request);
+        if !self.rules.read().unwrap().is_allowed(ip) {
+            tracing::debug!(?ip, "request rejected by IP filter");
+            return ResponseFuture::Rejected {
+                grpc: is_grpc(request.headers()),
+            };
+        }
+
+        ResponseFuture::Inner {
+            future: self.inner.call(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`IpFilter`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Rejected {
+            grpc: bool,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Rejected { grpc } => {
+                let response = if *grpc {
+                    grpc_trailers_only(GRPC_PERMISSION_DENIED, Some("address not allowed"))
+                } else {
+                    let mut response = Response::new(B::default());
+                    *response.status_mut() = StatusCode::FORBIDDEN;
+                    response
+                };
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut request = request.body(()).unwrap();
+        request.extensions_mut().insert(ConnectInfo {
+            local_addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            remote_addr: SocketAddr::new(peer.parse().unwrap(), 40000),
+            tls: false,
+        });
+        request
+    }
+
+    async fn status(layer: &IpFilterLayer, request: Request<()>) -> StatusCode {
+        let svc = layer.layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(String::new()))
+        }));
+        svc.oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn parses_and_matches_ranges() {
+        assert_eq!(net("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(net("2001:db8::1").to_string(), "2001:db8::1/128");
+        assert_eq!(net("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("10.0.0.0/x".parse::<IpNet>().is_err());
+        assert!("example.com".parse::<IpNet>().is_err());
+
+        assert!(net("10.0.0.0/8").contains("10.255.0.1".parse().unwrap()));
+        assert!(!net("10.0.0.0/8").contains("11.0.0.1".parse().unwrap()));
+        assert!(net("10.0.0.0/8").contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!net("10.0.0.0/8").contains("2001:db8::1".parse().unwrap()));
+        assert!(net("2001:db8::/32").contains("2001:db8:1::1".parse().unwrap()));
+        assert!(net("0.0.0.0/0").contains("192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_forwarded_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "192.0.2.1, 198.51.100.1:1234".parse().unwrap(),
+        );
+        assert_eq!(
+            forwarded_for(&headers),
+            [Some([192, 0, 2, 1].into()), Some([198, 51, 100, 1].into())]
+        );
+
+        headers.insert(
+            header::FORWARDED,
+            r#"for=192.0.2.60;proto=http, For="[2001:db8::1]:4711", for=_hidden"#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            forwarded_for(&headers),
+            [
+                Some([192, 0, 2, 60].into()),
+                Some("2001:db8::1".parse().unwrap()),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_by_allowlist_and_denylist() {
+        let layer = IpFilterLayer::new()
+            .allow([net("10.0.0.0/8")])
+            .deny([net("10.0.0.13")]);
+
+        assert_eq!(
+            status(&layer, request("10.0.0.1", &[])).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&layer, request("10.0.0.13", &[])).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&layer, request("192.0.2.1", &[])).await,
+            StatusCode::FORBIDDEN
+        );
+        // The client address is unknown.
+        assert_eq!(
+            status(&layer, Request::new(())).await,
+            StatusCode::FORBIDDEN
+        );
+
+        let grpc = request("192.0.2.1", &[("content-type", "application/grpc")]);
+        let svc = layer.layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(String::new()))
+        }));
+        let response = svc.oneshot(grpc).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["grpc-status"], "7");
+    }
+
+    #[tokio::test]
+    async fn only_trusts_forwarded_headers_from_trusted_proxies() {
+        let layer = IpFilterLayer::new()
+            .allow([net("10.0.0.0/8")])
+            .trusted_proxies([net("192.168.0.0/16")]);
+        let spoofed = [("x-forwarded-for", "10.0.0.1")];
+
+        // An untrusted peer cannot claim an allowed address.
+        assert_eq!(
+            status(&layer, request("192.0.2.1", &spoofed)).await,
+            StatusCode::FORBIDDEN
+        );
+        // A trusted proxy can, and chains of trusted proxies are skipped.
+        assert_eq!(
+            status(&layer, request("192.168.0.1", &spoofed)).await,
+            StatusCode::OK
+        );
+        let chain = [("forwarded", "for=10.0.0.1, for=192.168.5.5")];
+        assert_eq!(
+            status(&layer, request("192.168.0.1", &chain)).await,
+            StatusCode::OK
+        );
+        // A client forging an earlier hop is still identified by the last
+        // untrusted one.
+        let forged = [("x-forwarded-for", "10.0.0.1, 192.0.2.1")];
+        assert_eq!(
+            status(&layer, request("192.168.0.1", &forged)).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_lists_at_runtime() {
+        let layer = IpFilterLayer::new();
+        let handle = layer.handle();
+        assert_eq!(
+            status(&layer, request("192.0.2.1", &[])).await,
+            StatusCode::OK
+        );
+
+        handle.deny([net("192.0.2.0/24")]);
+        assert_eq!(handle.denied(), [net("192.0.2.0/24")]);
+        assert_eq!(
+            status(&layer, request("192.0.2.1", &[])).await,
+            StatusCode::FORBIDDEN
+        );
+
+        handle.set_deny([]);
+        handle.set_allow([net("198.51.100.0/24")]);
+        assert_eq!(
+            status(&layer, request("192.0.2.1", &[])).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(&layer, request("198.51.100.7", &[])).await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Capping in-flight requests by shedding excess load.
+//!
+//! [`LoadShedLayer`] lets at most a fixed number of requests be in flight at
+//! once, counting each from when it is received until its response body
+//! has been sent or dropped. Requests over the cap are answered right away
+//! without calling the inner service:
+//!
+//! * HTTP requests get `503 Service Unavailable` with a `retry-after`
+//!   header;
+//! * gRPC requests get a trailers-only response with `grpc-status` 14
+//!   (`UNAVAILABLE`), which clients retry.
+//!
+//! Unlike `tower::load_shed`, which fails the request with an error that
+//! makes the server reset the HTTP/2 stream, shed requests receive a
+//! well-formed response.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::load_shed::LoadShedLayer;
+//!
+//! let layer = LoadShedLayer::new(1024).on_shed(|parts| {
+//!     tracing::warn!(path = parts.uri.path(), "shedding request");
+//! });
+//!
+//! // Later, e.g. when exporting metrics:
+//! let shed = layer.shed_count();
+//! # let _ = shed;
+//! ```
+
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::request;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tower::Layer;
+use tower::Service;
+
+use crate::middleware::callback::is_grpc;
+
+const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_MESSAGE_HEADER: HeaderName = HeaderName::from_static("grpc-message");
+
+const GRPC_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/grpc");
+const GRPC_UNAVAILABLE_CODE: HeaderValue = HeaderValue::from_static("14");
+const GRPC_OVERLOADED_MESSAGE: HeaderValue = HeaderValue::from_static("server%20overloaded");
+
+type OnShed = Arc<dyn Fn(&request::Parts) + Send + Sync>;
+
+/// [`Layer`] that caps in-flight requests; see the [module docs](self).
+///
+/// All services produced by the layer (and their clones) share one cap.
+#[derive(Clone)]
+pub struct LoadShedLayer {
+    shared: Arc<Shared>,
+    retry_after: HeaderValue,
+    on_shed: Option<OnShed>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    max_in_flight: usize,
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl std::fmt::Debug for LoadShedLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadShedLayer")
+            .field("shared", &self.shared)
+            .field("retry_after", &self.retry_after)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LoadShedLayer {
+    /// Allow at most `max_in_flight` requests to be in flight at once.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                max_in_flight,
+                in_flight: AtomicUsize::new(0),
+                shed: AtomicU64::new(0),
+            }),
+            retry_after: HeaderValue::from_static("1"),
+            on_shed: None,
+        }
+    }
+
+    /// The `retry-after` sent with shed HTTP requests, rounded up to whole
+    /// seconds. Defaults to one second.
+    pub fn retry_after(self, retry_after: Duration) -> Self {
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Self {
+            retry_after: seconds.into(),
+            ..self
+        }
+    }
+
+    /// Call `on_shed` with every request that is shed.
+    pub fn on_shed<F>(self, on_shed: F) -> Self
+    where
+        F: Fn(&request::Parts) + Send + Sync + 'static,
+    {
+        Self {
+            on_shed: Some(Arc::new(on_shed)),
+            ..self
+        }
+    }
+
+    /// The number of requests shed so far.
+    pub fn shed_count(&self) -> u64 {
+        self.shared.shed.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.shared.in_flight.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Layer<S> for LoadShedLayer {
+    type Service = LoadShed<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadShed {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`LoadShedLayer`].
+#[derive(Debug, Clone)]
+pub struct LoadShed<S> {
+    inner: S,
+    layer: LoadShedLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for LoadShed<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<LoadShedBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let Some(permit) = Permit::acquire(&self.layer.shared) else {
+            self.layer.shared.shed.fetch_add(1, Ordering::Relaxed);
+            let (parts, _body) = request.into_parts();
+            tracing::debug!(
+                path = parts.uri.path(),
+                "shedding request over the in-flight cap"
+            );
+            if let Some(on_shed) = &self.layer.on_shed {
+                on_shed(&parts);
+            }
+            return ResponseFuture::Shed {
+                grpc: is_grpc(&parts.headers),
+                retry_after: self.layer.retry_after.clone(),
+            };
+        };
+
+        ResponseFuture::Inner {
+            future: self.inner.call(request),
+            permit: Some(permit),
+        }
+    }
+}
+
+/// A slot in the in-flight cap, released on drop.
+#[derive(Debug)]
+struct Permit(Arc<Shared>);
+
+impl Permit {
+    fn acquire(shared: &Arc<Shared>) -> Option<Self> {
+        shared
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < shared.max_in_flight).then_some(in_flight + 1)
+            })
+            .ok()
+            .map(|_| Self(shared.clone()))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pin_project! {
+    /// Response future for [`LoadShed`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+            permit: Option<Permit>,
+        },
+        Shed {
+            grpc: bool,
+            retry_after: HeaderValue,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<LoadShedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future, permit } => {
+                let response = ready!(future.poll(cx))?;
+                let permit = permit.take();
+                Poll::Ready(Ok(response.map(|inner| LoadShedBody {
+                    inner: Some(inner),
+                    _permit: permit,
+                })))
+            }
+            ResponseFutureProj::Shed { grpc, retry_after } => {
+                let mut response = Response::new(LoadShedBody {
+                    inner: None,
+                    _permit: None,
+                });
+                let headers = response.headers_mut();
+                if *grpc {
+                    headers.insert(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+                    headers.insert(GRPC_STATUS_HEADER, GRPC_UNAVAILABLE_CODE);
+                    headers.insert(GRPC_MESSAGE_HEADER, GRPC_OVERLOADED_MESSAGE);
+                } else {
+                    headers.insert(http::header::RETRY_AFTER, retry_after.clone());
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                }
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`LoadShed`], holding the request's place in the
+    /// in-flight cap until it is dropped.
+    pub struct LoadShedBody<B> {
+        #[pin]
+        inner: Option<B>,
+        _permit: Option<Permit>,
+    }
+}
+
+impl<B: Body> Body for LoadShedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => inner.is_end_stream(),
+            None => true,
+        }
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match &self.inner {
+            Some(inner) => inner.size_hint(),
+            None => http_body::SizeHint::with_exact(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    fn service(
+        layer: &LoadShedLayer,
+    ) -> impl Service<
+        Request<()>,
+        Response = Response<LoadShedBody<Full<Bytes>>>,
+        Error = Infallible,
+    > + Clone {
+        layer.layer(tower::service_fn(|_: Request<()>| async {
+            Ok(Response::new(Full::new(Bytes::from_static(b"ok"))))
+        }))
+    }
+
+    fn request(content_type: &str) -> Request<()> {
+        Request::builder()
+            .uri("/busy")
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_over_the_cap() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let layer = LoadShedLayer::new(1).on_shed({
+            let paths = paths.clone();
+            move |parts| paths.lock().unwrap().push(parts.uri.path().to_owned())
+        });
+        let svc = service(&layer);
+
+        // The first response body holds the only slot until it is dropped.
+        let held = svc.clone().oneshot(request("text/plain")).await.unwrap();
+        assert_eq!(held.status(), StatusCode::OK);
+        assert_eq!(layer.in_flight(), 1);
+
+        let response = svc.clone().oneshot(request("text/plain")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[http::header::RETRY_AFTER], "1");
+
+        let response = svc
+            .clone()
+            .oneshot(request("application/grpc"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[GRPC_STATUS_HEADER], "14");
+        assert!(response.body().is_end_stream());
+
+        assert_eq!(layer.shed_count(), 2);
+        assert_eq!(*paths.lock().unwrap(), ["/busy", "/busy"]);
+
+        drop(held);
+        assert_eq!(layer.in_flight(), 0);
+        let response = svc.oneshot(request("text/plain")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn rounds_retry_after_up() {
+        let layer = LoadShedLayer::new(1).retry_after(Duration::from_millis(2500));
+        assert_eq!(layer.retry_after, "3");
+    }
+}
@@ -0,0 +1,826 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access logs: one `tracing` event per request.
+//!
+//! [`LoggingLayer`] logs every request once it is over: when the response
+//! body has finished streaming, when the service or the body fails, or
+//! when the client abandons the request. Each event carries the method,
+//! path, response status, gRPC status and latency, the sizes of the request
+//! and response bodies, plus, as configured, the client's address, its
+//! `user-agent` and an allowlist of request headers.
+//!
+//! Body sizes are counted as the bodies stream: `bytes_received` is what
+//! the service read of the request body, and `bytes_sent` what the client
+//! was sent of the response body, after compression when the layer sits
+//! outside the compression layer, as in a
+//! [`MiddlewareStack`](super::stack::MiddlewareStack). If the client goes
+//! away while the response body is still streaming, the event is marked
+//! `truncated`.
+//!
+//! Each request also gets a `request` span, with `method` and `path`
+//! fields and the `status`, `grpc_status` and `error` of the outcome
+//! recorded as they become known. The inner service's future and the
+//! response body are polled inside it, so events the handler emits carry
+//! the request's context, and the span stays open until the response body
+//! has finished streaming. The access log event is emitted inside it too.
+//!
+//! Events are emitted at:
+//!
+//! * `INFO` for successful requests, of which only a configurable share is
+//!   logged, see [`LoggingLayer::success_sample_rate`];
+//! * `WARN` for failed requests (a `5xx` status, a non-`OK` gRPC status or
+//!   an error) and for requests slower than
+//!   [`LoggingLayer::slow_threshold`], which are always logged;
+//! * `INFO` for cancelled requests, which are always logged too.
+//!
+//! The inner service receives the request body wrapped in a
+//! [`LoggingRequestBody`], which counts its bytes; body-polymorphic services
+//! like `axum::Router` do not notice.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::logging::LoggingLayer;
+//!
+//! let layer = LoggingLayer::new()
+//!     .headers([http::HeaderName::from_static("x-request-id")])
+//!     .success_sample_rate(0.1)
+//!     .slow_threshold(Duration::from_secs(1));
+//! # let _ = layer;
+//! ```
+
+use bytes::Buf;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use std::time::Instant;
+use tower::Layer;
+use tower::Service;
+use tracing::Instrument;
+use tracing::Span;
+use tracing::field::Empty;
+use tracing::instrument::Instrumented;
+
+use super::callback::Classification;
+use super::sampling::random_unit;
+use crate::ConnectInfo;
+
+/// [`Layer`] that logs every request; see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct LoggingLayer {
+    headers: Vec<HeaderName>,
+    peer_addr: bool,
+    user_agent: bool,
+    success_sample_rate: f64,
+    slow_threshold: Option<Duration>,
+}
+
+impl Default for LoggingLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoggingLayer {
+    /// Log every request, with the client's address and `user-agent` but no
+    /// other headers, and no slow-request threshold.
+    pub fn new() -> Self {
+        Self {
+            headers: Vec::new(),
+            peer_addr: true,
+            user_agent: true,
+            success_sample_rate: 1.0,
+            slow_threshold: None,
+        }
+    }
+
+    /// Request headers to record, in a `headers` field. None by default;
+    /// avoid headers carrying credentials.
+    pub fn headers<I>(self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        Self {
+            headers: headers.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Whether to record the client's address, in a `peer_addr` field.
+    /// Defaults to `true`.
+    pub fn peer_addr(self, peer_addr: bool) -> Self {
+        Self { peer_addr, ..self }
+    }
+
+    /// Whether to record the `user-agent` header, in a `user_agent` field.
+    /// Defaults to `true`.
+    pub fn user_agent(self, user_agent: bool) -> Self {
+        Self { user_agent, ..self }
+    }
+
+    /// The share of successful requests to log. Defaults to 1, logging all
+    /// of them; failed, slow and cancelled requests are always logged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1.
+    pub fn success_sample_rate(self, rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "sampling rate must be between 0 and 1"
+        );
+        Self {
+            success_sample_rate: rate,
+            ..self
+        }
+    }
+
+    /// Log requests taking longer than `threshold` to complete at `WARN`,
+    /// whatever their outcome.
+    pub fn slow_threshold(self, threshold: Duration) -> Self {
+        Self {
+            slow_threshold: Some(threshold),
+            ..self
+        }
+    }
+
+    fn request_log<B>(&self, request: &Request<B>) -> RequestLog {
+        let headers = request.headers();
+        let peer_addr = self
+            .peer_addr
+            .then(|| request.extensions().get::<ConnectInfo>())
+            .flatten()
+            .map(|connect_info| *connect_info.remote_addr());
+        let user_agent = self
+            .user_agent
+            .then(|| headers.get(header::USER_AGENT).cloned())
+            .flatten();
+        let recorded_headers = self
+            .headers
+            .iter()
+            .filter_map(|name| Some((name.clone(), headers.get(name)?.clone())))
+            .collect();
+
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = request.uri().path(),
+            status = Empty,
+            grpc_status = Empty,
+            error = Empty,
+        );
+
+        RequestLog {
+            span,
+            success_sample_rate: self.success_sample_rate,
+            slow_threshold: self.slow_threshold,
+            start: Instant::now(),
+            method: request.method().clone(),
+            path: request.uri().path().to_owned(),
+            peer_addr,
+            user_agent,
+            headers: recorded_headers,
+            status: None,
+            grpc_status: None,
+            failed: false,
+            bytes_received: Arc::default(),
+            bytes_sent: 0,
+        }
+    }
+}
+
+impl<S> Layer<S> for LoggingLayer {
+    type Service = Logging<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Logging {
+            inner,
+            layer: Arc::new(self.clone()),
+        }
+    }
+}
+
+/// Service returned by [`LoggingLayer`].
+#[derive(Debug, Clone)]
+pub struct Logging<S> {
+    inner: S,
+    layer: Arc<LoggingLayer>,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Logging<S>
+where
+    S: Service<Request<LoggingRequestBody<RequestBody>>, Response = Response<ResponseBody>>,
+    S::Error: fmt::Display,
+    ResponseBody: Body<Error: fmt::Display>,
+{
+    type Response = Response<LoggingBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let log = self.layer.request_log(&request);
+        let span = log.span.clone();
+        let bytes = log.bytes_received.clone();
+        let request = request.map(|inner| LoggingRequestBody { inner, bytes });
+        let inner = {
+            let _guard = span.enter();
+            self.inner.call(request)
+        };
+        ResponseFuture {
+            inner: inner.instrument(span),
+            log: Some(log),
+        }
+    }
+}
+
+/// What is known about a request, logged once it is over.
+struct RequestLog {
+    // Closed when the log is dropped, i.e. once the request is over.
+    span: Span,
+    success_sample_rate: f64,
+    slow_threshold: Option<Duration>,
+    start: Instant,
+    method: Method,
+    path: String,
+    peer_addr: Option<SocketAddr>,
+    user_agent: Option<HeaderValue>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    status: Option<u16>,
+    grpc_status: Option<i32>,
+    failed: bool,
+    // Shared with the request body, which the service may move elsewhere.
+    bytes_received: Arc<AtomicU64>,
+    bytes_sent: u64,
+}
+
+/// How a request ended.
+enum Outcome<'a> {
+    Completed,
+    Error(&'a dyn fmt::Display),
+    Cancelled,
+    /// Cancelled while the response body was streaming.
+    Truncated,
+}
+
+/// Formats the recorded headers as `name: value, ...`.
+struct Headers<'a>(&'a [(HeaderName, HeaderValue)]);
+
+impl fmt::Display for Headers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{name}: {}", String::from_utf8_lossy(value.as_bytes()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Emits `tracing` events at a level chosen at runtime.
+macro_rules! log_event {
+    ($warn:expr, $($args:tt)+) => {
+        if $warn {
+            tracing::warn!($($args)+)
+        } else {
+            tracing::info!($($args)+)
+        }
+    };
+}
+
+impl RequestLog {
+    fn classify(&mut self, classification: &Classification) {
+        if let Classification::Grpc { code, .. } = classification {
+            self.grpc_status = Some(*code);
+            self.span.record("grpc_status", code);
+        }
+        self.failed |= classification.is_failure();
+    }
+
+    fn finish(self, outcome: Outcome<'_>) {
+        let _guard = self.span.enter();
+        if let Outcome::Error(error) = outcome {
+            self.span.record("error", tracing::field::display(error));
+        }
+
+        let latency = self.start.elapsed();
+        let slow = self
+            .slow_threshold
+            .is_some_and(|threshold| latency > threshold);
+        let failed = self.failed || matches!(outcome, Outcome::Error(_));
+        if matches!(outcome, Outcome::Completed)
+            && !failed
+            && !slow
+            && random_unit() >= self.success_sample_rate
+        {
+            return;
+        }
+
+        let (message, error) = match outcome {
+            Outcome::Completed if failed => ("request failed", None),
+            Outcome::Completed => ("request completed", None),
+            Outcome::Error(error) => ("request failed", Some(tracing::field::display(error))),
+            Outcome::Cancelled | Outcome::Truncated => ("request cancelled", None),
+        };
+        let user_agent = self
+            .user_agent
+            .as_ref()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()));
+        log_event!(
+            failed || slow,
+            method = %self.method,
+            path = self.path,
+            status = self.status,
+            grpc_status = self.grpc_status,
+            latency = ?latency,
+            bytes_received = self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent = self.bytes_sent,
+            truncated = matches!(outcome, Outcome::Truncated),
+            slow,
+            error,
+            peer_addr = self.peer_addr.map(tracing::field::display),
+            user_agent = user_agent.as_deref(),
+            headers = (!self.headers.is_empty()).then(|| tracing::field::display(Headers(&self.headers))),
+            "{message}"
+        );
+    }
+}
+
+pin_project! {
+    /// Response future for [`Logging`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: Instrumented<F>,
+        log: Option<RequestLog>,
+    }
+
+    impl<F> PinnedDrop for ResponseFuture<F> {
+        fn drop(this: Pin<&mut Self>) {
+            // Dropped before the inner service produced a response.
+            if let Some(log) = this.project().log.take() {
+                log.finish(Outcome::Cancelled);
+            }
+        }
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+    E: fmt::Display,
+{
+    type Output = Result<Response<LoggingBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        let mut log = this.log.take().expect("polled after completion");
+
+        match result {
+            Ok(response) => {
+                let (head, body) = response.into_parts();
+                log.status = Some(head.status.as_u16());
+                log.span.record("status", head.status.as_u16());
+                let classification = Classification::from_response(&head);
+                if let Some(classification) = &classification {
+                    log.classify(classification);
+                }
+                Poll::Ready(Ok(Response::from_parts(
+                    head,
+                    LoggingBody {
+                        inner: body,
+                        log: Some(log),
+                        classify_trailers: classification.is_none(),
+                    },
+                )))
+            }
+            Err(error) => {
+                log.finish(Outcome::Error(&error));
+                Poll::Ready(Err(error))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`Logging`]. Logs the request once the body ends,
+    /// fails, or is dropped.
+    pub struct LoggingBody<B>
+    where
+        B: Body,
+    {
+        #[pin]
+        inner: B,
+        log: Option<RequestLog>,
+        classify_trailers: bool,
+    }
+
+    impl<B> PinnedDrop for LoggingBody<B>
+    where
+        B: Body,
+    {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(log) = this.log.take() {
+                // Bodies known to be empty may be dropped without a poll.
+                if this.inner.is_end_stream() {
+                    log.finish(Outcome::Completed);
+                } else {
+                    log.finish(Outcome::Truncated);
+                }
+            }
+        }
+    }
+}
+
+impl<B> Body for LoggingBody<B>
+where
+    B: Body,
+    B::Error: fmt::Display,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let result = {
+            let _guard = this.log.as_ref().map(|log| log.span.enter());
+            ready!(this.inner.poll_frame(cx))
+        };
+
+        match &result {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref()
+                    && let Some(log) = this.log.as_mut()
+                {
+                    log.bytes_sent += data.remaining() as u64;
+                } else if let Some(trailers) = frame.trailers_ref()
+                    && let Some(log) = this.log.take()
+                {
+                    end_of_stream(log, *this.classify_trailers, Some(trailers));
+                }
+            }
+            Some(Err(error)) => {
+                if let Some(log) = this.log.take() {
+                    log.finish(Outcome::Error(error));
+                }
+            }
+            None => {
+                if let Some(log) = this.log.take() {
+                    end_of_stream(log, *this.classify_trailers, None);
+                }
+            }
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pin_project! {
+    /// Request body for [`Logging`], counting the bytes the service reads.
+    pub struct LoggingRequestBody<B> {
+        #[pin]
+        inner: B,
+        bytes: Arc<AtomicU64>,
+    }
+}
+
+impl<B: Body> Body for LoggingRequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let result = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &result
+            && let Some(data) = frame.data_ref()
+        {
+            this.bytes
+                .fetch_add(data.remaining() as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn end_of_stream(mut log: RequestLog, classify_trailers: bool, trailers: Option<&HeaderMap>) {
+    if classify_trailers && let Some(classification) = Classification::from_trailers(trailers) {
+        log.classify(&classification);
+    }
+    log.finish(Outcome::Completed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    /// The level and fields of an event, and the span it was emitted in.
+    type Event = (tracing::Level, BTreeMap<String, String>, Option<u64>);
+
+    /// A subscriber recording every event and the fields of every span.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<RecorderState>>);
+
+    #[derive(Default)]
+    struct RecorderState {
+        events: Vec<Event>,
+        // Fields and reference count of every span, by id.
+        spans: HashMap<u64, (BTreeMap<String, String>, usize)>,
+        entered: Vec<u64>,
+        closed: Vec<u64>,
+    }
+
+    struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().to_owned(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut state = self.0.lock().unwrap();
+            let id = state.spans.len() as u64 + 1;
+            let mut fields = BTreeMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            state.spans.insert(id, (fields, 1));
+            tracing::span::Id::from_u64(id)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            if let Some((fields, _)) = self.0.lock().unwrap().spans.get_mut(&span.into_u64()) {
+                values.record(&mut Fields(fields));
+            }
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = BTreeMap::new();
+            event.record(&mut Fields(&mut fields));
+            let mut state = self.0.lock().unwrap();
+            let span = state.entered.last().copied();
+            state.events.push((*event.metadata().level(), fields, span));
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.push(span.into_u64());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+
+        fn clone_span(&self, span: &tracing::span::Id) -> tracing::span::Id {
+            if let Some((_, refs)) = self.0.lock().unwrap().spans.get_mut(&span.into_u64()) {
+                *refs += 1;
+            }
+            span.clone()
+        }
+
+        fn try_close(&self, span: tracing::span::Id) -> bool {
+            let mut state = self.0.lock().unwrap();
+            let id = span.into_u64();
+            let Some((_, refs)) = state.spans.get_mut(&id) else {
+                return false;
+            };
+            *refs -= 1;
+            if *refs > 0 {
+                return false;
+            }
+            state.closed.push(id);
+            true
+        }
+    }
+
+    async fn log(layer: LoggingLayer, request: Request<()>) -> Vec<Event> {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let svc = layer.layer(tower::service_fn(
+            |request: Request<LoggingRequestBody<()>>| async move {
+                let status = match request.uri().path() {
+                    "/fail" => 500,
+                    "/slow" => {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        200
+                    }
+                    _ => 200,
+                };
+                let mut response = Response::new(Full::new(Bytes::from_static(b"ok")));
+                *response.status_mut() = status.try_into().unwrap();
+                Ok::<_, Infallible>(response)
+            },
+        ));
+        let response = svc.oneshot(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        std::mem::take(&mut recorder.0.lock().unwrap().events)
+    }
+
+    #[tokio::test]
+    async fn logs_the_configured_fields() {
+        let request = || {
+            Request::get("/path")
+                .header(header::USER_AGENT, "curl/8.0")
+                .header("x-request-id", "abc")
+                .header("authorization", "secret")
+                .body(())
+                .unwrap()
+        };
+
+        let events = log(LoggingLayer::new(), request()).await;
+        let [(level, fields, _)] = &events[..] else {
+            panic!("expected one event: {events:?}");
+        };
+        assert_eq!(*level, tracing::Level::INFO);
+        assert_eq!(fields["message"], "request completed");
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["path"], "/path");
+        assert_eq!(fields["status"], "200");
+        assert_eq!(fields["user_agent"], "curl/8.0");
+        assert_eq!(fields["bytes_received"], "0");
+        assert_eq!(fields["bytes_sent"], "2");
+        assert_eq!(fields["truncated"], "false");
+        assert!(!fields.contains_key("headers"));
+
+        let layer = LoggingLayer::new()
+            .user_agent(false)
+            .headers([HeaderName::from_static("x-request-id")]);
+        let events = log(layer, request()).await;
+        let fields = &events[0].1;
+        assert_eq!(fields["headers"], "x-request-id: abc");
+        assert!(!fields.contains_key("user_agent"));
+    }
+
+    #[tokio::test]
+    async fn samples_successes_and_escalates_failures_and_slow_requests() {
+        let layer = LoggingLayer::new().success_sample_rate(0.0);
+        assert!(
+            log(layer.clone(), Request::get("/").body(()).unwrap())
+                .await
+                .is_empty()
+        );
+
+        let events = log(layer.clone(), Request::get("/fail").body(()).unwrap()).await;
+        assert_eq!(events[0].0, tracing::Level::WARN);
+        assert_eq!(events[0].1["message"], "request failed");
+
+        let layer = layer.slow_threshold(Duration::from_millis(10));
+        let events = log(layer, Request::get("/slow").body(()).unwrap()).await;
+        assert_eq!(events[0].0, tracing::Level::WARN);
+        assert_eq!(events[0].1["slow"], "true");
+    }
+
+    #[tokio::test]
+    async fn logs_cancelled_requests() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let svc = LoggingLayer::new().layer(tower::service_fn(
+            |_: Request<LoggingRequestBody<()>>| async {
+                std::future::pending::<Result<Response<Full<Bytes>>, Infallible>>().await
+            },
+        ));
+        let result =
+            tokio::time::timeout(Duration::from_millis(10), svc.oneshot(Request::new(()))).await;
+        assert!(result.is_err());
+
+        let events = &recorder.0.lock().unwrap().events;
+        assert_eq!(events[0].1["message"], "request cancelled");
+        assert!(!events[0].1.contains_key("status"));
+    }
+
+    #[tokio::test]
+    async fn instruments_the_request() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let svc = LoggingLayer::new().layer(tower::service_fn(
+            |_: Request<LoggingRequestBody<()>>| async {
+                tokio::task::yield_now().await;
+                tracing::info!("inside the handler");
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"ok"))))
+            },
+        ));
+        let response = svc
+            .oneshot(Request::get("/path").body(()).unwrap())
+            .await
+            .unwrap();
+        assert!(recorder.0.lock().unwrap().closed.is_empty());
+        response.into_body().collect().await.unwrap();
+
+        let state = recorder.0.lock().unwrap();
+        let [handler, access_log] = &state.events[..] else {
+            panic!("expected two events: {:?}", state.events);
+        };
+        assert_eq!(handler.1["message"], "inside the handler");
+        assert_eq!(handler.2, Some(1));
+        assert_eq!(access_log.1["message"], "request completed");
+        assert_eq!(access_log.2, Some(1));
+
+        assert_eq!(state.closed, [1]);
+        let span = &state.spans[&1].0;
+        assert_eq!(span["method"], "GET");
+        assert_eq!(span["path"], "/path");
+        assert_eq!(span["status"], "200");
+    }
+
+    #[tokio::test]
+    async fn logs_body_sizes_and_truncation() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let svc = LoggingLayer::new().layer(tower::service_fn(
+            |request: Request<LoggingRequestBody<Full<Bytes>>>| async move {
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                let frames = futures::stream::iter([
+                    Ok::<_, Infallible>(Frame::data(body.clone())),
+                    Ok(Frame::data(body)),
+                ]);
+                let frames = futures::StreamExt::chain(frames, futures::stream::pending());
+                Ok::<_, Infallible>(Response::new(http_body_util::StreamBody::new(frames)))
+            },
+        ));
+        let request = Request::post("/upload")
+            .body(Full::new(Bytes::from_static(b"hello")))
+            .unwrap();
+        let mut body = svc.oneshot(request).await.unwrap().into_body();
+        body.frame().await.unwrap().unwrap();
+        body.frame().await.unwrap().unwrap();
+        // The client goes away before the body ended.
+        drop(body);
+
+        let events = &recorder.0.lock().unwrap().events;
+        let [(_, fields, _)] = &events[..] else {
+            panic!("expected one event: {events:?}");
+        };
+        assert_eq!(fields["message"], "request cancelled");
+        assert_eq!(fields["bytes_received"], "5");
+        assert_eq!(fields["bytes_sent"], "10");
+        assert_eq!(fields["truncated"], "true");
+    }
+}
@@ -0,0 +1,965 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Request metrics in the Prometheus text exposition format.
+//!
+//! [`Metrics`] is a [`MakeCallbackHandler`] that records, for every request
+//! passing through a [`CallbackLayer`]:
+//!
+//! - `http_requests_total`, a counter of completed requests,
+//! - `http_requests_in_flight`, a gauge of requests being served,
+//! - `http_request_duration_seconds`, a histogram of the time from the
+//!   request reaching the layer until its response body ended,
+//! - `http_request_size_bytes` and `http_response_size_bytes`, histograms
+//!   of body sizes, as streamed through the layer,
+//! - `http_responses_truncated_total`, a counter of responses whose body
+//!   was cut short because the client went away,
+//! - `http_request_encodings_total` and `http_response_encodings_total`,
+//!   counters of requests by the `content-encoding` of their request and
+//!   response bodies.
+//!
+//! Series are labeled by `method` and `path`, and the completed-request
+//! metrics also by `status` and, for gRPC responses, `grpc_status`. Serve
+//! [`Metrics::encode`] from a scrape endpoint to export them.
+//!
+//! Installed with `Builder::connection_handler`, [`Metrics`] is also a
+//! [`MakeConnectionHandler`] recording the server's connection churn:
+//!
+//! - `http_connections_accepted_total`, a counter of accepted connections,
+//! - `http_connections_active`, a gauge of open connections,
+//! - `http_connections_closed_total`, a counter of closed connections by
+//!   `reason`: `completed`, `error`, `aborted`, `handshake_failed` for TLS
+//!   handshake failures, `handshake_timeout` for TLS handshakes that
+//!   exceeded `Config::tls_handshake_timeout`, or `client_rejected` for
+//!   clients whose certificate `Builder::client_identity` rejected,
+//! - `http_tls_handshake_duration_seconds`, a histogram of the time from
+//!   accepting a TLS connection until its handshake completed, using the
+//!   duration buckets.
+//!
+//! These are only exported once a connection has been recorded.
+//!
+//! The encoding counters are labeled by `encoding` only: one of `identity`,
+//! `gzip`, `deflate`, `br`, `zstd` or `compress`, `multiple` for stacked
+//! codings and `other` for anything else. Request encodings also carry an
+//! `outcome`: `ok`, or, for compressed requests rejected with the statuses
+//! used by [`RequestDecompressionLayer`], `unsupported` (415), `too_large`
+//! (413) or `invalid` (400). Place the metrics layer outside the
+//! decompression and compression layers so it sees the encodings on the
+//! wire.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::metrics::Metrics;
+//!
+//! let metrics = Metrics::new();
+//! let scrape = metrics.clone();
+//! let app: axum::Router = axum::Router::new()
+//!     .route("/", axum::routing::get(|| async { "hello" }))
+//!     .layer(metrics.layer())
+//!     .route(
+//!         "/metrics",
+//!         axum::routing::get(move || async move { scrape.encode() }),
+//!     );
+//! # let _ = app;
+//! # let _ = sui_http::Builder::new().connection_handler(metrics.clone());
+//! ```
+//!
+//! [`CallbackLayer`]: super::callback::CallbackLayer
+//! [`RequestDecompressionLayer`]: super::decompression::RequestDecompressionLayer
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use http::HeaderMap;
+use http::StatusCode;
+use http::header;
+use http::request;
+use http::response;
+
+use super::callback::CallbackLayer;
+use super::callback::Classification;
+use super::callback::MakeCallbackHandler;
+use super::callback::RequestHandler;
+use super::callback::ResponseHandler;
+use crate::AcceptedConnection;
+use crate::CloseReason;
+use crate::ConnectionClosed;
+use crate::ConnectionHandler;
+use crate::MakeConnectionHandler;
+use crate::TlsHandshake;
+
+const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const DEFAULT_SIZE_BUCKETS: &[f64] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+
+type PathLabel = dyn Fn(&request::Parts) -> String + Send + Sync;
+
+/// Collects request metrics; see the [module docs](self).
+///
+/// Clones share the same series.
+#[derive(Clone)]
+pub struct Metrics {
+    path_label: Arc<PathLabel>,
+    duration_buckets: Arc<[f64]>,
+    size_buckets: Arc<[f64]>,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics")
+            .field("duration_buckets", &self.duration_buckets)
+            .field("size_buckets", &self.size_buckets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            path_label: Arc::new(|request: &request::Parts| request.uri.path().to_owned()),
+            duration_buckets: DEFAULT_DURATION_BUCKETS.into(),
+            size_buckets: DEFAULT_SIZE_BUCKETS.into(),
+            state: Default::default(),
+        }
+    }
+
+    /// Set the function computing the `path` label of a request.
+    ///
+    /// Defaults to the request's URI path, which is bounded for gRPC
+    /// services but not for REST APIs with path parameters; use this to
+    /// map such paths to their route template.
+    pub fn path_label<F>(self, path_label: F) -> Self
+    where
+        F: Fn(&request::Parts) -> String + Send + Sync + 'static,
+    {
+        Self {
+            path_label: Arc::new(path_label),
+            ..self
+        }
+    }
+
+    /// Set the upper bounds, in seconds, of the request duration histogram
+    /// buckets.
+    pub fn duration_buckets(self, buckets: impl Into<Vec<f64>>) -> Self {
+        Self {
+            duration_buckets: sorted(buckets.into()),
+            ..self
+        }
+    }
+
+    /// Set the upper bounds, in bytes, of the body size histogram buckets.
+    pub fn size_buckets(self, buckets: impl Into<Vec<f64>>) -> Self {
+        Self {
+            size_buckets: sorted(buckets.into()),
+            ..self
+        }
+    }
+
+    /// A [`CallbackLayer`] recording into these metrics.
+    pub fn layer(&self) -> CallbackLayer<Self> {
+        CallbackLayer::new(self.clone())
+    }
+
+    /// Encode all series in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Total number of completed requests.",
+        );
+        for (labels, series) in &state.completed {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{{}}} {}",
+                labels.encode(),
+                series.duration.count
+            );
+        }
+
+        header(
+            &mut out,
+            "http_requests_in_flight",
+            "gauge",
+            "Number of requests currently being served.",
+        );
+        for ((method, path), in_flight) in &state.in_flight {
+            let _ = writeln!(
+                out,
+                "http_requests_in_flight{{method=\"{}\",path=\"{}\"}} {in_flight}",
+                escape(method),
+                escape(path),
+            );
+        }
+
+        histogram_family(
+            &mut out,
+            "http_request_duration_seconds",
+            "Time from receiving a request until its response body ended.",
+            &self.duration_buckets,
+            state.completed.iter().map(|(l, s)| (l, &s.duration)),
+        );
+        histogram_family(
+            &mut out,
+            "http_request_size_bytes",
+            "Size of request bodies.",
+            &self.size_buckets,
+            state.completed.iter().map(|(l, s)| (l, &s.request_size)),
+        );
+        histogram_family(
+            &mut out,
+            "http_response_size_bytes",
+            "Size of response bodies.",
+            &self.size_buckets,
+            state.completed.iter().map(|(l, s)| (l, &s.response_size)),
+        );
+
+        header(
+            &mut out,
+            "http_responses_truncated_total",
+            "counter",
+            "Total number of responses whose body was cut short by the client going away.",
+        );
+        for (labels, series) in &state.completed {
+            let _ = writeln!(
+                out,
+                "http_responses_truncated_total{{{}}} {}",
+                labels.encode(),
+                series.truncated
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_encodings_total",
+            "counter",
+            "Total number of requests by request body content-encoding.",
+        );
+        for ((encoding, outcome), count) in &state.request_encodings {
+            let _ = writeln!(
+                out,
+                "http_request_encodings_total{{encoding=\"{encoding}\",outcome=\"{outcome}\"}} {count}",
+            );
+        }
+
+        header(
+            &mut out,
+            "http_response_encodings_total",
+            "counter",
+            "Total number of responses by response body content-encoding.",
+        );
+        for (encoding, count) in &state.response_encodings {
+            let _ = writeln!(
+                out,
+                "http_response_encodings_total{{encoding=\"{encoding}\"}} {count}",
+            );
+        }
+
+        if let Some(connections) = &state.connections {
+            connections.encode(&mut out, &self.duration_buckets);
+        }
+
+        out
+    }
+
+    fn record(&self, completed: Completed) {
+        let mut state = self.state.lock().unwrap();
+        let key = (
+            completed.labels.method.clone(),
+            completed.labels.path.clone(),
+        );
+        if let Some(in_flight) = state.in_flight.get_mut(&key) {
+            *in_flight -= 1;
+        }
+        *state
+            .request_encodings
+            .entry((completed.request_encoding, completed.request_outcome))
+            .or_default() += 1;
+        if let Some(encoding) = completed.response_encoding {
+            *state.response_encodings.entry(encoding).or_default() += 1;
+        }
+
+        let series = state
+            .completed
+            .entry(completed.labels)
+            .or_insert_with(|| Series {
+                duration: Histogram::new(self.duration_buckets.len()),
+                request_size: Histogram::new(self.size_buckets.len()),
+                response_size: Histogram::new(self.size_buckets.len()),
+                truncated: 0,
+            });
+        series.truncated += u64::from(completed.truncated);
+        series
+            .duration
+            .observe(&self.duration_buckets, completed.latency.as_secs_f64());
+        series
+            .request_size
+            .observe(&self.size_buckets, completed.request_bytes as f64);
+        series
+            .response_size
+            .observe(&self.size_buckets, completed.response_bytes as f64);
+    }
+}
+
+impl MakeCallbackHandler for Metrics {
+    type RequestHandler = RequestMetrics;
+    type ResponseHandler = ResponseMetrics;
+
+    fn make_handler(&self, request: &request::Parts) -> (RequestMetrics, ResponseMetrics) {
+        let method = request.method.as_str().to_owned();
+        let path = (self.path_label)(request);
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .in_flight
+            .entry((method.clone(), path.clone()))
+            .or_default() += 1;
+
+        let request_bytes = Arc::new(AtomicU64::new(0));
+        (
+            RequestMetrics {
+                bytes: request_bytes.clone(),
+            },
+            ResponseMetrics {
+                metrics: self.clone(),
+                labels: Labels {
+                    method,
+                    path,
+                    status: String::new(),
+                    grpc_status: String::new(),
+                },
+                request_bytes,
+                response_bytes: 0,
+                request_encoding: encoding_label(&request.headers),
+                request_outcome: "ok",
+                response_encoding: None,
+                grpc: false,
+                truncated: false,
+                latency: None,
+                start: std::time::Instant::now(),
+            },
+        )
+    }
+}
+
+impl MakeConnectionHandler for Metrics {
+    type Handler = ConnectionMetrics;
+
+    fn on_accept(&self, _connection: &AcceptedConnection<'_>) -> ConnectionMetrics {
+        let mut state = self.state.lock().unwrap();
+        let connections = state
+            .connections
+            .get_or_insert_with(|| ConnectionSeries::new(self.duration_buckets.len()));
+        connections.accepted += 1;
+        connections.active += 1;
+        ConnectionMetrics {
+            metrics: self.clone(),
+        }
+    }
+}
+
+/// [`ConnectionHandler`] produced by [`Metrics`].
+#[derive(Debug)]
+pub struct ConnectionMetrics {
+    metrics: Metrics,
+}
+
+impl ConnectionHandler for ConnectionMetrics {
+    fn on_tls_handshake(&mut self, handshake: &TlsHandshake) {
+        let mut state = self.metrics.state.lock().unwrap();
+        if let Some(connections) = &mut state.connections {
+            connections.tls_handshake.observe(
+                &self.metrics.duration_buckets,
+                handshake.duration.as_secs_f64(),
+            );
+        }
+    }
+
+    fn on_close(&mut self, close: &ConnectionClosed) {
+        let mut state = self.metrics.state.lock().unwrap();
+        if let Some(connections) = &mut state.connections {
+            connections.active -= 1;
+            *connections
+                .closed
+                .entry(close_reason_label(close.reason))
+                .or_default() += 1;
+        }
+    }
+}
+
+fn close_reason_label(reason: CloseReason) -> &'static str {
+    match reason {
+        CloseReason::Completed => "completed",
+        CloseReason::Error => "error",
+        CloseReason::HandshakeFailed => "handshake_failed",
+        CloseReason::HandshakeTimedOut => "handshake_timeout",
+        CloseReason::ClientRejected => "client_rejected",
+        CloseReason::Aborted => "aborted",
+    }
+}
+
+/// [`RequestHandler`] produced by [`Metrics`].
+#[derive(Debug)]
+pub struct RequestMetrics {
+    bytes: Arc<AtomicU64>,
+}
+
+impl RequestHandler for RequestMetrics {
+    fn on_body_chunk<B>(&mut self, chunk: &B)
+    where
+        B: bytes::Buf,
+    {
+        self.bytes
+            .fetch_add(chunk.remaining() as u64, Ordering::Relaxed);
+    }
+}
+
+/// [`ResponseHandler`] produced by [`Metrics`].
+///
+/// The request is recorded when the handler is dropped, so requests whose
+/// response body is abandoned midway are still counted.
+#[derive(Debug)]
+pub struct ResponseMetrics {
+    metrics: Metrics,
+    labels: Labels,
+    request_bytes: Arc<AtomicU64>,
+    response_bytes: u64,
+    request_encoding: &'static str,
+    request_outcome: &'static str,
+    response_encoding: Option<&'static str>,
+    grpc: bool,
+    truncated: bool,
+    latency: Option<Duration>,
+    start: std::time::Instant,
+}
+
+impl ResponseHandler for ResponseMetrics {
+    fn on_response(&mut self, response: &response::Parts, latency: Duration) {
+        self.labels.status = response.status.as_str().to_owned();
+        if self.request_encoding != "identity" {
+            self.request_outcome = match response.status {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported",
+                StatusCode::PAYLOAD_TOO_LARGE => "too_large",
+                StatusCode::BAD_REQUEST => "invalid",
+                _ => "ok",
+            };
+        }
+        self.response_encoding = Some(encoding_label(&response.headers));
+        match Classification::from_response(response) {
+            Some(Classification::Grpc { code, .. }) => {
+                self.labels.grpc_status = code.to_string();
+            }
+            Some(Classification::Http(_)) => {}
+            None => self.grpc = true,
+        }
+        self.latency = Some(latency);
+    }
+
+    fn on_service_error<E>(&mut self, _error: &E, latency: Duration)
+    where
+        E: std::fmt::Display + 'static,
+    {
+        self.labels.status = "error".to_owned();
+        self.latency = Some(latency);
+    }
+
+    fn on_body_chunk<B>(&mut self, chunk: &B)
+    where
+        B: bytes::Buf,
+    {
+        self.response_bytes += chunk.remaining() as u64;
+    }
+
+    fn on_end_of_stream(&mut self, trailers: Option<&HeaderMap>, latency: Duration) {
+        if self.grpc
+            && let Some(Classification::Grpc { code, .. }) = Classification::from_trailers(trailers)
+        {
+            self.labels.grpc_status = code.to_string();
+        }
+        self.latency = Some(latency);
+    }
+
+    fn on_cancel(&mut self, _latency: Duration) {
+        // Only once the response started; before that the whole request is
+        // cancelled.
+        self.truncated = self.response_encoding.is_some();
+    }
+}
+
+impl Drop for ResponseMetrics {
+    fn drop(&mut self) {
+        self.metrics.record(Completed {
+            labels: std::mem::take(&mut self.labels),
+            latency: self.latency.unwrap_or_else(|| self.start.elapsed()),
+            request_bytes: self.request_bytes.load(Ordering::Relaxed),
+            response_bytes: self.response_bytes,
+            request_encoding: self.request_encoding,
+            request_outcome: self.request_outcome,
+            response_encoding: self.response_encoding,
+            truncated: self.truncated,
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: BTreeMap<(String, String), i64>,
+    completed: BTreeMap<Labels, Series>,
+    request_encodings: BTreeMap<(&'static str, &'static str), u64>,
+    response_encodings: BTreeMap<&'static str, u64>,
+    // `None` until a connection is recorded.
+    connections: Option<ConnectionSeries>,
+}
+
+#[derive(Debug)]
+struct ConnectionSeries {
+    accepted: u64,
+    active: i64,
+    closed: BTreeMap<&'static str, u64>,
+    tls_handshake: Histogram,
+}
+
+impl ConnectionSeries {
+    fn new(buckets: usize) -> Self {
+        Self {
+            accepted: 0,
+            active: 0,
+            closed: BTreeMap::new(),
+            tls_handshake: Histogram::new(buckets),
+        }
+    }
+
+    fn encode(&self, out: &mut String, duration_buckets: &[f64]) {
+        header(
+            out,
+            "http_connections_accepted_total",
+            "counter",
+            "Total number of accepted connections.",
+        );
+        let _ = writeln!(out, "http_connections_accepted_total {}", self.accepted);
+
+        header(
+            out,
+            "http_connections_active",
+            "gauge",
+            "Number of open connections.",
+        );
+        let _ = writeln!(out, "http_connections_active {}", self.active);
+
+        header(
+            out,
+            "http_connections_closed_total",
+            "counter",
+            "Total number of closed connections by reason.",
+        );
+        for (reason, count) in &self.closed {
+            let _ = writeln!(
+                out,
+                "http_connections_closed_total{{reason=\"{reason}\"}} {count}"
+            );
+        }
+
+        header(
+            out,
+            "http_tls_handshake_duration_seconds",
+            "histogram",
+            "Time from accepting a TLS connection until its handshake completed.",
+        );
+        self.tls_handshake.encode(
+            out,
+            "http_tls_handshake_duration_seconds",
+            "",
+            duration_buckets,
+        );
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Labels {
+    method: String,
+    path: String,
+    status: String,
+    grpc_status: String,
+}
+
+impl Labels {
+    fn encode(&self) -> String {
+        format!(
+            "method=\"{}\",path=\"{}\",status=\"{}\",grpc_status=\"{}\"",
+            escape(&self.method),
+            escape(&self.path),
+            escape(&self.status),
+            escape(&self.grpc_status),
+        )
+    }
+}
+
+struct Completed {
+    labels: Labels,
+    latency: Duration,
+    request_bytes: u64,
+    response_bytes: u64,
+    request_encoding: &'static str,
+    request_outcome: &'static str,
+    /// `None` if no response was produced.
+    response_encoding: Option<&'static str>,
+    truncated: bool,
+}
+
+#[derive(Debug)]
+struct Series {
+    duration: Histogram,
+    request_size: Histogram,
+    response_size: Histogram,
+    truncated: u64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    // Non-cumulative counts per bucket; the last entry counts values above
+    // every bound.
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(buckets: usize) -> Self {
+        Self {
+            counts: vec![0; buckets + 1],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        let bucket = bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn encode(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64]) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn histogram_family<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    bounds: &[f64],
+    series: impl Iterator<Item = (&'a Labels, &'a Histogram)>,
+) {
+    header(out, name, "histogram", help);
+    for (labels, histogram) in series {
+        histogram.encode(out, name, &labels.encode(), bounds);
+    }
+}
+
+/// The `encoding` label for the `content-encoding` in `headers`, drawn from
+/// a fixed set so clients cannot inflate the number of series.
+fn encoding_label(headers: &HeaderMap) -> &'static str {
+    let mut codings = headers
+        .get_all(header::CONTENT_ENCODING)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or("?").split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"));
+    let Some(coding) = codings.next() else {
+        return "identity";
+    };
+    if codings.next().is_some() {
+        return "multiple";
+    }
+    ["gzip", "deflate", "br", "zstd", "compress"]
+        .into_iter()
+        .find(|known| coding.eq_ignore_ascii_case(known))
+        .or_else(|| coding.eq_ignore_ascii_case("x-gzip").then_some("gzip"))
+        .unwrap_or("other")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn sorted(mut buckets: Vec<f64>) -> Arc<[f64]> {
+    buckets.sort_by(f64::total_cmp);
+    buckets.dedup();
+    buckets.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::callback::RequestBody;
+    use bytes::Bytes;
+    use http::Request;
+    use http::Response;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use tower::ServiceBuilder;
+    use tower::ServiceExt;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let bounds = [1.0, 10.0];
+        let mut histogram = Histogram::new(bounds.len());
+        for value in [0.5, 1.0, 5.0, 50.0] {
+            histogram.observe(&bounds, value);
+        }
+
+        let mut out = String::new();
+        histogram.encode(&mut out, "x", "a=\"b\"", &bounds);
+        assert_eq!(
+            out,
+            "x_bucket{a=\"b\",le=\"1\"} 2\n\
+             x_bucket{a=\"b\",le=\"10\"} 3\n\
+             x_bucket{a=\"b\",le=\"+Inf\"} 4\n\
+             x_sum{a=\"b\"} 56.5\n\
+             x_count{a=\"b\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[tokio::test]
+    async fn records_completed_requests() {
+        let metrics = Metrics::new();
+        let svc = ServiceBuilder::new().layer(metrics.layer()).service_fn(
+            |request: Request<RequestBody<Full<Bytes>, RequestMetrics>>| async move {
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                let empty = body.is_empty();
+                let mut response = Response::new(Full::new(body));
+                if empty {
+                    *response.status_mut() = http::StatusCode::BAD_REQUEST;
+                }
+                Ok::<_, Infallible>(response)
+            },
+        );
+
+        let request = Request::post("/echo")
+            .body(Full::new(Bytes::from_static(b"hello")))
+            .unwrap();
+        let response = svc.clone().oneshot(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        let request = Request::post("/echo").body(Full::default()).unwrap();
+        let response = svc.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        drop(response);
+
+        let encoded = metrics.encode();
+        let ok = "method=\"POST\",path=\"/echo\",status=\"200\",grpc_status=\"\"";
+        assert!(encoded.contains(&format!("http_requests_total{{{ok}}} 1\n")));
+        assert!(encoded.contains(&format!("http_request_size_bytes_sum{{{ok}}} 5\n")));
+        assert!(encoded.contains(&format!("http_response_size_bytes_sum{{{ok}}} 5\n")));
+        assert!(encoded.contains(
+            "http_requests_total{method=\"POST\",path=\"/echo\",status=\"400\",grpc_status=\"\"} 1\n"
+        ));
+        assert!(encoded.contains("http_requests_in_flight{method=\"POST\",path=\"/echo\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn counts_truncated_responses() {
+        let metrics = Metrics::new();
+        let svc = ServiceBuilder::new().layer(metrics.layer()).service_fn(
+            |_: Request<RequestBody<Full<Bytes>, RequestMetrics>>| async {
+                let frames = futures::stream::iter([Ok::<_, Infallible>(http_body::Frame::data(
+                    Bytes::from_static(b"part"),
+                ))]);
+                let frames = futures::StreamExt::chain(frames, futures::stream::pending());
+                Ok::<_, Infallible>(Response::new(http_body_util::StreamBody::new(frames)))
+            },
+        );
+
+        let response = svc.oneshot(Request::new(Full::default())).await.unwrap();
+        let mut body = response.into_body();
+        body.frame().await.unwrap().unwrap();
+        drop(body);
+
+        let encoded = metrics.encode();
+        let labels = "method=\"GET\",path=\"/\",status=\"200\",grpc_status=\"\"";
+        assert!(encoded.contains(&format!("http_responses_truncated_total{{{labels}}} 1\n")));
+        assert!(encoded.contains(&format!("http_response_size_bytes_sum{{{labels}}} 4\n")));
+    }
+
+    #[tokio::test]
+    async fn labels_grpc_status_from_trailers() {
+        let metrics = Metrics::new();
+        let svc = ServiceBuilder::new().layer(metrics.layer()).service_fn(
+            |_: Request<RequestBody<Full<Bytes>, RequestMetrics>>| async {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", "5".parse().unwrap());
+                let body = Full::new(Bytes::new()).with_trailers(async { Some(Ok(trailers)) });
+                let mut response = Response::new(body);
+                response
+                    .headers_mut()
+                    .insert("content-type", "application/grpc".parse().unwrap());
+                Ok::<_, Infallible>(response)
+            },
+        );
+
+        let request = Request::post("/pkg.Service/Method")
+            .body(Full::<Bytes>::default())
+            .unwrap();
+        let response = svc.oneshot(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        assert!(metrics.encode().contains(
+            "http_requests_total{method=\"POST\",path=\"/pkg.Service/Method\",status=\"200\",grpc_status=\"5\"} 1\n"
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn records_content_encodings() {
+        use crate::middleware::decompression::RequestDecompressionLayer;
+
+        let metrics = Metrics::new();
+        let svc = ServiceBuilder::new()
+            .layer(metrics.layer())
+            .layer(RequestDecompressionLayer::new())
+            .service_fn(|_| async {
+                let mut response = Response::new(Full::new(Bytes::new()));
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_ENCODING, "br".parse().unwrap());
+                Ok::<_, Infallible>(response)
+            });
+
+        for (encoding, body) in [
+            (None, &b"plain"[..]),
+            (Some("gzip"), b"not gzip"),
+            (Some("snappy"), b"..."),
+            (Some("zstd"), b"..."),
+        ] {
+            let mut request = Request::post("/upload");
+            if let Some(encoding) = encoding {
+                request = request.header(header::CONTENT_ENCODING, encoding);
+            }
+            let request = request.body(Full::new(Bytes::from_static(body))).unwrap();
+            let response = svc.clone().oneshot(request).await.unwrap();
+            response.into_body().collect().await.unwrap();
+        }
+
+        let encoded = metrics.encode();
+        for series in [
+            "http_request_encodings_total{encoding=\"identity\",outcome=\"ok\"} 1\n",
+            "http_request_encodings_total{encoding=\"gzip\",outcome=\"invalid\"} 1\n",
+            "http_request_encodings_total{encoding=\"other\",outcome=\"unsupported\"} 1\n",
+            "http_request_encodings_total{encoding=\"zstd\",outcome=\"unsupported\"} 1\n",
+            "http_response_encodings_total{encoding=\"br\"} 1\n",
+            "http_response_encodings_total{encoding=\"identity\"} 3\n",
+        ] {
+            assert!(encoded.contains(series), "missing {series}");
+        }
+    }
+
+    #[test]
+    fn records_connections() {
+        let metrics = Metrics::new().duration_buckets([0.1]);
+        assert!(!metrics.encode().contains("http_connections"));
+
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 8080));
+        let accepted = AcceptedConnection::new(&addr, &addr);
+        let mut tls = MakeConnectionHandler::on_accept(&metrics, &accepted);
+        tls.on_tls_handshake(&TlsHandshake {
+            duration: Duration::from_millis(5),
+            alpn_protocol: None,
+        });
+        let mut failed = MakeConnectionHandler::on_accept(&metrics, &accepted);
+        failed.on_close(&ConnectionClosed {
+            duration: Duration::from_millis(10),
+            bytes_read: 0,
+            bytes_written: 0,
+            reason: CloseReason::HandshakeFailed,
+        });
+        let mut timed_out = MakeConnectionHandler::on_accept(&metrics, &accepted);
+        timed_out.on_close(&ConnectionClosed {
+            duration: Duration::from_millis(10),
+            bytes_read: 0,
+            bytes_written: 0,
+            reason: CloseReason::HandshakeTimedOut,
+        });
+
+        let encoded = metrics.encode();
+        for series in [
+            "http_connections_accepted_total 3\n",
+            "http_connections_active 1\n",
+            "http_connections_closed_total{reason=\"handshake_failed\"} 1\n",
+            "http_connections_closed_total{reason=\"handshake_timeout\"} 1\n",
+            "http_tls_handshake_duration_seconds_bucket{le=\"0.1\"} 1\n",
+            "http_tls_handshake_duration_seconds_count 1\n",
+        ] {
+            assert!(encoded.contains(series), "missing {series}");
+        }
+    }
+
+    #[test]
+    fn labels_encodings_from_a_fixed_set() {
+        let label = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_ENCODING, value.parse().unwrap());
+            encoding_label(&headers)
+        };
+        assert_eq!(encoding_label(&HeaderMap::new()), "identity");
+        assert_eq!(label("identity"), "identity");
+        assert_eq!(label("X-GZIP"), "gzip");
+        assert_eq!(label("gzip, identity"), "gzip");
+        assert_eq!(label("deflate, br"), "multiple");
+        assert_eq!(label("lzma"), "other");
+    }
+}
@@ -1,2 +1,38 @@
+pub mod access_log;
+pub mod alt_svc;
+pub mod auth;
+pub mod cache;
 pub mod callback;
+pub mod catch_panic;
+mod checksum;
+#[cfg(feature = "compression")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "compression")))]
+pub mod compression;
+pub mod cors;
+#[cfg(feature = "compression")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "compression")))]
+pub mod decompression;
+pub mod error;
+#[cfg(feature = "fault-injection")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "fault-injection")))]
+pub mod fault_injection;
 pub mod grpc_timeout;
+pub mod health;
+pub mod ip_filter;
+pub mod load_shed;
+pub mod logging;
+#[cfg(feature = "metrics")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "metrics")))]
+pub mod metrics;
+pub mod otel;
+pub mod rate_limit;
+pub mod request_id;
+pub mod response_headers;
+pub mod routing;
+pub mod sampling;
+pub mod stack;
+pub mod timeout;
+pub mod trace_context;
+pub mod trailers;
+pub mod upload;
+pub mod warmup;
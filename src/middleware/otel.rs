@@ -0,0 +1,624 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-request `tracing` spans following the OpenTelemetry HTTP and RPC
+//! semantic conventions.
+//!
+//! [`OtelSpanLayer`] opens a span for every request, with fields named
+//! after the OpenTelemetry attributes (`http.request.method`, `url.path`,
+//! `http.response.status_code`, `rpc.grpc.status_code`, ...) and the
+//! `otel.name`, `otel.kind` and `otel.status_code` fields understood by
+//! `tracing-opentelemetry`, so the spans export as proper server spans
+//! when that subscriber layer is installed.
+//!
+//! The inner service and the response body are polled inside the span,
+//! and the span stays open until the response body has finished
+//! streaming, so its duration covers the whole exchange and the gRPC
+//! status carried in the trailers is recorded on it.
+//!
+//! The span is also inserted into the request extensions as a
+//! [`RequestSpan`], so handlers can record their own fields on it instead
+//! of opening disjoint spans. `tracing` only records fields declared when
+//! the span is created; use [`OtelSpanLayer::make_span`] to declare
+//! application fields alongside the standard ones.
+//!
+//! Requests that a [`SamplingLayer`] decided not to sample get no span.
+//!
+//! With a [`TraceContextLayer`], the span also carries the `trace_id` and
+//! `span_id` of the request's [`TraceContext`].
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::middleware::otel::OtelSpanLayer;
+//!
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(OtelSpanLayer::new())
+//!     .service_fn(|_: Request<()>| async {
+//!         tracing::info!("handling request inside the request span");
+//!         Ok::<_, std::convert::Infallible>(Response::new(http_body_util::Empty::<bytes::Bytes>::new()))
+//!     });
+//! # let _ = service;
+//! ```
+//!
+//! [`SamplingLayer`]: super::sampling::SamplingLayer
+//! [`TraceContextLayer`]: super::trace_context::TraceContextLayer
+//! [`TraceContext`]: super::trace_context::TraceContext
+
+use http::HeaderMap;
+use http::Request;
+use http::Response;
+use http::request;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+use tracing::Span;
+use tracing::field::Empty;
+
+use super::callback::Classification;
+use super::sampling::Sampled;
+use super::trace_context::TraceContext;
+
+type MakeSpan = dyn Fn(&request::Parts) -> Span + Send + Sync;
+
+/// Request extension holding the span [`OtelSpanLayer`] opened for the
+/// request.
+#[derive(Debug, Clone)]
+pub struct RequestSpan(Span);
+
+impl RequestSpan {
+    pub fn span(&self) -> &Span {
+        &self.0
+    }
+}
+
+/// [`Layer`] that wraps every request in an OpenTelemetry-style span; see
+/// the [module docs](self).
+#[derive(Clone)]
+pub struct OtelSpanLayer {
+    make_span: Arc<MakeSpan>,
+}
+
+impl std::fmt::Debug for OtelSpanLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtelSpanLayer").finish_non_exhaustive()
+    }
+}
+
+impl Default for OtelSpanLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OtelSpanLayer {
+    pub fn new() -> Self {
+        Self {
+            make_span: Arc::new(default_span),
+        }
+    }
+
+    /// Create request spans with `make_span` instead of the default span.
+    ///
+    /// The layer records the response status, gRPC status and errors on
+    /// the fields of [the default span](default_span), so a custom span
+    /// should declare those fields (as `tracing::field::Empty`) too.
+    ///
+    /// ```
+    /// use sui_http::middleware::otel::OtelSpanLayer;
+    /// use tracing::field::Empty;
+    ///
+    /// let layer = OtelSpanLayer::new().make_span(|request| {
+    ///     tracing::info_span!(
+    ///         "request",
+    ///         otel.name = %request.uri.path(),
+    ///         otel.kind = "server",
+    ///         otel.status_code = Empty,
+    ///         http.request.method = request.method.as_str(),
+    ///         url.path = request.uri.path(),
+    ///         http.response.status_code = Empty,
+    ///         rpc.grpc.status_code = Empty,
+    ///         error.type = Empty,
+    ///         exception.message = Empty,
+    ///         checkpoint = Empty,
+    ///     )
+    /// });
+    /// # let _ = layer;
+    /// ```
+    pub fn make_span<F>(self, make_span: F) -> Self
+    where
+        F: Fn(&request::Parts) -> Span + Send + Sync + 'static,
+    {
+        Self {
+            make_span: Arc::new(make_span),
+        }
+    }
+}
+
+impl<S> Layer<S> for OtelSpanLayer {
+    type Service = OtelSpan<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OtelSpan {
+            inner,
+            make_span: self.make_span.clone(),
+        }
+    }
+}
+
+/// Service returned by [`OtelSpanLayer`].
+#[derive(Clone)]
+pub struct OtelSpan<S> {
+    inner: S,
+    make_span: Arc<MakeSpan>,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for OtelSpan<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtelSpan")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for OtelSpan<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    S::Error: std::fmt::Display,
+    ResponseBody: Body<Error: std::fmt::Display>,
+{
+    type Response = Response<OtelBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+        let span = if Sampled::from_extensions(&parts.extensions) {
+            (self.make_span)(&parts)
+        } else {
+            Span::none()
+        };
+        parts.extensions.insert(RequestSpan(span.clone()));
+        let request = Request::from_parts(parts, body);
+        let inner = {
+            let _guard = span.enter();
+            self.inner.call(request)
+        };
+
+        ResponseFuture {
+            inner,
+            span: Some(span),
+        }
+    }
+}
+
+/// The span [`OtelSpanLayer`] creates by default.
+///
+/// Its fields are `otel.name`, `otel.kind`, `otel.status_code`,
+/// `http.request.method`, `url.path`, `url.scheme`,
+/// `network.protocol.version`, `http.response.status_code`, `rpc.system`,
+/// `rpc.service`, `rpc.method`, `rpc.grpc.status_code`, `error.type`,
+/// `exception.message`, `trace_id` and `span_id`.
+pub fn default_span(request: &request::Parts) -> Span {
+    let method = request.method.as_str();
+    let path = request.uri.path();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format_args!("{method} {path}"),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = method,
+        url.path = path,
+        url.scheme = request.uri.scheme_str(),
+        network.protocol.version = ?request.version,
+        http.response.status_code = Empty,
+        rpc.system = Empty,
+        rpc.service = Empty,
+        rpc.method = Empty,
+        rpc.grpc.status_code = Empty,
+        error.type = Empty,
+        exception.message = Empty,
+        trace_id = Empty,
+        span_id = Empty,
+    );
+
+    if let Some(context) = request.extensions.get::<TraceContext>() {
+        context.record(&span);
+    }
+    if super::callback::is_grpc(&request.headers)
+        && let Some((service, method)) = path.trim_start_matches('/').split_once('/')
+    {
+        span.record("otel.name", path.trim_start_matches('/'));
+        span.record("rpc.system", "grpc");
+        span.record("rpc.service", service);
+        span.record("rpc.method", method);
+    }
+
+    span
+}
+
+fn record_classification(span: &Span, classification: &Classification) {
+    if let Classification::Grpc { code, message } = classification {
+        span.record("rpc.grpc.status_code", code);
+        if let Some(message) = message
+            && classification.is_failure()
+        {
+            span.record("exception.message", message.as_str());
+        }
+    }
+    if classification.is_failure() {
+        span.record("otel.status_code", "ERROR");
+        if let Classification::Grpc { code, .. } = classification {
+            span.record("error.type", code);
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`OtelSpan`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        span: Option<Span>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    E: std::fmt::Display,
+{
+    type Output = Result<Response<OtelBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = {
+            let _guard = this.span.as_ref().map(Span::enter);
+            ready!(this.inner.poll(cx))
+        };
+        let span = this.span.take().expect("polled after completion");
+
+        match result {
+            Ok(response) => {
+                let (head, body) = response.into_parts();
+                span.record("http.response.status_code", head.status.as_u16());
+                let classification = Classification::from_response(&head);
+                if let Some(classification) = &classification {
+                    if let Classification::Http(status) = classification
+                        && status.is_server_error()
+                    {
+                        span.record("error.type", head.status.as_str());
+                    }
+                    record_classification(&span, classification);
+                }
+
+                Poll::Ready(Ok(Response::from_parts(
+                    head,
+                    OtelBody {
+                        inner: body,
+                        span: Some(span),
+                        classify_trailers: classification.is_none(),
+                    },
+                )))
+            }
+            Err(error) => {
+                span.record("otel.status_code", "ERROR");
+                span.record("error.type", "service_error");
+                span.record("exception.message", tracing::field::display(&error));
+                Poll::Ready(Err(error))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response body for [`OtelSpan`]. Closes the request span once the
+    /// body ends, fails, or is dropped.
+    pub struct OtelBody<B> {
+        #[pin]
+        inner: B,
+        span: Option<Span>,
+        classify_trailers: bool,
+    }
+}
+
+impl<B> Body for OtelBody<B>
+where
+    B: Body,
+    B::Error: std::fmt::Display,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let result = {
+            let _guard = this.span.as_ref().map(Span::enter);
+            ready!(this.inner.poll_frame(cx))
+        };
+
+        let Some(span) = this.span.as_ref() else {
+            return Poll::Ready(result);
+        };
+        match &result {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    end_of_stream(span, *this.classify_trailers, Some(trailers));
+                    *this.span = None;
+                }
+            }
+            Some(Err(error)) => {
+                span.record("otel.status_code", "ERROR");
+                span.record("error.type", "body_error");
+                span.record("exception.message", tracing::field::display(error));
+                *this.span = None;
+            }
+            None => {
+                end_of_stream(span, *this.classify_trailers, None);
+                *this.span = None;
+            }
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn end_of_stream(span: &Span, classify_trailers: bool, trailers: Option<&HeaderMap>) {
+    if classify_trailers && let Some(classification) = Classification::from_trailers(trailers) {
+        record_classification(span, &classification);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    /// A subscriber recording the fields of the spans that are closed.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<RecorderState>>);
+
+    #[derive(Default)]
+    struct RecorderState {
+        next_id: u64,
+        // Fields and reference count of every open span.
+        open: HashMap<u64, (BTreeMap<String, String>, usize)>,
+        closed: Vec<BTreeMap<String, String>>,
+    }
+
+    struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_owned(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut state = self.0.lock().unwrap();
+            state.next_id += 1;
+            let mut fields = BTreeMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            let id = state.next_id;
+            state.open.insert(id, (fields, 1));
+            tracing::span::Id::from_u64(id)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            if let Some((fields, _)) = self.0.lock().unwrap().open.get_mut(&span.into_u64()) {
+                values.record(&mut Fields(fields));
+            }
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+
+        fn clone_span(&self, span: &tracing::span::Id) -> tracing::span::Id {
+            if let Some((_, refs)) = self.0.lock().unwrap().open.get_mut(&span.into_u64()) {
+                *refs += 1;
+            }
+            span.clone()
+        }
+
+        fn try_close(&self, span: tracing::span::Id) -> bool {
+            let mut state = self.0.lock().unwrap();
+            let id = span.into_u64();
+            let Some((_, refs)) = state.open.get_mut(&id) else {
+                return false;
+            };
+            *refs -= 1;
+            if *refs > 0 {
+                return false;
+            }
+            let (fields, _) = state.open.remove(&id).unwrap();
+            state.closed.push(fields);
+            true
+        }
+    }
+
+    fn grpc_service(
+        status: &'static str,
+    ) -> impl Service<
+        Request<()>,
+        Response = Response<OtelBody<impl Body<Data = Bytes, Error = Infallible>>>,
+        Error = Infallible,
+    > {
+        OtelSpanLayer::new().layer(tower::service_fn(move |_: Request<()>| async move {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", status.parse().unwrap());
+            let body = Full::new(Bytes::from_static(b"ok"))
+                .with_trailers(async { Some(Ok::<_, Infallible>(trailers)) });
+            let mut response = Response::new(body);
+            response
+                .headers_mut()
+                .insert("content-type", "application/grpc".parse().unwrap());
+            Ok::<_, Infallible>(response)
+        }))
+    }
+
+    fn grpc_request() -> Request<()> {
+        Request::post("/pkg.Service/Method")
+            .header("content-type", "application/grpc")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn span_closes_after_the_body_with_grpc_status() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let response = grpc_service("13").oneshot(grpc_request()).await.unwrap();
+        assert!(recorder.0.lock().unwrap().closed.is_empty());
+
+        response.into_body().collect().await.unwrap();
+        let state = recorder.0.lock().unwrap();
+        let [span] = &state.closed[..] else {
+            panic!("expected one closed span");
+        };
+        assert_eq!(span["otel.name"], "pkg.Service/Method");
+        assert_eq!(span["otel.kind"], "server");
+        assert_eq!(span["http.request.method"], "POST");
+        assert_eq!(span["url.path"], "/pkg.Service/Method");
+        assert_eq!(span["http.response.status_code"], "200");
+        assert_eq!(span["rpc.system"], "grpc");
+        assert_eq!(span["rpc.service"], "pkg.Service");
+        assert_eq!(span["rpc.method"], "Method");
+        assert_eq!(span["rpc.grpc.status_code"], "13");
+        assert_eq!(span["otel.status_code"], "ERROR");
+    }
+
+    #[tokio::test]
+    async fn handlers_record_on_the_request_span() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let layer = OtelSpanLayer::new().make_span(|request| {
+            tracing::info_span!(
+                "request",
+                url.path = request.uri.path(),
+                http.response.status_code = Empty,
+                checkpoint = Empty,
+            )
+        });
+        let svc = layer.layer(tower::service_fn(|request: Request<()>| async move {
+            let span = request.extensions().get::<RequestSpan>().unwrap();
+            span.span().record("checkpoint", 42);
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+        }));
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        let state = recorder.0.lock().unwrap();
+        let [span] = &state.closed[..] else {
+            panic!("expected one closed span");
+        };
+        assert_eq!(span["url.path"], "/");
+        assert_eq!(span["http.response.status_code"], "200");
+        assert_eq!(span["checkpoint"], "42");
+    }
+
+    #[tokio::test]
+    async fn records_the_trace_context() {
+        use crate::middleware::trace_context::TraceContextLayer;
+
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let request = || {
+            Request::builder()
+                .header(
+                    "traceparent",
+                    "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                )
+                .body(())
+                .unwrap()
+        };
+        let service = tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+        });
+
+        // Either layer may be the outer one.
+        let outer = TraceContextLayer::new().layer(OtelSpanLayer::new().layer(service));
+        let inner = OtelSpanLayer::new().layer(TraceContextLayer::new().layer(service));
+        let response = outer.oneshot(request()).await.unwrap();
+        response.into_body().collect().await.unwrap();
+        let response = inner.oneshot(request()).await.unwrap();
+        response.into_body().collect().await.unwrap();
+
+        let state = recorder.0.lock().unwrap();
+        assert_eq!(state.closed.len(), 2);
+        for span in &state.closed {
+            assert_eq!(span["trace_id"], "0af7651916cd43dd8448eb211c80319c");
+            assert_eq!(span["span_id"].len(), 16);
+            assert_ne!(span["span_id"], "b7ad6b7169203331");
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_unsampled_requests() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let mut request = grpc_request();
+        request.extensions_mut().insert(Sampled::new(false));
+        let response = grpc_service("0").oneshot(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+        assert!(recorder.0.lock().unwrap().closed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn passes_responses_through() {
+        let response = grpc_service("0").oneshot(grpc_request()).await.unwrap();
+        let collected = response.into_body().collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(collected.to_bytes(), "ok");
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-client rate limiting.
+//!
+//! [`RateLimitLayer`] gives every client IP address its own token bucket.
+//! Each request takes a token. A client that runs out is rejected until its
+//! bucket refills, without calling the inner service:
+//!
+//! * HTTP requests get `429 Too Many Requests` with a `retry-after` header;
+//! * gRPC requests get a trailers-only response with `grpc-status` 8
+//!   (`RESOURCE_EXHAUSTED`).
+//!
+//! The client's address is the peer address from the [`ConnectInfo`]
+//! request extension. Behind a load balancer it can be taken from a header
+//! the load balancer sets, such as `x-forwarded-for`, instead. Only do this
+//! when every request passes through that load balancer, since clients can
+//! otherwise set the header themselves. Requests without a known IP address,
+//! such as those on Unix sockets, are not limited.
+//!
+//! A client is forgotten once its bucket has refilled, since a new bucket
+//! would be full too. Forgotten clients are swept out periodically by a
+//! background task, and when the number of tracked clients reaches its
+//! [maximum](RateLimitLayer::max_clients) the least recently seen clients
+//! are evicted, so one-off clients cannot grow the state without bound.
+//! [`RateLimitLayer::clients`] reports how many clients are tracked.
+//!
+//! # Example
+//!
+//! ```
+//! use sui_http::middleware::rate_limit::RateLimitLayer;
+//!
+//! // 10 requests/s per client, with bursts of up to 50 requests.
+//! let layer = RateLimitLayer::new(10.0, 50.0)
+//!     .trusted_header(http::HeaderName::from_static("x-forwarded-for"));
+//! # let _ = layer;
+//! ```
+
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use pin_project_lite::pin_project;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+use tower::Layer;
+use tower::Service;
+
+use crate::ConnectInfo;
+use crate::middleware::callback::is_grpc;
+
+const GRPC_STATUS_HEADER: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_MESSAGE_HEADER: HeaderName = HeaderName::from_static("grpc-message");
+
+const GRPC_CONTENT_TYPE: HeaderValue = HeaderValue::from_static("application/grpc");
+const GRPC_RESOURCE_EXHAUSTED_CODE: HeaderValue = HeaderValue::from_static("8");
+const GRPC_RATE_LIMITED_MESSAGE: HeaderValue = HeaderValue::from_static("rate%20limit%20exceeded");
+
+/// Buckets are pruned when the number of tracked clients reaches this, or
+/// twice the number left after the previous pruning.
+const MIN_PRUNE_THRESHOLD: usize = 1024;
+
+const DEFAULT_MAX_CLIENTS: usize = 100_000;
+const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// [`Layer`] that limits the request rate of each client IP address; see the
+/// [module docs](self).
+///
+/// All services produced by the layer (and their clones) share one set of
+/// buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<Mutex<Limiter>>,
+    trusted_header: Option<HeaderName>,
+}
+
+impl RateLimitLayer {
+    /// Allow each client `rate` requests per second on average, and bursts
+    /// of up to `burst` requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not positive or `burst` is less than one.
+    pub fn new(rate: f64, burst: f64) -> Self {
+        assert!(rate > 0.0, "rate must be positive");
+        assert!(burst >= 1.0, "burst must allow at least one request");
+
+        Self {
+            limiter: Arc::new(Mutex::new(Limiter {
+                rate,
+                burst,
+                buckets: HashMap::new(),
+                prune_threshold: MIN_PRUNE_THRESHOLD,
+                max_clients: DEFAULT_MAX_CLIENTS,
+                sweep_interval: DEFAULT_SWEEP_INTERVAL,
+                sweeping: false,
+            })),
+            trusted_header: None,
+        }
+    }
+
+    /// Track at most `max_clients` clients, evicting the least recently seen
+    /// ones (which then start over with a full bucket) to make room for new
+    /// ones. Defaults to 100,000.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_clients` is zero.
+    pub fn max_clients(self, max_clients: usize) -> Self {
+        assert!(max_clients > 0, "max_clients must be positive");
+        {
+            let mut limiter = self.limiter.lock().unwrap();
+            limiter.max_clients = max_clients;
+            limiter.prune_threshold = limiter.prune_threshold.min(max_clients);
+        }
+        self
+    }
+
+    /// How often the background task forgets clients whose buckets have
+    /// refilled. Defaults to one minute.
+    ///
+    /// The task is spawned on the Tokio runtime serving the first request,
+    /// and stops once the layer and all its services are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sweep_interval` is zero.
+    pub fn sweep_interval(self, sweep_interval: Duration) -> Self {
+        assert!(!sweep_interval.is_zero(), "sweep_interval must be positive");
+        self.limiter.lock().unwrap().sweep_interval = sweep_interval;
+        self
+    }
+
+    /// The number of clients currently tracked.
+    pub fn clients(&self) -> usize {
+        self.limiter.lock().unwrap().buckets.len()
+    }
+
+    /// Identify clients by the last address in `header`, falling back to the
+    /// peer address when it is missing or invalid.
+    ///
+    /// The last address is the one added by the closest proxy, so only set
+    /// this when that proxy is trusted to set the header.
+    pub fn trusted_header(self, header: HeaderName) -> Self {
+        Self {
+            trusted_header: Some(header),
+            ..self
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            trusted_header: self.trusted_header.clone(),
+        }
+    }
+}
+
+/// Service returned by [`RateLimitLayer`].
+#[derive(Debug, Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<Mutex<Limiter>>,
+    trusted_header: Option<HeaderName>,
+}
+
+impl<S> RateLimit<S> {
+    fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        self.trusted_header
+            .as_ref()
+            .and_then(|header| request.headers().get(header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .or_else(|| {
+                request
+                    .extensions()
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|connect_info| connect_info.remote_addr.ip())
+            })
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for RateLimit<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        if let Some(ip) = self.client_ip(&request) {
+            let acquired = {
+                let mut limiter = self.limiter.lock().unwrap();
+                if !limiter.sweeping {
+                    limiter.sweeping = spawn_sweeper(&self.limiter, limiter.sweep_interval);
+                }
+                limiter.try_acquire(ip, Instant::now())
+            };
+            if let Err(retry_after) = acquired {
+                tracing::debug!(%ip, "request rejected by rate limit");
+                return ResponseFuture::Rejected {
+                    grpc: is_grpc(request.headers()),
+                    retry_after,
+                };
+            }
+        }
+
+        ResponseFuture::Inner {
+            future: self.inner.call(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`RateLimit`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Rejected {
+            grpc: bool,
+            retry_after: u64,
+        },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Rejected { grpc, retry_after } => {
+                let mut response = Response::new(B::default());
+                let headers = response.headers_mut();
+                if *grpc {
+                    headers.insert(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+                    headers.insert(GRPC_STATUS_HEADER, GRPC_RESOURCE_EXHAUSTED_CODE);
+                    headers.insert(GRPC_MESSAGE_HEADER, GRPC_RATE_LIMITED_MESSAGE);
+                } else {
+                    headers.insert(http::header::RETRY_AFTER, (*retry_after).into());
+                    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                }
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+/// Token buckets holding up to `burst` tokens, refilled at `rate` tokens
+/// per second.
+#[derive(Debug)]
+struct Limiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+    prune_threshold: usize,
+    max_clients: usize,
+    sweep_interval: Duration,
+    /// Whether the sweeper task has been spawned.
+    sweeping: bool,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Limiter {
+    /// Takes a token from `ip`'s bucket, or returns the number of seconds
+    /// until one is available.
+    fn try_acquire(&mut self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        if self.buckets.len() >= self.prune_threshold && !self.buckets.contains_key(&ip) {
+            self.prune(now);
+            if self.buckets.len() >= self.max_clients {
+                // Leave some headroom so eviction is not needed on every new
+                // client.
+                self.evict_least_recent(self.max_clients - (self.max_clients / 8).max(1));
+            }
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil() as u64)
+        }
+    }
+
+    /// Forgets clients whose buckets have refilled, since a new bucket would
+    /// be full too.
+    fn prune(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_refill);
+            bucket.tokens + elapsed.as_secs_f64() * rate < burst
+        });
+        self.prune_threshold = (self.buckets.len() * 2)
+            .max(MIN_PRUNE_THRESHOLD)
+            .min(self.max_clients);
+    }
+
+    /// Forgets the least recently seen clients until `len` are left.
+    fn evict_least_recent(&mut self, len: usize) {
+        let evict = self.buckets.len().saturating_sub(len);
+        if evict == 0 {
+            return;
+        }
+        let mut last_seen: Vec<Instant> = self
+            .buckets
+            .values()
+            .map(|bucket| bucket.last_refill)
+            .collect();
+        let (_, cutoff, _) = last_seen.select_nth_unstable(evict - 1);
+        let cutoff = *cutoff;
+        let mut evicted = 0;
+        self.buckets.retain(|_, bucket| {
+            let keep = evicted == evict || bucket.last_refill > cutoff;
+            evicted += usize::from(!keep);
+            keep
+        });
+        tracing::debug!(evicted, "rate limiter evicted least recently seen clients");
+    }
+}
+
+/// Spawns the task pruning `limiter` every `interval`, returning whether it
+/// was spawned: it cannot be outside of a Tokio runtime.
+fn spawn_sweeper(limiter: &Arc<Mutex<Limiter>>, interval: Duration) -> bool {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return false;
+    };
+    let limiter: Weak<Mutex<Limiter>> = Arc::downgrade(limiter);
+    runtime.spawn(async move {
+        let mut interval =
+            tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let Some(limiter) = limiter.upgrade() else {
+                return;
+            };
+            let mut limiter = limiter.lock().unwrap();
+            limiter.prune(Instant::now());
+            tracing::trace!(clients = limiter.buckets.len(), "swept rate limiter");
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn request(ip: [u8; 4], content_type: &str) -> Request<()> {
+        let mut request = Request::builder()
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo {
+            local_addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            remote_addr: SocketAddr::from((ip, 40000)),
+            tls: false,
+        });
+        request
+    }
+
+    fn service(
+        layer: &RateLimitLayer,
+    ) -> impl Service<Request<()>, Response = Response<String>, Error = Infallible> + Clone {
+        layer.layer(tower::service_fn(|_: Request<()>| async {
+            Ok(Response::new(String::new()))
+        }))
+    }
+
+    #[test]
+    fn buckets_refill_per_client() {
+        let layer = RateLimitLayer::new(2.0, 2.0);
+        let mut limiter = layer.limiter.lock().unwrap();
+        let a = IpAddr::from([10, 0, 0, 1]);
+        let b = IpAddr::from([10, 0, 0, 2]);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire(a, start).is_ok());
+        assert!(limiter.try_acquire(a, start).is_ok());
+        assert_eq!(limiter.try_acquire(a, start), Err(1));
+        assert!(limiter.try_acquire(b, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.try_acquire(a, later).is_ok());
+        assert!(limiter.try_acquire(a, later).is_err());
+    }
+
+    #[test]
+    fn prunes_full_buckets() {
+        let layer = RateLimitLayer::new(1.0, 1.0);
+        let mut limiter = layer.limiter.lock().unwrap();
+        let start = Instant::now();
+        for i in 0..MIN_PRUNE_THRESHOLD as u32 {
+            limiter
+                .try_acquire(IpAddr::from(i.to_be_bytes()), start)
+                .unwrap();
+        }
+
+        limiter
+            .try_acquire(IpAddr::from([10, 0, 0, 1]), start + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_clients() {
+        let layer = RateLimitLayer::new(1.0, 2.0).max_clients(8);
+        let mut limiter = layer.limiter.lock().unwrap();
+        let start = Instant::now();
+        for i in 0..8u8 {
+            let now = start + Duration::from_millis(i.into());
+            limiter
+                .try_acquire(IpAddr::from([10, 0, 0, i]), now)
+                .unwrap();
+        }
+
+        // Nothing has refilled, so the oldest client makes room.
+        let now = start + Duration::from_millis(10);
+        limiter
+            .try_acquire(IpAddr::from([10, 0, 1, 0]), now)
+            .unwrap();
+        assert_eq!(limiter.buckets.len(), 8);
+        assert!(!limiter.buckets.contains_key(&IpAddr::from([10, 0, 0, 0])));
+        assert!(limiter.buckets.contains_key(&IpAddr::from([10, 0, 0, 1])));
+    }
+
+    #[tokio::test]
+    async fn sweeps_idle_clients_in_the_background() {
+        let layer = RateLimitLayer::new(1000.0, 1.0).sweep_interval(Duration::from_millis(10));
+        let response = service(&layer)
+            .oneshot(request([10, 0, 0, 1], "text/plain"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(layer.clients(), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(layer.clients(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_http_and_grpc_requests() {
+        let layer = RateLimitLayer::new(1.0, 1.0);
+        let svc = service(&layer);
+
+        let response = svc
+            .clone()
+            .oneshot(request([10, 0, 0, 1], "text/plain"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = svc
+            .clone()
+            .oneshot(request([10, 0, 0, 1], "text/plain"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[http::header::RETRY_AFTER], "1");
+
+        let response = svc
+            .clone()
+            .oneshot(request([10, 0, 0, 1], "application/grpc"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[GRPC_STATUS_HEADER], "8");
+
+        // Other clients have their own bucket.
+        let response = svc
+            .oneshot(request([10, 0, 0, 2], "text/plain"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn uses_trusted_header() {
+        let layer = RateLimitLayer::new(1.0, 1.0)
+            .trusted_header(HeaderName::from_static("x-forwarded-for"));
+        let svc = service(&layer);
+
+        // Both requests come from the same proxy, for different clients.
+        for client in ["192.0.2.1, 198.51.100.1", "192.0.2.1, 198.51.100.2"] {
+            let mut request = request([10, 0, 0, 1], "text/plain");
+            request
+                .headers_mut()
+                .insert("x-forwarded-for", HeaderValue::from_static(client));
+            let response = svc.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let mut request = request([10, 0, 0, 2], "text/plain");
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", HeaderValue::from_static("198.51.100.1"));
+        let response = svc.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
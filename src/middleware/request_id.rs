@@ -0,0 +1,254 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Middleware that assigns every request an id for correlating logs.
+//!
+//! [`RequestIdLayer`] reads the `x-request-id` header of incoming requests,
+//! generating a UUIDv7 when it is absent, and makes the id available as a
+//! [`RequestId`] request extension. The id is also set on the request
+//! headers, so it is forwarded by proxies built on the request, and echoed
+//! on the response unless the service already set the header.
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::middleware::request_id::RequestId;
+//! use sui_http::middleware::request_id::RequestIdLayer;
+//!
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(RequestIdLayer::new())
+//!     .service_fn(|request: Request<()>| async move {
+//!         let request_id = request.extensions().get::<RequestId>().unwrap();
+//!         tracing::info!(%request_id, "handling request");
+//!         Ok::<_, std::convert::Infallible>(Response::new(String::new()))
+//!     });
+//! # let _ = service;
+//! ```
+
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::SystemTime;
+use tower::Layer;
+use tower::Service;
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Request extension holding the id of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Generates a new UUIDv7 request id.
+    pub fn generate() -> Self {
+        let uuid = uuid_v7();
+        Self(HeaderValue::from_str(&uuid).expect("uuids are valid header values"))
+    }
+
+    /// The id as a header value.
+    pub fn header_value(&self) -> &HeaderValue {
+        &self.0
+    }
+
+    /// The id as a string, if it is valid visible ASCII.
+    pub fn as_str(&self) -> Option<&str> {
+        self.0.to_str().ok()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.0.as_bytes()))
+    }
+}
+
+/// [`Layer`] that assigns request ids; see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct RequestIdLayer {
+    header_name: HeaderName,
+}
+
+impl Default for RequestIdLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self {
+            header_name: X_REQUEST_ID,
+        }
+    }
+
+    /// Read and write the id in `header_name` instead of `x-request-id`.
+    pub fn header_name(self, header_name: HeaderName) -> Self {
+        Self { header_name }
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService {
+            inner,
+            header_name: self.header_name.clone(),
+        }
+    }
+}
+
+/// Service returned by [`RequestIdLayer`].
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+    header_name: HeaderName,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for RequestIdService<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future {
+        let request_id = match request.headers().get(&self.header_name) {
+            Some(value) if !value.is_empty() => RequestId(value.clone()),
+            _ => {
+                let request_id = RequestId::generate();
+                request
+                    .headers_mut()
+                    .insert(self.header_name.clone(), request_id.0.clone());
+                request_id
+            }
+        };
+        let header_value = request_id.0.clone();
+        request.extensions_mut().insert(request_id);
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            header: Some((self.header_name.clone(), header_value)),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`RequestIdService`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        header: Option<(HeaderName, HeaderValue)>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        if let Some((name, value)) = this.header.take() {
+            response.headers_mut().entry(name).or_insert(value);
+        }
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Formats a UUIDv7: a 48-bit millisecond Unix timestamp followed by
+/// random bits, so ids sort by creation time.
+///
+/// The random bits come from the standard library's randomly keyed hasher,
+/// which is unpredictable enough for correlation ids and avoids a
+/// dependency on a RNG crate.
+fn uuid_v7() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let random = || {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.finish()
+    };
+
+    let high = (millis << 16) | 0x7000 | (random() & 0x0fff);
+    let low = (random() & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn service() -> impl Service<Request<()>, Response = Response<String>, Error = Infallible> {
+        RequestIdLayer::new().layer(tower::service_fn(|request: Request<()>| async move {
+            let request_id = request.extensions().get::<RequestId>().unwrap();
+            assert_eq!(
+                Some(request_id.header_value()),
+                request.headers().get(X_REQUEST_ID)
+            );
+            Ok(Response::new(request_id.to_string()))
+        }))
+    }
+
+    #[test]
+    fn generates_uuid_v7() {
+        let id = uuid_v7();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "7");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+        assert_ne!(id, uuid_v7());
+    }
+
+    #[tokio::test]
+    async fn keeps_incoming_id() {
+        let request = Request::builder()
+            .header(X_REQUEST_ID, "abc")
+            .body(())
+            .unwrap();
+        let response = service().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[X_REQUEST_ID], "abc");
+        assert_eq!(response.body(), "abc");
+    }
+
+    #[tokio::test]
+    async fn generates_missing_id() {
+        let response = service().oneshot(Request::new(())).await.unwrap();
+        let id = response.headers()[X_REQUEST_ID].to_str().unwrap();
+        assert_eq!(id, response.body());
+        assert_eq!(id.len(), 36);
+    }
+}
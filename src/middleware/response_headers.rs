@@ -0,0 +1,290 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Setting a fixed set of headers on every response.
+//!
+//! [`ResponseHeadersLayer`] adds headers like `Server`,
+//! `Strict-Transport-Security` or `X-Content-Type-Options` to every
+//! response. Each header either overwrites whatever the service set,
+//! is only set when the service did not set it, or is appended to the
+//! values the service set. Headers like these mean nothing to gRPC clients,
+//! so gRPC responses can be left untouched with
+//! [`ResponseHeadersLayer::skip_grpc`].
+//!
+//! # Example
+//!
+//! ```
+//! use http::HeaderName;
+//! use http::HeaderValue;
+//! use std::time::Duration;
+//! use sui_http::middleware::response_headers::ResponseHeadersLayer;
+//!
+//! let layer = ResponseHeadersLayer::new()
+//!     .server(HeaderValue::from_static("sui-node"))
+//!     .hsts(Duration::from_secs(365 * 24 * 60 * 60))
+//!     .nosniff()
+//!     .append(
+//!         HeaderName::from_static("vary"),
+//!         HeaderValue::from_static("origin"),
+//!     )
+//!     .skip_grpc(true);
+//! # let _ = layer;
+//! ```
+
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::header;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tower::Layer;
+use tower::Service;
+
+use super::callback::is_grpc;
+
+/// How a header is combined with the headers set by the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Overwrite,
+    IfMissing,
+    Append,
+}
+
+/// [`Layer`] that sets headers on every response; see the
+/// [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeadersLayer {
+    headers: Arc<Vec<(HeaderName, HeaderValue, Mode)>>,
+    skip_grpc: bool,
+}
+
+impl ResponseHeadersLayer {
+    /// A layer setting no headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn header(mut self, name: HeaderName, value: HeaderValue, mode: Mode) -> Self {
+        Arc::make_mut(&mut self.headers).push((name, value, mode));
+        self
+    }
+
+    /// Set `name` to `value`, replacing any values set by the service.
+    pub fn overwrite(self, name: HeaderName, value: HeaderValue) -> Self {
+        self.header(name, value, Mode::Overwrite)
+    }
+
+    /// Set `name` to `value` unless the service set it.
+    pub fn if_missing(self, name: HeaderName, value: HeaderValue) -> Self {
+        self.header(name, value, Mode::IfMissing)
+    }
+
+    /// Add `value` to any values of `name` set by the service.
+    pub fn append(self, name: HeaderName, value: HeaderValue) -> Self {
+        self.header(name, value, Mode::Append)
+    }
+
+    /// Set the `Server` header, replacing any set by the service, so that
+    /// the server does not reveal the software behind it.
+    pub fn server(self, value: HeaderValue) -> Self {
+        self.overwrite(header::SERVER, value)
+    }
+
+    /// Tell browsers to only use HTTPS for the host, and its subdomains,
+    /// for `max_age`, unless the service set `Strict-Transport-Security`.
+    pub fn hsts(self, max_age: Duration) -> Self {
+        let value = format!("max-age={}; includeSubDomains", max_age.as_secs());
+        self.if_missing(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::try_from(value).expect("max-age is a valid header value"),
+        )
+    }
+
+    /// Set `X-Content-Type-Options: nosniff`, which keeps browsers from
+    /// second-guessing the response's content type.
+    pub fn nosniff(self) -> Self {
+        self.overwrite(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )
+    }
+
+    /// Leave the responses to gRPC requests untouched.
+    ///
+    /// Default is `false`.
+    pub fn skip_grpc(self, skip_grpc: bool) -> Self {
+        Self { skip_grpc, ..self }
+    }
+}
+
+impl<S> Layer<S> for ResponseHeadersLayer {
+    type Service = ResponseHeaders<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseHeaders {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`ResponseHeadersLayer`].
+#[derive(Debug, Clone)]
+pub struct ResponseHeaders<S> {
+    inner: S,
+    layer: ResponseHeadersLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for ResponseHeaders<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let skip = self.layer.skip_grpc && is_grpc(request.headers());
+        ResponseFuture {
+            inner: self.inner.call(request),
+            layer: (!skip).then(|| self.layer.clone()),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`ResponseHeaders`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        // `None` if the response is left untouched.
+        layer: Option<ResponseHeadersLayer>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        if let Some(layer) = this.layer.take()
+            && !(layer.skip_grpc && is_grpc(response.headers()))
+        {
+            let headers = response.headers_mut();
+            for (name, value, mode) in layer.headers.iter() {
+                match mode {
+                    Mode::Overwrite => {
+                        headers.insert(name, value.clone());
+                    }
+                    Mode::IfMissing => {
+                        headers.entry(name).or_insert_with(|| value.clone());
+                    }
+                    Mode::Append => {
+                        headers.append(name, value.clone());
+                    }
+                }
+            }
+        }
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    /// Answers with the headers a service typically sets itself.
+    async fn service(request: Request<()>) -> Result<Response<()>, Infallible> {
+        let mut response = Response::new(());
+        let headers = response.headers_mut();
+        headers.insert(header::SERVER, HeaderValue::from_static("hyper"));
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=60"),
+        );
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if let Some(content_type) = request.headers().get(header::CONTENT_TYPE) {
+            headers.insert(header::CONTENT_TYPE, content_type.clone());
+        }
+        Ok(response)
+    }
+
+    fn layer() -> ResponseHeadersLayer {
+        ResponseHeadersLayer::new()
+            .server(HeaderValue::from_static("sui"))
+            .hsts(Duration::from_secs(3600))
+            .nosniff()
+            .append(header::VARY, HeaderValue::from_static("origin"))
+    }
+
+    #[tokio::test]
+    async fn combines_headers_with_those_of_the_service() {
+        let svc = layer().layer(tower::service_fn(service));
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        let headers = response.headers();
+
+        assert_eq!(headers[header::SERVER], "sui");
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=60");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        let vary = headers.get_all(header::VARY).iter().collect::<Vec<_>>();
+        assert_eq!(vary, ["accept-encoding", "origin"]);
+
+        let svc = layer().layer(tower::service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(Response::new(()))
+        }));
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(
+            response.headers()[header::STRICT_TRANSPORT_SECURITY],
+            "max-age=3600; includeSubDomains"
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_grpc_responses() {
+        let grpc = || {
+            Request::builder()
+                .header(header::CONTENT_TYPE, "application/grpc")
+                .body(())
+                .unwrap()
+        };
+
+        let svc = layer().layer(tower::service_fn(service));
+        let response = svc.oneshot(grpc()).await.unwrap();
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+
+        let svc = layer().skip_grpc(true).layer(tower::service_fn(service));
+        let response = svc.clone().oneshot(grpc()).await.unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::X_CONTENT_TYPE_OPTIONS)
+        );
+        assert_eq!(response.headers()[header::SERVER], "hyper");
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+    }
+}
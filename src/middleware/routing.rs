@@ -0,0 +1,436 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rule based request routing.
+//!
+//! [`RoutingRules`] is an ordered list of [`Rule`]s, each pairing a
+//! [`RuleMatch`] on the request's method, path and headers with a
+//! [`RuleAction`]: forward the request to a named service or reject it with
+//! a status code. The first matching rule wins; requests that match no rule
+//! are sent to the fallback service.
+//!
+//! Rules are plain data, so they can be built from whatever configuration
+//! format a binary already loads, which allows simple gateway behavior (for
+//! example rejecting a deprecated API version) to be changed without a code
+//! change.
+//!
+//! # Example
+//!
+//! ```
+//! use http::StatusCode;
+//! use sui_http::middleware::routing::Rule;
+//! use sui_http::middleware::routing::RuleAction;
+//! use sui_http::middleware::routing::RuleMatch;
+//! use sui_http::middleware::routing::RoutingRules;
+//!
+//! let rules = RoutingRules::new()
+//!     .rule(Rule::new(
+//!         RuleMatch::any().header("x-api-version", "1"),
+//!         RuleAction::Reject(StatusCode::GONE),
+//!     ))
+//!     .rule(Rule::new(
+//!         RuleMatch::any().path_prefix("/v2/"),
+//!         RuleAction::route("v2"),
+//!     ));
+//! # let _ = rules;
+//! ```
+
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use pin_project_lite::pin_project;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+/// Conditions a request must satisfy for a [`Rule`] to apply.
+///
+/// Every condition that is set must hold; [`RuleMatch::any`] matches every
+/// request.
+#[derive(Debug, Clone, Default)]
+pub struct RuleMatch {
+    methods: Vec<Method>,
+    path: Option<PathMatch>,
+    headers: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+#[derive(Debug, Clone)]
+enum PathMatch {
+    Exact(String),
+    Prefix(String),
+}
+
+impl RuleMatch {
+    /// A match without conditions.
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    /// Require the request method to be `method`.
+    ///
+    /// May be called multiple times to accept any of several methods.
+    pub fn method(mut self, method: Method) -> Self {
+        self.methods.push(method);
+        self
+    }
+
+    /// Require the request path to be exactly `path`.
+    pub fn path(self, path: impl Into<String>) -> Self {
+        Self {
+            path: Some(PathMatch::Exact(path.into())),
+            ..self
+        }
+    }
+
+    /// Require the request path to start with `prefix`.
+    pub fn path_prefix(self, prefix: impl Into<String>) -> Self {
+        Self {
+            path: Some(PathMatch::Prefix(prefix.into())),
+            ..self
+        }
+    }
+
+    /// Require the request to carry header `name` with exactly `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` is not a valid header name or value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("invalid header name"),
+            Some(HeaderValue::try_from(value).expect("invalid header value")),
+        ));
+        self
+    }
+
+    /// Require the request to carry header `name`, with any value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    pub fn header_present(mut self, name: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("invalid header name"),
+            None,
+        ));
+        self
+    }
+
+    fn matches<B>(&self, request: &Request<B>) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(request.method()) {
+            return false;
+        }
+
+        let path = request.uri().path();
+        match &self.path {
+            Some(PathMatch::Exact(exact)) if path != exact => return false,
+            Some(PathMatch::Prefix(prefix)) if !path.starts_with(prefix.as_str()) => {
+                return false;
+            }
+            _ => {}
+        }
+
+        self.headers.iter().all(|(name, value)| match value {
+            Some(value) => request
+                .headers()
+                .get_all(name)
+                .iter()
+                .any(|actual| actual == value),
+            None => request.headers().contains_key(name),
+        })
+    }
+}
+
+/// What to do with a request matched by a [`Rule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Forward the request to the service registered under this name.
+    Route(String),
+    /// Respond immediately with an empty response with this status.
+    Reject(StatusCode),
+}
+
+impl RuleAction {
+    pub fn route(name: impl Into<String>) -> Self {
+        Self::Route(name.into())
+    }
+}
+
+/// A single routing rule.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    matcher: RuleMatch,
+    action: RuleAction,
+}
+
+impl Rule {
+    pub fn new(matcher: RuleMatch, action: RuleAction) -> Self {
+        Self { matcher, action }
+    }
+}
+
+/// An ordered list of [`Rule`]s, evaluated first-match-wins.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingRules {
+    rules: Vec<Rule>,
+}
+
+impl RoutingRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a rule, evaluated after every rule added before it.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    fn action_for<B>(&self, request: &Request<B>) -> Option<&RuleAction> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(request))
+            .map(|rule| &rule.action)
+    }
+}
+
+impl FromIterator<Rule> for RoutingRules {
+    fn from_iter<T: IntoIterator<Item = Rule>>(iter: T) -> Self {
+        Self {
+            rules: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// [`Layer`] that applies [`RoutingRules`] in front of a fallback service.
+///
+/// Named services are registered with [`RoutingLayer::route`]; they must
+/// have the same type as the fallback service, so box them (for example
+/// with `tower::util::BoxCloneService`) when they differ.
+#[derive(Debug, Clone)]
+pub struct RoutingLayer<S> {
+    rules: Arc<RoutingRules>,
+    routes: HashMap<String, S>,
+}
+
+impl<S> RoutingLayer<S> {
+    pub fn new(rules: RoutingRules) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Register `service` under `name` for [`RuleAction::Route`].
+    pub fn route(mut self, name: impl Into<String>, service: S) -> Self {
+        self.routes.insert(name.into(), service);
+        self
+    }
+}
+
+impl<S: Clone> Layer<S> for RoutingLayer<S> {
+    type Service = Routing<S>;
+
+    fn layer(&self, fallback: S) -> Self::Service {
+        Routing {
+            rules: self.rules.clone(),
+            routes: self.routes.clone(),
+            fallback,
+        }
+    }
+}
+
+/// Service returned by [`RoutingLayer`].
+///
+/// The services must be `Clone`: the selected one is driven to readiness
+/// inside the response future, as it is not known which service a request
+/// will go to until it arrives.
+#[derive(Debug, Clone)]
+pub struct Routing<S> {
+    rules: Arc<RoutingRules>,
+    routes: HashMap<String, S>,
+    fallback: S,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Routing<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Request<RequestBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let service = match self.rules.action_for(&request) {
+            None => self.fallback.clone(),
+            Some(RuleAction::Route(name)) => match self.routes.get(name) {
+                Some(service) => service.clone(),
+                None => {
+                    tracing::warn!(route = %name, "routing rule refers to unknown route");
+                    return ResponseFuture::reject(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            },
+            Some(RuleAction::Reject(status)) => {
+                tracing::debug!(
+                    method = %request.method(),
+                    path = request.uri().path(),
+                    %status,
+                    "request rejected by routing rule"
+                );
+                return ResponseFuture::reject(*status);
+            }
+        };
+
+        ResponseFuture::Inner {
+            future: service.oneshot(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Routing`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<S, Req>
+    where
+        S: Service<Req>,
+    {
+        Inner {
+            #[pin]
+            future: Oneshot<S, Req>,
+        },
+        Reject {
+            status: StatusCode,
+        },
+    }
+}
+
+impl<S, Req> ResponseFuture<S, Req>
+where
+    S: Service<Req>,
+{
+    fn reject(status: StatusCode) -> Self {
+        Self::Reject { status }
+    }
+}
+
+impl<S, Req, B> Future for ResponseFuture<S, Req>
+where
+    S: Service<Req, Response = Response<B>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Reject { status } => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = *status;
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::util::BoxCloneService;
+
+    fn named(name: &'static str) -> BoxCloneService<Request<()>, Response<String>, Infallible> {
+        BoxCloneService::new(tower::service_fn(move |_: Request<()>| async move {
+            Ok::<_, Infallible>(Response::new(name.to_owned()))
+        }))
+    }
+
+    fn router() -> Routing<BoxCloneService<Request<()>, Response<String>, Infallible>> {
+        let rules = RoutingRules::new()
+            .rule(Rule::new(
+                RuleMatch::any().header("x-api-version", "1"),
+                RuleAction::Reject(StatusCode::GONE),
+            ))
+            .rule(Rule::new(
+                RuleMatch::any().method(Method::POST).path_prefix("/v2/"),
+                RuleAction::route("v2"),
+            ))
+            .rule(Rule::new(
+                RuleMatch::any().path("/missing"),
+                RuleAction::route("missing"),
+            ));
+
+        RoutingLayer::new(rules)
+            .route("v2", named("v2"))
+            .layer(named("fallback"))
+    }
+
+    async fn send(request: Request<()>) -> Response<String> {
+        router().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn first_matching_rule_wins() {
+        let response = send(
+            Request::post("/v2/items")
+                .header("x-api-version", "1")
+                .body(())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(response.body(), "");
+
+        let response = send(Request::post("/v2/items").body(()).unwrap()).await;
+        assert_eq!(response.body(), "v2");
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_use_fallback() {
+        let response = send(Request::get("/v2/items").body(()).unwrap()).await;
+        assert_eq!(response.body(), "fallback");
+
+        let response = send(
+            Request::post("/v2/items")
+                .header("x-api-version", "2")
+                .body(())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.body(), "v2");
+    }
+
+    #[tokio::test]
+    async fn unknown_route_is_an_internal_error() {
+        let response = send(Request::get("/missing").body(()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn header_present() {
+        let matcher = RuleMatch::any().header_present("authorization");
+        assert!(!matcher.matches(&Request::new(())));
+        assert!(
+            matcher.matches(
+                &Request::get("/")
+                    .header("authorization", "x")
+                    .body(())
+                    .unwrap()
+            )
+        );
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A single sampling decision per request, shared by all layers.
+//!
+//! [`SamplingLayer`] decides whether a request is sampled, at a configurable
+//! rate, and stores the decision in a [`Sampled`] request extension. Layers
+//! further in that log, trace or otherwise observe only a subset of
+//! requests can then all pick the same subset, instead of each rolling its
+//! own dice:
+//!
+//! * [`OtelSpanLayer`] creates no span for unsampled requests;
+//! * [`MakeCallbackHandler`] implementations can check
+//!   [`Sampled::from_extensions`] on the request parts they are given.
+//!
+//! Requests that were sampled (or not) upstream keep that decision, so a
+//! trace is sampled consistently across services. The upstream decision is
+//! read from the sampled flag of a W3C `traceparent` header, or from the
+//! B3 `b3`, `x-b3-sampled` or `x-b3-flags` headers. Requests without any of
+//! these are sampled at random.
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::middleware::sampling::Sampled;
+//! use sui_http::middleware::sampling::SamplingLayer;
+//!
+//! // Log 1% of requests.
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(SamplingLayer::new(0.01))
+//!     .service_fn(|request: Request<()>| async move {
+//!         if Sampled::from_extensions(request.extensions()) {
+//!             tracing::info!(uri = %request.uri(), "handling request");
+//!         }
+//!         Ok::<_, std::convert::Infallible>(Response::new(String::new()))
+//!     });
+//! # let _ = service;
+//! ```
+//!
+//! [`OtelSpanLayer`]: super::otel::OtelSpanLayer
+//! [`MakeCallbackHandler`]: super::callback::MakeCallbackHandler
+
+use http::HeaderMap;
+use http::HeaderName;
+use http::Request;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use tower::Layer;
+use tower::Service;
+
+pub(crate) const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+pub(crate) const B3: HeaderName = HeaderName::from_static("b3");
+pub(crate) const X_B3_SAMPLED: HeaderName = HeaderName::from_static("x-b3-sampled");
+pub(crate) const X_B3_FLAGS: HeaderName = HeaderName::from_static("x-b3-flags");
+
+/// Request extension holding the sampling decision for the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampled(bool);
+
+impl Sampled {
+    pub fn new(sampled: bool) -> Self {
+        Self(sampled)
+    }
+
+    pub fn is_sampled(self) -> bool {
+        self.0
+    }
+
+    /// Whether the request with `extensions` is sampled. Requests that did
+    /// not pass through a [`SamplingLayer`] are.
+    pub fn from_extensions(extensions: &http::Extensions) -> bool {
+        extensions.get::<Self>().is_none_or(|sampled| sampled.0)
+    }
+}
+
+/// The sampling decision made upstream, if the headers carry one.
+fn upstream_decision(headers: &HeaderMap) -> Option<bool> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    // version-traceid-parentid-flags, with the sampled flag in bit 0.
+    if let Some(flags) = header(TRACEPARENT).and_then(|value| value.trim().split('-').nth(3))
+        && let Ok(flags) = u8::from_str_radix(flags, 16)
+    {
+        return Some(flags & 1 == 1);
+    }
+
+    // Either just the decision, or traceid-spanid[-decision[-parentid]].
+    if let Some(value) = header(B3) {
+        let fields: Vec<&str> = value.trim().split('-').collect();
+        let decision = match fields.len() {
+            1 => Some(fields[0]),
+            3 | 4 => Some(fields[2]),
+            _ => None,
+        };
+        if let Some(decision) = decision.and_then(parse_b3_decision) {
+            return Some(decision);
+        }
+    }
+
+    if header(X_B3_FLAGS).is_some_and(|flags| flags.trim() == "1") {
+        return Some(true);
+    }
+    header(X_B3_SAMPLED).and_then(parse_b3_decision)
+}
+
+/// Parses a B3 sampling state; `d` is the debug flag, which implies
+/// sampling.
+pub(crate) fn parse_b3_decision(value: &str) -> Option<bool> {
+    match value.trim() {
+        "1" | "d" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// A uniformly distributed value in `[0, 1)`.
+///
+/// Like request ids, this draws on the standard library's randomly keyed
+/// hasher rather than a RNG crate.
+pub(crate) fn random_unit() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A uniformly distributed `u64`, drawn like [`random_unit`].
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// [`Layer`] that makes the sampling decision for every request; see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct SamplingLayer {
+    rate: f64,
+    honor_upstream: bool,
+}
+
+impl SamplingLayer {
+    /// Sample requests with probability `rate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between 0 and 1.
+    pub fn new(rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "sampling rate must be between 0 and 1"
+        );
+        Self {
+            rate,
+            honor_upstream: true,
+        }
+    }
+
+    /// Whether to keep sampling decisions made upstream. Defaults to `true`;
+    /// disable it when clients are not trusted to choose, since sampled
+    /// requests are more expensive to serve.
+    pub fn honor_upstream(self, honor_upstream: bool) -> Self {
+        Self {
+            honor_upstream,
+            ..self
+        }
+    }
+
+    fn decide(&self, headers: &HeaderMap) -> bool {
+        if self.honor_upstream
+            && let Some(sampled) = upstream_decision(headers)
+        {
+            return sampled;
+        }
+        random_unit() < self.rate
+    }
+}
+
+impl<S> Layer<S> for SamplingLayer {
+    type Service = Sampling<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Sampling {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`SamplingLayer`].
+#[derive(Debug, Clone)]
+pub struct Sampling<S> {
+    inner: S,
+    layer: SamplingLayer,
+}
+
+impl<S, RequestBody> Service<Request<RequestBody>> for Sampling<S>
+where
+    S: Service<Request<RequestBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future {
+        // A decision made by an outer sampling layer stands.
+        if request.extensions().get::<Sampled>().is_none() {
+            let sampled = self.layer.decide(request.headers());
+            request.extensions_mut().insert(Sampled(sampled));
+        }
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use http::Response;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn headers(name: HeaderName, value: &'static str) -> HeaderMap {
+        [(name, HeaderValue::from_static(value))]
+            .into_iter()
+            .collect()
+    }
+
+    async fn sampled(layer: SamplingLayer, request: Request<()>) -> bool {
+        let svc = layer.layer(tower::service_fn(|request: Request<()>| async move {
+            let sampled = Sampled::from_extensions(request.extensions());
+            Ok::<_, Infallible>(Response::new(sampled))
+        }));
+        svc.oneshot(request).await.unwrap().into_body()
+    }
+
+    #[test]
+    fn reads_upstream_decisions() {
+        let cases = [
+            (
+                TRACEPARENT,
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ),
+            (B3, "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1"),
+            (B3, "d"),
+            (X_B3_SAMPLED, "1"),
+            (X_B3_FLAGS, "1"),
+        ];
+        for (name, value) in cases {
+            assert_eq!(upstream_decision(&headers(name, value)), Some(true));
+        }
+
+        let cases = [
+            (
+                TRACEPARENT,
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00",
+            ),
+            (B3, "0"),
+            (X_B3_SAMPLED, "false"),
+        ];
+        for (name, value) in cases {
+            assert_eq!(upstream_decision(&headers(name, value)), Some(false));
+        }
+
+        assert_eq!(upstream_decision(&HeaderMap::new()), None);
+        assert_eq!(upstream_decision(&headers(B3, "80f198ee-e457b5a2")), None);
+        assert_eq!(upstream_decision(&headers(TRACEPARENT, "garbage")), None);
+    }
+
+    #[test]
+    fn samples_at_the_configured_rate() {
+        let layer = SamplingLayer::new(0.25);
+        let sampled = (0..10_000)
+            .filter(|_| layer.decide(&HeaderMap::new()))
+            .count();
+        assert!((2_000..3_000).contains(&sampled), "{sampled}");
+    }
+
+    #[tokio::test]
+    async fn stores_the_decision() {
+        assert!(sampled(SamplingLayer::new(1.0), Request::new(())).await);
+        assert!(!sampled(SamplingLayer::new(0.0), Request::new(())).await);
+
+        let upstream = || {
+            Request::builder()
+                .header(X_B3_SAMPLED, "1")
+                .body(())
+                .unwrap()
+        };
+        assert!(sampled(SamplingLayer::new(0.0), upstream()).await);
+        assert!(!sampled(SamplingLayer::new(0.0).honor_upstream(false), upstream()).await);
+
+        let mut request = Request::new(());
+        request.extensions_mut().insert(Sampled::new(true));
+        assert!(sampled(SamplingLayer::new(0.0), request).await);
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Curated middleware stacks.
+//!
+//! [`MiddlewareStack`] composes the common layers in an order that works:
+//!
+//! 1. [request IDs](super::request_id), outermost, so everything below sees
+//!    and logs the ID;
+//! 2. [logging](super::logging) and [metrics](super::metrics), which observe
+//!    the response the client actually gets, timeouts included;
+//! 3. [compression](super::compression), so logged and measured sizes are
+//!    the bytes on the wire;
+//! 4. the [timeout](super::timeout) or [gRPC deadline](super::grpc_timeout),
+//!    innermost, so it bounds only the service and its timeout response
+//!    still passes through every other layer.
+//!
+//! [`MiddlewareStack::rest_defaults`] and [`MiddlewareStack::grpc_defaults`]
+//! are sensible starting points whose layers can each be replaced or
+//! removed. Apply a stack with [`Builder::middleware`](crate::Builder::middleware),
+//! or to a single service as a [`Layer`].
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::logging::LoggingLayer;
+//! use sui_http::middleware::stack::MiddlewareStack;
+//! use sui_http::middleware::timeout::TimeoutLayer;
+//!
+//! let stack = MiddlewareStack::rest_defaults()
+//!     .timeout(TimeoutLayer::new(Duration::from_secs(5)))
+//!     .logging(LoggingLayer::new().success_sample_rate(0.1));
+//! let builder = sui_http::Builder::new().middleware(stack);
+//! # let _ = builder;
+//! ```
+
+use http::Request;
+use http::Response;
+use std::time::Duration;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceBuilder;
+use tower::util::BoxCloneService;
+
+use super::grpc_timeout::GrpcTimeout;
+use super::logging::LoggingLayer;
+use super::request_id::RequestIdLayer;
+use super::timeout::TimeoutLayer;
+use crate::BoxError;
+use crate::body;
+use crate::body::BoxBody;
+
+#[cfg(feature = "compression")]
+use super::compression::CompressionLayer;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+
+/// The service a [`MiddlewareStack`] produces.
+pub type StackService = BoxCloneService<Request<BoxBody>, Response<BoxBody>, BoxError>;
+
+/// The service wrapped by the layers added with
+/// [`Builder::layer`](crate::Builder::layer): the served service, boxed,
+/// accepting requests with any body.
+///
+/// Accepting any body lets layers that wrap the request body, such as
+/// [`CallbackLayer`](super::callback::CallbackLayer), be used as they are.
+#[derive(Debug, Clone)]
+pub struct LayerService(StackService);
+
+impl LayerService {
+    pub(crate) fn new(service: StackService) -> Self {
+        Self(service)
+    }
+}
+
+impl<B> Service<Request<B>> for LayerService
+where
+    B: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = BoxError;
+    type Future = <StackService as Service<Request<BoxBody>>>::Future;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        self.0.call(request.map(body::boxed))
+    }
+}
+
+/// The default deadline of [`MiddlewareStack::rest_defaults`].
+const DEFAULT_REST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+enum Deadline {
+    Http(TimeoutLayer),
+    Grpc { server_timeout: Option<Duration> },
+}
+
+/// A set of common layers applied in a fixed, working order; see the
+/// [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct MiddlewareStack {
+    request_id: Option<RequestIdLayer>,
+    logging: Option<LoggingLayer>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    #[cfg(feature = "compression")]
+    compression: Option<CompressionLayer>,
+    deadline: Option<Deadline>,
+}
+
+impl MiddlewareStack {
+    /// A stack without any layers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request IDs, logging, response compression (with the `compression`
+    /// feature) and a 30 second [`TimeoutLayer`].
+    pub fn rest_defaults() -> Self {
+        Self::new()
+            .request_id(RequestIdLayer::new())
+            .logging(LoggingLayer::new())
+            .timeout(TimeoutLayer::new(DEFAULT_REST_TIMEOUT))
+            .with_default_compression()
+    }
+
+    /// Request IDs, logging and [`GrpcTimeout`] enforcing client deadlines.
+    ///
+    /// There is no HTTP-level compression, since gRPC negotiates message
+    /// compression itself through `grpc-encoding`.
+    pub fn grpc_defaults() -> Self {
+        Self::new()
+            .request_id(RequestIdLayer::new())
+            .logging(LoggingLayer::new())
+            .grpc_timeout(None)
+    }
+
+    #[cfg(feature = "compression")]
+    fn with_default_compression(self) -> Self {
+        self.compression(CompressionLayer::new())
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn with_default_compression(self) -> Self {
+        self
+    }
+
+    /// Replace or, with `None`, remove the request ID layer.
+    pub fn request_id(self, request_id: impl Into<Option<RequestIdLayer>>) -> Self {
+        Self {
+            request_id: request_id.into(),
+            ..self
+        }
+    }
+
+    /// Replace or, with `None`, remove the logging layer.
+    pub fn logging(self, logging: impl Into<Option<LoggingLayer>>) -> Self {
+        Self {
+            logging: logging.into(),
+            ..self
+        }
+    }
+
+    /// Record request metrics into `metrics`, or with `None`, stop. Off by
+    /// default, since exporting needs the caller's [`Metrics`].
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "metrics")))]
+    pub fn metrics(self, metrics: impl Into<Option<Metrics>>) -> Self {
+        Self {
+            metrics: metrics.into(),
+            ..self
+        }
+    }
+
+    /// Replace or, with `None`, remove the compression layer.
+    #[cfg(feature = "compression")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "compression")))]
+    pub fn compression(self, compression: impl Into<Option<CompressionLayer>>) -> Self {
+        Self {
+            compression: compression.into(),
+            ..self
+        }
+    }
+
+    /// Bound requests with `timeout` or, with `None`, not at all. Replaces
+    /// a [gRPC deadline](Self::grpc_timeout).
+    pub fn timeout(self, timeout: impl Into<Option<TimeoutLayer>>) -> Self {
+        Self {
+            deadline: timeout.into().map(Deadline::Http),
+            ..self
+        }
+    }
+
+    /// Enforce the deadline gRPC clients send in `grpc-timeout`, capped at
+    /// `server_timeout` if set, with [`GrpcTimeout`]. Replaces an
+    /// [HTTP timeout](Self::timeout).
+    pub fn grpc_timeout(self, server_timeout: Option<Duration>) -> Self {
+        Self {
+            deadline: Some(Deadline::Grpc { server_timeout }),
+            ..self
+        }
+    }
+}
+
+impl<S, ResponseBody> Layer<S> for MiddlewareStack
+where
+    S: Service<
+            Request<BoxBody>,
+            Response = Response<ResponseBody>,
+            Error: Into<BoxError>,
+            Future: Send,
+        > + Clone
+        + Send
+        + 'static,
+    ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+{
+    type Service = StackService;
+
+    fn layer(&self, service: S) -> Self::Service {
+        let mut service = boxed(service);
+        match &self.deadline {
+            Some(Deadline::Http(timeout)) => service = boxed(timeout.layer(service)),
+            Some(Deadline::Grpc { server_timeout }) => {
+                service = boxed(GrpcTimeout::new(service, *server_timeout))
+            }
+            None => {}
+        }
+        #[cfg(feature = "compression")]
+        if let Some(compression) = &self.compression {
+            service = boxed(compression.layer(service));
+        }
+        // The callback and logging layers wrap the request body to observe
+        // it.
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            service = boxed(
+                ServiceBuilder::new()
+                    .layer(metrics.layer())
+                    .map_request(|request: Request<_>| request.map(body::boxed))
+                    .service(service),
+            );
+        }
+        if let Some(logging) = &self.logging {
+            service = boxed(
+                ServiceBuilder::new()
+                    .layer(logging.clone())
+                    .map_request(|request: Request<_>| request.map(body::boxed))
+                    .service(service),
+            );
+        }
+        if let Some(request_id) = &self.request_id {
+            service = boxed(request_id.layer(service));
+        }
+        service
+    }
+}
+
+pub(crate) fn boxed<S, ResponseBody>(service: S) -> StackService
+where
+    S: Service<
+            Request<BoxBody>,
+            Response = Response<ResponseBody>,
+            Error: Into<BoxError>,
+            Future: Send,
+        > + Clone
+        + Send
+        + 'static,
+    ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+{
+    ServiceBuilder::new()
+        .layer(BoxCloneService::layer())
+        .map_response(|response: Response<ResponseBody>| response.map(body::boxed))
+        .map_err(Into::into)
+        .service(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    fn request(headers: &[(&str, &str)]) -> Request<BoxBody> {
+        let mut request = Request::post("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rest_defaults_time_out_inside_the_request_id() {
+        let stack =
+            MiddlewareStack::rest_defaults().timeout(TimeoutLayer::new(Duration::from_millis(10)));
+        let svc = stack.layer(tower::service_fn(|_: Request<BoxBody>| async {
+            std::future::pending::<Result<Response<BoxBody>, BoxError>>().await
+        }));
+
+        let response = svc.oneshot(request(&[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        // The timeout response still passes through the request ID layer.
+        assert!(response.headers().contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn grpc_defaults_enforce_client_deadlines() {
+        let svc = MiddlewareStack::grpc_defaults().layer(tower::service_fn(
+            |_: Request<BoxBody>| async {
+                std::future::pending::<Result<Response<BoxBody>, BoxError>>().await
+            },
+        ));
+
+        let response = svc
+            .oneshot(request(&[
+                ("content-type", "application/grpc"),
+                ("grpc-timeout", "10m"),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["grpc-status"], "4");
+        assert!(response.headers().contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn layers_can_be_removed() {
+        let svc = MiddlewareStack::rest_defaults()
+            .request_id(None)
+            .timeout(None)
+            .layer(tower::service_fn(|_: Request<BoxBody>| async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok::<_, BoxError>(Response::new(body::full("ok")))
+            }));
+
+        let response = svc.oneshot(request(&[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("x-request-id"));
+    }
+}
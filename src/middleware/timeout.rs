@@ -0,0 +1,200 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server-side deadlines for plain HTTP requests.
+//!
+//! [`TimeoutLayer`] gives the inner service a fixed time to produce a
+//! response. Past it, the inner future is dropped and the client gets an
+//! empty `504 Gateway Timeout` response, or whichever status was configured
+//! with [`TimeoutLayer::status`].
+//!
+//! gRPC requests are passed through untouched: they carry their own
+//! deadline, which [`GrpcTimeout`] enforces with a gRPC status, so a stack
+//! serving both REST and gRPC endpoints can use both layers.
+//!
+//! Only the response head is timed; streaming response bodies can take as
+//! long as they need.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::timeout::TimeoutLayer;
+//!
+//! let app: axum::Router = axum::Router::new()
+//!     .route("/", axum::routing::get(|| async { "hello" }))
+//!     .layer(TimeoutLayer::new(Duration::from_secs(30)));
+//! # let _ = app;
+//! ```
+//!
+//! [`GrpcTimeout`]: super::grpc_timeout::GrpcTimeout
+
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+use tokio::time::Sleep;
+use tower::Layer;
+use tower::Service;
+
+use crate::body::Either;
+use crate::body::Empty;
+use crate::middleware::callback::is_grpc;
+use crate::middleware::grpc_timeout::MaybeEmptyBody;
+
+/// [`Layer`] that enforces a deadline on HTTP requests; see the
+/// [module docs](self).
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+    status: StatusCode,
+}
+
+impl TimeoutLayer {
+    /// Time out requests whose response takes longer than `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            status: StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// The status of responses to timed out requests. Defaults to
+    /// `504 Gateway Timeout`; `503 Service Unavailable` and
+    /// `408 Request Timeout` are common alternatives.
+    pub fn status(self, status: StatusCode) -> Self {
+        Self { status, ..self }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            layer: *self,
+        }
+    }
+}
+
+/// Service returned by [`TimeoutLayer`].
+#[derive(Debug, Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    layer: TimeoutLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Timeout<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: http_body::Body,
+{
+    type Response = Response<MaybeEmptyBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let sleep = (!is_grpc(request.headers())).then(|| tokio::time::sleep(self.layer.timeout));
+        ResponseFuture {
+            inner: self.inner.call(request),
+            sleep,
+            status: self.layer.status,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Timeout`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        #[pin]
+        sleep: Option<Sleep>,
+        status: StatusCode,
+    }
+}
+
+impl<F, ResponseBody, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<ResponseBody>, E>>,
+    ResponseBody: http_body::Body,
+{
+    type Output = Result<Response<MaybeEmptyBody<ResponseBody>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(result.map(|response| response.map(Either::left)));
+        }
+
+        if let Some(sleep) = this.sleep.as_pin_mut() {
+            ready!(sleep.poll(cx));
+            let mut response = Response::new(Either::right(Empty::new()));
+            *response.status_mut() = *this.status;
+            return Poll::Ready(Ok(response));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    async fn call(layer: TimeoutLayer, request: Request<()>) -> StatusCode {
+        let svc = layer.layer(tower::service_fn(|_: Request<()>| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok::<_, Infallible>(Response::new(http_body_util::Empty::<bytes::Bytes>::new()))
+        }));
+        svc.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_requests() {
+        let layer = TimeoutLayer::new(Duration::from_millis(20));
+        assert_eq!(
+            call(layer, Request::new(())).await,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(
+            call(
+                layer.status(StatusCode::SERVICE_UNAVAILABLE),
+                Request::new(())
+            )
+            .await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            call(TimeoutLayer::new(Duration::from_secs(5)), Request::new(())).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_grpc_requests_alone() {
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            call(TimeoutLayer::new(Duration::from_millis(20)), request).await,
+            StatusCode::OK
+        );
+    }
+}
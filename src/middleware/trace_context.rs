@@ -0,0 +1,527 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! W3C trace context extraction and propagation.
+//!
+//! [`TraceContextLayer`] reads the trace context of incoming requests from
+//! the `traceparent` and `tracestate` headers, or with
+//! [`TraceContextLayer::b3`] from the B3 `b3` or `x-b3-*` headers, and
+//! starts a new trace for requests without one. The request is then given
+//! a fresh span id as a child of the caller's span, and the resulting
+//! [`TraceContext`] is stored as a request extension.
+//!
+//! Handlers pass the context on to downstream RPC clients with
+//! [`TraceContext::inject`], so their spans join the same trace. The
+//! request's own `traceparent` header is rewritten to the new context, so
+//! proxies built on the request propagate it too, and it is echoed on the
+//! response unless the service set one.
+//!
+//! The trace and span ids are recorded on the request span of an
+//! [`OtelSpanLayer`], whichever of the two layers is the outer one. This
+//! layer only moves context along; exporting spans is left to the
+//! `tracing` subscriber.
+//!
+//! Requests without an upstream context are sampled according to the
+//! [`Sampled`] extension, if a [`SamplingLayer`] runs first.
+//!
+//! # Example
+//!
+//! ```
+//! use http::HeaderMap;
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::middleware::trace_context::TraceContext;
+//! use sui_http::middleware::trace_context::TraceContextLayer;
+//!
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(TraceContextLayer::new().b3(true))
+//!     .service_fn(|request: Request<()>| async move {
+//!         let context = request.extensions().get::<TraceContext>().unwrap();
+//!         let mut downstream = HeaderMap::new();
+//!         context.inject(&mut downstream);
+//!         // ... call a downstream service with `downstream` ...
+//!         Ok::<_, std::convert::Infallible>(Response::new(String::new()))
+//!     });
+//! # let _ = service;
+//! ```
+//!
+//! [`OtelSpanLayer`]: super::otel::OtelSpanLayer
+//! [`Sampled`]: super::sampling::Sampled
+//! [`SamplingLayer`]: super::sampling::SamplingLayer
+
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+
+use super::otel::RequestSpan;
+use super::sampling::B3;
+use super::sampling::Sampled;
+use super::sampling::TRACEPARENT;
+use super::sampling::X_B3_FLAGS;
+use super::sampling::X_B3_SAMPLED;
+use super::sampling::parse_b3_decision;
+use super::sampling::random_u64;
+
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+const X_B3_TRACE_ID: HeaderName = HeaderName::from_static("x-b3-traceid");
+const X_B3_SPAN_ID: HeaderName = HeaderName::from_static("x-b3-spanid");
+
+/// Request extension holding the trace context of the request; see the
+/// [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    sampled: bool,
+    trace_state: Option<HeaderValue>,
+}
+
+impl TraceContext {
+    /// The context of a new trace, without a parent.
+    pub fn new_root(sampled: bool) -> Self {
+        let trace_id = loop {
+            let trace_id = (u128::from(random_u64()) << 64) | u128::from(random_u64());
+            if trace_id != 0 {
+                break trace_id;
+            }
+        };
+        Self {
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id: None,
+            sampled,
+            trace_state: None,
+        }
+    }
+
+    /// The context carried by `headers`: the W3C `traceparent` and
+    /// `tracestate` headers or, if `b3` is set and there is no valid
+    /// `traceparent`, the B3 headers.
+    ///
+    /// Its span id is that of the caller; use [`Self::child`] for the span
+    /// serving the request.
+    pub fn from_headers(headers: &HeaderMap, b3: bool) -> Option<Self> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(mut context) = header(TRACEPARENT).and_then(parse_traceparent) {
+            context.trace_state = headers
+                .get(TRACESTATE)
+                .filter(|state| !state.is_empty())
+                .cloned();
+            return Some(context);
+        }
+        if !b3 {
+            return None;
+        }
+
+        // traceid-spanid[-decision[-parentid]]
+        if let Some(value) = header(B3) {
+            let fields: Vec<&str> = value.trim().split('-').collect();
+            if fields.len() >= 2 {
+                let sampled = fields.get(2).copied().and_then(parse_b3_decision);
+                return parse_b3(fields[0], fields[1], sampled);
+            }
+        }
+        let sampled = if header(X_B3_FLAGS).is_some_and(|flags| flags.trim() == "1") {
+            Some(true)
+        } else {
+            header(X_B3_SAMPLED).and_then(parse_b3_decision)
+        };
+        parse_b3(header(X_B3_TRACE_ID)?, header(X_B3_SPAN_ID)?, sampled)
+    }
+
+    /// The context of a new span in the same trace, as a child of this
+    /// one.
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: new_span_id(),
+            parent_span_id: Some(self.span_id),
+            trace_state: self.trace_state.clone(),
+            ..*self
+        }
+    }
+
+    /// The 128-bit trace id.
+    pub fn trace_id(&self) -> u128 {
+        self.trace_id
+    }
+
+    /// The 64-bit id of the span.
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    /// The id of the parent span, `None` at the root of a trace.
+    pub fn parent_span_id(&self) -> Option<u64> {
+        self.parent_span_id
+    }
+
+    /// Whether the trace is sampled.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// The vendor-specific `tracestate` passed along with the context.
+    pub fn trace_state(&self) -> Option<&HeaderValue> {
+        self.trace_state.as_ref()
+    }
+
+    /// The trace id as 32 lowercase hex digits, as logged by most tracing
+    /// backends.
+    pub fn trace_id_hex(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    /// The `traceparent` header value for this context.
+    pub fn traceparent(&self) -> HeaderValue {
+        let value = format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        );
+        HeaderValue::try_from(value).expect("traceparent is a valid header value")
+    }
+
+    /// Set the `traceparent` and `tracestate` headers of a downstream
+    /// request, so the downstream span becomes a child of this one.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        headers.insert(TRACEPARENT, self.traceparent());
+        match &self.trace_state {
+            Some(state) => {
+                headers.insert(TRACESTATE, state.clone());
+            }
+            None => {
+                headers.remove(TRACESTATE);
+            }
+        }
+    }
+
+    /// Record the trace and span ids on `span`, if it declares the
+    /// `trace_id` and `span_id` fields.
+    pub(crate) fn record(&self, span: &tracing::Span) {
+        span.record("trace_id", self.trace_id_hex().as_str());
+        span.record("span_id", format!("{:016x}", self.span_id).as_str());
+    }
+}
+
+fn new_span_id() -> u64 {
+    loop {
+        let span_id = random_u64();
+        if span_id != 0 {
+            return span_id;
+        }
+    }
+}
+
+/// Parses `version-traceid-parentid-flags`. Versions above `00` may append
+/// fields, which are ignored.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut fields = value.trim().split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let span_id = fields.next()?;
+    let flags = fields.next()?;
+    if version.len() != 2
+        || version == "ff"
+        || (version == "00" && fields.next().is_some())
+        || flags.len() != 2
+    {
+        return None;
+    }
+    u8::from_str_radix(version, 16).ok()?;
+
+    Some(TraceContext {
+        trace_id: parse_id(trace_id, 32)?,
+        span_id: parse_id(span_id, 16)?.try_into().ok()?,
+        parent_span_id: None,
+        sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+        trace_state: None,
+    })
+}
+
+/// Parses B3 trace and span ids; 64-bit trace ids are left-padded. A
+/// missing sampling decision counts as sampled.
+fn parse_b3(trace_id: &str, span_id: &str, sampled: Option<bool>) -> Option<TraceContext> {
+    let trace_id = match trace_id.trim().len() {
+        16 => parse_id(trace_id.trim(), 16)?,
+        _ => parse_id(trace_id.trim(), 32)?,
+    };
+    Some(TraceContext {
+        trace_id,
+        span_id: parse_id(span_id.trim(), 16)?.try_into().ok()?,
+        parent_span_id: None,
+        sampled: sampled.unwrap_or(true),
+        trace_state: None,
+    })
+}
+
+/// Parses a non-zero id of exactly `len` lowercase hex digits.
+fn parse_id(id: &str, len: usize) -> Option<u128> {
+    if id.len() != len || !id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    u128::from_str_radix(id, 16).ok().filter(|id| *id != 0)
+}
+
+/// [`Layer`] that extracts and propagates trace context; see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct TraceContextLayer {
+    b3: bool,
+    echo: bool,
+}
+
+impl Default for TraceContextLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceContextLayer {
+    pub fn new() -> Self {
+        Self {
+            b3: false,
+            echo: true,
+        }
+    }
+
+    /// Also accept B3 headers from callers that do not send `traceparent`.
+    ///
+    /// Default is `false`.
+    pub fn b3(self, b3: bool) -> Self {
+        Self { b3, ..self }
+    }
+
+    /// Whether to set `traceparent` on responses that do not carry one.
+    ///
+    /// Default is `true`.
+    pub fn echo(self, echo: bool) -> Self {
+        Self { echo, ..self }
+    }
+}
+
+impl<S> Layer<S> for TraceContextLayer {
+    type Service = TraceContextService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceContextService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service returned by [`TraceContextLayer`].
+#[derive(Debug, Clone)]
+pub struct TraceContextService<S> {
+    inner: S,
+    layer: TraceContextLayer,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for TraceContextService<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future {
+        let sampled = Sampled::from_extensions(request.extensions());
+        let context = match TraceContext::from_headers(request.headers(), self.layer.b3) {
+            Some(parent) => parent.child(),
+            None => TraceContext::new_root(sampled),
+        };
+
+        context.inject(request.headers_mut());
+        if let Some(span) = request.extensions().get::<RequestSpan>() {
+            context.record(span.span());
+        }
+        let traceparent = self.layer.echo.then(|| context.traceparent());
+        request.extensions_mut().insert(context);
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            traceparent,
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`TraceContextService`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        traceparent: Option<HeaderValue>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        if let Some(traceparent) = this.traceparent.take() {
+            response
+                .headers_mut()
+                .entry(TRACEPARENT)
+                .or_insert(traceparent);
+        }
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+    const PARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    /// Serves `request`, returning the context the service saw, the
+    /// `traceparent` it was forwarded and the response.
+    async fn serve(
+        layer: TraceContextLayer,
+        request: Request<()>,
+    ) -> (TraceContext, HeaderValue, Response<()>) {
+        let svc = layer.layer(tower::service_fn(|request: Request<()>| async move {
+            Ok::<_, Infallible>(Response::new(request))
+        }));
+        let response = svc.oneshot(request).await.unwrap();
+        let (parts, request) = response.into_parts();
+        let context = request.extensions().get::<TraceContext>().unwrap().clone();
+        let forwarded = request.headers()[TRACEPARENT].clone();
+        (context, forwarded, Response::from_parts(parts, ()))
+    }
+
+    #[test]
+    fn parses_traceparent() {
+        let context =
+            TraceContext::from_headers(&headers(&[("traceparent", PARENT)]), false).unwrap();
+        assert_eq!(context.trace_id_hex(), TRACE_ID);
+        assert_eq!(context.span_id(), 0xb7ad6b7169203331);
+        assert!(context.is_sampled());
+        assert_eq!(context.traceparent(), PARENT);
+
+        // Future versions may add fields.
+        let future = "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-extra";
+        let context =
+            TraceContext::from_headers(&headers(&[("traceparent", future)]), false).unwrap();
+        assert!(!context.is_sampled());
+
+        let invalid = [
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+            "00-0af7651916cd43dd-b7ad6b7169203331-01",
+        ];
+        for value in invalid {
+            let headers: HeaderMap = [(TRACEPARENT, HeaderValue::from_static(value))]
+                .into_iter()
+                .collect();
+            assert_eq!(TraceContext::from_headers(&headers, false), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn parses_b3_only_when_enabled() {
+        let single = headers(&[("b3", "80f198ee56343ba8-e457b5a2e4d86bd1-0")]);
+        assert_eq!(TraceContext::from_headers(&single, false), None);
+        let context = TraceContext::from_headers(&single, true).unwrap();
+        assert_eq!(context.trace_id_hex(), "000000000000000080f198ee56343ba8");
+        assert_eq!(context.span_id(), 0xe457b5a2e4d86bd1);
+        assert!(!context.is_sampled());
+
+        let multi = headers(&[
+            ("x-b3-traceid", "80f198ee56343ba864fe8b2a57d3eff7"),
+            ("x-b3-spanid", "e457b5a2e4d86bd1"),
+            ("x-b3-sampled", "1"),
+        ]);
+        let context = TraceContext::from_headers(&multi, true).unwrap();
+        assert_eq!(context.trace_id_hex(), "80f198ee56343ba864fe8b2a57d3eff7");
+        assert!(context.is_sampled());
+
+        // traceparent wins.
+        let both = headers(&[
+            ("traceparent", PARENT),
+            ("b3", "80f198ee56343ba8-e457b5a2e4d86bd1-0"),
+        ]);
+        let context = TraceContext::from_headers(&both, true).unwrap();
+        assert_eq!(context.trace_id_hex(), TRACE_ID);
+    }
+
+    #[tokio::test]
+    async fn continues_upstream_traces() {
+        let request = Request::builder()
+            .header("traceparent", PARENT)
+            .header("tracestate", "vendor=value")
+            .body(())
+            .unwrap();
+        let (context, forwarded, response) = serve(TraceContextLayer::new(), request).await;
+
+        assert_eq!(context.trace_id_hex(), TRACE_ID);
+        assert_eq!(context.parent_span_id(), Some(0xb7ad6b7169203331));
+        assert_ne!(context.span_id(), 0xb7ad6b7169203331);
+        assert_eq!(context.trace_state().unwrap(), "vendor=value");
+        assert_eq!(forwarded, context.traceparent());
+        assert_eq!(response.headers()[TRACEPARENT], context.traceparent());
+
+        let mut downstream = HeaderMap::new();
+        context.child().inject(&mut downstream);
+        let downstream = TraceContext::from_headers(&downstream, false).unwrap();
+        assert_eq!(downstream.trace_id(), context.trace_id());
+        assert_eq!(downstream.trace_state().unwrap(), "vendor=value");
+    }
+
+    #[tokio::test]
+    async fn starts_new_traces() {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(Sampled::new(false));
+        let (context, _, response) = serve(TraceContextLayer::new().echo(false), request).await;
+
+        assert_ne!(context.trace_id(), 0);
+        assert_eq!(context.parent_span_id(), None);
+        assert!(!context.is_sampled());
+        assert!(!response.headers().contains_key(TRACEPARENT));
+
+        let (other, _, _) = serve(TraceContextLayer::new(), Request::new(())).await;
+        assert_ne!(other.trace_id(), context.trace_id());
+        assert!(other.is_sampled());
+    }
+}
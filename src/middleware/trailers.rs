@@ -0,0 +1,482 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Middleware that lets handlers register response trailers.
+//!
+//! [`TrailerLayer`] inserts a [`TrailerSink`] into the extensions of every
+//! request. Handlers (or inner middleware) add headers to the sink at any
+//! point before the response body completes, and the layer's
+//! [`TrailersBody`] appends them as a trailers frame once the inner body
+//! ends. This lets services that are not built on tonic emit gRPC-style
+//! trailers such as `grpc-status` without hand-rolling a body type.
+//!
+//! Without the layer, [`WithTrailers`] appends the trailers a future
+//! resolves to once a body ends, and [`grpc_trailers_only`] builds the
+//! trailers-only responses gRPC uses to fail a call without a message.
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::middleware::trailers::TrailerLayer;
+//! use sui_http::middleware::trailers::TrailerSink;
+//!
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(TrailerLayer::new())
+//!     .service_fn(|request: Request<()>| async move {
+//!         let trailers = request.extensions().get::<TrailerSink>().unwrap();
+//!         trailers.insert(
+//!             http::HeaderName::from_static("grpc-status"),
+//!             http::HeaderValue::from_static("0"),
+//!         );
+//!         Ok::<_, std::convert::Infallible>(Response::new(String::from("hello")))
+//!     });
+//! # let _ = service;
+//! ```
+
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use tower::Layer;
+use tower::Service;
+
+/// Request extension used to register trailers to be appended to the
+/// response body.
+///
+/// Cloning a `TrailerSink` yields a handle to the same set of trailers, so
+/// it can be moved into a spawned task that produces a streaming body.
+#[derive(Debug, Default, Clone)]
+pub struct TrailerSink(Arc<Mutex<HeaderMap>>);
+
+impl TrailerSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a trailer, replacing any previous value with the same name.
+    pub fn insert(&self, name: impl Into<HeaderName>, value: HeaderValue) {
+        self.0.lock().unwrap().insert(name.into(), value);
+    }
+
+    /// Appends a trailer, keeping any previous values with the same name.
+    pub fn append(&self, name: impl Into<HeaderName>, value: HeaderValue) {
+        self.0.lock().unwrap().append(name.into(), value);
+    }
+
+    /// Inserts every header in `trailers`, replacing existing values.
+    pub fn extend(&self, trailers: HeaderMap) {
+        self.0.lock().unwrap().extend(trailers);
+    }
+
+    /// Returns `true` if no trailers have been registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    fn take(&self) -> HeaderMap {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// [`Layer`] that installs a [`TrailerSink`] on every request.
+///
+/// See the [module docs](self) for details.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TrailerLayer(());
+
+impl TrailerLayer {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl<S> Layer<S> for TrailerLayer {
+    type Service = Trailers<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trailers { inner }
+    }
+}
+
+/// Middleware that installs a [`TrailerSink`] on every request.
+#[derive(Debug, Clone, Copy)]
+pub struct Trailers<S> {
+    inner: S,
+}
+
+impl<S> Trailers<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Trailers<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+    type Response = Response<TrailersBody<ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future {
+        let sink = TrailerSink::new();
+        request.extensions_mut().insert(sink.clone());
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            sink: Some(sink),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Trailers`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        sink: Option<TrailerSink>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<TrailersBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let sink = this.sink.take().expect("polled after completion");
+
+        Poll::Ready(Ok(response.map(|body| TrailersBody::new(body, sink))))
+    }
+}
+
+pin_project! {
+    /// Body that appends the trailers registered with a [`TrailerSink`]
+    /// once the inner body completes.
+    ///
+    /// If the inner body produces its own trailers the registered ones are
+    /// merged into them, replacing values with the same name.
+    pub struct TrailersBody<B> {
+        #[pin]
+        inner: B,
+        sink: TrailerSink,
+        done: bool,
+    }
+}
+
+impl<B> TrailersBody<B> {
+    pub fn new(inner: B, sink: TrailerSink) -> Self {
+        Self {
+            inner,
+            sink,
+            done: false,
+        }
+    }
+}
+
+impl<B> Body for TrailersBody<B>
+where
+    B: Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        match ready!(this.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_trailers() {
+                Ok(mut trailers) => {
+                    *this.done = true;
+                    trailers.extend(this.sink.take());
+                    Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                }
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => {
+                *this.done = true;
+                let trailers = this.sink.take();
+                if trailers.is_empty() {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || (self.inner.is_end_stream() && self.sink.is_empty())
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pin_project! {
+    /// Body that appends the trailers `trailers` resolves to once the inner
+    /// body completes.
+    ///
+    /// The future is only polled after the inner body has ended, so it can
+    /// wait on work that finishes with the stream. If the inner body
+    /// produces its own trailers, the resolved ones are merged into them,
+    /// replacing values with the same name.
+    pub struct WithTrailers<B, F> {
+        #[pin]
+        inner: B,
+        #[pin]
+        trailers: F,
+        inner_trailers: Option<HeaderMap>,
+        inner_done: bool,
+        done: bool,
+    }
+}
+
+impl<B, F> WithTrailers<B, F> {
+    pub fn new(inner: B, trailers: F) -> Self {
+        Self {
+            inner,
+            trailers,
+            inner_trailers: None,
+            inner_done: false,
+            done: false,
+        }
+    }
+}
+
+impl<B, F> Body for WithTrailers<B, F>
+where
+    B: Body,
+    F: Future<Output = HeaderMap>,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        if !*this.inner_done {
+            match ready!(this.inner.poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_trailers() {
+                    Ok(trailers) => *this.inner_trailers = Some(trailers),
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {}
+            }
+            *this.inner_done = true;
+        }
+
+        let extra = ready!(this.trailers.poll(cx));
+        *this.done = true;
+        let mut trailers = this.inner_trailers.take().unwrap_or_default();
+        trailers.extend(extra);
+        if trailers.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A trailers-only gRPC response: an empty `200 OK` response whose headers
+/// carry `grpc-status` `code` and, if given, the percent-encoded
+/// `grpc-message`.
+///
+/// ```
+/// use sui_http::middleware::trailers::grpc_trailers_only;
+///
+/// let response: http::Response<String> = grpc_trailers_only(5, Some("no such checkpoint"));
+/// assert_eq!(response.headers()["grpc-status"], "5");
+/// assert_eq!(response.headers()["grpc-message"], "no%20such%20checkpoint");
+/// ```
+pub fn grpc_trailers_only<B: Default>(code: i32, message: Option<&str>) -> Response<B> {
+    let mut response = Response::new(B::default());
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    headers.insert(HeaderName::from_static("grpc-status"), code.into());
+    if let Some(message) = message {
+        let message = HeaderValue::try_from(percent_encode(message)).expect("percent-encoded");
+        headers.insert(HeaderName::from_static("grpc-message"), message);
+    }
+    response
+}
+
+/// Percent-encodes a `grpc-message`, escaping the same characters as tonic.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for &byte in message.as_bytes() {
+        if byte.is_ascii_graphic() && !b"\"#%<>?`{}".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use std::convert::Infallible;
+    use tower::ServiceBuilder;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn appends_registered_trailers() {
+        let svc = ServiceBuilder::new().layer(TrailerLayer::new()).service_fn(
+            |request: Request<()>| async move {
+                let sink = request.extensions().get::<TrailerSink>().unwrap().clone();
+                sink.insert(
+                    HeaderName::from_static("grpc-status"),
+                    HeaderValue::from_static("0"),
+                );
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from_static(b"hello"))))
+            },
+        );
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        let body = response.into_body();
+        assert!(!body.is_end_stream());
+        let collected = body.collect().await.unwrap();
+        assert_eq!(
+            collected.trailers().unwrap().get("grpc-status").unwrap(),
+            "0"
+        );
+        assert_eq!(collected.to_bytes(), "hello");
+    }
+
+    #[tokio::test]
+    async fn trailers_can_be_registered_while_streaming() {
+        let sink = TrailerSink::new();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let stream = futures::stream::once({
+            let sink = sink.clone();
+            async move {
+                rx.await.unwrap();
+                sink.insert(
+                    HeaderName::from_static("x-late"),
+                    HeaderValue::from_static("1"),
+                );
+                Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"data")))
+            }
+        });
+        let body = TrailersBody::new(http_body_util::StreamBody::new(stream), sink);
+
+        tx.send(()).unwrap();
+        let collected = body.collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap().get("x-late").unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn merges_with_inner_trailers() {
+        let mut inner_trailers = HeaderMap::new();
+        inner_trailers.insert("x-inner", HeaderValue::from_static("a"));
+        inner_trailers.insert("x-shared", HeaderValue::from_static("inner"));
+        let inner = Full::new(Bytes::from_static(b"data"))
+            .with_trailers(async move { Some(Ok(inner_trailers)) });
+
+        let sink = TrailerSink::new();
+        sink.insert(
+            HeaderName::from_static("x-shared"),
+            HeaderValue::from_static("sink"),
+        );
+        let collected = TrailersBody::new(inner, sink).collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers.get("x-inner").unwrap(), "a");
+        assert_eq!(trailers.get("x-shared").unwrap(), "sink");
+    }
+
+    #[tokio::test]
+    async fn no_trailers_when_none_registered() {
+        let body = TrailersBody::new(Full::new(Bytes::from_static(b"data")), TrailerSink::new());
+        let collected = body.collect().await.unwrap();
+        assert!(collected.trailers().is_none());
+    }
+
+    #[tokio::test]
+    async fn with_trailers_appends_the_resolved_trailers() {
+        let mut inner_trailers = HeaderMap::new();
+        inner_trailers.insert("x-inner", HeaderValue::from_static("a"));
+        let inner = Full::new(Bytes::from_static(b"data"))
+            .with_trailers(async move { Some(Ok::<_, Infallible>(inner_trailers)) });
+
+        let body = WithTrailers::new(inner, async {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            trailers
+        });
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers.get("x-inner").unwrap(), "a");
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+        assert_eq!(collected.to_bytes(), "data");
+
+        let body = WithTrailers::new(Full::new(Bytes::new()), async { HeaderMap::new() });
+        assert!(body.collect().await.unwrap().trailers().is_none());
+    }
+
+    #[test]
+    fn builds_trailers_only_responses() {
+        let response: Response<String> = grpc_trailers_only(4, Some("Timeout expired"));
+        assert_eq!(response.headers()["content-type"], "application/grpc");
+        assert_eq!(response.headers()["grpc-status"], "4");
+        assert_eq!(response.headers()["grpc-message"], "Timeout%20expired");
+
+        let response: Response<String> = grpc_trailers_only(0, None);
+        assert!(!response.headers().contains_key("grpc-message"));
+
+        assert_eq!(percent_encode("100% é"), "100%25%20%C3%A9");
+    }
+}
@@ -0,0 +1,733 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resumable uploads of large request bodies.
+//!
+//! [`ResumableUploadLayer`] lets clients upload a resource in chunks with
+//! `PUT` or `PATCH` requests. Each chunk names its position with a
+//! `content-range` header, e.g. `bytes 0-1048575/10485760`. When a
+//! connection drops mid-upload, the client asks how much the server has with
+//! an empty request carrying `content-range: bytes */10485760`. The answer
+//! is `204 No Content` with an `upload-offset` header, and the client resumes
+//! from that offset.
+//!
+//! The layer tracks the offset and a running CRC32C of each upload, keyed by
+//! request path. Chunks that do not start at the current offset are rejected
+//! with `409 Conflict` and the `upload-offset` header, without calling the
+//! inner service. The request body of an accepted chunk is checked as the
+//! service reads it: the body fails with an [`UploadError`] if its length
+//! does not match the range. On the final chunk the body also fails if the
+//! `x-upload-crc32c` trailer (eight hex digits) does not match the checksum
+//! of the whole upload. The service stores the bytes; it can read the
+//! chunk's position from the [`UploadChunk`] request extension.
+//!
+//! A chunk only advances the offset once the service has read its whole
+//! body and responded with a success status.
+//!
+//! # Example
+//!
+//! ```
+//! use http::Request;
+//! use http::Response;
+//! use http_body_util::BodyExt;
+//! use sui_http::middleware::upload::ResumableUploadLayer;
+//! use sui_http::middleware::upload::UploadBody;
+//! use sui_http::middleware::upload::UploadChunk;
+//!
+//! let service = tower::ServiceBuilder::new()
+//!     .layer(ResumableUploadLayer::new())
+//!     .service_fn(|request: Request<UploadBody<String>>| async move {
+//!         let chunk = request.extensions().get::<UploadChunk>().copied();
+//!         // Fails if the chunk is short or the final checksum is wrong.
+//!         let data = request.into_body().collect().await?.to_bytes();
+//!         // Write `data` at `chunk.offset()`...
+//!         let _ = (chunk, data);
+//!         Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Response::new(String::new()))
+//!     });
+//! # let _ = service;
+//! ```
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use http::header;
+use http_body::Body;
+use http_body::Frame;
+use pin_project_lite::pin_project;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+use crate::BoxError;
+use crate::middleware::checksum;
+
+/// Response header carrying the number of bytes of an upload received so
+/// far.
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+
+/// Request trailer carrying the CRC32C of a whole upload, as eight
+/// lowercase or uppercase hex digits.
+pub const UPLOAD_CRC32C: HeaderName = HeaderName::from_static("x-upload-crc32c");
+
+/// [`Layer`](tower::Layer) that tracks resumable uploads; see the
+/// [module docs](self).
+///
+/// All services produced by the layer (and their clones) share the state of
+/// in-progress uploads.
+#[derive(Debug, Clone, Default)]
+pub struct ResumableUploadLayer {
+    uploads: Arc<Mutex<HashMap<String, Upload>>>,
+}
+
+impl ResumableUploadLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> tower::Layer<S> for ResumableUploadLayer {
+    type Service = ResumableUpload<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResumableUpload {
+            inner,
+            uploads: self.uploads.clone(),
+        }
+    }
+}
+
+/// The progress of an in-progress upload.
+#[derive(Debug, Clone, Copy, Default)]
+struct Upload {
+    offset: u64,
+    crc32c: u32,
+    total: Option<u64>,
+}
+
+/// Request extension describing the chunk of an upload carried by the
+/// request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadChunk {
+    offset: u64,
+    len: u64,
+    total: Option<u64>,
+}
+
+impl UploadChunk {
+    /// The position of the chunk's first byte in the upload.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The length of the chunk in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the chunk is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The length of the whole upload, if the client has declared it.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Whether the chunk completes the upload.
+    pub fn is_final(&self) -> bool {
+        self.total == Some(self.offset + self.len)
+    }
+}
+
+/// The error an [`UploadBody`] fails with when the chunk it carries is
+/// corrupt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadError {
+    /// The body's length does not match its `content-range`.
+    Length { expected: u64, received: u64 },
+    /// The `x-upload-crc32c` trailer does not match the upload's checksum,
+    /// or is not valid hex.
+    Checksum { expected: String, actual: u32 },
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Length { expected, received } => write!(
+                f,
+                "upload chunk length mismatch: content-range declares {expected} bytes, \
+                 received {received}"
+            ),
+            Self::Checksum { expected, actual } => write!(
+                f,
+                "upload checksum mismatch: trailer is {expected:?}, computed {actual:08x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// Service returned by [`ResumableUploadLayer`].
+#[derive(Debug, Clone)]
+pub struct ResumableUpload<S> {
+    inner: S,
+    uploads: Arc<Mutex<HashMap<String, Upload>>>,
+}
+
+impl<S, RequestBody, ResponseBody> tower::Service<Request<RequestBody>> for ResumableUpload<S>
+where
+    S: tower::Service<Request<UploadBody<RequestBody>>, Response = Response<ResponseBody>>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let is_upload = matches!(*request.method(), Method::PUT | Method::PATCH)
+            && request.headers().contains_key(header::CONTENT_RANGE);
+        if !is_upload {
+            return ResponseFuture::Inner {
+                future: self.inner.call(request.map(UploadBody::untracked)),
+                commit: None,
+            };
+        }
+
+        let Some(range) = request
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(ContentRange::parse)
+        else {
+            return ResponseFuture::respond(StatusCode::BAD_REQUEST, None);
+        };
+
+        let key = request.uri().path().to_owned();
+        let upload = self
+            .uploads
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or_default();
+
+        if range.total.is_some() && upload.total.is_some() && range.total != upload.total {
+            tracing::debug!(path = key, "upload length changed between chunks");
+            return ResponseFuture::respond(StatusCode::CONFLICT, Some(upload.offset));
+        }
+        let Some((start, end)) = range.range else {
+            // `bytes */total` asks how far the upload has got.
+            return ResponseFuture::respond(StatusCode::NO_CONTENT, Some(upload.offset));
+        };
+        if range.total.is_some_and(|total| end >= total) {
+            return ResponseFuture::respond(StatusCode::RANGE_NOT_SATISFIABLE, None);
+        }
+        if start != upload.offset {
+            tracing::debug!(
+                path = key,
+                start,
+                offset = upload.offset,
+                "upload chunk does not start at the current offset"
+            );
+            return ResponseFuture::respond(StatusCode::CONFLICT, Some(upload.offset));
+        }
+
+        let chunk = UploadChunk {
+            offset: start,
+            len: end - start + 1,
+            total: range.total.or(upload.total),
+        };
+        let completed = Arc::new(Mutex::new(None));
+        let (mut parts, body) = request.into_parts();
+        parts.extensions.insert(chunk);
+        let body = UploadBody {
+            inner: body,
+            tracker: Some(Tracker {
+                crc32c: upload.crc32c,
+                expected: chunk.len,
+                received: 0,
+                is_final: chunk.is_final(),
+                completed: completed.clone(),
+            }),
+        };
+
+        ResponseFuture::Inner {
+            future: self.inner.call(Request::from_parts(parts, body)),
+            commit: Some(Commit {
+                uploads: self.uploads.clone(),
+                key,
+                chunk,
+                completed,
+            }),
+        }
+    }
+}
+
+/// A parsed `content-range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ContentRange {
+    /// The inclusive byte range, or `None` for `bytes */total`.
+    range: Option<(u64, u64)>,
+    total: Option<u64>,
+}
+
+impl ContentRange {
+    fn parse(value: &HeaderValue) -> Option<Self> {
+        let (range, total) = value
+            .to_str()
+            .ok()?
+            .strip_prefix("bytes ")?
+            .split_once('/')?;
+        let total = match total {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        let range = match range {
+            "*" => {
+                // Only a known length can be queried.
+                total?;
+                None
+            }
+            range => {
+                let (start, end) = range.split_once('-')?;
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if end < start {
+                    return None;
+                }
+                Some((start, end))
+            }
+        };
+        Some(Self { range, total })
+    }
+}
+
+/// Advances an upload once its chunk has been accepted.
+#[derive(Debug)]
+struct Commit {
+    uploads: Arc<Mutex<HashMap<String, Upload>>>,
+    key: String,
+    chunk: UploadChunk,
+    /// Set by the body to the upload's checksum once the chunk has been
+    /// read in full.
+    completed: Arc<Mutex<Option<u32>>>,
+}
+
+impl Commit {
+    /// Records the chunk if its body was read in full, returning the new
+    /// offset of the upload.
+    fn apply(self) -> u64 {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get(&self.key).copied().unwrap_or_default();
+        let Some(crc32c) = self.completed.lock().unwrap().take() else {
+            return upload.offset;
+        };
+        // A concurrent request for the same chunk may have won the race.
+        if upload.offset != self.chunk.offset {
+            return upload.offset;
+        }
+
+        let offset = upload.offset + self.chunk.len;
+        if self.chunk.is_final() {
+            uploads.remove(&self.key);
+        } else {
+            uploads.insert(
+                self.key,
+                Upload {
+                    offset,
+                    crc32c,
+                    total: self.chunk.total,
+                },
+            );
+        }
+        offset
+    }
+}
+
+pin_project! {
+    /// Response future for [`ResumableUpload`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+            commit: Option<Commit>,
+        },
+        Respond {
+            status: StatusCode,
+            offset: Option<u64>,
+        },
+    }
+}
+
+impl<F> ResponseFuture<F> {
+    fn respond(status: StatusCode, offset: Option<u64>) -> Self {
+        Self::Respond { status, offset }
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future, commit } => {
+                let mut response = ready!(future.poll(cx))?;
+                if let Some(commit) = commit.take()
+                    && response.status().is_success()
+                {
+                    let offset = commit.apply();
+                    set_offset(response.headers_mut(), offset);
+                }
+                Poll::Ready(Ok(response))
+            }
+            ResponseFutureProj::Respond { status, offset } => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = *status;
+                if let Some(offset) = offset {
+                    set_offset(response.headers_mut(), *offset);
+                }
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+fn set_offset(headers: &mut HeaderMap, offset: u64) {
+    headers.insert(UPLOAD_OFFSET, offset.into());
+}
+
+/// Checks a chunk's body as it is read.
+#[derive(Debug)]
+struct Tracker {
+    crc32c: u32,
+    expected: u64,
+    received: u64,
+    is_final: bool,
+    completed: Arc<Mutex<Option<u32>>>,
+}
+
+impl Tracker {
+    fn data(&mut self, data: &Bytes) -> Result<(), UploadError> {
+        self.received += data.len() as u64;
+        if self.received > self.expected {
+            return Err(self.length_error());
+        }
+        self.crc32c = checksum::crc32c(self.crc32c, data);
+        Ok(())
+    }
+
+    fn end(&mut self, trailers: Option<&HeaderMap>) -> Result<(), UploadError> {
+        if self.received != self.expected {
+            return Err(self.length_error());
+        }
+        let expected = trailers
+            .and_then(|trailers| trailers.get(UPLOAD_CRC32C))
+            .filter(|_| self.is_final);
+        if let Some(expected) = expected {
+            let matches = expected
+                .to_str()
+                .ok()
+                .and_then(|expected| u32::from_str_radix(expected, 16).ok())
+                == Some(self.crc32c);
+            if !matches {
+                return Err(UploadError::Checksum {
+                    expected: String::from_utf8_lossy(expected.as_bytes()).into_owned(),
+                    actual: self.crc32c,
+                });
+            }
+        }
+        *self.completed.lock().unwrap() = Some(self.crc32c);
+        Ok(())
+    }
+
+    fn length_error(&self) -> UploadError {
+        UploadError::Length {
+            expected: self.expected,
+            received: self.received,
+        }
+    }
+}
+
+pin_project! {
+    /// Request body for [`ResumableUpload`].
+    ///
+    /// The body of an upload chunk fails with an [`UploadError`] if it is
+    /// corrupt; other request bodies are passed through unchanged.
+    pub struct UploadBody<B> {
+        #[pin]
+        inner: B,
+        tracker: Option<Tracker>,
+    }
+}
+
+impl<B> UploadBody<B> {
+    fn untracked(inner: B) -> Self {
+        Self {
+            inner,
+            tracker: None,
+        }
+    }
+}
+
+impl<B: Default> Default for UploadBody<B> {
+    fn default() -> Self {
+        Self::untracked(B::default())
+    }
+}
+
+impl<B> Body for UploadBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx)).map(|frame| frame.map_err(Into::into));
+        let Some(tracker) = this.tracker else {
+            return Poll::Ready(frame);
+        };
+
+        let checked = match &frame {
+            Some(Ok(frame)) => match frame.data_ref() {
+                Some(data) => tracker.data(data),
+                None => tracker.end(frame.trailers_ref()),
+            },
+            Some(Err(_)) => Ok(()),
+            None => tracker.end(None),
+        };
+        if let Err(e) = checked {
+            // Report the error once.
+            *this.tracker = None;
+            return Poll::Ready(Some(Err(e.into())));
+        }
+        if !matches!(frame, Some(Ok(ref frame)) if frame.is_data()) {
+            *this.tracker = None;
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // The end of the body must be observed to check its length.
+        self.tracker.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use http_body_util::Full;
+    use http_body_util::StreamBody;
+    use std::convert::Infallible;
+    use tower::Layer;
+    use tower::Service;
+    use tower::ServiceExt;
+
+    type TestBody = http_body_util::combinators::UnsyncBoxBody<Bytes, Infallible>;
+
+    /// A service appending each chunk to a shared buffer.
+    fn service(
+        stored: Arc<Mutex<Vec<u8>>>,
+    ) -> impl Service<Request<TestBody>, Response = Response<String>, Error = Infallible> + Clone
+    {
+        ResumableUploadLayer::new().layer(tower::service_fn(
+            move |request: Request<UploadBody<TestBody>>| {
+                let stored = stored.clone();
+                async move {
+                    let chunk = request.extensions().get::<UploadChunk>().copied();
+                    let mut response = Response::new(String::new());
+                    match request.into_body().collect().await {
+                        Ok(data) => {
+                            let mut stored = stored.lock().unwrap();
+                            assert_eq!(chunk.unwrap().offset(), stored.len() as u64);
+                            stored.extend_from_slice(&data.to_bytes());
+                        }
+                        Err(e) => {
+                            *response.status_mut() = StatusCode::BAD_REQUEST;
+                            *response.body_mut() = e.to_string();
+                        }
+                    }
+                    Ok(response)
+                }
+            },
+        ))
+    }
+
+    fn chunk(range: &str, data: &'static [u8], crc32c: Option<String>) -> Request<TestBody> {
+        let mut frames = vec![Ok(Frame::data(Bytes::from_static(data)))];
+        if let Some(crc32c) = crc32c {
+            let mut trailers = HeaderMap::new();
+            trailers.insert(UPLOAD_CRC32C, crc32c.parse().unwrap());
+            frames.push(Ok(Frame::trailers(trailers)));
+        }
+        Request::builder()
+            .method(Method::PUT)
+            .uri("/artifact")
+            .header(header::CONTENT_RANGE, range)
+            .body(StreamBody::new(futures::stream::iter(frames)).boxed_unsync())
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_content_range() {
+        let parse = |value| ContentRange::parse(&HeaderValue::from_static(value));
+        assert_eq!(
+            parse("bytes 0-9/100"),
+            Some(ContentRange {
+                range: Some((0, 9)),
+                total: Some(100),
+            })
+        );
+        assert_eq!(
+            parse("bytes 10-19/*"),
+            Some(ContentRange {
+                range: Some((10, 19)),
+                total: None,
+            })
+        );
+        assert_eq!(
+            parse("bytes */100"),
+            Some(ContentRange {
+                range: None,
+                total: Some(100),
+            })
+        );
+        assert_eq!(parse("bytes */*"), None);
+        assert_eq!(parse("bytes 9-0/100"), None);
+        assert_eq!(parse("items 0-9/100"), None);
+    }
+
+    #[tokio::test]
+    async fn resumes_and_verifies_upload() {
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let mut svc = service(stored.clone());
+        let crc32c = format!("{:08x}", checksum::crc32c(0, b"hello world"));
+
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(chunk("bytes 0-5/11", b"hello ", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "6");
+
+        // A retried chunk is rejected, reporting the offset to resume from.
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(chunk("bytes 0-5/11", b"hello ", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "6");
+
+        let query = Request::builder()
+            .method(Method::PUT)
+            .uri("/artifact")
+            .header(header::CONTENT_RANGE, "bytes */11")
+            .body(TestBody::default())
+            .unwrap();
+        let response = svc.ready().await.unwrap().call(query).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "6");
+
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(chunk("bytes 6-10/11", b"world", Some(crc32c)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "11");
+        assert_eq!(&*stored.lock().unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn rejects_checksum_mismatch() {
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let mut svc = service(stored.clone());
+
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(chunk("bytes 0-4/5", b"hello", Some("00000000".into())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.body().contains("checksum mismatch"));
+
+        // The failed chunk did not advance the upload.
+        let response = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(chunk("bytes 5-9/10", b"world", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "0");
+    }
+
+    #[tokio::test]
+    async fn rejects_short_chunk() {
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let response = service(stored)
+            .oneshot(chunk("bytes 0-9/*", b"hello", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.body().contains("length mismatch"));
+    }
+
+    #[tokio::test]
+    async fn passes_through_other_requests() {
+        let svc = ResumableUploadLayer::new().layer(tower::service_fn(
+            |request: Request<UploadBody<Full<Bytes>>>| async move {
+                assert!(request.extensions().get::<UploadChunk>().is_none());
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                Ok::<_, Infallible>(Response::new(String::from_utf8(body.to_vec()).unwrap()))
+            },
+        ));
+        let request = Request::builder()
+            .method(Method::POST)
+            .body(Full::new(Bytes::from_static(b"hi")))
+            .unwrap();
+        let response = svc.oneshot(request).await.unwrap();
+        assert_eq!(response.body(), "hi");
+        assert!(!response.headers().contains_key(UPLOAD_OFFSET));
+    }
+}
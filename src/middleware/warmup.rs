@@ -0,0 +1,252 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rate limiting that ramps up after startup.
+//!
+//! Right after a server starts its caches are cold, and a thundering herd of
+//! clients reconnecting at once can overwhelm it before it has warmed up.
+//! [`WarmupRateLimitLayer`] limits the request rate to a low floor at first
+//! and raises it linearly to the configured maximum over a warm-up window.
+//! Requests over the limit are rejected with `503 Service Unavailable` and a
+//! `retry-after` header, so well behaved clients back off and retry.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use sui_http::middleware::warmup::WarmupRateLimitLayer;
+//!
+//! // Start at 100 requests/s, reaching 10k requests/s after one minute.
+//! let layer = WarmupRateLimitLayer::new(100.0, 10_000.0, Duration::from_secs(60));
+//! # let _ = layer;
+//! ```
+
+use http::HeaderValue;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+use tower::Layer;
+use tower::Service;
+
+/// [`Layer`] that limits the request rate, ramping up from a floor to a
+/// maximum over a warm-up window.
+///
+/// The warm-up window starts when the layer is created; all services
+/// produced by the layer (and their clones) share one limit.
+#[derive(Debug, Clone)]
+pub struct WarmupRateLimitLayer {
+    limiter: Arc<Mutex<Limiter>>,
+}
+
+impl WarmupRateLimitLayer {
+    /// Allow `initial_rate` requests per second at first, increasing
+    /// linearly to `max_rate` requests per second over `window`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either rate is not positive or `initial_rate` exceeds
+    /// `max_rate`.
+    pub fn new(initial_rate: f64, max_rate: f64, window: Duration) -> Self {
+        Self::starting_at(initial_rate, max_rate, window, Instant::now())
+    }
+
+    /// Like [`WarmupRateLimitLayer::new`], but with the warm-up window
+    /// starting at `start` rather than now.
+    pub fn starting_at(initial_rate: f64, max_rate: f64, window: Duration, start: Instant) -> Self {
+        assert!(
+            initial_rate > 0.0 && initial_rate <= max_rate,
+            "rates must satisfy 0 < initial_rate <= max_rate"
+        );
+
+        Self {
+            limiter: Arc::new(Mutex::new(Limiter {
+                initial_rate,
+                max_rate,
+                window,
+                start,
+                tokens: initial_rate,
+                last_refill: start,
+            })),
+        }
+    }
+}
+
+impl<S> Layer<S> for WarmupRateLimitLayer {
+    type Service = WarmupRateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WarmupRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+/// Service returned by [`WarmupRateLimitLayer`].
+#[derive(Debug, Clone)]
+pub struct WarmupRateLimit<S> {
+    inner: S,
+    limiter: Arc<Mutex<Limiter>>,
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for WarmupRateLimit<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>>,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        if let Err(rate) = self.limiter.lock().unwrap().try_acquire(Instant::now()) {
+            tracing::debug!(rate, "request rejected by warm-up rate limit");
+            return ResponseFuture::Rejected;
+        }
+
+        ResponseFuture::Inner {
+            future: self.inner.call(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`WarmupRateLimit`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Rejected,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Rejected => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                response
+                    .headers_mut()
+                    .insert(http::header::RETRY_AFTER, HeaderValue::from_static("1"));
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+/// A token bucket whose rate (and capacity, one second's worth of tokens)
+/// grows during the warm-up window.
+#[derive(Debug)]
+struct Limiter {
+    initial_rate: f64,
+    max_rate: f64,
+    window: Duration,
+    start: Instant,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Limiter {
+    fn rate(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.window {
+            return self.max_rate;
+        }
+        let progress = elapsed.as_secs_f64() / self.window.as_secs_f64();
+        self.initial_rate + (self.max_rate - self.initial_rate) * progress
+    }
+
+    /// Takes a token, or returns the current rate if none is available.
+    fn try_acquire(&mut self, now: Instant) -> Result<(), f64> {
+        let rate = self.rate(now);
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    #[test]
+    fn rate_ramps_up_over_the_window() {
+        let start = Instant::now();
+        let layer = WarmupRateLimitLayer::starting_at(10.0, 110.0, Duration::from_secs(10), start);
+        let limiter = layer.limiter.lock().unwrap();
+
+        assert_eq!(limiter.rate(start), 10.0);
+        assert_eq!(limiter.rate(start + Duration::from_secs(5)), 60.0);
+        assert_eq!(limiter.rate(start + Duration::from_secs(10)), 110.0);
+        assert_eq!(limiter.rate(start + Duration::from_secs(60)), 110.0);
+    }
+
+    #[test]
+    fn bucket_refills_at_the_current_rate() {
+        let start = Instant::now();
+        let layer = WarmupRateLimitLayer::starting_at(2.0, 100.0, Duration::from_secs(100), start);
+        let mut limiter = layer.limiter.lock().unwrap();
+
+        assert!(limiter.try_acquire(start).is_ok());
+        assert!(limiter.try_acquire(start).is_ok());
+        assert!(limiter.try_acquire(start).is_err());
+
+        // Half a second later the rate is ~2.5/s, refilling one token.
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.try_acquire(later).is_ok());
+        assert!(limiter.try_acquire(later).is_err());
+
+        // After the window the full rate is available.
+        let warm = start + Duration::from_secs(200);
+        for _ in 0..100 {
+            assert!(limiter.try_acquire(warm).is_ok());
+        }
+        assert!(limiter.try_acquire(warm).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_over_the_limit() {
+        let svc = WarmupRateLimitLayer::new(1.0, 1.0, Duration::ZERO).layer(tower::service_fn(
+            |_: Request<()>| async { Ok::<_, Infallible>(Response::new(String::new())) },
+        ));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[http::header::RETRY_AFTER], "1");
+    }
+}
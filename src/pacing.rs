@@ -0,0 +1,147 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-connection request pacing, see `Config::max_connection_request_rate`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+use std::time::Duration;
+
+use pin_project_lite::pin_project;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+use tower::Service;
+use tower::ServiceExt;
+use tower::util::Oneshot;
+
+/// Delays requests on a single connection so that they are handed to the
+/// inner service at no more than `rate` per second, after an initial burst
+/// of up to `rate` requests.
+#[derive(Clone)]
+pub(crate) struct Paced<S> {
+    inner: S,
+    pacer: Arc<Mutex<Pacer>>,
+}
+
+impl<S> Paced<S> {
+    pub(crate) fn new(inner: S, rate: u32) -> Self {
+        Self {
+            inner,
+            pacer: Arc::new(Mutex::new(Pacer::new(rate))),
+        }
+    }
+}
+
+impl<S, Req> Service<Req> for Paced<S>
+where
+    S: Service<Req> + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = PacedFuture<S, Req>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The inner service is driven to readiness after the delay.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let delay = self.pacer.lock().unwrap().reserve(Instant::now());
+        if !delay.is_zero() {
+            tracing::trace!(?delay, "pacing request on connection");
+        }
+
+        PacedFuture {
+            sleep: (!delay.is_zero()).then(|| tokio::time::sleep(delay)),
+            future: self.inner.clone().oneshot(request),
+        }
+    }
+}
+
+pin_project! {
+    pub(crate) struct PacedFuture<S, Req>
+    where
+        S: Service<Req>,
+    {
+        #[pin]
+        sleep: Option<Sleep>,
+        #[pin]
+        future: Oneshot<S, Req>,
+    }
+}
+
+impl<S, Req> Future for PacedFuture<S, Req>
+where
+    S: Service<Req>,
+{
+    type Output = Result<S::Response, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        if let Some(sleep) = this.sleep.as_mut().as_pin_mut() {
+            ready!(sleep.poll(cx));
+            this.sleep.set(None);
+        }
+        this.future.poll(cx)
+    }
+}
+
+/// A generic cell rate algorithm scheduler: every request is assigned the
+/// next free slot, spaced `interval` apart, and waits for it unless it is
+/// within the burst tolerance.
+struct Pacer {
+    interval: Duration,
+    burst_tolerance: Duration,
+    theoretical_arrival: Option<Instant>,
+}
+
+impl Pacer {
+    fn new(rate: u32) -> Self {
+        let rate = rate.max(1);
+        let interval = Duration::from_secs(1) / rate;
+        Self {
+            interval,
+            burst_tolerance: interval * (rate - 1),
+            theoretical_arrival: None,
+        }
+    }
+
+    /// Reserves the next slot, returning how long the request must wait
+    /// for it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let arrival = self.theoretical_arrival.map_or(now, |tat| tat.max(now));
+        self.theoretical_arrival = Some(arrival + self.interval);
+        arrival
+            .saturating_duration_since(now)
+            .saturating_sub(self.burst_tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_paces() {
+        let mut pacer = Pacer::new(4);
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            assert_eq!(pacer.reserve(now), Duration::ZERO);
+        }
+        assert_eq!(pacer.reserve(now), Duration::from_millis(250));
+        assert_eq!(pacer.reserve(now), Duration::from_millis(500));
+
+        // Once the connection has been idle the burst is available again.
+        let later = now + Duration::from_secs(10);
+        for _ in 0..4 {
+            assert_eq!(pacer.reserve(later), Duration::ZERO);
+        }
+        assert_eq!(pacer.reserve(later), Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for the HAProxy [PROXY protocol], versions 1 and 2.
+//!
+//! L4 load balancers terminate the client's TCP connection and open a new
+//! one to the backend, so without help every connection appears to come
+//! from the load balancer. With the PROXY protocol enabled the load
+//! balancer prefixes each connection with a small header carrying the
+//! original client address, which is parsed here before any HTTP (or TLS)
+//! processing and substituted as the connection's remote address.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::task::JoinSet;
+
+use crate::Listener;
+
+const V1_PREFIX: &[u8] = b"PROXY";
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest possible v1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// A [`Listener`] that reads a PROXY protocol header from every accepted
+/// connection before handing it out.
+///
+/// Headers are read concurrently in background tasks so that a slow or
+/// silent peer cannot stall the accept loop. Connections that do not start
+/// with a valid header, or fail to send one within the timeout, are
+/// dropped.
+pub(crate) struct ProxyProtocolListener<L: Listener> {
+    inner: L,
+    timeout: Duration,
+    max_pending: usize,
+    pending: JoinSet<io::Result<(L::Io, SocketAddr)>>,
+}
+
+impl<L> ProxyProtocolListener<L>
+where
+    L: Listener<Addr = SocketAddr>,
+{
+    pub(crate) fn new(inner: L, timeout: Duration, max_pending: usize) -> Self {
+        Self {
+            inner,
+            timeout,
+            max_pending,
+            pending: JoinSet::new(),
+        }
+    }
+}
+
+impl<L> Listener for ProxyProtocolListener<L>
+where
+    L: Listener<Addr = SocketAddr>,
+{
+    type Io = L::Io;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                (mut io, remote_addr) = self.inner.accept(), if self.pending.len() < self.max_pending => {
+                    let timeout = self.timeout;
+                    self.pending.spawn(async move {
+                        let header = tokio::time::timeout(timeout, read_header(&mut io))
+                            .await
+                            .map_err(|_| {
+                                io::Error::new(io::ErrorKind::TimedOut, "PROXY header read timed out")
+                            })??;
+                        Ok((io, header.unwrap_or(remote_addr)))
+                    });
+                }
+                Some(result) = self.pending.join_next() => {
+                    match result {
+                        Ok(Ok(accepted)) => return accepted,
+                        Ok(Err(e)) => {
+                            tracing::debug!(error = %e, "error reading PROXY protocol header");
+                        }
+                        // If a task panics, just propagate it
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    }
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+
+    fn local_addrs(&self) -> io::Result<Vec<Self::Addr>> {
+        self.inner.local_addrs()
+    }
+
+    fn accepted_local_addr(&self, io: &Self::Io) -> Option<Self::Addr> {
+        self.inner.accepted_local_addr(io)
+    }
+
+    #[cfg(unix)]
+    fn try_clone_fds(&self) -> io::Result<Vec<std::os::fd::OwnedFd>> {
+        self.inner.try_clone_fds()
+    }
+}
+
+/// Reads a v1 or v2 PROXY protocol header from `io`.
+///
+/// Returns the original source address, or `None` when the header
+/// describes a connection that was not proxied (v1 `UNKNOWN`, v2 `LOCAL`,
+/// or a non-IP address family), in which case the transport's peer address
+/// should be used.
+pub(crate) async fn read_header<R>(io: &mut R) -> io::Result<Option<SocketAddr>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut prefix = [0u8; 5];
+    io.read_exact(&mut prefix).await?;
+
+    if prefix == V1_PREFIX {
+        read_v1(io).await
+    } else if prefix == V2_SIGNATURE[..5] {
+        read_v2(io).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<R>(io: &mut R) -> io::Result<Option<SocketAddr>>
+where
+    R: AsyncRead + Unpin,
+{
+    // Read byte by byte so that nothing past the header is consumed.
+    let mut line = Vec::with_capacity(V1_MAX_LENGTH);
+    line.extend_from_slice(V1_PREFIX);
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(io.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY v1 header is not valid ASCII"))?;
+    let mut parts = line.split(' ');
+    parts.next(); // "PROXY"
+
+    match parts.next() {
+        Some("UNKNOWN") => return Ok(None),
+        Some("TCP4") | Some("TCP6") => {}
+        _ => return Err(invalid("unsupported PROXY v1 protocol")),
+    }
+
+    let (Some(src_ip), Some(_dst_ip), Some(src_port), Some(_dst_port), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(invalid("malformed PROXY v1 header"));
+    };
+
+    let ip: IpAddr = src_ip
+        .parse()
+        .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+    let port: u16 = src_port
+        .parse()
+        .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+async fn read_v2<R>(io: &mut R) -> io::Result<Option<SocketAddr>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 11];
+    io.read_exact(&mut header).await?;
+
+    if header[..7] != V2_SIGNATURE[5..] {
+        return Err(invalid("invalid PROXY v2 signature"));
+    }
+
+    let version_command = header[7];
+    let family_protocol = header[8];
+    let length = u16::from_be_bytes([header[9], header[10]]) as usize;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    // Always consume the full address block, including any TLVs, so the
+    // stream is positioned at the start of the proxied payload.
+    let mut addresses = vec![0u8; length];
+    io.read_exact(&mut addresses).await?;
+
+    match version_command & 0x0f {
+        // LOCAL: a health check from the proxy itself.
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    }
+
+    let source = match family_protocol >> 4 {
+        // AF_INET
+        0x1 => {
+            if addresses.len() < 12 {
+                return Err(invalid("truncated PROXY v2 IPv4 addresses"));
+            }
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[0..4]).unwrap());
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            SocketAddr::new(ip.into(), port)
+        }
+        // AF_INET6
+        0x2 => {
+            if addresses.len() < 36 {
+                return Err(invalid("truncated PROXY v2 IPv6 addresses"));
+            }
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[0..16]).unwrap());
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            SocketAddr::new(ip.into(), port)
+        }
+        // AF_UNSPEC, AF_UNIX
+        _ => return Ok(None),
+    };
+
+    Ok(Some(source))
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(mut input: &[u8]) -> (io::Result<Option<SocketAddr>>, Vec<u8>) {
+        let result = read_header(&mut input).await;
+        (result, input.to_vec())
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (result, rest) =
+            parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(result.unwrap(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (result, _) = parse(b"PROXY TCP6 ::1 ::2 4000 443\r\n").await;
+        assert_eq!(result.unwrap(), Some("[::1]:4000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (result, rest) = parse(b"PROXY UNKNOWN\r\nabc").await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"abc");
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        assert!(parse(b"PROXY TCP4 1.2.3.4\r\n").await.0.is_err());
+        assert!(parse(b"PROXY TCP4 nope 1.2.3.4 1 2\r\n").await.0.is_err());
+        assert!(parse(&[b'P'; 200]).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_header() {
+        assert!(parse(b"GET / HTTP/1.1\r\n").await.0.is_err());
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4() {
+        let mut addresses = vec![10, 0, 0, 1, 10, 0, 0, 2];
+        addresses.extend_from_slice(&1234u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+        // A trailing TLV must be skipped.
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]);
+        let mut input = v2(0x1, 0x11, &addresses);
+        input.extend_from_slice(b"payload");
+
+        let (result, rest) = parse(&input).await;
+        assert_eq!(result.unwrap(), Some("10.0.0.1:1234".parse().unwrap()));
+        assert_eq!(rest, b"payload");
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6() {
+        let mut addresses = Ipv6Addr::LOCALHOST.octets().to_vec();
+        addresses.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets());
+        addresses.extend_from_slice(&9000u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+
+        let (result, _) = parse(&v2(0x1, 0x21, &addresses)).await;
+        assert_eq!(result.unwrap(), Some("[::1]:9000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let (result, rest) = parse(&v2(0x0, 0x00, &[])).await;
+        assert_eq!(result.unwrap(), None);
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_truncated() {
+        assert!(parse(&v2(0x1, 0x11, &[1, 2, 3])).await.0.is_err());
+    }
+}
@@ -0,0 +1,1112 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal method and path router.
+//!
+//! [`Router`] dispatches requests to services by method and path pattern,
+//! for servers that need a handful of REST routes next to a gRPC service
+//! without pulling in a web framework. Patterns are made of `/`-separated
+//! segments, each either a literal or a `{name}` capture matching any single
+//! non-empty segment. A trailing `{*name}` capture matches the rest of the
+//! path. Captured values are available to the service through the
+//! [`PathParams`] request extension.
+//!
+//! Routes are tried in the order they were added and the first match wins.
+//! A request whose path matches a route but not its method gets
+//! `405 Method Not Allowed` with an `allow` header. A request matching no
+//! route goes to the fallback service, or gets `404 Not Found` without one.
+//!
+//! [`AlpnRouter`] instead picks a service by the protocol negotiated through
+//! TLS ALPN, for serving e.g. gRPC to `h2` clients and a REST API to
+//! `http/1.1` clients on the same port.
+//!
+//! [`HostRouter`] picks a service by the host the request is addressed to,
+//! for serving several hostnames on one address.
+//!
+//! [`Mount`] picks a service by path prefix, optionally stripping the
+//! prefix, for co-hosting services of different types, such as a gRPC
+//! server and a REST API, without a router framework.
+//!
+//! # Example
+//!
+//! ```
+//! use http::Method;
+//! use http::Request;
+//! use http::Response;
+//! use sui_http::router::PathParams;
+//! use sui_http::router::Router;
+//! use tower::util::BoxCloneService;
+//!
+//! type Svc = BoxCloneService<Request<String>, Response<String>, std::convert::Infallible>;
+//!
+//! let get_checkpoint: Svc = BoxCloneService::new(tower::service_fn(
+//!     |request: Request<String>| async move {
+//!         let params = request.extensions().get::<PathParams>().unwrap();
+//!         let sequence_number: u64 = params.parse("seq").unwrap();
+//!         Ok(Response::new(format!("checkpoint {sequence_number}")))
+//!     },
+//! ));
+//!
+//! let router = Router::new().route(Method::GET, "/checkpoints/{seq}", get_checkpoint);
+//! # let _ = router;
+//! ```
+
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Response;
+use http::StatusCode;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use tower::Service;
+use tower::ServiceExt;
+
+use crate::BoxError;
+use crate::body::BoxBody;
+use crate::middleware::stack::StackService;
+use crate::middleware::stack::boxed;
+use tower::util::Oneshot;
+
+/// A parsed path pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    /// Matches the rest of the path, which may be empty.
+    Rest(String),
+}
+
+impl Pattern {
+    /// # Panics
+    ///
+    /// Panics if `pattern` does not start with `/`, has a malformed or
+    /// duplicate capture, or has a `{*rest}` capture that is not last.
+    fn parse(pattern: &str) -> Self {
+        let path = pattern
+            .strip_prefix('/')
+            .unwrap_or_else(|| panic!("route pattern {pattern:?} must start with '/'"));
+
+        let mut segments = Vec::new();
+        let mut names = Vec::new();
+        for segment in path.split('/') {
+            if matches!(segments.last(), Some(Segment::Rest(_))) {
+                panic!("`{{*rest}}` capture must be last in route pattern {pattern:?}");
+            }
+            let segment = match segment
+                .strip_prefix('{')
+                .and_then(|capture| capture.strip_suffix('}'))
+            {
+                Some(capture) => {
+                    let (name, rest) = match capture.strip_prefix('*') {
+                        Some(name) => (name, true),
+                        None => (capture, false),
+                    };
+                    assert!(
+                        !name.is_empty() && !name.contains(['{', '}', '*']),
+                        "malformed capture {segment:?} in route pattern {pattern:?}"
+                    );
+                    assert!(
+                        !names.contains(&name),
+                        "duplicate capture {name:?} in route pattern {pattern:?}"
+                    );
+                    names.push(name);
+                    if rest {
+                        Segment::Rest(name.to_owned())
+                    } else {
+                        Segment::Param(name.to_owned())
+                    }
+                }
+                None => {
+                    assert!(
+                        !segment.contains(['{', '}']),
+                        "malformed capture {segment:?} in route pattern {pattern:?}"
+                    );
+                    Segment::Literal(segment.to_owned())
+                }
+            };
+            segments.push(segment);
+        }
+        Self { segments }
+    }
+
+    /// Matches `path` against the pattern, returning the captured values.
+    fn matches(&self, path: &str) -> Option<PathParams> {
+        let mut rest = path.strip_prefix('/')?;
+        let mut params = Vec::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            if let Segment::Rest(name) = segment {
+                params.push((name.clone(), rest.to_owned()));
+                return Some(PathParams(params));
+            }
+
+            let (value, remaining) = match rest.split_once('/') {
+                Some((value, remaining)) => (value, Some(remaining)),
+                None => (rest, None),
+            };
+            match segment {
+                Segment::Literal(literal) if literal == value => {}
+                Segment::Param(name) if !value.is_empty() => {
+                    params.push((name.clone(), value.to_owned()));
+                }
+                _ => return None,
+            }
+
+            let is_last = i + 1 == self.segments.len();
+            match (remaining, is_last) {
+                (None, true) => return Some(PathParams(params)),
+                (Some(remaining), false) => rest = remaining,
+                // A `{*rest}` capture may match an empty remainder.
+                (None, false) if matches!(self.segments[i + 1], Segment::Rest(_)) => rest = "",
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+/// Request extension holding the values captured from the path by the
+/// route that matched it.
+///
+/// Values are as they appear in the path, without percent-decoding.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams(Vec<(String, String)>);
+
+impl PathParams {
+    /// The value captured by `{name}`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value captured by `{name}`, parsed as a `T`.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, PathParamError<T::Err>> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| PathParamError::Missing(name.to_owned()))?;
+        value.parse().map_err(|error| PathParamError::Invalid {
+            name: name.to_owned(),
+            error,
+        })
+    }
+
+    /// Iterates over the captured names and values in pattern order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// The error returned by [`PathParams::parse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathParamError<E> {
+    /// The route has no capture with this name.
+    Missing(String),
+    /// The captured value could not be parsed.
+    Invalid { name: String, error: E },
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for PathParamError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "no path parameter named {name:?}"),
+            Self::Invalid { name, error } => write!(f, "invalid path parameter {name:?}: {error}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for PathParamError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Missing(_) => None,
+            Self::Invalid { error, .. } => Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Route<S> {
+    method: Method,
+    pattern: Pattern,
+    service: S,
+}
+
+/// A method and path router; see the [module docs](self).
+///
+/// All routes must have the same service type, so box them (for example
+/// with `tower::util::BoxCloneService`) when they differ. The services must
+/// be `Clone`: the selected one is driven to readiness inside the response
+/// future, as it is not known which service a request will go to until it
+/// arrives.
+#[derive(Debug, Clone)]
+pub struct Router<S> {
+    routes: Arc<Vec<Route<S>>>,
+    fallback: Option<S>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Self {
+            routes: Arc::new(Vec::new()),
+            fallback: None,
+        }
+    }
+
+    /// Send `method` requests whose path matches `pattern` to `service`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` is malformed; see the [module docs](self).
+    pub fn route(mut self, method: Method, pattern: &str, service: S) -> Self
+    where
+        S: Clone,
+    {
+        Arc::make_mut(&mut self.routes).push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            service,
+        });
+        self
+    }
+
+    /// Send requests matching no route to `service`, e.g. a gRPC server.
+    pub fn fallback(self, service: S) -> Self {
+        Self {
+            fallback: Some(service),
+            ..self
+        }
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for Router<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Request<RequestBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request<RequestBody>) -> Self::Future {
+        let mut allowed = Vec::new();
+        for route in self.routes.iter() {
+            let Some(params) = route.pattern.matches(request.uri().path()) else {
+                continue;
+            };
+            if route.method != request.method() {
+                if !allowed.contains(&route.method) {
+                    allowed.push(route.method.clone());
+                }
+                continue;
+            }
+
+            request.extensions_mut().insert(params);
+            return ResponseFuture::Inner {
+                future: route.service.clone().oneshot(request),
+            };
+        }
+
+        if !allowed.is_empty() {
+            let allow = allowed
+                .iter()
+                .map(Method::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return ResponseFuture::Respond {
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                allow: HeaderValue::from_str(&allow).ok(),
+            };
+        }
+
+        match &self.fallback {
+            Some(fallback) => ResponseFuture::Inner {
+                future: fallback.clone().oneshot(request),
+            },
+            None => ResponseFuture::Respond {
+                status: StatusCode::NOT_FOUND,
+                allow: None,
+            },
+        }
+    }
+}
+
+/// A router dispatching requests by the protocol their connection
+/// negotiated through TLS ALPN, read from the [`AlpnProtocol`] request
+/// extension.
+///
+/// Requests whose protocol matches no registered service, including every
+/// request on a plaintext connection, go to the fallback service, or get
+/// `404 Not Found` without one. As with [`Router`], all services must have
+/// the same type and be `Clone`.
+///
+/// [`AlpnProtocol`]: crate::AlpnProtocol
+#[derive(Debug, Clone)]
+pub struct AlpnRouter<S> {
+    protocols: Arc<Vec<(Vec<u8>, S)>>,
+    fallback: Option<S>,
+}
+
+impl<S> Default for AlpnRouter<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> AlpnRouter<S> {
+    pub fn new() -> Self {
+        Self {
+            protocols: Arc::new(Vec::new()),
+            fallback: None,
+        }
+    }
+
+    /// Send requests on connections that negotiated `protocol`, e.g.
+    /// `"h2"`, to `service`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a service was already registered for `protocol`.
+    pub fn protocol(mut self, protocol: impl AsRef<[u8]>, service: S) -> Self
+    where
+        S: Clone,
+    {
+        let protocol = protocol.as_ref();
+        assert!(
+            !self.protocols.iter().any(|(p, _)| p == protocol),
+            "duplicate service for ALPN protocol {:?}",
+            String::from_utf8_lossy(protocol)
+        );
+        Arc::make_mut(&mut self.protocols).push((protocol.to_vec(), service));
+        self
+    }
+
+    /// Send requests matching no protocol to `service`.
+    pub fn fallback(self, service: S) -> Self {
+        Self {
+            fallback: Some(service),
+            ..self
+        }
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for AlpnRouter<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Request<RequestBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let negotiated = request.extensions().get::<crate::AlpnProtocol>();
+        let service = negotiated
+            .and_then(|negotiated| {
+                self.protocols
+                    .iter()
+                    .find(|(protocol, _)| protocol == negotiated.as_bytes())
+            })
+            .map(|(_, service)| service)
+            .or(self.fallback.as_ref());
+
+        match service {
+            Some(service) => ResponseFuture::Inner {
+                future: service.clone().oneshot(request),
+            },
+            None => ResponseFuture::Respond {
+                status: StatusCode::NOT_FOUND,
+                allow: None,
+            },
+        }
+    }
+}
+
+/// A router dispatching requests by the host they are addressed to: the
+/// `:authority` of HTTP/2 requests, or the `Host` header of HTTP/1 ones.
+///
+/// Hosts are compared case-insensitively and without their port. A host
+/// is either an exact name, like `api.example.com`, or a wildcard like
+/// `*.example.com`, which matches every subdomain of `example.com` but not
+/// `example.com` itself. Exact names take precedence over wildcards, and
+/// longer wildcards over shorter ones, whatever the order they were added
+/// in.
+///
+/// Requests for any other host, or without one, go to the fallback
+/// service, or get `404 Not Found` without one. As with [`Router`], all
+/// services must have the same type and be `Clone`.
+#[derive(Debug, Clone)]
+pub struct HostRouter<S> {
+    hosts: Arc<Vec<(HostMatch, S)>>,
+    fallback: Option<S>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostMatch {
+    Exact(String),
+    /// The suffix after the `*`, including its leading dot.
+    Wildcard(String),
+}
+
+impl HostMatch {
+    fn parse(host: &str) -> Self {
+        let host = normalize_host(host);
+        let parsed = match host.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') && suffix.len() > 1 => {
+                Self::Wildcard(suffix.to_owned())
+            }
+            _ => Self::Exact(host.clone()),
+        };
+        let name = match &parsed {
+            Self::Exact(name) | Self::Wildcard(name) => name,
+        };
+        assert!(
+            !name.contains('*'),
+            "wildcard host must look like `*.example.com`, got {host:?}"
+        );
+        parsed
+    }
+
+    /// How well `host` is matched, with exact matches ranking highest, or
+    /// `None` if it is not.
+    fn rank(&self, host: &str) -> Option<usize> {
+        match self {
+            Self::Exact(exact) => (exact == host).then_some(usize::MAX),
+            Self::Wildcard(suffix) => (host.len() > suffix.len()
+                && host.ends_with(suffix.as_str()))
+            .then_some(suffix.len()),
+        }
+    }
+}
+
+/// Lowercases `host` and strips its port and any trailing dot.
+fn normalize_host(host: &str) -> String {
+    let host = match host.strip_prefix('[') {
+        // An IPv6 address, whose colons are not a port separator.
+        Some(rest) => rest.split_once(']').map_or(host, |(address, _)| address),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn request_host<B>(request: &Request<B>) -> Option<String> {
+    let host = match request.uri().authority() {
+        Some(authority) => authority.as_str(),
+        None => request.headers().get(http::header::HOST)?.to_str().ok()?,
+    };
+    // Drop any userinfo, which browsers never send but the authority
+    // grammar allows.
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    Some(normalize_host(host))
+}
+
+impl<S> Default for HostRouter<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> HostRouter<S> {
+    pub fn new() -> Self {
+        Self {
+            hosts: Arc::new(Vec::new()),
+            fallback: None,
+        }
+    }
+
+    /// Send requests for `host`, e.g. `"api.example.com"` or
+    /// `"*.example.com"`, to `service`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a service was already registered for `host`, or if `host`
+    /// has a `*` anywhere but as its whole first label.
+    pub fn host(mut self, host: &str, service: S) -> Self
+    where
+        S: Clone,
+    {
+        let host = HostMatch::parse(host);
+        assert!(
+            !self.hosts.iter().any(|(h, _)| *h == host),
+            "duplicate service for host {host:?}"
+        );
+        Arc::make_mut(&mut self.hosts).push((host, service));
+        self
+    }
+
+    /// Send requests matching no host to `service`.
+    pub fn fallback(self, service: S) -> Self {
+        Self {
+            fallback: Some(service),
+            ..self
+        }
+    }
+}
+
+impl<S, RequestBody, ResponseBody> Service<Request<RequestBody>> for HostRouter<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<ResponseBody>> + Clone,
+    ResponseBody: Default,
+{
+    type Response = Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Request<RequestBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let service = request_host(&request)
+            .and_then(|host| {
+                self.hosts
+                    .iter()
+                    .filter_map(|(pattern, service)| Some((pattern.rank(&host)?, service)))
+                    .max_by_key(|(rank, _)| *rank)
+            })
+            .map(|(_, service)| service)
+            .or(self.fallback.as_ref());
+
+        match service {
+            Some(service) => ResponseFuture::Inner {
+                future: service.clone().oneshot(request),
+            },
+            None => ResponseFuture::Respond {
+                status: StatusCode::NOT_FOUND,
+                allow: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MountPoint {
+    prefix: String,
+    strip: bool,
+    service: StackService,
+}
+
+/// A router dispatching requests by path prefix to services of any type.
+///
+/// A prefix such as `/api` matches `/api` itself and every path below it,
+/// like `/api/v1/objects`, but not `/apis`. The longest matching prefix
+/// wins. Services mounted with [`Mount::at`] see the request unchanged,
+/// while those mounted with [`Mount::nest`] see it with the prefix removed
+/// from its path, so `/api/v1/objects` reaches them as `/v1/objects`.
+///
+/// Services are boxed, with their response bodies boxed into [`BoxBody`]
+/// and their errors into a boxed error, so each can have its own type.
+/// Requests matching no prefix go to the fallback service, or get `404 Not
+/// Found` without one.
+///
+/// ```
+/// use sui_http::router::Mount;
+///
+/// let grpc = axum::Router::new();
+/// let rest = axum::Router::new()
+///     .route("/health", axum::routing::get(|| async { "ok" }));
+///
+/// let mount = Mount::new().at("/sui.rpc.v2", grpc).nest("/api", rest);
+/// # let _ = mount;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Mount {
+    mounts: Arc<Vec<MountPoint>>,
+    fallback: Option<StackService>,
+}
+
+impl Mount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send requests under `prefix` to `service`, with their path
+    /// unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` does not start with `/`, ends with `/`, or was
+    /// already mounted.
+    pub fn at<S, ResponseBody>(self, prefix: &str, service: S) -> Self
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        self.mount(prefix, false, boxed(service))
+    }
+
+    /// Send requests under `prefix` to `service`, with `prefix` stripped
+    /// from their path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` does not start with `/`, ends with `/`, or was
+    /// already mounted.
+    pub fn nest<S, ResponseBody>(self, prefix: &str, service: S) -> Self
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        self.mount(prefix, true, boxed(service))
+    }
+
+    fn mount(mut self, prefix: &str, strip: bool, service: StackService) -> Self {
+        assert!(
+            prefix.starts_with('/') && !prefix.ends_with('/'),
+            "mount prefix must start and not end with `/`, got {prefix:?}"
+        );
+        assert!(
+            !self.mounts.iter().any(|mount| mount.prefix == prefix),
+            "duplicate service for prefix {prefix:?}"
+        );
+        Arc::make_mut(&mut self.mounts).push(MountPoint {
+            prefix: prefix.to_owned(),
+            strip,
+            service,
+        });
+        self
+    }
+
+    /// Send requests matching no prefix to `service`.
+    pub fn fallback<S, ResponseBody>(self, service: S) -> Self
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        Self {
+            fallback: Some(boxed(service)),
+            ..self
+        }
+    }
+}
+
+/// The rest of `path` below `prefix`, if it is under it.
+fn below_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Removes `prefix` from the path of `uri`, leaving `/` if nothing is left.
+fn strip_prefix(uri: http::Uri, prefix: &str) -> http::Uri {
+    let Some(rest) = below_prefix(uri.path(), prefix) else {
+        return uri;
+    };
+    let path = if rest.is_empty() { "/" } else { rest };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+    let mut parts = uri.clone().into_parts();
+    match path_and_query.parse() {
+        Ok(path_and_query) => parts.path_and_query = Some(path_and_query),
+        Err(_) => return uri,
+    }
+    http::Uri::from_parts(parts).unwrap_or(uri)
+}
+
+impl<RequestBody> Service<Request<RequestBody>> for Mount
+where
+    RequestBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture<StackService, Request<BoxBody>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let mut request = request.map(crate::body::boxed);
+        let path = request.uri().path();
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|mount| below_prefix(path, &mount.prefix).is_some())
+            .max_by_key(|mount| mount.prefix.len());
+
+        let service = match mount {
+            Some(mount) => {
+                if mount.strip {
+                    let uri = std::mem::take(request.uri_mut());
+                    *request.uri_mut() = strip_prefix(uri, &mount.prefix);
+                }
+                &mount.service
+            }
+            None => match &self.fallback {
+                Some(fallback) => fallback,
+                None => {
+                    return ResponseFuture::Respond {
+                        status: StatusCode::NOT_FOUND,
+                        allow: None,
+                    };
+                }
+            },
+        };
+        ResponseFuture::Inner {
+            future: service.clone().oneshot(request),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for the routers in this module.
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<S, Req>
+    where
+        S: Service<Req>,
+    {
+        Inner {
+            #[pin]
+            future: Oneshot<S, Req>,
+        },
+        Respond {
+            status: StatusCode,
+            allow: Option<HeaderValue>,
+        },
+    }
+}
+
+impl<S, Req, B> Future for ResponseFuture<S, Req>
+where
+    S: Service<Req, Response = Response<B>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Respond { status, allow } => {
+                let mut response = Response::new(B::default());
+                *response.status_mut() = *status;
+                if let Some(allow) = allow.take() {
+                    response.headers_mut().insert(http::header::ALLOW, allow);
+                }
+                Poll::Ready(Ok(response))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::util::BoxCloneService;
+
+    type TestService = BoxCloneService<Request<()>, Response<String>, Infallible>;
+
+    /// A service responding with its name and the captured parameters.
+    fn named(name: &'static str) -> TestService {
+        BoxCloneService::new(tower::service_fn(move |request: Request<()>| async move {
+            let params = request
+                .extensions()
+                .get::<PathParams>()
+                .map(|params| {
+                    params
+                        .iter()
+                        .map(|(name, value)| format!(" {name}={value}"))
+                        .collect::<String>()
+                })
+                .unwrap_or_default();
+            Ok(Response::new(format!("{name}{params}")))
+        }))
+    }
+
+    fn router() -> Router<TestService> {
+        Router::new()
+            .route(Method::GET, "/objects/latest", named("latest"))
+            .route(Method::GET, "/objects/{id}", named("get"))
+            .route(Method::DELETE, "/objects/{id}", named("delete"))
+            .route(
+                Method::GET,
+                "/objects/{id}/versions/{version}",
+                named("version"),
+            )
+            .route(Method::GET, "/static/{*path}", named("static"))
+    }
+
+    async fn send(router: Router<TestService>, method: Method, path: &str) -> Response<String> {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    fn matches_patterns() {
+        let pattern = Pattern::parse("/a/{b}/c");
+        assert_eq!(pattern.matches("/a/x/c").unwrap().get("b"), Some("x"));
+        assert!(pattern.matches("/a//c").is_none());
+        assert!(pattern.matches("/a/x").is_none());
+        assert!(pattern.matches("/a/x/c/d").is_none());
+
+        let pattern = Pattern::parse("/files/{*path}");
+        assert_eq!(
+            pattern.matches("/files/a/b.txt").unwrap().get("path"),
+            Some("a/b.txt")
+        );
+        assert_eq!(pattern.matches("/files").unwrap().get("path"), Some(""));
+        assert!(pattern.matches("/other").is_none());
+
+        assert!(Pattern::parse("/").matches("/").is_some());
+        assert!(Pattern::parse("/").matches("/a").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate capture")]
+    fn rejects_duplicate_captures() {
+        Pattern::parse("/{id}/{id}");
+    }
+
+    #[test]
+    #[should_panic(expected = "must be last")]
+    fn rejects_rest_capture_before_the_end() {
+        Pattern::parse("/{*rest}/more");
+    }
+
+    #[test]
+    fn parses_typed_params() {
+        let params = Pattern::parse("/{seq}").matches("/42").unwrap();
+        assert_eq!(params.parse::<u64>("seq"), Ok(42));
+        assert!(matches!(
+            params.parse::<u64>("other"),
+            Err(PathParamError::Missing(_))
+        ));
+
+        let params = Pattern::parse("/{seq}").matches("/forty-two").unwrap();
+        assert!(matches!(
+            params.parse::<u64>("seq"),
+            Err(PathParamError::Invalid { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn routes_by_method_and_path() {
+        let response = send(router(), Method::GET, "/objects/0x5").await;
+        assert_eq!(response.body(), "get id=0x5");
+
+        let response = send(router(), Method::DELETE, "/objects/0x5").await;
+        assert_eq!(response.body(), "delete id=0x5");
+
+        // Earlier routes win.
+        let response = send(router(), Method::GET, "/objects/latest").await;
+        assert_eq!(response.body(), "latest");
+
+        let response = send(router(), Method::GET, "/objects/0x5/versions/3").await;
+        assert_eq!(response.body(), "version id=0x5 version=3");
+
+        let response = send(router(), Method::GET, "/static/css/site.css").await;
+        assert_eq!(response.body(), "static path=css/site.css");
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_methods_and_paths() {
+        let response = send(router(), Method::POST, "/objects/0x5").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[http::header::ALLOW], "GET, DELETE");
+
+        let response = send(router(), Method::GET, "/unknown").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let router = router().fallback(named("grpc"));
+        let response = send(router, Method::POST, "/sui.rpc.v2.LedgerService/GetObject").await;
+        assert_eq!(response.body(), "grpc");
+    }
+
+    async fn send_alpn(
+        router: AlpnRouter<TestService>,
+        protocol: Option<&[u8]>,
+    ) -> Response<String> {
+        let mut request = Request::new(());
+        if let Some(protocol) = protocol {
+            request
+                .extensions_mut()
+                .insert(crate::AlpnProtocol::new(protocol));
+        }
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_by_alpn_protocol() {
+        let router = AlpnRouter::new()
+            .protocol("h2", named("grpc"))
+            .protocol("http/1.1", named("rest"));
+
+        let response = send_alpn(router.clone(), Some(b"h2")).await;
+        assert_eq!(response.body(), "grpc");
+        let response = send_alpn(router.clone(), Some(b"http/1.1")).await;
+        assert_eq!(response.body(), "rest");
+
+        let response = send_alpn(router.clone(), Some(b"acme-tls/1")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send_alpn(router.clone(), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let router = router.fallback(named("plaintext"));
+        let response = send_alpn(router, None).await;
+        assert_eq!(response.body(), "plaintext");
+    }
+
+    async fn send_host(router: HostRouter<TestService>, uri: &str, host: &str) -> Response<String> {
+        let request = Request::builder()
+            .uri(uri)
+            .header(http::header::HOST, host)
+            .body(())
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_by_host() {
+        let router = HostRouter::new()
+            .host("*.example.com", named("subdomain"))
+            .host("*.rpc.example.com", named("rpc"))
+            .host("example.com", named("apex"))
+            .host("API.example.com", named("api"));
+
+        let response = send_host(router.clone(), "/", "api.example.com").await;
+        assert_eq!(response.body(), "api");
+        let response = send_host(router.clone(), "/", "Example.COM.:8443").await;
+        assert_eq!(response.body(), "apex");
+        let response = send_host(router.clone(), "/", "www.example.com").await;
+        assert_eq!(response.body(), "subdomain");
+        let response = send_host(router.clone(), "/", "mainnet.rpc.example.com").await;
+        assert_eq!(response.body(), "rpc");
+
+        // The `:authority` of HTTP/2 requests wins over `Host`.
+        let response = send_host(router.clone(), "https://api.example.com/", "example.com").await;
+        assert_eq!(response.body(), "api");
+
+        let response = send_host(router.clone(), "/", "example.org").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = send_host(router.clone(), "/", "[::1]:8080").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let router = router.fallback(named("default"));
+        let response = send_host(router.clone(), "/", "example.org").await;
+        assert_eq!(response.body(), "default");
+        let response = router.oneshot(Request::new(())).await.unwrap();
+        assert_eq!(response.body(), "default");
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard host")]
+    fn rejects_partial_wildcards() {
+        HostRouter::<TestService>::new().host("api*.example.com", named("api"));
+    }
+
+    /// A service responding with its name and the path it was sent.
+    fn echo_path(
+        name: &'static str,
+    ) -> impl Service<
+        Request<BoxBody>,
+        Response = Response<String>,
+        Error = Infallible,
+        Future: Send,
+    > + Clone
+    + Send
+    + 'static {
+        tower::service_fn(move |request: Request<BoxBody>| async move {
+            Ok(Response::new(format!("{name} {}", request.uri())))
+        })
+    }
+
+    async fn send_mount(mount: Mount, uri: &str) -> Response<BoxBody> {
+        let request = Request::builder().uri(uri).body(()).unwrap();
+        mount
+            .oneshot(request.map(|()| crate::body::empty()))
+            .await
+            .unwrap()
+    }
+
+    async fn mounted_body(mount: Mount, uri: &str) -> String {
+        let response = send_mount(mount, uri).await;
+        let body = crate::body::collect_to_bytes(response.into_body(), 1024).await;
+        String::from_utf8(body.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn mounts_services_by_prefix() {
+        let mount = Mount::new()
+            .at("/grpc", echo_path("grpc"))
+            .nest("/api", echo_path("api"))
+            .nest("/api/v2", echo_path("v2"));
+
+        assert_eq!(
+            mounted_body(mount.clone(), "/grpc/Service/Method").await,
+            "grpc /grpc/Service/Method"
+        );
+        assert_eq!(
+            mounted_body(mount.clone(), "/api/objects?limit=1").await,
+            "api /objects?limit=1"
+        );
+        assert_eq!(mounted_body(mount.clone(), "/api").await, "api /");
+        // The longest prefix wins.
+        assert_eq!(
+            mounted_body(mount.clone(), "/api/v2/objects").await,
+            "v2 /objects"
+        );
+
+        let response = send_mount(mount.clone(), "/apis").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mount = mount.fallback(echo_path("other"));
+        assert_eq!(mounted_body(mount, "/apis").await, "other /apis");
+    }
+
+    #[test]
+    #[should_panic(expected = "mount prefix")]
+    fn rejects_prefixes_ending_in_a_slash() {
+        Mount::new().at("/api/", echo_path("api"));
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+
+use crate::BoxError;
+
+/// A certificate and key generated in memory, for serving TLS during local
+/// development and tests without creating files with openssl.
+///
+/// Clients do not trust it, so they have to be configured to, e.g. by
+/// adding [`SelfSignedCertificate::pem`] as a root certificate.
+///
+/// ```
+/// let cert = sui_http::SelfSignedCertificate::new(["localhost"]).unwrap();
+/// let builder = sui_http::Builder::new().tls_config(cert.server_config().unwrap());
+/// # let _ = builder;
+/// ```
+#[derive(Debug)]
+pub struct SelfSignedCertificate {
+    certificate: CertificateDer<'static>,
+    pem: String,
+    key: Vec<u8>,
+}
+
+impl SelfSignedCertificate {
+    /// Generates a certificate valid for each of `hostnames`, which may
+    /// also be IP addresses.
+    pub fn new<I>(hostnames: I) -> Result<Self, BoxError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let hostnames = hostnames.into_iter().map(Into::into).collect::<Vec<_>>();
+        let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(hostnames)?;
+        Ok(Self {
+            pem: cert.pem(),
+            certificate: cert.der().clone(),
+            key: key_pair.serialize_der(),
+        })
+    }
+
+    /// The certificate, in DER.
+    pub fn certificate(&self) -> &CertificateDer<'static> {
+        &self.certificate
+    }
+
+    /// The certificate, in PEM.
+    pub fn pem(&self) -> &str {
+        &self.pem
+    }
+
+    /// A TLS config serving the certificate, for [`Builder::tls_config`].
+    ///
+    /// [`Builder::tls_config`]: crate::Builder::tls_config
+    pub fn server_config(&self) -> Result<rustls::ServerConfig, BoxError> {
+        let key = PrivateKeyDer::Pkcs8(self.key.clone().into());
+        let tls_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![self.certificate.clone()], key)?;
+        Ok(tls_config)
+    }
+}
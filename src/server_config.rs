@@ -0,0 +1,464 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server options loaded from a configuration file; see [`ServerConfig`].
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::BoxError;
+use crate::Builder;
+use crate::Config;
+use crate::ServerHandle;
+use crate::body::BoxBody;
+use http::Request;
+use http::Response;
+use tower::Service;
+
+/// Server options that can be loaded from a service's configuration file,
+/// and turned into a configured [`Builder`].
+///
+/// Every field is optional; options left out keep the defaults of
+/// [`Config`] and [`Builder`]. Durations are written either as a number of
+/// seconds or as a string with a unit, such as `"500ms"`, `"30s"`, `"5m"`
+/// or `"1h"`. Unknown fields are rejected, so that a misspelled option is
+/// not silently ignored.
+///
+/// ```
+/// let config: sui_http::server_config::ServerConfig = serde_json::from_str(
+///     r#"{
+///         "addresses": ["127.0.0.1:0"],
+///         "max_connection_age": "5m",
+///         "idle_timeout": 60,
+///         "http2": { "max_concurrent_streams": 1000 }
+///     }"#,
+/// )
+/// .unwrap();
+/// let builder = config.builder().unwrap();
+/// # let _ = builder;
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct ServerConfig {
+    /// The addresses to listen on, for [`ServerConfig::serve`], e.g.
+    /// `"0.0.0.0:443"` or `"localhost:8080"`.
+    pub addresses: Vec<String>,
+    /// Serve TLS with the certificate and key in these files.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
+    pub tls: Option<TlsFiles>,
+    /// See [`Config::tls_handshake_timeout`].
+    #[cfg(feature = "tls")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub tls_handshake_timeout: Option<Duration>,
+    /// See [`Config::max_connection_age`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub max_connection_age: Option<Duration>,
+    /// See [`Config::max_connection_age_grace`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub max_connection_age_grace: Option<Duration>,
+    /// See [`Config::max_connection_age_jitter`].
+    #[serde(deserialize_with = "deserialize_jitter")]
+    pub max_connection_age_jitter: Option<f64>,
+    /// See [`Config::max_requests_per_connection`].
+    pub max_requests_per_connection: Option<u64>,
+    /// See [`Config::idle_timeout`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub idle_timeout: Option<Duration>,
+    /// See [`Config::shutdown_grace_period`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub shutdown_grace_period: Option<Duration>,
+    /// See [`Config::shutdown_connection_grace`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub shutdown_connection_grace: Option<Duration>,
+    /// See [`Config::max_connections`].
+    pub max_connections: Option<usize>,
+    /// See [`Config::max_pending_connections`].
+    pub max_pending_connections: Option<usize>,
+    /// See [`Config::tcp_keepalive`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub tcp_keepalive: Option<Duration>,
+    /// See [`Config::tcp_nodelay`].
+    pub tcp_nodelay: Option<bool>,
+    /// See [`Config::accept_http1`].
+    pub accept_http1: Option<bool>,
+    /// See [`Config::accept_http2`].
+    pub accept_http2: Option<bool>,
+    /// HTTP/1 options.
+    pub http1: Http1Config,
+    /// HTTP/2 options.
+    pub http2: Http2Config,
+    /// Compress responses with these settings.
+    #[cfg(feature = "compression")]
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "compression")))]
+    pub compression: Option<CompressionConfig>,
+}
+
+/// The PEM files of a TLS certificate chain and its private key; see
+/// [`Builder::tls_single_cert`].
+#[cfg(feature = "tls")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "tls")))]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct TlsFiles {
+    pub cert: std::path::PathBuf,
+    pub key: std::path::PathBuf,
+}
+
+/// The HTTP/1 options of a [`ServerConfig`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Http1Config {
+    /// See [`Config::http1_header_read_timeout`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub header_read_timeout: Option<Duration>,
+    /// See [`Config::http1_max_headers`].
+    pub max_headers: Option<usize>,
+    /// See [`Config::http1_max_buf_size`].
+    pub max_buf_size: Option<usize>,
+    /// See [`Config::http1_keep_alive`].
+    pub keep_alive: Option<bool>,
+}
+
+/// The HTTP/2 options of a [`ServerConfig`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Http2Config {
+    /// See [`Config::max_concurrent_streams`].
+    pub max_concurrent_streams: Option<u32>,
+    /// See [`Config::initial_stream_window_size`].
+    pub initial_stream_window_size: Option<u32>,
+    /// See [`Config::initial_connection_window_size`].
+    pub initial_connection_window_size: Option<u32>,
+    /// See [`Config::max_frame_size`].
+    pub max_frame_size: Option<u32>,
+    /// See [`Config::http2_max_header_list_size`].
+    pub max_header_list_size: Option<u32>,
+    /// See [`Config::http2_keepalive_interval`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub keepalive_interval: Option<Duration>,
+    /// See [`Config::http2_keepalive_timeout`].
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub keepalive_timeout: Option<Duration>,
+}
+
+/// The response compression options of a [`ServerConfig`]; see
+/// [`CompressionLayer`](crate::middleware::compression::CompressionLayer).
+#[cfg(feature = "compression")]
+#[cfg_attr(doc_cfg, doc(cfg(feature = "compression")))]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct CompressionConfig {
+    /// Whether to offer `gzip`. Defaults to `true`.
+    pub gzip: Option<bool>,
+    /// Whether to offer `deflate`. Defaults to `true`.
+    pub deflate: Option<bool>,
+    /// The level, from 1 (fastest) to 9 (best).
+    pub level: Option<u32>,
+    /// The size in bytes below which bodies are left uncompressed.
+    pub min_size: Option<u64>,
+}
+
+impl ServerConfig {
+    /// The connection options, applied to the defaults of [`Config`].
+    pub fn config(&self) -> Config {
+        let mut config = Config::default();
+        let http1 = &self.http1;
+        let http2 = &self.http2;
+
+        #[cfg(feature = "tls")]
+        if let Some(timeout) = self.tls_handshake_timeout {
+            config = config.tls_handshake_timeout(timeout);
+        }
+        if let Some(age) = self.max_connection_age {
+            config = config.max_connection_age(age);
+        }
+        if let Some(grace) = self.max_connection_age_grace {
+            config = config.max_connection_age_grace(grace);
+        }
+        if let Some(jitter) = self.max_connection_age_jitter {
+            config = config.max_connection_age_jitter(jitter);
+        }
+        if let Some(max) = self.max_requests_per_connection {
+            config = config.max_requests_per_connection(max);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            config = config.idle_timeout(timeout);
+        }
+        if let Some(grace_period) = self.shutdown_grace_period {
+            config = config.shutdown_grace_period(grace_period);
+        }
+        if let Some(grace) = self.shutdown_connection_grace {
+            config = config.shutdown_connection_grace(grace);
+        }
+        if let Some(max) = self.max_connections {
+            config = config.max_connections(max);
+        }
+        if let Some(max) = self.max_pending_connections {
+            config = config.max_pending_connections(max);
+        }
+        if let Some(keepalive) = self.tcp_keepalive {
+            config = config.tcp_keepalive(Some(keepalive));
+        }
+        if let Some(enabled) = self.tcp_nodelay {
+            config = config.tcp_nodelay(enabled);
+        }
+        if let Some(accept) = self.accept_http1 {
+            config = config.accept_http1(accept);
+        }
+        if let Some(accept) = self.accept_http2 {
+            config = config.accept_http2(accept);
+        }
+
+        if let Some(timeout) = http1.header_read_timeout {
+            config = config.http1_header_read_timeout(Some(timeout));
+        }
+        if let Some(max) = http1.max_headers {
+            config = config.http1_max_headers(max);
+        }
+        if let Some(max) = http1.max_buf_size {
+            config = config.http1_max_buf_size(max);
+        }
+        if let Some(enabled) = http1.keep_alive {
+            config = config.http1_keep_alive(enabled);
+        }
+
+        if let Some(max) = http2.max_concurrent_streams {
+            config = config.max_concurrent_streams(max);
+        }
+        if let Some(size) = http2.initial_stream_window_size {
+            config = config.initial_stream_window_size(size);
+        }
+        if let Some(size) = http2.initial_connection_window_size {
+            config = config.initial_connection_window_size(size);
+        }
+        if let Some(size) = http2.max_frame_size {
+            config = config.max_frame_size(size);
+        }
+        if let Some(max) = http2.max_header_list_size {
+            config = config.http2_max_header_list_size(max);
+        }
+        if let Some(interval) = http2.keepalive_interval {
+            config = config.http2_keepalive_interval(Some(interval));
+        }
+        if let Some(timeout) = http2.keepalive_timeout {
+            config = config.http2_keepalive_timeout(Some(timeout));
+        }
+
+        config
+    }
+
+    /// A [`Builder`] with the [connection options](Self::config), TLS and
+    /// compression configured.
+    ///
+    /// Compression is added with [`Builder::layer`], inside any
+    /// [`Builder::middleware`] stack set afterwards. Fails if the TLS files
+    /// cannot be loaded.
+    pub fn builder(&self) -> Result<Builder, BoxError> {
+        #[allow(unused_mut)]
+        let mut builder = Builder::new().config(self.config());
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            builder = builder.tls_single_cert(&tls.cert, &tls.key)?;
+        }
+
+        #[cfg(feature = "compression")]
+        if let Some(compression) = &self.compression {
+            use crate::middleware::compression::CompressionLayer;
+            use crate::middleware::compression::Level;
+
+            let mut layer = CompressionLayer::new();
+            if let Some(gzip) = compression.gzip {
+                layer = layer.gzip(gzip);
+            }
+            if let Some(deflate) = compression.deflate {
+                layer = layer.deflate(deflate);
+            }
+            if let Some(level) = compression.level {
+                layer = layer.level(Level::Precise(level));
+            }
+            if let Some(min_size) = compression.min_size {
+                layer = layer.min_size(min_size);
+            }
+            builder = builder.layer(layer);
+        }
+
+        Ok(builder)
+    }
+
+    /// Serve `service` on [`ServerConfig::addresses`] with the
+    /// [configured builder](Self::builder).
+    pub fn serve<S, ResponseBody>(
+        &self,
+        service: S,
+    ) -> Result<ServerHandle<std::net::SocketAddr>, BoxError>
+    where
+        S: Service<
+                Request<BoxBody>,
+                Response = Response<ResponseBody>,
+                Error: Into<BoxError>,
+                Future: Send,
+            > + Clone
+            + Send
+            + 'static,
+        ResponseBody: http_body::Body<Data = bytes::Bytes, Error: Into<BoxError>> + Send + 'static,
+    {
+        if self.addresses.is_empty() {
+            return Err("no addresses to serve on".into());
+        }
+        self.builder()?.serve_addrs(&self.addresses, service)
+    }
+}
+
+/// Rejects jitter [`Config::max_connection_age_jitter`] would panic on.
+fn deserialize_jitter<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let jitter = f64::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&jitter) {
+        return Err(serde::de::Error::custom(format!(
+            "max connection age jitter must be in 0.0..=1.0, got {jitter}"
+        )));
+    }
+    Ok(Some(jitter))
+}
+
+/// Deserializes an optional duration from a number of seconds or a string
+/// with a unit.
+mod duration {
+    use serde::Deserializer;
+    use serde::de::Error;
+    use serde::de::Visitor;
+    use std::time::Duration;
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DurationVisitor).map(Some)
+    }
+
+    struct DurationVisitor;
+
+    impl Visitor<'_> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(r#"a number of seconds or a duration such as "500ms", "30s", "5m" or "1h""#)
+        }
+
+        fn visit_u64<E: Error>(self, secs: u64) -> Result<Duration, E> {
+            Ok(Duration::from_secs(secs))
+        }
+
+        fn visit_i64<E: Error>(self, secs: i64) -> Result<Duration, E> {
+            u64::try_from(secs)
+                .map(Duration::from_secs)
+                .map_err(|_| E::custom("durations cannot be negative"))
+        }
+
+        fn visit_f64<E: Error>(self, secs: f64) -> Result<Duration, E> {
+            Duration::try_from_secs_f64(secs).map_err(E::custom)
+        }
+
+        fn visit_str<E: Error>(self, value: &str) -> Result<Duration, E> {
+            parse(value).ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+        }
+    }
+
+    pub(super) fn parse(value: &str) -> Option<Duration> {
+        let value = value.trim();
+        let split = value.find(|c: char| !c.is_ascii_digit())?;
+        let (amount, unit) = value.split_at(split);
+        let amount = amount.parse::<u64>().ok()?;
+        let duration = match unit.trim() {
+            "ms" => Duration::from_millis(amount),
+            "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount.checked_mul(60)?),
+            "h" => Duration::from_secs(amount.checked_mul(60 * 60)?),
+            _ => return None,
+        };
+        Some(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(duration::parse("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(duration::parse("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(duration::parse("5 m"), Some(Duration::from_secs(300)));
+        assert_eq!(duration::parse("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(duration::parse("30"), None);
+        assert_eq!(duration::parse("s"), None);
+        assert_eq!(duration::parse("3d"), None);
+    }
+
+    #[test]
+    fn deserializes_and_rejects_unknown_fields() {
+        let config: ServerConfig = serde_json::from_str(
+            r#"{
+                "max_connection_age": "5m",
+                "idle_timeout": 60,
+                "http1": { "max_headers": 50, "keep_alive": false },
+                "http2": { "keepalive_interval": "10s" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_connection_age, Some(Duration::from_secs(300)));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.http1.max_headers, Some(50));
+        assert_eq!(config.http1.keep_alive, Some(false));
+        assert_eq!(
+            config.http2.keepalive_interval,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(config.max_connections, None);
+
+        let config = config.config();
+        assert_eq!(config.max_connection_age, Some(Duration::from_secs(300)));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(60)));
+
+        let err = serde_json::from_str::<ServerConfig>(r#"{ "max_conection_age": 1 }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `max_conection_age`"), "{err}");
+        let err = serde_json::from_str::<ServerConfig>(r#"{ "idle_timeout": "soon" }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid value"), "{err}");
+        let err = serde_json::from_str::<ServerConfig>(r#"{ "max_connection_age_jitter": 2 }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("must be in 0.0..=1.0"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn serves_on_the_configured_addresses() {
+        let config: ServerConfig =
+            serde_json::from_str(r#"{ "addresses": ["127.0.0.1:0", "127.0.0.1:0"] }"#).unwrap();
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        let handle = config.serve(app).unwrap();
+        assert_eq!(handle.local_addrs().len(), 2);
+
+        for addr in handle.local_addrs() {
+            let response = reqwest::get(format!("http://{addr}")).await.unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+        handle.shutdown().await;
+
+        assert!(ServerConfig::default().serve(axum::Router::new()).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! systemd socket activation.
+//!
+//! With socket activation, systemd binds the sockets of a `.socket` unit
+//! itself, possibly on privileged ports or on demand, and passes them to
+//! the service as file descriptors starting at 3, announced through the
+//! `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES` environment variables
+//! (see `sd_listen_fds(3)`).
+//!
+//! Nothing is adopted implicitly: call [`listen_fds`] to take the passed
+//! sockets, or [`Builder::serve_systemd`](crate::Builder::serve_systemd) to
+//! serve on all of them.
+
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Whether the passed file descriptors have been taken, so that each is
+/// owned at most once.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// A socket passed to this process by systemd.
+#[derive(Debug)]
+pub struct ListenFd {
+    fd: OwnedFd,
+    name: Option<String>,
+}
+
+impl ListenFd {
+    /// The socket's name, from `FileDescriptorName=` in the socket unit.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Takes ownership of the socket.
+    pub fn into_fd(self) -> OwnedFd {
+        self.fd
+    }
+
+    /// Converts the socket into a TCP listener, failing if it is not a
+    /// TCP stream socket.
+    pub(crate) fn into_tcp_listener(self) -> std::io::Result<std::net::TcpListener> {
+        let socket = socket2::Socket::from(self.fd);
+        let is_tcp =
+            socket.r#type()? == socket2::Type::STREAM && socket.local_addr()?.as_socket().is_some();
+        if !is_tcp {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "systemd socket {} is not a TCP stream socket",
+                    self.name.as_deref().unwrap_or("(unnamed)")
+                ),
+            ));
+        }
+        Ok(socket.into())
+    }
+}
+
+impl AsFd for ListenFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<OwnedFd> for ListenFd {
+    fn from(fd: OwnedFd) -> Self {
+        Self { fd, name: None }
+    }
+}
+
+/// Takes the sockets systemd passed to this process, in the order of the
+/// socket unit.
+///
+/// Returns no sockets if the process was not socket activated (or the
+/// variables are addressed to another process, such as our parent), and
+/// on every call after the first, since the sockets can only be owned
+/// once. The environment variables are left as they are; systemd
+/// addresses them by pid, so child processes ignore them.
+pub fn listen_fds() -> std::io::Result<Vec<ListenFd>> {
+    let passed = parse_env(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    )?;
+    if passed.is_empty() || TAKEN.swap(true, Ordering::AcqRel) {
+        return Ok(Vec::new());
+    }
+
+    passed
+        .into_iter()
+        .map(|(raw_fd, name)| {
+            // SAFETY: systemd passed us these descriptors, and `TAKEN`
+            // ensures they are only taken once.
+            let fd = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+            // Keep the sockets from leaking into processes we spawn.
+            socket2::SockRef::from(&fd).set_cloexec(true)?;
+            Ok(ListenFd { fd, name })
+        })
+        .collect()
+}
+
+/// Returns each passed descriptor along with its name, if any.
+fn parse_env(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+    pid: u32,
+) -> std::io::Result<Vec<(RawFd, Option<String>)>> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let Some(listen_pid) = listen_pid else {
+        return Ok(Vec::new());
+    };
+    let listen_pid = listen_pid
+        .parse::<u32>()
+        .map_err(|e| invalid(format!("invalid LISTEN_PID {listen_pid:?}: {e}")))?;
+    if listen_pid != pid {
+        return Ok(Vec::new());
+    }
+
+    let listen_fds = listen_fds.unwrap_or("0");
+    let count = listen_fds
+        .parse::<RawFd>()
+        .ok()
+        .filter(|count| (0..=RawFd::MAX - LISTEN_FDS_START).contains(count))
+        .ok_or_else(|| invalid(format!("invalid LISTEN_FDS {listen_fds:?}")))?;
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut names = listen_fdnames
+        .map(|names| names.split(':').map(str::to_owned).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if !names.is_empty() && names.len() != count as usize {
+        return Err(invalid(format!(
+            "LISTEN_FDNAMES names {} sockets but LISTEN_FDS is {count}",
+            names.len()
+        )));
+    }
+    names.resize(count as usize, String::new());
+
+    Ok((LISTEN_FDS_START..)
+        .zip(names)
+        .map(|(fd, name)| (fd, Some(name).filter(|name| !name.is_empty())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_variables_for_other_processes() {
+        assert!(parse_env(None, Some("2"), None, 42).unwrap().is_empty());
+        assert!(
+            parse_env(Some("7"), Some("2"), None, 42)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(parse_env(Some("nope"), Some("2"), None, 42).is_err());
+    }
+
+    #[test]
+    fn maps_descriptors_to_names() {
+        assert_eq!(
+            parse_env(Some("42"), Some("3"), Some("http:grpc:"), 42).unwrap(),
+            [
+                (3, Some("http".to_owned())),
+                (4, Some("grpc".to_owned())),
+                (5, None)
+            ]
+        );
+        assert_eq!(
+            parse_env(Some("42"), Some("1"), None, 42).unwrap(),
+            [(3, None)]
+        );
+        assert!(parse_env(Some("42"), Some("2"), Some("http"), 42).is_err());
+        assert!(parse_env(Some("42"), Some("-1"), None, 42).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Utilities for testing services served by this crate without sockets.
+//!
+//! [`Builder::serve_in_memory`] serves a service exactly like
+//! [`Builder::serve`] does, with the same [`Config`] and connection
+//! handling, but over in-memory duplex streams. The returned
+//! [`TestClient`] opens a fresh in-memory connection per request.
+//!
+//! # Example
+//!
+//! ```
+//! # async {
+//! let app = axum::Router::new().route("/", axum::routing::get(|| async { "hello" }));
+//! let (handle, client) = sui_http::Builder::new().serve_in_memory(app).unwrap();
+//!
+//! let response = client
+//!     .send(http::Request::get("/").body(String::new()).unwrap())
+//!     .await
+//!     .unwrap();
+//! assert_eq!(response.status(), 200);
+//! # handle.shutdown().await;
+//! # };
+//! ```
+//!
+//! [`Builder::serve_in_memory`]: crate::Builder::serve_in_memory
+//! [`Builder::serve`]: crate::Builder::serve
+//! [`Config`]: crate::Config
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use http::Request;
+use http::Response;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+
+use crate::BoxError;
+use crate::body::BoxBody;
+
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The address of one end of an in-memory connection.
+///
+/// The server end is always `MemoryAddr(0)`; client ends are numbered from
+/// 1 in the order they connect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryAddr(pub u64);
+
+impl std::fmt::Display for MemoryAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "memory:{}", self.0)
+    }
+}
+
+/// The server side of the in-memory transport.
+#[derive(Debug)]
+pub(crate) struct MemoryListener {
+    incoming: mpsc::UnboundedReceiver<(DuplexStream, MemoryAddr)>,
+}
+
+impl crate::Listener for MemoryListener {
+    type Io = DuplexStream;
+    type Addr = MemoryAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(connection) => connection,
+            // Every client is gone, so no connection will ever arrive.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(MemoryAddr(0))
+    }
+}
+
+/// The HTTP version [`TestClient`] speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TestProtocol {
+    #[default]
+    Http1,
+    Http2,
+}
+
+/// Client for a server started with [`Builder::serve_in_memory`].
+///
+/// [`Builder::serve_in_memory`]: crate::Builder::serve_in_memory
+#[derive(Debug, Clone)]
+pub struct TestClient {
+    connect: mpsc::UnboundedSender<(DuplexStream, MemoryAddr)>,
+    next_addr: std::sync::Arc<AtomicU64>,
+    protocol: TestProtocol,
+}
+
+impl TestClient {
+    /// Use `protocol` for requests sent by this client. Defaults to
+    /// HTTP/1.1.
+    pub fn protocol(self, protocol: TestProtocol) -> Self {
+        Self { protocol, ..self }
+    }
+
+    /// Opens a new in-memory connection and sends `request` over it.
+    ///
+    /// Requests may use origin-form URIs such as `/path`; a scheme and
+    /// authority are filled in when speaking HTTP/2, which requires them.
+    pub async fn send<B>(&self, request: Request<B>) -> Result<Response<BoxBody>, BoxError>
+    where
+        B: http_body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let (client, server) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        let addr = MemoryAddr(self.next_addr.fetch_add(1, Ordering::Relaxed));
+        self.connect
+            .send((server, addr))
+            .map_err(|_| "in-memory server has shut down")?;
+
+        let request = request.map(crate::body::boxed);
+        let io = TokioIo::new(client);
+        let response = match self.protocol {
+            TestProtocol::Http1 => {
+                let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await?;
+                tokio::spawn(connection);
+                sender.send_request(request).await?
+            }
+            TestProtocol::Http2 => {
+                let (mut sender, connection) =
+                    hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await?;
+                tokio::spawn(connection);
+                sender.send_request(with_absolute_uri(request)?).await?
+            }
+        };
+
+        Ok(response.map(crate::body::boxed))
+    }
+}
+
+fn with_absolute_uri<B>(mut request: Request<B>) -> Result<Request<B>, BoxError> {
+    let uri = request.uri();
+    if uri.scheme().is_none() || uri.authority().is_none() {
+        let path_and_query = uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/")
+            .to_owned();
+        *request.uri_mut() = http::Uri::builder()
+            .scheme("http")
+            .authority("localhost")
+            .path_and_query(path_and_query)
+            .build()?;
+    }
+    Ok(request)
+}
+
+pub(crate) fn pair() -> (MemoryListener, TestClient) {
+    let (connect, incoming) = mpsc::unbounded_channel();
+    (
+        MemoryListener { incoming },
+        TestClient {
+            connect,
+            next_addr: std::sync::Arc::new(AtomicU64::new(1)),
+            protocol: TestProtocol::default(),
+        },
+    )
+}
@@ -0,0 +1,106 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timestamps separating time spent in the server from time spent in the
+//! service.
+//!
+//! Every request carries a [`RequestTiming`] extension recording when its
+//! connection was accepted and when the parsed request was handed to the
+//! service. For the first request on a connection, the difference covers
+//! the TLS handshake, reading and parsing the request head, and waiting
+//! for the connection task to be scheduled, i.e. the latency the server
+//! adds before the service ever sees the request.
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Request extension with timestamps of the request's journey through the
+/// server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTiming {
+    accepted_at: Instant,
+    received_at: Instant,
+    first_on_connection: bool,
+}
+
+impl RequestTiming {
+    /// When the listener accepted the request's connection, before its TLS
+    /// handshake. With `Config::proxy_protocol`, this is after the PROXY
+    /// header has been read.
+    pub fn accepted_at(&self) -> Instant {
+        self.accepted_at
+    }
+
+    /// When the request head had been read and the request was handed to
+    /// the service.
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Whether this is the first request served on its connection.
+    pub fn is_first_on_connection(&self) -> bool {
+        self.first_on_connection
+    }
+
+    /// Time between accepting the connection and handing this request to
+    /// the service.
+    ///
+    /// Only the first request on a connection has a meaningful value; later
+    /// requests would also count the time the connection spent serving or
+    /// waiting for earlier requests, so `None` is returned for them.
+    pub fn time_before_service(&self) -> Option<Duration> {
+        self.first_on_connection
+            .then(|| self.received_at.saturating_duration_since(self.accepted_at))
+    }
+}
+
+/// The timing state of a single connection.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionTiming {
+    accepted_at: Instant,
+    served_any: Arc<AtomicBool>,
+}
+
+impl ConnectionTiming {
+    pub(crate) fn new(accepted_at: Instant) -> Self {
+        Self {
+            accepted_at,
+            served_any: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Timing for a request being handed to the service now.
+    pub(crate) fn request_received(&self) -> RequestTiming {
+        RequestTiming {
+            accepted_at: self.accepted_at,
+            received_at: Instant::now(),
+            first_on_connection: !self.served_any.swap(true, Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_first_request_reports_time_before_service() {
+        let accepted_at = Instant::now();
+        let timing = ConnectionTiming::new(accepted_at);
+
+        let first = timing.request_received();
+        assert!(first.is_first_on_connection());
+        assert_eq!(
+            first.time_before_service(),
+            Some(first.received_at() - accepted_at)
+        );
+
+        let second = timing.clone().request_received();
+        assert!(!second.is_first_on_connection());
+        assert_eq!(second.accepted_at(), accepted_at);
+        assert_eq!(second.time_before_service(), None);
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `AF_VSOCK` listener support.
+//!
+//! vsock is the socket family used to talk between a virtual machine (or an
+//! AWS Nitro enclave) and its host. Addresses are a `(cid, port)` pair
+//! rather than an IP address, so connections accepted through
+//! [`VsockListener`] are reported with a [`VsockAddr`] as their remote
+//! address in [`ConnectionInfo`] and [`ConnectInfo`].
+//!
+//! [`ConnectionInfo`]: crate::ConnectionInfo
+//! [`ConnectInfo`]: crate::ConnectInfo
+
+use std::io;
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::task::ready;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::io::unix::AsyncFd;
+
+use crate::listener::AcceptErrors;
+
+const DEFAULT_BACKLOG: libc::c_int = 1024;
+
+/// The address of one end of a vsock connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VsockAddr {
+    cid: u32,
+    port: u32,
+}
+
+impl VsockAddr {
+    /// Wildcard CID, used to bind a listener on every CID of the local
+    /// machine.
+    pub const CID_ANY: u32 = libc::VMADDR_CID_ANY;
+
+    pub fn new(cid: u32, port: u32) -> Self {
+        Self { cid, port }
+    }
+
+    /// The context identifier of this end of the connection.
+    pub fn cid(&self) -> u32 {
+        self.cid
+    }
+
+    /// The port of this end of the connection.
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    fn to_raw(self) -> libc::sockaddr_vm {
+        // SAFETY: `sockaddr_vm` is plain-old-data, all zeroes is a valid value.
+        let mut raw: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        raw.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        raw.svm_cid = self.cid;
+        raw.svm_port = self.port;
+        raw
+    }
+
+    fn from_raw(raw: &libc::sockaddr_vm) -> Self {
+        Self {
+            cid: raw.svm_cid,
+            port: raw.svm_port,
+        }
+    }
+}
+
+impl std::fmt::Display for VsockAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "vsock://{}:{}", self.cid, self.port)
+    }
+}
+
+/// A vsock socket server, listening for connections.
+#[derive(Debug)]
+pub struct VsockListener {
+    inner: AsyncFd<OwnedFd>,
+    accept_errors: AcceptErrors,
+}
+
+impl VsockListener {
+    /// Creates a new `VsockListener` bound to the specified address.
+    ///
+    /// Use [`VsockAddr::CID_ANY`] as the CID to accept connections on any
+    /// CID of the local machine.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn bind(addr: VsockAddr) -> io::Result<Self> {
+        // SAFETY: plain syscall, the returned descriptor is checked below.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly created descriptor that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let raw = addr.to_raw();
+        // SAFETY: `raw` is a valid `sockaddr_vm` and the length matches it.
+        let rv = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &raw as *const libc::sockaddr_vm as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: plain syscall on a descriptor we own.
+        if unsafe { libc::listen(fd.as_raw_fd(), DEFAULT_BACKLOG) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            inner: AsyncFd::new(fd)?,
+            accept_errors: AcceptErrors::default(),
+        })
+    }
+
+    /// Handles accept errors according to `accept_errors`.
+    pub(crate) fn with_accept_errors(self, accept_errors: AcceptErrors) -> Self {
+        Self {
+            accept_errors,
+            ..self
+        }
+    }
+
+    /// Accepts a new incoming connection to this listener.
+    pub async fn accept(&self) -> io::Result<(VsockStream, VsockAddr)> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+
+            match guard.try_io(|inner| {
+                // SAFETY: `sockaddr_vm` is plain-old-data.
+                let mut raw: libc::sockaddr_vm = unsafe { mem::zeroed() };
+                let mut len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+                // SAFETY: `raw` and `len` describe a valid, writable buffer.
+                let fd = unsafe {
+                    libc::accept4(
+                        inner.as_raw_fd(),
+                        &mut raw as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                        &mut len,
+                        libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    )
+                };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // SAFETY: `accept4` returned a new descriptor that nothing else owns.
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                Ok((fd, VsockAddr::from_raw(&raw)))
+            }) {
+                Ok(result) => {
+                    let (fd, addr) = result?;
+                    return Ok((VsockStream::new(fd)?, addr));
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Returns the local address that this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<VsockAddr> {
+        // SAFETY: `sockaddr_vm` is plain-old-data.
+        let mut raw: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        // SAFETY: `raw` and `len` describe a valid, writable buffer.
+        let rv = unsafe {
+            libc::getsockname(
+                self.inner.as_raw_fd(),
+                &mut raw as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VsockAddr::from_raw(&raw))
+    }
+}
+
+impl crate::Listener for VsockListener {
+    type Io = VsockStream;
+    type Addr = VsockAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let mut backoff = self.accept_errors.backoff();
+        loop {
+            match Self::accept(self).await {
+                Ok(tup) => return tup,
+                Err(e) => backoff.handle_accept_error(e).await,
+            }
+        }
+    }
+
+    #[inline]
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Self::local_addr(self)
+    }
+}
+
+/// A connected vsock stream.
+#[derive(Debug)]
+pub struct VsockStream {
+    inner: AsyncFd<OwnedFd>,
+}
+
+impl VsockStream {
+    fn new(fd: OwnedFd) -> io::Result<Self> {
+        Ok(Self {
+            inner: AsyncFd::new(fd)?,
+        })
+    }
+}
+
+impl AsyncRead for VsockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| {
+                // SAFETY: `unfilled` is a valid, writable buffer of the given length.
+                let n = unsafe {
+                    libc::read(
+                        inner.as_raw_fd(),
+                        unfilled.as_mut_ptr() as *mut libc::c_void,
+                        unfilled.len(),
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(result) => {
+                    let n = result?;
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for VsockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+
+            match guard.try_io(|inner| {
+                // SAFETY: `buf` is a valid, readable buffer of the given length.
+                let n = unsafe {
+                    libc::write(
+                        inner.as_raw_fd(),
+                        buf.as_ptr() as *const libc::c_void,
+                        buf.len(),
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: plain syscall on a descriptor we own.
+        if unsafe { libc::shutdown(self.inner.as_raw_fd(), libc::SHUT_WR) } < 0 {
+            return Poll::Ready(Err(io::Error::last_os_error()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
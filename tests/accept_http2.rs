@@ -0,0 +1,62 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Config::accept_http2`.
+//!
+//! An HTTP/1-only server must pin the protocol rather than sniff it: a
+//! client speaking HTTP/2 with prior knowledge has to be refused instead of
+//! being served over HTTP/2 anyway.
+
+const MESSAGE: &str = "Hello, World!";
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { MESSAGE }))
+}
+
+#[tokio::test]
+async fn accept_http2_false_rejects_http2_prior_knowledge() {
+    let config = sui_http::Config::default().accept_http2(false);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let result = client
+        .get(format!("http://{}", handle.local_addr()))
+        .send()
+        .await;
+    assert!(
+        result.is_err(),
+        "accept_http2(false) was ignored: HTTP/2 request succeeded"
+    );
+}
+
+#[tokio::test]
+async fn accept_http2_false_serves_http1() {
+    let config = sui_http::Config::default().accept_http2(false);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let response = reqwest::get(format!("http://{}", handle.local_addr()))
+        .await
+        .unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_11);
+    assert_eq!(response.bytes().await.unwrap(), MESSAGE.as_bytes());
+}
+
+#[tokio::test]
+async fn serving_without_any_protocol_fails() {
+    let config = sui_http::Config::default()
+        .accept_http1(false)
+        .accept_http2(false);
+    let result = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app());
+    assert!(result.is_err());
+}
@@ -0,0 +1,307 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::acme`, against a minimal ACME CA that validates
+//! TLS-ALPN-01 challenges by connecting to the server like a real one.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::routing::post;
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rcgen::BasicConstraints;
+use rcgen::CertificateParams;
+use rcgen::IsCa;
+use rcgen::KeyPair;
+use serde_json::Value;
+use serde_json::json;
+use sui_http::acme::AcmeConfig;
+use sui_http::acme::DirStore;
+use sui_http::rustls;
+
+/// The DER encoding of the `id-pe-acmeIdentifier` extension's OID.
+const ACME_IDENTIFIER_OID: &[u8] = &[0x06, 0x08, 0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1F];
+
+struct Ca {
+    url: String,
+    cert: rcgen::Certificate,
+    key: KeyPair,
+    /// The port of the server whose challenges are validated.
+    server_port: u16,
+    state: Mutex<CaState>,
+}
+
+#[derive(Default)]
+struct CaState {
+    orders: usize,
+    validated: bool,
+    chain: Option<String>,
+}
+
+type Response = ([(&'static str, String); 2], axum::Json<Value>);
+
+impl Ca {
+    async fn start(server_port: u16) -> Arc<Self> {
+        let listener = tokio::net::TcpListener::bind("localhost:0").await.unwrap();
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Arc::new(Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            cert: params.self_signed(&key).unwrap(),
+            key,
+            server_port,
+            state: Mutex::default(),
+        });
+
+        let app = axum::Router::new()
+            .route("/directory", axum::routing::get(directory))
+            .route("/nonce", axum::routing::head(|| async { nonce() }))
+            .route("/account", post(account))
+            .route("/order", post(new_order))
+            .route("/order/1", post(order))
+            .route("/authz/1", post(authorization))
+            .route("/challenge/1", post(challenge))
+            .route("/finalize/1", post(finalize))
+            .route("/cert/1", post(certificate))
+            .with_state(ca.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        ca
+    }
+
+    fn directory(&self) -> String {
+        format!("{}/directory", self.url)
+    }
+
+    fn respond(&self, location: &str, body: Value) -> Response {
+        let location = format!("{}{location}", self.url);
+        (
+            [("replay-nonce", "nonce".to_owned()), ("location", location)],
+            axum::Json(body),
+        )
+    }
+
+    fn order(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let status = match (&state.chain, state.validated) {
+            (Some(_), _) => "valid",
+            (None, true) => "ready",
+            (None, false) => "pending",
+        };
+        json!({
+            "status": status,
+            "authorizations": [format!("{}/authz/1", self.url)],
+            "finalize": format!("{}/finalize/1", self.url),
+            "certificate": state.chain.as_ref().map(|_| format!("{}/cert/1", self.url)),
+        })
+    }
+}
+
+fn nonce() -> [(&'static str, &'static str); 1] {
+    [("replay-nonce", "nonce")]
+}
+
+/// The decoded payload of a JWS request body.
+fn payload(jws: &Value) -> Vec<u8> {
+    URL_SAFE_NO_PAD
+        .decode(jws["payload"].as_str().unwrap())
+        .unwrap()
+}
+
+async fn directory(State(ca): State<Arc<Ca>>) -> axum::Json<Value> {
+    axum::Json(json!({
+        "newNonce": format!("{}/nonce", ca.url),
+        "newAccount": format!("{}/account", ca.url),
+        "newOrder": format!("{}/order", ca.url),
+    }))
+}
+
+async fn account(State(ca): State<Arc<Ca>>, axum::Json(jws): axum::Json<Value>) -> Response {
+    let account: Value = serde_json::from_slice(&payload(&jws)).unwrap();
+    assert_eq!(account["termsOfServiceAgreed"], true);
+    ca.respond("/account/1", json!({ "status": "valid" }))
+}
+
+async fn new_order(State(ca): State<Arc<Ca>>, axum::Json(jws): axum::Json<Value>) -> Response {
+    let order: Value = serde_json::from_slice(&payload(&jws)).unwrap();
+    assert_eq!(
+        order["identifiers"],
+        json!([{ "type": "dns", "value": "localhost" }])
+    );
+    ca.state.lock().unwrap().orders += 1;
+    ca.respond("/order/1", ca.order())
+}
+
+async fn order(State(ca): State<Arc<Ca>>) -> Response {
+    ca.respond("/order/1", ca.order())
+}
+
+async fn authorization(State(ca): State<Arc<Ca>>) -> Response {
+    let status = if ca.state.lock().unwrap().validated {
+        "valid"
+    } else {
+        "pending"
+    };
+    let authorization = json!({
+        "status": status,
+        "identifier": { "type": "dns", "value": "localhost" },
+        "challenges": [
+            { "type": "http-01", "url": format!("{}/unsupported", ca.url), "token": "t" },
+            { "type": "tls-alpn-01", "url": format!("{}/challenge/1", ca.url), "token": "t" },
+        ],
+    });
+    ca.respond("/authz/1", authorization)
+}
+
+/// Validates the challenge by fetching the certificate the server presents
+/// for `acme-tls/1`.
+async fn challenge(State(ca): State<Arc<Ca>>) -> Response {
+    let port = ca.server_port;
+    let cert = tokio::task::spawn_blocking(move || {
+        let mut config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"acme-tls/1".to_vec()];
+        let mut tls =
+            rustls::ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap())
+                .unwrap();
+        let mut tcp = std::net::TcpStream::connect(("localhost", port)).unwrap();
+        while tls.is_handshaking() {
+            tls.complete_io(&mut tcp).unwrap();
+        }
+        assert_eq!(tls.alpn_protocol(), Some(&b"acme-tls/1"[..]));
+        tls.peer_certificates().unwrap()[0].to_vec()
+    })
+    .await
+    .unwrap();
+    assert!(
+        cert.windows(ACME_IDENTIFIER_OID.len())
+            .any(|window| window == ACME_IDENTIFIER_OID),
+        "challenge certificate has no acmeIdentifier extension"
+    );
+
+    ca.state.lock().unwrap().validated = true;
+    ca.respond("/challenge/1", json!({ "status": "valid" }))
+}
+
+async fn finalize(State(ca): State<Arc<Ca>>, axum::Json(jws): axum::Json<Value>) -> Response {
+    let request: Value = serde_json::from_slice(&payload(&jws)).unwrap();
+    let csr = URL_SAFE_NO_PAD
+        .decode(request["csr"].as_str().unwrap())
+        .unwrap();
+    let csr = rcgen::CertificateSigningRequestParams::from_der(&csr.into()).unwrap();
+    let cert = csr.signed_by(&ca.cert, &ca.key).unwrap();
+    ca.state.lock().unwrap().chain = Some(format!("{}{}", cert.pem(), ca.cert.pem()));
+    ca.respond("/order/1", ca.order())
+}
+
+async fn certificate(State(ca): State<Arc<Ca>>) -> ([(&'static str, &'static str); 1], String) {
+    let chain = ca.state.lock().unwrap().chain.clone().unwrap();
+    (nonce(), chain)
+}
+
+#[derive(Debug)]
+struct AcceptAnyCertificate;
+
+impl rustls::client::danger::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::pki_types::CertificateDer<'_>,
+        _: &[rustls::pki_types::CertificateDer<'_>],
+        _: &rustls::pki_types::ServerName<'_>,
+        _: &[u8],
+        _: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _: &[u8],
+        _: &rustls::pki_types::CertificateDer<'_>,
+        _: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _: &[u8],
+        _: &rustls::pki_types::CertificateDer<'_>,
+        _: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+async fn hello(
+    _: http::Request<sui_http::body::BoxBody>,
+) -> Result<http::Response<sui_http::body::BoxBody>, std::convert::Infallible> {
+    Ok(http::Response::new(sui_http::body::full("hello")))
+}
+
+fn serve(
+    ca: &Ca,
+    listener: std::net::TcpListener,
+    store: &std::path::Path,
+) -> sui_http::ServerHandle {
+    let acme = AcmeConfig::new(["localhost"])
+        .directory(ca.directory())
+        .contact("ops@example.com")
+        .store(DirStore::new(store));
+    sui_http::Builder::new()
+        .acme(acme)
+        .serve_listener(listener, tower::service_fn(hello))
+        .unwrap()
+}
+
+/// Retries a request until the server has installed its certificate.
+async fn get(ca: &Ca, port: u16) -> String {
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.cert.pem().as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let url = format!("https://localhost:{port}");
+    for _ in 0..100 {
+        if let Ok(response) = client.get(&url).send().await {
+            return response.text().await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("server never served a certificate issued by the CA");
+}
+
+#[tokio::test]
+async fn obtains_a_certificate_and_reuses_it_after_restarting() {
+    let store = std::env::temp_dir().join(format!("sui-http-acme-{}", std::process::id()));
+    let listener = std::net::TcpListener::bind("localhost:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let ca = Ca::start(port).await;
+
+    let handle = serve(&ca, listener, &store);
+    assert_eq!(get(&ca, port).await, "hello");
+    assert_eq!(ca.state.lock().unwrap().orders, 1);
+    handle.shutdown().await;
+
+    // A restarted server loads the certificate from the store instead of
+    // ordering another one.
+    let listener = std::net::TcpListener::bind("localhost:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve(&ca, listener, &store);
+    assert_eq!(get(&ca, port).await, "hello");
+    assert_eq!(ca.state.lock().unwrap().orders, 1);
+    handle.shutdown().await;
+
+    std::fs::remove_dir_all(store).unwrap();
+}
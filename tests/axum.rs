@@ -0,0 +1,34 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::serve_axum`.
+
+use std::net::SocketAddr;
+
+use axum::extract::ConnectInfo;
+
+#[tokio::test]
+async fn axum_connect_info_extractor() {
+    let app = axum::Router::new().route(
+        "/",
+        axum::routing::get(
+            |ConnectInfo(addr): ConnectInfo<SocketAddr>| async move { addr.to_string() },
+        ),
+    );
+    let handle = sui_http::Builder::new()
+        .serve_axum(("127.0.0.1", 0), app)
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/", handle.local_addr()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let peer: SocketAddr = response.text().await.unwrap().parse().unwrap();
+    assert!(peer.ip().is_loopback());
+    assert_ne!(peer.port(), handle.local_addr().port());
+
+    handle.shutdown().await;
+}
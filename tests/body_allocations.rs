@@ -0,0 +1,78 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks that observing a streaming response body does not allocate per
+//! chunk.
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::cell::Cell;
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use sui_http::middleware::logging::LoggingLayer;
+use sui_http::middleware::metrics::Metrics;
+use tower::Layer;
+use tower::ServiceExt;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CHUNKS: usize = 1000;
+
+async fn stream<B>(
+    _request: http::Request<B>,
+) -> Result<http::Response<sui_http::body::BoxBody>, Infallible> {
+    let frames = (0..CHUNKS)
+        .map(|_| Ok::<_, Infallible>(http_body::Frame::data(Bytes::from_static(b"chunk"))))
+        .collect::<Vec<_>>();
+    let body = http_body_util::StreamBody::new(futures::stream::iter(frames));
+    Ok(http::Response::new(sui_http::body::boxed(body)))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn streaming_through_callback_and_logging_does_not_allocate_per_chunk() {
+    let service = tower::service_fn(stream);
+    let service = LoggingLayer::new().layer(Metrics::new().layer().layer(service));
+
+    let response = service
+        .oneshot(http::Request::new(sui_http::body::empty()))
+        .await
+        .unwrap();
+    let mut body = sui_http::body::boxed(response.into_body());
+
+    let before = ALLOCATIONS.with(Cell::get);
+    let mut chunks = 0;
+    while let Some(frame) = body.frame().await {
+        if frame.unwrap().is_data() {
+            chunks += 1;
+        }
+    }
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+
+    assert_eq!(chunks, CHUNKS);
+    // Ending the stream records the request, which may allocate a little.
+    assert!(
+        allocations < 10,
+        "{allocations} allocations for {CHUNKS} chunks"
+    );
+}
@@ -0,0 +1,104 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A client that gives up on a request must not leave its handler running:
+//! the handler future is dropped as soon as the HTTP/2 stream is reset or
+//! the HTTP/1 connection closes, and `ResponseHandler::on_cancel` fires.
+
+use http::request;
+use http::response;
+use std::time::Duration;
+use sui_http::middleware::callback::CallbackLayer;
+use sui_http::middleware::callback::MakeCallbackHandler;
+use sui_http::middleware::callback::ResponseHandler;
+use tokio::sync::mpsc;
+
+#[derive(Clone)]
+struct MakeCancelHandler(mpsc::UnboundedSender<&'static str>);
+
+struct CancelHandler(mpsc::UnboundedSender<&'static str>);
+
+impl ResponseHandler for CancelHandler {
+    fn on_response(&mut self, _response: &response::Parts, _latency: Duration) {}
+
+    fn on_service_error<E>(&mut self, _error: &E, _latency: Duration)
+    where
+        E: std::fmt::Display + 'static,
+    {
+    }
+
+    fn on_cancel(&mut self, _latency: Duration) {
+        let _ = self.0.send("on_cancel");
+    }
+}
+
+impl MakeCallbackHandler for MakeCancelHandler {
+    type RequestHandler = ();
+    type ResponseHandler = CancelHandler;
+
+    fn make_handler(&self, _request: &request::Parts) -> ((), CancelHandler) {
+        ((), CancelHandler(self.0.clone()))
+    }
+}
+
+/// Reports when the handler future holding it is dropped.
+struct DropGuard(mpsc::UnboundedSender<&'static str>);
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        let _ = self.0.send("handler dropped");
+    }
+}
+
+async fn assert_cancelled(client: reqwest::Client) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let handler_sender = sender.clone();
+    let app = axum::Router::new()
+        .route(
+            "/",
+            axum::routing::get(move || {
+                let guard = DropGuard(handler_sender.clone());
+                async move {
+                    std::future::pending::<()>().await;
+                    drop(guard);
+                }
+            }),
+        )
+        .layer(CallbackLayer::new(MakeCancelHandler(sender)));
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let result = client
+        .get(format!("http://{}", handle.local_addr()))
+        .timeout(Duration::from_millis(100))
+        .send()
+        .await;
+    assert!(result.unwrap_err().is_timeout());
+    drop(client);
+
+    let mut events = Vec::new();
+    while events.len() < 2 {
+        let event = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("request was not cancelled")
+            .unwrap();
+        events.push(event);
+    }
+    events.sort();
+    assert_eq!(events, ["handler dropped", "on_cancel"]);
+}
+
+#[tokio::test]
+async fn http1_disconnect_cancels_the_handler() {
+    assert_cancelled(reqwest::Client::new()).await;
+}
+
+#[tokio::test]
+async fn http2_reset_cancels_the_handler() {
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    assert_cancelled(client).await;
+}
@@ -0,0 +1,144 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::client_identity`, mapping client certificates to a
+//! typed principal.
+
+use std::sync::Arc;
+
+use rcgen::BasicConstraints;
+use rcgen::CertificateParams;
+use rcgen::DnType;
+use rcgen::IsCa;
+use rcgen::KeyPair;
+use sui_http::rustls;
+use sui_http::rustls::pki_types::CertificateDer;
+use sui_http::rustls::pki_types::PrivateKeyDer;
+
+#[derive(Clone, Debug, PartialEq)]
+struct User(&'static str);
+
+struct Pki {
+    ca: rcgen::Certificate,
+    ca_key: KeyPair,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        Self { ca, ca_key }
+    }
+
+    fn issue(&self, name: &str) -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_owned()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+        (cert, key)
+    }
+
+    fn server_config(&self) -> rustls::ServerConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(self.ca.der().clone()).unwrap();
+        let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+            .allow_unauthenticated()
+            .build()
+            .unwrap();
+        let (cert, key) = self.issue("localhost");
+        rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+            .unwrap()
+    }
+
+    fn client(&self, identity: Option<(&rcgen::Certificate, &KeyPair)>) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(
+                reqwest::Certificate::from_pem(self.ca.pem().as_bytes()).unwrap(),
+            );
+        if let Some((cert, key)) = identity {
+            let pem = format!("{}{}", cert.pem(), key.serialize_pem());
+            builder = builder.identity(reqwest::Identity::from_pem(pem.as_bytes()).unwrap());
+        }
+        builder.build().unwrap()
+    }
+}
+
+async fn whoami(
+    request: http::Request<sui_http::body::BoxBody>,
+) -> Result<http::Response<sui_http::body::BoxBody>, std::convert::Infallible> {
+    let user = request
+        .extensions()
+        .get::<User>()
+        .map_or("anonymous", |user| user.0);
+    Ok(http::Response::new(sui_http::body::full(user)))
+}
+
+fn serve(pki: &Pki, alice: CertificateDer<'static>) -> sui_http::ServerHandle {
+    sui_http::Builder::new()
+        .tls_config(pki.server_config())
+        .client_identity(move |certs| match certs.peer_certs().first() {
+            Some(leaf) if *leaf == alice => Ok(User("alice")),
+            _ => Err("unknown client certificate".into()),
+        })
+        .serve(("localhost", 0), tower::service_fn(whoami))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn inserts_the_identity_into_requests() {
+    let pki = Pki::new();
+    let (alice, alice_key) = pki.issue("alice");
+    let handle = serve(&pki, alice.der().clone());
+    let url = format!("https://localhost:{}", handle.local_addr().port());
+
+    let body = pki
+        .client(Some((&alice, &alice_key)))
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "alice");
+
+    // Clients without a certificate are served without an identity.
+    let body = pki
+        .client(None)
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "anonymous");
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn rejects_clients_that_fail_to_map() {
+    let pki = Pki::new();
+    let (alice, _) = pki.issue("alice");
+    let handle = serve(&pki, alice.der().clone());
+    let url = format!("https://localhost:{}", handle.local_addr().port());
+
+    let (mallory, mallory_key) = pki.issue("mallory");
+    let result = pki
+        .client(Some((&mallory, &mallory_key)))
+        .get(&url)
+        .send()
+        .await;
+    assert!(result.is_err(), "{result:?}");
+
+    handle.shutdown().await;
+}
@@ -0,0 +1,46 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Every request must carry a `ConnectInfo` extension describing the
+//! connection it arrived on.
+
+use sui_http::ConnectInfo;
+
+#[tokio::test]
+async fn connect_info_is_inserted_into_requests() {
+    let app = axum::Router::new().route(
+        "/",
+        axum::routing::get(
+            |axum::Extension(info): axum::Extension<ConnectInfo>| async move {
+                format!(
+                    "{} {} {}",
+                    info.local_addr(),
+                    info.remote_addr(),
+                    info.is_tls()
+                )
+            },
+        ),
+    );
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}", handle.local_addr()))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let remote_addr = *handle
+        .connections()
+        .values()
+        .next()
+        .unwrap()
+        .remote_address();
+    let expected = format!("{} {} false", handle.local_addr(), remote_addr);
+    assert_eq!(response, expected);
+}
@@ -0,0 +1,190 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A `MakeConnectionHandler` observes every accepted connection until it
+//! closes, however it closes.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use sui_http::AcceptedConnection;
+use sui_http::CloseReason;
+use sui_http::ConnectionClosed;
+use sui_http::ConnectionHandler;
+use sui_http::MakeConnectionHandler;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+#[derive(Debug)]
+enum Event {
+    Accepted(SocketAddr),
+    Closed(ConnectionClosed),
+}
+
+struct MakeRecorder(mpsc::UnboundedSender<Event>);
+
+struct Recorder(mpsc::UnboundedSender<Event>);
+
+impl MakeConnectionHandler for MakeRecorder {
+    type Handler = Recorder;
+
+    fn on_accept(&self, connection: &AcceptedConnection<'_>) -> Recorder {
+        let remote_addr = *connection.remote_addr::<SocketAddr>().unwrap();
+        let _ = self.0.send(Event::Accepted(remote_addr));
+        Recorder(self.0.clone())
+    }
+}
+
+impl ConnectionHandler for Recorder {
+    fn on_close(&mut self, close: &ConnectionClosed) {
+        let _ = self.0.send(Event::Closed(close.clone()));
+    }
+}
+
+/// Serves `/`, and `/wedged`, which never responds and reports when it
+/// was reached on `reached`.
+fn app(reached: mpsc::UnboundedSender<()>) -> axum::Router {
+    axum::Router::new()
+        .route("/", axum::routing::get(|| async { "ok" }))
+        .route(
+            "/wedged",
+            axum::routing::get(move || {
+                let _ = reached.send(());
+                std::future::pending::<String>()
+            }),
+        )
+}
+
+async fn next(events: &mut mpsc::UnboundedReceiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("no connection event")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reports_accept_and_close() {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let handle = sui_http::Builder::new()
+        .connection_handler(MakeRecorder(sender))
+        .serve(("localhost", 0), app(mpsc::unbounded_channel().0))
+        .unwrap();
+
+    let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    socket.read_to_end(&mut response).await.unwrap();
+
+    let Event::Accepted(remote_addr) = next(&mut events).await else {
+        panic!("expected an accept first");
+    };
+    assert_eq!(remote_addr, socket.local_addr().unwrap());
+
+    let Event::Closed(close) = next(&mut events).await else {
+        panic!("expected a close");
+    };
+    assert_eq!(close.reason, CloseReason::Completed);
+    assert_eq!(close.bytes_read, request.len() as u64);
+    assert_eq!(close.bytes_written, response.len() as u64);
+}
+
+#[tokio::test]
+async fn reports_connections_aborted_on_shutdown() {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (reached, mut wedged) = mpsc::unbounded_channel();
+    let handle = sui_http::Builder::new()
+        .config(sui_http::Config::default().shutdown_grace_period(Duration::from_millis(100)))
+        .connection_handler(MakeRecorder(sender))
+        .serve(("localhost", 0), app(reached))
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET /wedged HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    assert!(matches!(next(&mut events).await, Event::Accepted(_)));
+    wedged.recv().await.unwrap();
+
+    handle.shutdown().await;
+    let Event::Closed(close) = next(&mut events).await else {
+        panic!("expected a close");
+    };
+    assert_eq!(close.reason, CloseReason::Aborted);
+}
+
+#[tokio::test]
+async fn metrics_record_connection_churn() {
+    let metrics = sui_http::middleware::metrics::Metrics::new();
+    let handle = sui_http::Builder::new()
+        .connection_handler(metrics.clone())
+        .serve(("localhost", 0), app(mpsc::unbounded_channel().0))
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    socket.read_to_end(&mut Vec::new()).await.unwrap();
+
+    // The connection task may still be winding down once the socket closed.
+    let closed = "http_connections_closed_total{reason=\"completed\"} 1\n";
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !metrics.encode().contains(closed) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connection close not recorded");
+    let encoded = metrics.encode();
+    assert!(encoded.contains("http_connections_accepted_total 1\n"));
+    assert!(encoded.contains("http_connections_active 0\n"));
+}
+
+/// Never finds a certificate; the tests using it fail before a client
+/// would need one.
+#[derive(Debug)]
+struct NoCertificate;
+
+impl sui_http::rustls::server::ResolvesServerCert for NoCertificate {
+    fn resolve(
+        &self,
+        _client_hello: sui_http::rustls::server::ClientHello<'_>,
+    ) -> Option<std::sync::Arc<sui_http::rustls::sign::CertifiedKey>> {
+        None
+    }
+}
+
+#[tokio::test]
+async fn reports_tls_handshake_timeouts() {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let tls_config = sui_http::rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(std::sync::Arc::new(NoCertificate));
+    let handle = sui_http::Builder::new()
+        .config(sui_http::Config::default().tls_handshake_timeout(Duration::from_millis(50)))
+        .tls_config(tls_config)
+        .connection_handler(MakeRecorder(sender))
+        .serve(("localhost", 0), app(mpsc::unbounded_channel().0))
+        .unwrap();
+
+    // A client that connects but never starts the handshake.
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    assert!(matches!(next(&mut events).await, Event::Accepted(_)));
+    let Event::Closed(close) = next(&mut events).await else {
+        panic!("expected a close");
+    };
+    assert_eq!(close.reason, CloseReason::HandshakeTimedOut);
+    // The server hung up on the client.
+    assert_eq!(socket.read(&mut [0; 1]).await.unwrap(), 0);
+}
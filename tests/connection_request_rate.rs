@@ -0,0 +1,57 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Config::max_connection_request_rate`.
+
+use std::time::Duration;
+use std::time::Instant;
+
+async fn send_concurrently(handle: &sui_http::ServerHandle, count: usize) -> Duration {
+    let addr = *handle.local_addr();
+    let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = h2::client::handshake(tcp).await.unwrap();
+    tokio::spawn(connection);
+
+    let start = Instant::now();
+    let mut responses = Vec::new();
+    for _ in 0..count {
+        let request = http::Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(())
+            .unwrap();
+        let mut send_request = send_request.clone().ready().await.unwrap();
+        let (response, _) = send_request.send_request(request, true).unwrap();
+        responses.push(response);
+    }
+    for response in responses {
+        assert!(response.await.unwrap().status().is_success());
+    }
+    start.elapsed()
+}
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "ok" }))
+}
+
+#[tokio::test]
+async fn requests_beyond_the_burst_are_paced() {
+    let config = sui_http::Config::default().max_connection_request_rate(20);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    // 20 requests fit in the burst, the next 20 are spaced 50ms apart.
+    let elapsed = send_concurrently(&handle, 40).await;
+    assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+}
+
+#[tokio::test]
+async fn unlimited_by_default() {
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let elapsed = send_concurrently(&handle, 40).await;
+    assert!(elapsed < Duration::from_millis(900), "{elapsed:?}");
+}
@@ -0,0 +1,121 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the drain notifications given to long-lived streams:
+//! the `DrainSignal` request extension and `Config::drain_reconnect_after`.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use sui_http::Config;
+use sui_http::DrainSignal;
+
+/// An endless stream that emits one chunk and then waits forever.
+fn endless_body() -> axum::body::Body {
+    let stream = futures::stream::once(async { Ok::<_, Infallible>(Bytes::from_static(b"first")) })
+        .chain(futures::stream::pending());
+    axum::body::Body::from_stream(stream)
+}
+
+#[tokio::test]
+async fn streams_are_ended_with_reconnect_trailers() {
+    let (drained_tx, drained_rx) = tokio::sync::oneshot::channel();
+    let drained_tx = std::sync::Arc::new(std::sync::Mutex::new(Some(drained_tx)));
+    let app = axum::Router::new().route(
+        "/stream",
+        axum::routing::get(
+            move |axum::Extension(drain): axum::Extension<DrainSignal>| {
+                let drained_tx = drained_tx.clone();
+                async move {
+                    assert!(!drain.is_draining());
+                    tokio::spawn(async move {
+                        drain.draining().await;
+                        let _ = drained_tx.lock().unwrap().take().unwrap().send(());
+                    });
+                    endless_body()
+                }
+            },
+        ),
+    );
+
+    let config = Config::default()
+        .max_connection_age(Duration::from_millis(200))
+        .max_connection_age_grace(Duration::from_secs(30))
+        .drain_reconnect_after(Duration::from_millis(200));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app)
+        .unwrap();
+    let addr = *handle.local_addr();
+
+    let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = h2::client::handshake(tcp).await.unwrap();
+    tokio::spawn(connection);
+
+    let request = http::Request::builder()
+        .uri(format!("http://{addr}/stream"))
+        .body(())
+        .unwrap();
+    let mut send_request = send_request.ready().await.unwrap();
+    let (response, _) = send_request.send_request(request, true).unwrap();
+    let response = response.await.unwrap();
+    assert!(response.status().is_success());
+    assert!(
+        response
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .is_none()
+    );
+
+    let mut body = response.into_body();
+    let first = body.data().await.expect("first chunk").unwrap();
+    assert_eq!(first, "first");
+
+    tokio::time::timeout(Duration::from_secs(10), drained_rx)
+        .await
+        .expect("drain signal never fired")
+        .unwrap();
+
+    let trailers = tokio::time::timeout(Duration::from_secs(10), body.trailers())
+        .await
+        .expect("stream was never ended")
+        .unwrap()
+        .expect("no trailers");
+    assert_eq!(trailers.get("grpc-status").unwrap(), "14");
+}
+
+#[tokio::test]
+async fn streams_run_until_grace_without_reconnect_deadline() {
+    let app = axum::Router::new().route("/stream", axum::routing::get(|| async { endless_body() }));
+
+    let config = Config::default()
+        .max_connection_age(Duration::from_millis(200))
+        .max_connection_age_grace(Duration::from_millis(500));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app)
+        .unwrap();
+    let addr = *handle.local_addr();
+
+    let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = h2::client::handshake(tcp).await.unwrap();
+    tokio::spawn(connection);
+
+    let request = http::Request::builder()
+        .uri(format!("http://{addr}/stream"))
+        .body(())
+        .unwrap();
+    let mut send_request = send_request.ready().await.unwrap();
+    let (response, _) = send_request.send_request(request, true).unwrap();
+    let mut body = response.await.unwrap().into_body();
+    body.data().await.expect("first chunk").unwrap();
+
+    // Without a reconnect deadline the stream is reset once the grace
+    // period expires rather than ended with trailers.
+    let result = tokio::time::timeout(Duration::from_secs(10), body.trailers())
+        .await
+        .expect("stream was never ended");
+    assert!(result.is_err(), "{result:?}");
+}
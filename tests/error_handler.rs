@@ -0,0 +1,88 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::error_handler`: service errors become responses and
+//! leave the connection, and its other streams, untouched.
+
+use sui_http::body::BoxBody;
+use sui_http::middleware::error::ErrorHandler;
+
+async fn fail_on_error_path(
+    request: http::Request<BoxBody>,
+) -> Result<http::Response<BoxBody>, std::io::Error> {
+    if request.uri().path() == "/error" {
+        Err(std::io::Error::other("service failed"))
+    } else {
+        Ok(http::Response::new(sui_http::body::full("ok")))
+    }
+}
+
+fn request(addr: std::net::SocketAddr, path: &str) -> http::Request<()> {
+    http::Request::builder()
+        .uri(format!("http://{addr}{path}"))
+        .body(())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn errors_become_responses_without_closing_the_connection() {
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), tower::service_fn(fail_on_error_path))
+        .unwrap();
+    let addr = *handle.local_addr();
+
+    let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = h2::client::handshake(tcp).await.unwrap();
+    tokio::spawn(connection);
+
+    let mut send_request = send_request.ready().await.unwrap();
+    let (response, _) = send_request
+        .send_request(request(addr, "/error"), true)
+        .unwrap();
+    assert_eq!(response.await.unwrap().status(), 500);
+
+    let mut grpc = request(addr, "/error");
+    grpc.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/grpc"),
+    );
+    let mut send_request = send_request.ready().await.unwrap();
+    let (response, _) = send_request.send_request(grpc, true).unwrap();
+    let response = response.await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["grpc-status"], "13");
+
+    // The same connection still serves requests.
+    let mut send_request = send_request.ready().await.unwrap();
+    let (response, _) = send_request.send_request(request(addr, "/"), true).unwrap();
+    assert_eq!(response.await.unwrap().status(), 200);
+    assert_eq!(handle.number_of_connections(), 1);
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn custom_error_handler_builds_the_response() {
+    let (handle, client) = sui_http::Builder::new()
+        .error_handler(ErrorHandler::new(|error, parts| {
+            let mut response =
+                http::Response::new(sui_http::body::full(format!("{}: {error}", parts.uri)));
+            *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+            response
+        }))
+        .serve_in_memory(tower::service_fn(fail_on_error_path))
+        .unwrap();
+
+    let response = client
+        .send(http::Request::get("/error").body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    let body = http_body_util::BodyExt::collect(response.into_body())
+        .await
+        .unwrap()
+        .to_bytes();
+    assert_eq!(body, "/error: service failed");
+
+    handle.shutdown().await;
+}
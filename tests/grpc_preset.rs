@@ -0,0 +1,63 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the gRPC preset: `Builder::grpc` and `Builder::serve_grpc`.
+
+use std::time::Duration;
+
+#[tokio::test]
+async fn serve_grpc_enforces_client_deadlines() {
+    let app = axum::Router::new().route(
+        "/echo.Echo/Slow",
+        axum::routing::post(|| async { std::future::pending::<String>().await }),
+    );
+    let handle = sui_http::Builder::grpc()
+        .serve_grpc(("localhost", 0), None, app)
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client
+            .post(format!("http://{}/echo.Echo/Slow", handle.local_addr()))
+            .header("content-type", "application/grpc")
+            .header("grpc-timeout", "50m")
+            .send(),
+    )
+    .await
+    .expect("deadline was not enforced")
+    .unwrap();
+
+    assert_eq!(response.headers()["grpc-status"], "4");
+}
+
+#[tokio::test]
+async fn serve_grpc_server_timeout_caps_deadline() {
+    let app = axum::Router::new().route(
+        "/echo.Echo/Slow",
+        axum::routing::post(|| async { std::future::pending::<String>().await }),
+    );
+    let handle = sui_http::Builder::grpc()
+        .serve_grpc(("localhost", 0), Some(Duration::from_millis(50)), app)
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        client
+            .post(format!("http://{}/echo.Echo/Slow", handle.local_addr()))
+            .header("content-type", "application/grpc")
+            .send(),
+    )
+    .await
+    .expect("server timeout was not enforced")
+    .unwrap();
+
+    assert_eq!(response.headers()["grpc-status"], "4");
+}
@@ -0,0 +1,59 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for handing listening sockets over to a new server.
+
+#![cfg(unix)]
+
+use sui_http::systemd::ListenFd;
+
+fn app(name: &'static str) -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(move || async move { name }))
+}
+
+async fn get(addr: std::net::SocketAddr) -> String {
+    // A fresh client per request, so that no pooled connection pins it to
+    // the old server.
+    reqwest::Client::new()
+        .get(format!("http://{addr}/"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn new_server_takes_over_the_listening_sockets() {
+    let old = sui_http::Builder::new()
+        .serve_addrs([("127.0.0.1", 0), ("127.0.0.1", 0)], app("old"))
+        .unwrap();
+    let addrs = old.local_addrs().to_vec();
+    assert_eq!(get(addrs[0]).await, "old");
+
+    let fds = old.listener_fds().unwrap();
+    let new = sui_http::Builder::new()
+        .serve_listen_fds(fds.into_iter().map(ListenFd::from), app("new"))
+        .unwrap();
+    assert_eq!(new.local_addrs(), addrs);
+
+    old.shutdown().await;
+    assert!(old.listener_fds().is_err());
+    for addr in addrs {
+        assert_eq!(get(addr).await, "new");
+    }
+}
+
+#[tokio::test]
+async fn unix_sockets_are_not_handed_over() {
+    let dir = std::env::temp_dir().join(format!("sui-http-handover-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let handle = sui_http::Builder::new()
+        .serve_unix(dir.join("server.sock"), app("unix"))
+        .unwrap();
+
+    assert!(handle.listener_fds().is_err());
+    handle.shutdown().await;
+    std::fs::remove_dir_all(dir).unwrap();
+}
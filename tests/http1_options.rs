@@ -0,0 +1,76 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The HTTP/1 connection options on `Config` must reach hyper.
+
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+/// Serves with `config`, writes `request` (shutting down the write side
+/// first if `half_close` is set) and returns everything the server sent
+/// until it closed the connection.
+async fn exchange(config: sui_http::Config, request: &[u8], half_close: bool) -> String {
+    let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+    let handle = sui_http::Builder::new()
+        .config(config.accept_http2(false))
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    if half_close {
+        stream.shutdown().await.unwrap();
+    }
+
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .expect("server did not close the connection")
+        .unwrap();
+    String::from_utf8(response).unwrap()
+}
+
+fn with_headers(count: usize) -> Vec<u8> {
+    let mut request = b"GET / HTTP/1.1\r\nHost: localhost\r\n".to_vec();
+    for i in 0..count {
+        request.extend_from_slice(format!("x-header-{i}: value\r\n").as_bytes());
+    }
+    request.extend_from_slice(b"Connection: close\r\n\r\n");
+    request
+}
+
+#[tokio::test]
+async fn max_headers_rejects_larger_requests() {
+    let config = sui_http::Config::default().http1_max_headers(8);
+    let response = exchange(config.clone(), &with_headers(4), false).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    let response = exchange(config, &with_headers(16), false).await;
+    assert!(response.starts_with("HTTP/1.1 431"), "{response}");
+}
+
+#[tokio::test]
+async fn max_buf_size_rejects_large_header_blocks() {
+    let config = sui_http::Config::default().http1_max_buf_size(8192);
+    let response = exchange(config, &with_headers(500), false).await;
+    assert!(response.starts_with("HTTP/1.1 431"), "{response}");
+}
+
+#[tokio::test]
+async fn disabling_keep_alive_closes_after_one_response() {
+    let config = sui_http::Config::default().http1_keep_alive(false);
+    let response = exchange(config, REQUEST, false).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("connection: close"), "{response}");
+}
+
+#[tokio::test]
+async fn half_close_answers_requests_after_client_eof() {
+    let config = sui_http::Config::default().http1_half_close(true);
+    let response = exchange(config, REQUEST, true).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
@@ -0,0 +1,56 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! HTTP/2 keepalive pings must close connections whose peer silently went
+//! away, even when no stream is open on them.
+
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// An empty SETTINGS frame.
+const SETTINGS: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "ok" }))
+}
+
+/// Opens an HTTP/2 connection that never answers the server again, and
+/// waits up to `wait` for the server to close it.
+async fn closed_within(config: sui_http::Config, wait: Duration) -> bool {
+    let handle = sui_http::Builder::new()
+        .config(config.accept_http1(false))
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(PREFACE).await.unwrap();
+    stream.write_all(SETTINGS).await.unwrap();
+
+    // Read (and ignore) whatever the server sends until it hangs up.
+    let drain = async {
+        let mut buf = [0; 1024];
+        while let Ok(read) = stream.read(&mut buf).await {
+            if read == 0 {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(wait, drain).await.is_ok()
+}
+
+#[tokio::test]
+async fn unacknowledged_pings_close_idle_connections() {
+    let config = sui_http::Config::default()
+        .http2_keepalive_interval(Some(Duration::from_millis(100)))
+        .http2_keepalive_timeout(Some(Duration::from_millis(100)));
+    assert!(closed_within(config, Duration::from_secs(5)).await);
+}
+
+#[tokio::test]
+async fn idle_connections_stay_open_without_keepalive() {
+    let config = sui_http::Config::default();
+    assert!(!closed_within(config, Duration::from_millis(500)).await);
+}
@@ -0,0 +1,133 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The HTTP/2 tuning knobs on `Config` must reach the peer: stream-level
+//! options are advertised in the server's initial SETTINGS frame and the
+//! connection window is opened with a WINDOW_UPDATE on stream 0.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// An empty SETTINGS frame.
+const SETTINGS: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
+
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FLAG_ACK: u8 = 0x1;
+
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The connection window every HTTP/2 connection starts with.
+const DEFAULT_WINDOW: u32 = 65_535;
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Frame {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header).await.unwrap();
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    Frame {
+        kind: header[3],
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload,
+    }
+}
+
+/// Connects to a server running `config` and returns the settings it
+/// advertised along with the size of its connection receive window.
+async fn advertised(config: sui_http::Config) -> (HashMap<u16, u32>, u32) {
+    let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+    let handle = sui_http::Builder::new()
+        .config(config.accept_http1(false))
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(PREFACE).await.unwrap();
+    stream.write_all(SETTINGS).await.unwrap();
+
+    let mut settings = None;
+    let mut window = DEFAULT_WINDOW;
+    // The server sends its SETTINGS, the ACK of ours and any WINDOW_UPDATE
+    // in no particular order; read until the connection goes quiet.
+    let mut acked = false;
+    loop {
+        let done = acked && settings.is_some();
+        let wait = if done {
+            Duration::from_millis(200)
+        } else {
+            Duration::from_secs(5)
+        };
+        let Ok(frame) = tokio::time::timeout(wait, read_frame(&mut stream)).await else {
+            assert!(done, "server did not complete the handshake");
+            break;
+        };
+        match frame.kind {
+            FRAME_SETTINGS if frame.flags & FLAG_ACK != 0 => acked = true,
+            FRAME_SETTINGS => {
+                settings = Some(
+                    frame
+                        .payload
+                        .chunks_exact(6)
+                        .map(|s| {
+                            (
+                                u16::from_be_bytes([s[0], s[1]]),
+                                u32::from_be_bytes([s[2], s[3], s[4], s[5]]),
+                            )
+                        })
+                        .collect(),
+                );
+            }
+            FRAME_WINDOW_UPDATE if frame.stream_id == 0 => {
+                let p = &frame.payload;
+                window += u32::from_be_bytes([p[0], p[1], p[2], p[3]]) & 0x7fff_ffff;
+            }
+            _ => {}
+        }
+    }
+    (settings.expect("server sent no SETTINGS"), window)
+}
+
+#[tokio::test]
+async fn tuning_knobs_are_advertised() {
+    let config = sui_http::Config::default()
+        .max_concurrent_streams(17)
+        .initial_stream_window_size(1 << 20)
+        .initial_connection_window_size(8 << 20)
+        .max_frame_size(32 << 10)
+        .http2_max_header_list_size(64 << 10);
+    let (settings, window) = advertised(config).await;
+
+    assert_eq!(settings.get(&SETTINGS_MAX_CONCURRENT_STREAMS), Some(&17));
+    assert_eq!(
+        settings.get(&SETTINGS_INITIAL_WINDOW_SIZE),
+        Some(&(1 << 20))
+    );
+    assert_eq!(settings.get(&SETTINGS_MAX_FRAME_SIZE), Some(&(32 << 10)));
+    assert_eq!(
+        settings.get(&SETTINGS_MAX_HEADER_LIST_SIZE),
+        Some(&(64 << 10))
+    );
+    assert_eq!(window, 8 << 20);
+}
+
+#[tokio::test]
+async fn default_stream_limit_is_advertised() {
+    let (settings, _) = advertised(sui_http::Config::default()).await;
+    assert_eq!(settings.get(&SETTINGS_MAX_CONCURRENT_STREAMS), Some(&200));
+}
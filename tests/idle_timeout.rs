@@ -0,0 +1,90 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connections without a request in flight for `Config::idle_timeout` are
+//! closed gracefully.
+
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+const SLOW_REQUEST: &[u8] = b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n";
+const IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+fn serve() -> sui_http::ServerHandle {
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|| async { "ok" }))
+        .route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(IDLE_TIMEOUT * 3).await;
+                "slow"
+            }),
+        );
+    sui_http::Builder::new()
+        .config(sui_http::Config::default().idle_timeout(IDLE_TIMEOUT))
+        .serve(("localhost", 0), app)
+        .unwrap()
+}
+
+async fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+        .await
+        .expect("server did not close the connection")
+        .unwrap();
+    String::from_utf8(rest).unwrap()
+}
+
+#[tokio::test]
+async fn closes_idle_http1_connections() {
+    let handle = serve();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(REQUEST).await.unwrap();
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+
+    let start = tokio::time::Instant::now();
+    assert_eq!(read_to_close(&mut stream).await, "");
+    assert!(start.elapsed() >= IDLE_TIMEOUT / 2);
+}
+
+#[tokio::test]
+async fn keeps_connections_with_requests_in_flight() {
+    let handle = serve();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(SLOW_REQUEST).await.unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("slow"), "{response}");
+}
+
+#[tokio::test]
+async fn sends_goaway_to_idle_http2_connections() {
+    let handle = serve();
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let url = format!("http://{}", handle.local_addr());
+    assert_eq!(
+        client.get(&url).send().await.unwrap().text().await.unwrap(),
+        "ok"
+    );
+    assert_eq!(handle.connections().len(), 1);
+
+    tokio::time::sleep(IDLE_TIMEOUT * 3).await;
+    assert_eq!(handle.connections().len(), 0);
+
+    // The client noticed the GOAWAY and opens a new connection.
+    assert_eq!(
+        client.get(&url).send().await.unwrap().text().await.unwrap(),
+        "ok"
+    );
+}
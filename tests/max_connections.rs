@@ -0,0 +1,61 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Config::max_connections`.
+//!
+//! Once the connection limit is reached the server must stop accepting
+//! rather than accept-and-drop: excess connections wait in the listen
+//! backlog and are served as soon as capacity frees up.
+
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+async fn read_response(socket: &mut tokio::net::TcpStream) -> String {
+    let mut buf = vec![0; 1024];
+    let n = socket.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_wait_for_capacity() {
+    let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+    let config = sui_http::Config::default().max_connections(1);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    // The first connection is served and kept alive.
+    let mut first = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    first.write_all(REQUEST).await.unwrap();
+    assert!(read_response(&mut first).await.starts_with("HTTP/1.1 200"));
+    assert_eq!(handle.number_of_connections(), 1);
+
+    // The second connection completes the TCP handshake through the
+    // kernel's backlog but is not accepted while the first is open.
+    let mut second = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    second.write_all(REQUEST).await.unwrap();
+    let mut buf = [0; 1];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), second.peek(&mut buf))
+            .await
+            .is_err(),
+        "connection over the limit was served"
+    );
+    assert_eq!(handle.number_of_connections(), 1);
+
+    // Closing the first connection frees capacity for the second.
+    drop(first);
+    let response = tokio::time::timeout(Duration::from_secs(10), read_response(&mut second))
+        .await
+        .expect("waiting connection was never accepted");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
@@ -0,0 +1,39 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A connection that has served `Config::max_requests_per_connection`
+//! requests is closed gracefully.
+
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+#[tokio::test]
+async fn closes_http1_connections_after_the_last_request() {
+    let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+    let handle = sui_http::Builder::new()
+        .config(sui_http::Config::default().max_requests_per_connection(2))
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+    stream.write_all(REQUEST).await.unwrap();
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let first = String::from_utf8_lossy(&buf[..n]).into_owned();
+    assert!(first.starts_with("HTTP/1.1 200"), "{first}");
+    assert!(!first.contains("connection: close"), "{first}");
+
+    stream.write_all(REQUEST).await.unwrap();
+    let mut rest = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+        .await
+        .expect("server did not close the connection")
+        .unwrap();
+    let second = String::from_utf8(rest).unwrap();
+    assert!(second.starts_with("HTTP/1.1 200"), "{second}");
+    assert!(second.contains("connection: close"), "{second}");
+}
@@ -0,0 +1,83 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::middleware` and the curated `MiddlewareStack` presets.
+
+use std::time::Duration;
+
+use sui_http::middleware::metrics::Metrics;
+use sui_http::middleware::stack::MiddlewareStack;
+use sui_http::middleware::timeout::TimeoutLayer;
+
+#[tokio::test]
+async fn builder_applies_the_stack_to_the_service() {
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|| async { "hello" }))
+        .route(
+            "/slow",
+            axum::routing::get(|| async { std::future::pending::<String>().await }),
+        );
+    let metrics = Metrics::new();
+    let stack = MiddlewareStack::rest_defaults()
+        .timeout(TimeoutLayer::new(Duration::from_millis(50)))
+        .metrics(metrics.clone());
+    let (handle, client) = sui_http::Builder::new()
+        .middleware(stack)
+        .serve_in_memory(app)
+        .unwrap();
+
+    let response = client
+        .send(http::Request::get("/").body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().contains_key("x-request-id"));
+
+    let response = client
+        .send(http::Request::get("/slow").body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 504);
+    assert!(response.headers().contains_key("x-request-id"));
+
+    // Metrics sit outside the timeout and record the timed out request.
+    let encoded = metrics.encode();
+    assert!(encoded.contains(r#"status="504""#), "{encoded}");
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn builder_applies_layers_outermost_first() {
+    let app = axum::Router::new().route(
+        "/slow",
+        axum::routing::get(|| async { std::future::pending::<String>().await }),
+    );
+    let metrics = Metrics::new();
+    let (handle, client) = sui_http::Builder::new()
+        .layer(metrics.layer())
+        .layer(tower::util::MapResponseLayer::new(
+            |mut response: http::Response<sui_http::body::BoxBody>| {
+                response
+                    .headers_mut()
+                    .insert("x-layered", http::HeaderValue::from_static("1"));
+                response
+            },
+        ))
+        .layer(TimeoutLayer::new(Duration::from_millis(50)))
+        .serve_in_memory(app)
+        .unwrap();
+
+    let response = client
+        .send(http::Request::get("/slow").body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 504);
+    assert_eq!(response.headers()["x-layered"], "1");
+
+    // The callback layer, added first, observes the timeout response.
+    let encoded = metrics.encode();
+    assert!(encoded.contains(r#"status="504""#), "{encoded}");
+
+    handle.shutdown().await;
+}
@@ -0,0 +1,52 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A server bound to several addresses serves the same service on each of
+//! them and shuts them all down together.
+
+use sui_http::ConnectInfo;
+
+fn app() -> axum::Router {
+    axum::Router::new().route(
+        "/",
+        axum::routing::get(
+            |axum::Extension(info): axum::Extension<ConnectInfo>| async move {
+                info.local_addr().to_string()
+            },
+        ),
+    )
+}
+
+#[tokio::test]
+async fn serves_every_address() {
+    let handle = sui_http::Builder::new()
+        .serve_addrs([("127.0.0.1", 0), ("127.0.0.1", 0)], app())
+        .unwrap();
+
+    let addrs = handle.local_addrs().to_vec();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0], addrs[1]);
+    assert_eq!(handle.local_addr(), &addrs[0]);
+
+    for addr in &addrs {
+        let response = reqwest::get(format!("http://{addr}"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(response, addr.to_string());
+    }
+
+    let report = handle.shutdown().await;
+    assert_eq!(report.listeners_closed, 2);
+    for addr in &addrs {
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn rejects_an_empty_address_list() {
+    let addrs: [(&str, u16); 0] = [];
+    assert!(sui_http::Builder::new().serve_addrs(addrs, app()).is_err());
+}
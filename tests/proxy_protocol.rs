@@ -0,0 +1,97 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration tests for `Config::proxy_protocol`.
+//!
+//! Behind an L4 load balancer every connection originates from the load
+//! balancer itself. With the PROXY protocol enabled the original client
+//! address from the header must be what middleware and handlers observe.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+fn app() -> axum::Router {
+    axum::Router::new().route(
+        "/",
+        axum::routing::get(
+            |axum::Extension(info): axum::Extension<sui_http::ConnectInfo>| async move {
+                info.remote_addr().to_string()
+            },
+        ),
+    )
+}
+
+async fn request(addr: SocketAddr, preamble: &[u8]) -> String {
+    let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    socket.write_all(preamble).await.unwrap();
+    socket
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    let _ = socket.read_to_string(&mut response).await;
+    response
+}
+
+#[tokio::test]
+async fn proxy_protocol_replaces_remote_addr() {
+    let config = sui_http::Config::default().proxy_protocol(true);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let response = request(
+        *handle.local_addr(),
+        b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("203.0.113.7:51234"), "{response}");
+
+    // Unproxied connections (e.g. load balancer health checks) keep the
+    // transport peer address.
+    let response = request(*handle.local_addr(), b"PROXY UNKNOWN\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
+
+#[tokio::test]
+async fn proxy_protocol_drops_connections_without_header() {
+    let config = sui_http::Config::default().proxy_protocol(true);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let response =
+        tokio::time::timeout(Duration::from_secs(10), request(*handle.local_addr(), b""))
+            .await
+            .expect("connection without a PROXY header was not dropped");
+    assert!(response.is_empty(), "{response}");
+}
+
+#[tokio::test]
+async fn proxy_protocol_header_timeout() {
+    let config = sui_http::Config::default()
+        .proxy_protocol(true)
+        .proxy_protocol_timeout(Duration::from_millis(200));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    // A partial header followed by silence must not hold the socket open.
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket.write_all(b"PROXY TCP4 ").await.unwrap();
+    let mut buf = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), socket.read_to_end(&mut buf))
+        .await
+        .expect("silent connection was never dropped")
+        .ok();
+    assert!(buf.is_empty());
+}
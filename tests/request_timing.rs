@@ -0,0 +1,55 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the `RequestTiming` request extension.
+
+use std::time::Duration;
+
+use axum::Extension;
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use hyper_util::rt::TokioIo;
+use sui_http::RequestTiming;
+
+async fn timing(Extension(timing): Extension<RequestTiming>) -> String {
+    match timing.time_before_service() {
+        Some(duration) => duration.as_millis().to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+#[tokio::test]
+async fn measures_time_before_the_first_request_reaches_the_service() {
+    let app = axum::Router::new().route("/", axum::routing::get(timing));
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app)
+        .unwrap();
+
+    let tcp = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(tcp))
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    // The connection is accepted right away, but the request head only
+    // arrives later.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let request = http::Request::get("/")
+            .body(Empty::<bytes::Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        bodies.push(String::from_utf8(body.to_vec()).unwrap());
+    }
+
+    let first: u64 = bodies[0].parse().unwrap();
+    assert!(first >= 100, "{first}ms");
+    assert_eq!(bodies[1], "none");
+
+    handle.shutdown().await;
+}
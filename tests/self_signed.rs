@@ -0,0 +1,55 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `SelfSignedCertificate`: serving TLS, and HTTP/2 over TLS,
+//! with a certificate generated in memory.
+
+use sui_http::SelfSignedCertificate;
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "hello" }))
+}
+
+fn client(cert: &SelfSignedCertificate) -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .add_root_certificate(reqwest::Certificate::from_pem(cert.pem().as_bytes()).unwrap())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn serves_http2_over_tls() {
+    let cert = SelfSignedCertificate::new(["localhost"]).unwrap();
+    let handle = sui_http::Builder::new()
+        .tls_config(cert.server_config().unwrap())
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let response = client(&cert)
+        .get(format!("https://localhost:{}", handle.local_addr().port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_2);
+    assert_eq!(response.text().await.unwrap(), "hello");
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn is_only_valid_for_its_hostnames() {
+    let cert = SelfSignedCertificate::new(["rpc.example.com"]).unwrap();
+    let handle = sui_http::Builder::new()
+        .tls_config(cert.server_config().unwrap())
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let result = client(&cert)
+        .get(format!("https://localhost:{}", handle.local_addr().port()))
+        .send()
+        .await;
+    assert!(result.is_err(), "{result:?}");
+
+    handle.shutdown().await;
+}
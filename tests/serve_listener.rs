@@ -0,0 +1,81 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for serving on sockets bound outside of the crate.
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "ok" }))
+}
+
+async fn get(addr: std::net::SocketAddr) -> String {
+    reqwest::get(format!("http://{addr}/"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn serve_pre_bound_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = sui_http::Builder::new()
+        .serve_listener(listener, app())
+        .unwrap();
+    assert_eq!(*handle.local_addr(), addr);
+    assert_eq!(get(addr).await, "ok");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_inherited_fd() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = std::os::fd::OwnedFd::from(listener);
+
+    let handle = sui_http::Builder::new().serve_fd(fd, app()).unwrap();
+    assert_eq!(*handle.local_addr(), addr);
+    assert_eq!(get(addr).await, "ok");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_listen_fds() {
+    use sui_http::systemd::ListenFd;
+
+    let listeners = [
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap(),
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap(),
+    ];
+    let addrs = listeners.each_ref().map(|l| l.local_addr().unwrap());
+    let fds = listeners.map(|l| ListenFd::from(std::os::fd::OwnedFd::from(l)));
+
+    let handle = sui_http::Builder::new()
+        .serve_listen_fds(fds, app())
+        .unwrap();
+    assert_eq!(handle.local_addrs(), addrs);
+    for addr in addrs {
+        assert_eq!(get(addr).await, "ok");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_listen_fds_rejects_non_tcp_sockets() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let fd = sui_http::systemd::ListenFd::from(std::os::fd::OwnedFd::from(socket));
+
+    assert!(
+        sui_http::Builder::new()
+            .serve_listen_fds([fd], app())
+            .is_err()
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_systemd_requires_socket_activation() {
+    assert!(sui_http::Builder::new().serve_systemd(app()).is_err());
+}
@@ -0,0 +1,269 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the `ShutdownReport` returned by `ServerHandle::shutdown`, and
+//! for the deadlines of the `ServerHandle` shutdown API.
+
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+fn app() -> axum::Router {
+    axum::Router::new()
+        .route("/", axum::routing::get(|| async { "ok" }))
+        .route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "slow"
+            }),
+        )
+        .route(
+            "/wedged",
+            axum::routing::get(|| async { std::future::pending::<String>().await }),
+        )
+}
+
+async fn wait_for_connections(handle: &sui_http::ServerHandle, count: usize) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while handle.number_of_connections() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connection was never accepted");
+}
+
+#[tokio::test]
+async fn idle_connections_are_drained() {
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(socket.read(&mut buf).await.unwrap() > 0);
+    wait_for_connections(&handle, 1).await;
+
+    let report = handle.shutdown().await;
+    assert!(report.is_clean(), "{report:?}");
+    assert_eq!(report.connections_drained, 1);
+    assert_eq!(report.listeners_closed, 1);
+    assert_eq!(handle.wait_for_shutdown().await, report);
+}
+
+#[tokio::test]
+async fn wedged_connections_are_aborted() {
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET /wedged HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    wait_for_connections(&handle, 1).await;
+
+    let report = handle.shutdown().await;
+    assert!(!report.is_clean());
+    assert_eq!(report.connections_drained, 0);
+    assert_eq!(report.connections_aborted, 1);
+    assert!(report.duration >= Duration::from_secs(1), "{report:?}");
+}
+
+#[tokio::test]
+async fn in_flight_requests_finish_within_the_grace_period() {
+    let config = sui_http::Config::default().shutdown_grace_period(Duration::from_secs(5));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    wait_for_connections(&handle, 1).await;
+
+    let report = handle.shutdown().await;
+    assert!(report.is_clean(), "{report:?}");
+    assert_eq!(report.connections_drained, 1);
+
+    let mut response = Vec::new();
+    socket.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("connection: close"), "{response}");
+    assert!(response.ends_with("slow"), "{response}");
+}
+
+#[tokio::test]
+async fn grace_period_bounds_the_shutdown() {
+    let config = sui_http::Config::default().shutdown_grace_period(Duration::from_millis(100));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET /wedged HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    wait_for_connections(&handle, 1).await;
+
+    let report = handle.shutdown().await;
+    assert_eq!(report.connections_aborted, 1);
+    assert!(report.duration < Duration::from_secs(1), "{report:?}");
+    assert_eq!(report.requests_aborted.len(), 1);
+    assert_eq!(report.requests_aborted[0].uri, "/wedged");
+}
+
+#[tokio::test]
+async fn connection_grace_resets_only_long_running_streams() {
+    let config = sui_http::Config::default()
+        .shutdown_grace_period(Duration::from_secs(30))
+        .shutdown_connection_grace(Duration::from_millis(500));
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let url = format!("http://{}", handle.local_addr());
+    let slow = tokio::spawn(client.get(format!("{url}/slow")).send());
+    let wedged = tokio::spawn(client.get(format!("{url}/wedged")).send());
+    wait_for_connections(&handle, 1).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let report = handle.shutdown().await;
+    assert!(report.duration < Duration::from_secs(5), "{report:?}");
+    assert_eq!(report.connections_aborted, 1);
+    let aborted = report
+        .requests_aborted
+        .iter()
+        .map(|request| request.uri.path())
+        .collect::<Vec<_>>();
+    assert_eq!(aborted, ["/wedged"]);
+
+    let slow = slow.await.unwrap().unwrap();
+    assert_eq!(slow.text().await.unwrap(), "slow");
+    assert!(wedged.await.unwrap().is_err());
+}
+
+async fn serve_wedged(grace_period: Duration) -> sui_http::ServerHandle {
+    let config = sui_http::Config::default().shutdown_grace_period(grace_period);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket
+        .write_all(b"GET /wedged HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    wait_for_connections(&handle, 1).await;
+    // The connection is aborted by the server; keep our end open until then.
+    tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = socket.read_to_end(&mut buf).await;
+    });
+    handle
+}
+
+#[tokio::test]
+async fn graceful_shutdown_deadline_overrides_the_grace_period() {
+    let handle = serve_wedged(Duration::from_secs(30)).await;
+
+    handle.graceful_shutdown(Duration::from_millis(100));
+    let report = handle.wait_for_completion().await;
+    assert_eq!(report.connections_aborted, 1);
+    assert!(report.duration < Duration::from_secs(5), "{report:?}");
+}
+
+#[tokio::test]
+async fn force_shutdown_cuts_a_graceful_shutdown_short() {
+    let handle = serve_wedged(Duration::from_secs(30)).await;
+
+    handle.graceful_shutdown(Duration::from_secs(30));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!handle.is_shutdown());
+    handle.force_shutdown();
+
+    let report = tokio::time::timeout(Duration::from_secs(5), handle.wait_for_completion())
+        .await
+        .expect("force_shutdown did not abort the draining connection");
+    assert!(!report.is_clean());
+    assert_eq!(report.connections_drained, 0);
+    assert_eq!(report.connections_aborted, 1);
+}
+
+#[tokio::test]
+async fn force_shutdown_does_not_wait_for_connections() {
+    let handle = serve_wedged(Duration::from_secs(30)).await;
+
+    handle.force_shutdown();
+    let report = tokio::time::timeout(Duration::from_secs(5), handle.wait_for_completion())
+        .await
+        .expect("force_shutdown waited for the connection to drain");
+    assert_eq!(report.connections_aborted, 1);
+}
+
+#[tokio::test]
+async fn http2_connections_are_sent_a_goaway() {
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    const SETTINGS: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
+    const FRAME_GOAWAY: u8 = 0x7;
+
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), app())
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    socket.write_all(PREFACE).await.unwrap();
+    socket.write_all(SETTINGS).await.unwrap();
+    wait_for_connections(&handle, 1).await;
+    handle.trigger_shutdown();
+
+    let goaway = async {
+        loop {
+            let mut header = [0; 9];
+            socket.read_exact(&mut header).await.unwrap();
+            let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let mut payload = vec![0; len];
+            socket.read_exact(&mut payload).await.unwrap();
+            if header[3] == FRAME_GOAWAY {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), goaway)
+        .await
+        .expect("server did not send a GOAWAY");
+    // A real client closes the connection once its streams are done.
+    drop(socket);
+    assert!(handle.wait_for_shutdown().await.is_clean());
+}
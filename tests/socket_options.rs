@@ -0,0 +1,58 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the listening socket options on `Config`.
+
+async fn get(addr: std::net::SocketAddr) -> reqwest::StatusCode {
+    reqwest::get(format!("http://{addr}/"))
+        .await
+        .unwrap()
+        .status()
+}
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "ok" }))
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn reuse_port_allows_sharing_an_address() {
+    let config = sui_http::Config::default().reuse_port(true);
+    let first = sui_http::Builder::new()
+        .config(config.clone())
+        .serve(("127.0.0.1", 0), app())
+        .unwrap();
+    let second = sui_http::Builder::new()
+        .config(config)
+        .serve(first.local_addr(), app())
+        .unwrap();
+    assert_eq!(first.local_addr(), second.local_addr());
+
+    assert_eq!(get(*first.local_addr()).await, reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn address_in_use_without_reuse_port() {
+    let first = sui_http::Builder::new()
+        .serve(("127.0.0.1", 0), app())
+        .unwrap();
+    assert!(
+        sui_http::Builder::new()
+            .serve(first.local_addr(), app())
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn serves_with_tuned_buffers_and_backlog() {
+    let config = sui_http::Config::default()
+        .send_buffer_size(256 * 1024)
+        .recv_buffer_size(256 * 1024)
+        .listen_backlog(16);
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve(("127.0.0.1", 0), app())
+        .unwrap();
+
+    assert_eq!(get(*handle.local_addr()).await, reqwest::StatusCode::OK);
+}
@@ -0,0 +1,76 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for the in-memory transport in `sui_http::test_util`.
+
+use std::time::Duration;
+
+use http_body_util::BodyExt;
+use sui_http::ConnectInfo;
+use sui_http::test_util::MemoryAddr;
+use sui_http::test_util::TestProtocol;
+
+fn app() -> axum::Router {
+    axum::Router::new()
+        .route("/", axum::routing::get(|| async { "hello" }))
+        .route(
+            "/peer",
+            axum::routing::get(
+                |axum::Extension(info): axum::Extension<ConnectInfo<MemoryAddr>>| async move {
+                    info.remote_addr.to_string()
+                },
+            ),
+        )
+}
+
+async fn get(client: &sui_http::test_util::TestClient, path: &str) -> String {
+    let response = client
+        .send(http::Request::get(path).body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn http1_and_http2_requests() {
+    let (handle, client) = sui_http::Builder::new().serve_in_memory(app()).unwrap();
+
+    assert_eq!(get(&client, "/").await, "hello");
+    let client = client.protocol(TestProtocol::Http2);
+    assert_eq!(get(&client, "/").await, "hello");
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn each_request_is_a_new_connection() {
+    let (_handle, client) = sui_http::Builder::new().serve_in_memory(app()).unwrap();
+
+    assert_eq!(get(&client, "/peer").await, "memory:1");
+    assert_eq!(get(&client, "/peer").await, "memory:2");
+}
+
+#[tokio::test]
+async fn middleware_stack_is_exercised() {
+    let app = tower::ServiceBuilder::new()
+        .layer(tower::layer::layer_fn(|inner| {
+            sui_http::middleware::grpc_timeout::GrpcTimeout::new(
+                inner,
+                Some(Duration::from_millis(50)),
+            )
+        }))
+        .service(axum::Router::new().route(
+            "/",
+            axum::routing::get(|| async { std::future::pending::<String>().await }),
+        ));
+    let (_handle, client) = sui_http::Builder::new().serve_in_memory(app).unwrap();
+
+    let response = client
+        .protocol(TestProtocol::Http2)
+        .send(http::Request::get("/").body(String::new()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.headers().get("grpc-status").unwrap(), "4");
+}
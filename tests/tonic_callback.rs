@@ -27,6 +27,7 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 use sui_http::middleware::callback::CallbackLayer;
 use sui_http::middleware::callback::MakeCallbackHandler;
 use sui_http::middleware::callback::RequestHandler;
@@ -92,10 +93,10 @@ impl RequestHandler for ReqH {
 }
 
 impl ResponseHandler for RespH {
-    fn on_response(&mut self, _parts: &http::response::Parts) {
+    fn on_response(&mut self, _parts: &http::response::Parts, _latency: Duration) {
         self.0.lock().unwrap().response_seen = true;
     }
-    fn on_service_error<E: std::fmt::Display + 'static>(&mut self, error: &E) {
+    fn on_service_error<E: std::fmt::Display + 'static>(&mut self, error: &E, _latency: Duration) {
         self.0
             .lock()
             .unwrap()
@@ -105,7 +106,7 @@ impl ResponseHandler for RespH {
     fn on_body_chunk<B: Buf>(&mut self, chunk: &B) {
         self.0.lock().unwrap().response_bytes += chunk.remaining();
     }
-    fn on_end_of_stream(&mut self, _trailers: Option<&http::HeaderMap>) {
+    fn on_end_of_stream(&mut self, _trailers: Option<&http::HeaderMap>, _latency: Duration) {
         self.0.lock().unwrap().response_end_seen = true;
     }
 }
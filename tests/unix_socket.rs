@@ -0,0 +1,99 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tests for `Builder::serve_unix`.
+
+#![cfg(unix)]
+
+use std::path::PathBuf;
+
+use http_body_util::BodyExt;
+use http_body_util::Empty;
+use hyper_util::rt::TokioIo;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("sui-http-{}-{name}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn app() -> axum::Router {
+    axum::Router::new().route("/", axum::routing::get(|| async { "ok" }))
+}
+
+async fn get(path: &PathBuf) -> String {
+    let stream = tokio::net::UnixStream::connect(path).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    let request = http::Request::get("/")
+        .body(Empty::<bytes::Bytes>::new())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn replaces_a_stale_socket_and_removes_it_on_shutdown() {
+    let path = socket_path("stale");
+    // Dropping a listener leaves its socket file behind, like a crash.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let handle = sui_http::Builder::new().serve_unix(&path, app()).unwrap();
+    assert_eq!(get(&path).await, "ok");
+
+    handle.shutdown().await;
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn refuses_to_replace_a_live_socket() {
+    let path = socket_path("live");
+    let handle = sui_http::Builder::new().serve_unix(&path, app()).unwrap();
+
+    let error = sui_http::Builder::new()
+        .serve_unix(&path, app())
+        .unwrap_err();
+    let error = error.downcast::<std::io::Error>().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    assert_eq!(get(&path).await, "ok");
+
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn lock_file_guards_the_socket() {
+    let path = socket_path("locked");
+    let config = sui_http::Config::default().unix_socket_lock_file(true);
+    let handle = sui_http::Builder::new()
+        .config(config.clone())
+        .serve_unix(&path, app())
+        .unwrap();
+
+    let mut lock_path = path.clone().into_os_string();
+    lock_path.push(".lock");
+    let pid = std::fs::read_to_string(&lock_path).unwrap();
+    assert_eq!(pid.trim(), std::process::id().to_string());
+
+    let error = sui_http::Builder::new()
+        .config(config.clone())
+        .serve_unix(&path, app())
+        .unwrap_err();
+    let error = error.downcast::<std::io::Error>().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+    // Once the first server is gone the lock is released.
+    handle.shutdown().await;
+    let handle = sui_http::Builder::new()
+        .config(config)
+        .serve_unix(&path, app())
+        .unwrap();
+    assert_eq!(get(&path).await, "ok");
+
+    handle.shutdown().await;
+    let _ = std::fs::remove_file(lock_path);
+}
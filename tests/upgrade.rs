@@ -0,0 +1,94 @@
+// Copyright (c) Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! HTTP/1 upgrades (`Connection: Upgrade`, as used by WebSockets) reach the
+//! served service, which can take over the upgraded connection.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use sui_http::body::BoxBody;
+use sui_http::middleware::logging::LoggingLayer;
+use sui_http::middleware::metrics::Metrics;
+use sui_http::middleware::stack::MiddlewareStack;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+/// Answers upgrade requests with `101 Switching Protocols` and echoes
+/// whatever is sent on the upgraded connection.
+async fn echo(mut request: http::Request<BoxBody>) -> Result<http::Response<BoxBody>, Infallible> {
+    let on_upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        let upgraded = on_upgrade.await.expect("connection was not upgraded");
+        let mut io = hyper_util::rt::TokioIo::new(upgraded);
+        let mut buf = [0; 4];
+        io.read_exact(&mut buf).await.unwrap();
+        io.write_all(&buf).await.unwrap();
+    });
+
+    let response = http::Response::builder()
+        .status(http::StatusCode::SWITCHING_PROTOCOLS)
+        .header(http::header::CONNECTION, "upgrade")
+        .header(http::header::UPGRADE, "echo")
+        .body(sui_http::body::empty())
+        .unwrap();
+    Ok(response)
+}
+
+async fn assert_upgrades(handle: &sui_http::ServerHandle) {
+    let mut stream = tokio::net::TcpStream::connect(handle.local_addr())
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /feed HTTP/1.1\r\nHost: localhost\r\nConnection: upgrade\r\nUpgrade: echo\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+
+    stream.write_all(b"ping").await.unwrap();
+    let mut echoed = [0; 4];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut echoed))
+        .await
+        .expect("upgraded connection was not echoed")
+        .unwrap();
+    assert_eq!(&echoed, b"ping");
+}
+
+#[tokio::test]
+async fn services_take_over_upgraded_connections() {
+    let handle = sui_http::Builder::new()
+        .serve(("localhost", 0), tower::service_fn(echo))
+        .unwrap();
+    assert_upgrades(&handle).await;
+}
+
+#[tokio::test]
+async fn upgrades_pass_through_the_middleware() {
+    let metrics = Metrics::new();
+    let handle = sui_http::Builder::new()
+        .middleware(MiddlewareStack::rest_defaults().metrics(metrics.clone()))
+        .layer(LoggingLayer::new())
+        .serve(("localhost", 0), tower::service_fn(echo))
+        .unwrap();
+    assert_upgrades(&handle).await;
+
+    let encoded = metrics.encode();
+    assert!(encoded.contains(r#"status="101""#), "{encoded}");
+}
+
+#[tokio::test]
+async fn http1_only_servers_support_upgrades() {
+    let handle = sui_http::Builder::new()
+        .config(sui_http::Config::default().accept_http2(false))
+        .serve(("localhost", 0), tower::service_fn(echo))
+        .unwrap();
+    assert_upgrades(&handle).await;
+}